@@ -61,8 +61,11 @@ impl ConnectionManager {
         )?;
 
         // Get memory usage
-        let mut stmt = conn_guard.prepare("PRAGMA database_size")?;
-        let memory_usage: i64 = stmt.query_row([], |row| row.get(0))?;
+        let memory_usage: i64 = conn_guard.query_row(
+            "SELECT CAST(COALESCE(SUM(memory_usage_bytes), 0) AS BIGINT) FROM duckdb_memory()",
+            [],
+            |row| row.get(0)
+        )?;
 
         // Get table count
         let mut stmt = conn_guard.prepare("
@@ -90,18 +93,18 @@ pub struct DatabaseInfo {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use tempfile::TempDir;
 
     #[tokio::test]
     async fn test_connection_manager() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap().to_string();
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.db").to_str().unwrap().to_string();
         
         let manager = ConnectionManager::new(db_path);
         
         // Test connection creation
         let conn = manager.create_connection().unwrap();
-        assert!(!conn.lock().await.is_readonly(duckdb::OpenFlags::SQLITE_OPEN_READWRITE).unwrap());
+        conn.lock().await.execute_batch("CREATE TABLE writable (id INTEGER)").unwrap();
         
         // Test connection health
         assert!(manager.test_connection().await.is_ok());
@@ -0,0 +1,263 @@
+use serde_json::Value as JsonValue;
+
+use crate::{
+    models::{Condition, DateUnit, Filter, FilterOp, RelativeRange},
+    utils::error::{AppError, AppResult},
+};
+
+/// A [`Filter`] compiled to a SQL predicate with positional `?` parameters.
+///
+/// `params` is bound with [`super::params::resolve_params`], so condition
+/// values may use the typed `{"type": ..., "value": ...}` form.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompiledFilter {
+    pub sql: String,
+    pub params: Vec<JsonValue>,
+}
+
+impl CompiledFilter {
+    /// ` WHERE <predicate>`, or an empty string when nothing is filtered
+    pub fn where_clause(&self) -> String {
+        if self.sql.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.sql)
+        }
+    }
+
+    /// Parameters in the form accepted by `resolve_params`
+    pub fn params_json(&self) -> JsonValue {
+        JsonValue::Array(self.params.clone())
+    }
+}
+
+/// Compile an optional filter; `None` yields an empty predicate
+pub fn compile_filter(filter: Option<&Filter>) -> AppResult<CompiledFilter> {
+    let mut compiled = CompiledFilter::default();
+    if let Some(filter) = filter {
+        compiled.sql = compile(filter, &mut compiled.params)?;
+    }
+    Ok(compiled)
+}
+
+fn compile(filter: &Filter, params: &mut Vec<JsonValue>) -> AppResult<String> {
+    match filter {
+        Filter::And { and } => compile_group(and, "AND", "TRUE", params),
+        Filter::Or { or } => compile_group(or, "OR", "FALSE", params),
+        Filter::Not { not } => Ok(format!("NOT ({})", compile(not, params)?)),
+        Filter::Condition(condition) => compile_condition(condition, params),
+    }
+}
+
+fn compile_group(
+    filters: &[Filter],
+    joiner: &str,
+    empty: &str,
+    params: &mut Vec<JsonValue>,
+) -> AppResult<String> {
+    if filters.is_empty() {
+        return Ok(empty.to_string());
+    }
+    let parts = filters
+        .iter()
+        .map(|f| compile(f, params).map(|sql| format!("({})", sql)))
+        .collect::<AppResult<Vec<_>>>()?;
+    Ok(parts.join(&format!(" {} ", joiner)))
+}
+
+fn compile_condition(condition: &Condition, params: &mut Vec<JsonValue>) -> AppResult<String> {
+    let field = quote_ident(&condition.field);
+    let value = &condition.value;
+
+    let comparison = |op: &str, params: &mut Vec<JsonValue>| -> AppResult<String> {
+        params.push(scalar(condition)?);
+        Ok(format!("{} {} ?", field, op))
+    };
+
+    match condition.op {
+        FilterOp::Eq => comparison("=", params),
+        FilterOp::Neq => comparison("<>", params),
+        FilterOp::Gt => comparison(">", params),
+        FilterOp::Gte => comparison(">=", params),
+        FilterOp::Lt => comparison("<", params),
+        FilterOp::Lte => comparison("<=", params),
+        FilterOp::Like => comparison("LIKE", params),
+        FilterOp::IsNull => Ok(format!("{} IS NULL", field)),
+        FilterOp::IsNotNull => Ok(format!("{} IS NOT NULL", field)),
+        FilterOp::In => {
+            let items = value
+                .as_array()
+                .ok_or_else(|| invalid(condition, "expects a list of values"))?;
+            if items.is_empty() {
+                return Ok("FALSE".to_string());
+            }
+            params.extend(items.iter().cloned());
+            Ok(format!("{} IN ({})", field, vec!["?"; items.len()].join(", ")))
+        }
+        FilterOp::Between => match value.as_array().map(|v| v.as_slice()) {
+            Some([low, high]) => {
+                params.push(low.clone());
+                params.push(high.clone());
+                Ok(format!("{} BETWEEN ? AND ?", field))
+            }
+            _ => Err(invalid(condition, "expects a [low, high] pair")),
+        },
+        FilterOp::Relative => {
+            let range: RelativeRange = serde_json::from_value(value.clone())
+                .map_err(|e| invalid(condition, &format!("expects {{\"last\": n, \"unit\": ...}}: {}", e)))?;
+            compile_relative(&field, &range, condition, params)
+        }
+    }
+}
+
+/// Relative ranges are evaluated against the current time at query time
+fn compile_relative(
+    field: &str,
+    range: &RelativeRange,
+    condition: &Condition,
+    params: &mut Vec<JsonValue>,
+) -> AppResult<String> {
+    let now = "CAST(now() AS TIMESTAMP)";
+    let unit = range.unit.as_str();
+    let months = match range.unit {
+        DateUnit::Month => Some(1),
+        DateUnit::Quarter => Some(3),
+        DateUnit::Year => Some(12),
+        DateUnit::Hour | DateUnit::Day | DateUnit::Week => None,
+    };
+
+    match range.last {
+        Some(last) if last < 0 => Err(invalid(condition, "expects a non-negative \"last\"")),
+        Some(last) => {
+            let interval = match months {
+                Some(months) => {
+                    params.push(JsonValue::from(last * months));
+                    "to_months(CAST(? AS INTEGER))".to_string()
+                }
+                None => {
+                    params.push(JsonValue::from(last));
+                    format!("to_{}s(CAST(? AS BIGINT))", unit)
+                }
+            };
+            Ok(format!("{} >= {} - {} AND {} <= {}", field, now, interval, field, now))
+        }
+        None => {
+            let start = format!("date_trunc('{}', {})", unit, now);
+            let length = match months {
+                Some(months) => format!("INTERVAL {} MONTH", months),
+                None => format!("INTERVAL 1 {}", unit.to_uppercase()),
+            };
+            Ok(format!("{} >= {} AND {} < {} + {}", field, start, field, start, length))
+        }
+    }
+}
+
+fn scalar(condition: &Condition) -> AppResult<JsonValue> {
+    match &condition.value {
+        JsonValue::Null => Err(invalid(condition, "requires a value; use is_null to match NULL")),
+        JsonValue::Array(_) => Err(invalid(condition, "expects a single value")),
+        value => Ok(value.clone()),
+    }
+}
+
+fn invalid(condition: &Condition, message: &str) -> AppError {
+    AppError::validation(format!(
+        "Invalid filter on {}: {:?} {}",
+        condition.field, condition.op, message
+    ))
+}
+
+/// Quote a column name for interpolation into generated SQL
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quote a string, e.g. a file path, as a literal in generated SQL
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::params::resolve_params;
+    use duckdb::{params_from_iter, Connection};
+    use serde_json::json;
+
+    fn count(conn: &Connection, filter: JsonValue) -> AppResult<i64> {
+        let filter: Filter = serde_json::from_value(filter).map_err(|e| AppError::validation(e.to_string()))?;
+        let compiled = compile_filter(Some(&filter))?;
+        let mut stmt = conn.prepare(&format!("SELECT COUNT(*) FROM sales{}", compiled.where_clause()))?;
+        let values = resolve_params(&stmt, Some(&compiled.params_json()))?;
+        Ok(stmt.query_row(params_from_iter(values), |row| row.get(0))?)
+    }
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE sales (region VARCHAR, amount INTEGER, sold_at TIMESTAMP);
+            INSERT INTO sales VALUES
+                ('north', 10, CAST(now() AS TIMESTAMP) - INTERVAL 2 DAY),
+                ('south', 20, CAST(now() AS TIMESTAMP) - INTERVAL 40 DAY),
+                ('north''east', 30, NULL),
+                (NULL, 40, '2020-06-01');
+        ").unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_compile_condition() {
+        let filter = Filter::condition("amount", FilterOp::Between, json!([10, 20]));
+        let compiled = compile_filter(Some(&filter)).unwrap();
+        assert_eq!(compiled.sql, "\"amount\" BETWEEN ? AND ?");
+        assert_eq!(compiled.params, vec![json!(10), json!(20)]);
+
+        assert_eq!(compile_filter(None).unwrap().where_clause(), "");
+    }
+
+    #[test]
+    fn test_filter_operators() {
+        let conn = setup();
+        assert_eq!(count(&conn, json!({"field": "region", "op": "eq", "value": "north'east"})).unwrap(), 1);
+        assert_eq!(count(&conn, json!({"field": "amount", "op": "gte", "value": 20})).unwrap(), 3);
+        assert_eq!(count(&conn, json!({"field": "region", "op": "in", "value": ["north", "south"]})).unwrap(), 2);
+        assert_eq!(count(&conn, json!({"field": "region", "op": "in", "value": []})).unwrap(), 0);
+        assert_eq!(count(&conn, json!({"field": "region", "op": "is_null"})).unwrap(), 1);
+        assert_eq!(count(&conn, json!({"region": "north"})).unwrap(), 2);
+        assert_eq!(
+            count(&conn, json!({"field": "sold_at", "op": "lt", "value": {"type": "date", "value": "2021-01-01"}})).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_filter_groups() {
+        let conn = setup();
+        let filter = json!({
+            "or": [
+                {"and": [
+                    {"field": "region", "op": "like", "value": "north%"},
+                    {"not": {"field": "amount", "op": "eq", "value": 30}}
+                ]},
+                {"field": "amount", "op": "gt", "value": 35}
+            ]
+        });
+        assert_eq!(count(&conn, filter).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_relative_dates() {
+        let conn = setup();
+        assert_eq!(count(&conn, json!({"field": "sold_at", "op": "relative", "value": {"last": 7, "unit": "day"}})).unwrap(), 1);
+        assert_eq!(count(&conn, json!({"field": "sold_at", "op": "relative", "value": {"last": 2, "unit": "month"}})).unwrap(), 2);
+        assert_eq!(count(&conn, json!({"field": "sold_at", "op": "relative", "value": {"unit": "hour"}})).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_invalid_operands() {
+        let conn = setup();
+        assert!(count(&conn, json!({"field": "amount", "op": "between", "value": [1]})).is_err());
+        assert!(count(&conn, json!({"field": "amount", "op": "eq"})).is_err());
+        assert!(count(&conn, json!({"field": "sold_at", "op": "relative", "value": {"last": 1, "unit": "fortnight"}})).is_err());
+    }
+}
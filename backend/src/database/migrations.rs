@@ -20,9 +20,8 @@ pub async fn run_migrations(conn: &Connection) -> anyhow::Result<()> {
         if *version > current_version {
             info!("Running migration {}: {}", version, migration.name);
             
-            match run_migration(conn, migration) {
+            match run_migration(conn, *version, migration) {
                 Ok(_) => {
-                    update_migration_version(conn, *version)?;
                     info!("Migration {} completed successfully", version);
                 }
                 Err(e) => {
@@ -78,8 +77,6 @@ fn get_migrations() -> Vec<(i32, Migration)> {
         (1, Migration {
             name: "Configure DuckDB and create data_sources table",
             sql: "
-                SET home_directory='/home/appuser';
-                INSTALL json;
                 LOAD json;
                 CREATE TABLE data_sources (
                     id VARCHAR PRIMARY KEY,
@@ -157,22 +154,316 @@ fn get_migrations() -> Vec<(i32, Migration)> {
                 CREATE INDEX idx_system_stats_recorded ON system_stats(recorded_at);
             ",
         }),
+        (6, Migration {
+            name: "Track every data source referenced by a cached query",
+            // Cached results are disposable, so the table is rebuilt rather than
+            // altered (DuckDB cannot alter indexed tables)
+            sql: "
+                DROP TABLE query_cache;
+                CREATE TABLE query_cache (
+                    id VARCHAR PRIMARY KEY,
+                    query_hash VARCHAR NOT NULL UNIQUE,
+                    query_sql TEXT NOT NULL,
+                    result_data JSON,
+                    data_source_id VARCHAR,
+                    source_ids VARCHAR[],
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    expires_at TIMESTAMP,
+                    FOREIGN KEY (data_source_id) REFERENCES data_sources(id)
+                );
+                CREATE INDEX idx_query_cache_hash ON query_cache(query_hash);
+                CREATE INDEX idx_query_cache_expires ON query_cache(expires_at);
+            ",
+        }),
+        (7, Migration {
+            name: "Create ingest_jobs table",
+            sql: "
+                CREATE TABLE ingest_jobs (
+                    id VARCHAR PRIMARY KEY,
+                    status VARCHAR NOT NULL CHECK (status IN ('queued', 'running', 'done', 'failed', 'cancelled')),
+                    data_source_id VARCHAR,
+                    job_info JSON NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                );
+            ",
+        }),
+        (8, Migration {
+            name: "Create folder_watches and watched_files tables",
+            sql: "
+                CREATE TABLE folder_watches (
+                    id VARCHAR PRIMARY KEY,
+                    data_source_id VARCHAR,
+                    watch_info JSON NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                );
+                CREATE TABLE watched_files (
+                    watch_id VARCHAR NOT NULL,
+                    path VARCHAR NOT NULL,
+                    size_bytes BIGINT,
+                    job_id VARCHAR,
+                    seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    PRIMARY KEY (watch_id, path)
+                );
+            ",
+        }),
+        (9, Migration {
+            name: "Allow local data sources",
+            // DuckDB can neither alter a CHECK constraint nor drop a table other
+            // tables reference, so data_sources is rebuilt along with them.
+            // The type CHECK is dropped rather than widened so later source
+            // types need no further rebuild; the backend sets the type itself.
+            // Cached results are disposable and not copied.
+            sql: "
+                CREATE TEMP TABLE data_sources_copy AS SELECT * FROM data_sources;
+                CREATE TEMP TABLE dashboard_configs_copy AS SELECT * FROM dashboard_configs;
+                CREATE TEMP TABLE analytics_metrics_copy AS SELECT * FROM analytics_metrics;
+                DROP TABLE query_cache;
+                DROP TABLE analytics_metrics;
+                DROP TABLE dashboard_configs;
+                DROP TABLE data_sources;
+
+                CREATE TABLE data_sources (
+                    id VARCHAR PRIMARY KEY,
+                    name VARCHAR NOT NULL,
+                    type VARCHAR NOT NULL,
+                    file_path VARCHAR,
+                    schema_info JSON,
+                    row_count BIGINT DEFAULT 0,
+                    size_bytes BIGINT DEFAULT 0,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                );
+                INSERT INTO data_sources SELECT * FROM data_sources_copy;
+
+                CREATE TABLE dashboard_configs (
+                    id VARCHAR PRIMARY KEY,
+                    name VARCHAR NOT NULL,
+                    layout JSON NOT NULL,
+                    filters JSON,
+                    data_source_id VARCHAR,
+                    refresh_interval INTEGER,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    FOREIGN KEY (data_source_id) REFERENCES data_sources(id)
+                );
+                INSERT INTO dashboard_configs SELECT * FROM dashboard_configs_copy;
+
+                CREATE TABLE analytics_metrics (
+                    id VARCHAR PRIMARY KEY,
+                    data_source_id VARCHAR NOT NULL,
+                    metric_name VARCHAR NOT NULL,
+                    metric_value DOUBLE,
+                    metadata JSON,
+                    calculated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    FOREIGN KEY (data_source_id) REFERENCES data_sources(id)
+                );
+                INSERT INTO analytics_metrics SELECT * FROM analytics_metrics_copy;
+                CREATE INDEX idx_analytics_metrics_source ON analytics_metrics(data_source_id);
+                CREATE INDEX idx_analytics_metrics_name ON analytics_metrics(metric_name);
+
+                CREATE TABLE query_cache (
+                    id VARCHAR PRIMARY KEY,
+                    query_hash VARCHAR NOT NULL UNIQUE,
+                    query_sql TEXT NOT NULL,
+                    result_data JSON,
+                    data_source_id VARCHAR,
+                    source_ids VARCHAR[],
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    expires_at TIMESTAMP,
+                    FOREIGN KEY (data_source_id) REFERENCES data_sources(id)
+                );
+                CREATE INDEX idx_query_cache_hash ON query_cache(query_hash);
+                CREATE INDEX idx_query_cache_expires ON query_cache(expires_at);
+
+                DROP TABLE data_sources_copy;
+                DROP TABLE dashboard_configs_copy;
+                DROP TABLE analytics_metrics_copy;
+            ",
+        }),
+        (10, Migration {
+            name: "Create remote_sources and source_refreshes tables",
+            sql: "
+                CREATE TABLE remote_sources (
+                    data_source_id VARCHAR PRIMARY KEY,
+                    remote_info JSON NOT NULL,
+                    next_refresh_at TIMESTAMP
+                );
+                CREATE TABLE source_refreshes (
+                    id VARCHAR PRIMARY KEY,
+                    data_source_id VARCHAR NOT NULL,
+                    status VARCHAR NOT NULL CHECK (status IN ('updated', 'unchanged', 'failed')),
+                    refresh_info JSON NOT NULL,
+                    started_at TIMESTAMP NOT NULL
+                );
+            ",
+        }),
+        (11, Migration {
+            name: "Create external_databases and external_tables tables",
+            sql: "
+                CREATE TABLE external_databases (
+                    id VARCHAR PRIMARY KEY,
+                    database_info JSON NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                );
+                CREATE TABLE external_tables (
+                    data_source_id VARCHAR PRIMARY KEY,
+                    database_id VARCHAR NOT NULL,
+                    schema_name VARCHAR NOT NULL,
+                    table_name VARCHAR NOT NULL
+                );
+            ",
+        }),
+        (12, Migration {
+            name: "Create incremental_configs table",
+            sql: "
+                CREATE TABLE incremental_configs (
+                    data_source_id VARCHAR PRIMARY KEY,
+                    config JSON NOT NULL
+                );
+            ",
+        }),
+        (13, Migration {
+            name: "Create source_versions table",
+            sql: "
+                CREATE TABLE source_versions (
+                    data_source_id VARCHAR NOT NULL,
+                    version BIGINT NOT NULL,
+                    version_info JSON NOT NULL,
+                    created_at TIMESTAMP NOT NULL,
+                    PRIMARY KEY (data_source_id, version)
+                );
+            ",
+        }),
+        (14, Migration {
+            name: "Create derived_sources and source_dependencies tables",
+            sql: "
+                CREATE TABLE derived_sources (
+                    data_source_id VARCHAR PRIMARY KEY,
+                    config JSON NOT NULL,
+                    refresh_on_change BOOLEAN NOT NULL DEFAULT false,
+                    checked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    next_refresh_at TIMESTAMP
+                );
+                CREATE TABLE source_dependencies (
+                    data_source_id VARCHAR NOT NULL,
+                    depends_on VARCHAR NOT NULL,
+                    PRIMARY KEY (data_source_id, depends_on)
+                );
+            ",
+        }),
+        (15, Migration {
+            name: "Create source_rejects table",
+            sql: "
+                CREATE TABLE source_rejects (
+                    data_source_id VARCHAR PRIMARY KEY,
+                    records JSON NOT NULL
+                );
+            ",
+        }),
+        (16, Migration {
+            name: "Store the content hash of data sources",
+            // Rebuilt like migration 9, as DuckDB cannot add a column to a
+            // table other tables reference. Cached results are not copied.
+            sql: "
+                CREATE TEMP TABLE data_sources_copy AS SELECT * FROM data_sources;
+                CREATE TEMP TABLE dashboard_configs_copy AS SELECT * FROM dashboard_configs;
+                CREATE TEMP TABLE analytics_metrics_copy AS SELECT * FROM analytics_metrics;
+                DROP TABLE query_cache;
+                DROP TABLE analytics_metrics;
+                DROP TABLE dashboard_configs;
+                DROP TABLE data_sources;
+
+                CREATE TABLE data_sources (
+                    id VARCHAR PRIMARY KEY,
+                    name VARCHAR NOT NULL,
+                    type VARCHAR NOT NULL,
+                    file_path VARCHAR,
+                    schema_info JSON,
+                    row_count BIGINT DEFAULT 0,
+                    size_bytes BIGINT DEFAULT 0,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    content_hash VARCHAR
+                );
+                INSERT INTO data_sources
+                    (id, name, type, file_path, schema_info, row_count, size_bytes, created_at, updated_at)
+                    SELECT * FROM data_sources_copy;
+
+                CREATE TABLE dashboard_configs (
+                    id VARCHAR PRIMARY KEY,
+                    name VARCHAR NOT NULL,
+                    layout JSON NOT NULL,
+                    filters JSON,
+                    data_source_id VARCHAR,
+                    refresh_interval INTEGER,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    FOREIGN KEY (data_source_id) REFERENCES data_sources(id)
+                );
+                INSERT INTO dashboard_configs SELECT * FROM dashboard_configs_copy;
+
+                CREATE TABLE analytics_metrics (
+                    id VARCHAR PRIMARY KEY,
+                    data_source_id VARCHAR NOT NULL,
+                    metric_name VARCHAR NOT NULL,
+                    metric_value DOUBLE,
+                    metadata JSON,
+                    calculated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    FOREIGN KEY (data_source_id) REFERENCES data_sources(id)
+                );
+                INSERT INTO analytics_metrics SELECT * FROM analytics_metrics_copy;
+                CREATE INDEX idx_analytics_metrics_source ON analytics_metrics(data_source_id);
+                CREATE INDEX idx_analytics_metrics_name ON analytics_metrics(metric_name);
+
+                CREATE TABLE query_cache (
+                    id VARCHAR PRIMARY KEY,
+                    query_hash VARCHAR NOT NULL UNIQUE,
+                    query_sql TEXT NOT NULL,
+                    result_data JSON,
+                    data_source_id VARCHAR,
+                    source_ids VARCHAR[],
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    expires_at TIMESTAMP,
+                    FOREIGN KEY (data_source_id) REFERENCES data_sources(id)
+                );
+                CREATE INDEX idx_query_cache_hash ON query_cache(query_hash);
+                CREATE INDEX idx_query_cache_expires ON query_cache(expires_at);
+
+                DROP TABLE data_sources_copy;
+                DROP TABLE dashboard_configs_copy;
+                DROP TABLE analytics_metrics_copy;
+            ",
+        }),
     ]
 }
 
-fn run_migration(conn: &Connection, migration: &Migration) -> DuckResult<()> {
-    conn.execute_batch(migration.sql)
+/// Apply a migration and record its version in one transaction, so a failed
+/// migration leaves the database as it was
+fn run_migration(conn: &Connection, version: i32, migration: &Migration) -> DuckResult<()> {
+    conn.execute_batch("BEGIN TRANSACTION")?;
+    let result = conn
+        .execute_batch(migration.sql)
+        .and_then(|_| update_migration_version(conn, version));
+    match result {
+        Ok(()) => conn.execute_batch("COMMIT"),
+        Err(e) => {
+            let _ = conn.execute_batch("ROLLBACK");
+            Err(e)
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use tempfile::TempDir;
 
     #[tokio::test]
     async fn test_migrations() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap();
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
         let conn = Connection::open(db_path).unwrap();
         
         // Run migrations
@@ -195,13 +486,23 @@ mod tests {
             |row| row.get(0)
         ).unwrap();
         
-        assert_eq!(version, 5);
+        assert_eq!(version, 16);
+
+        // Every data source type is accepted
+        for (id, r#type) in [("a", "file"), ("b", "local"), ("c", "database"), ("d", "api"), ("e", "derived")] {
+            conn.execute(
+                "INSERT INTO data_sources (id, name, type) VALUES (?, ?, ?)",
+                [id, r#type, r#type],
+            )
+            .unwrap();
+        }
     }
 
     #[tokio::test]
     async fn test_idempotent_migrations() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap();
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
         let conn = Connection::open(db_path).unwrap();
         
         // Run migrations twice
@@ -218,6 +519,28 @@ mod tests {
             |row| row.get(0)
         ).unwrap();
         
-        assert_eq!(version, 5);
+        assert_eq!(version, 16);
+    }
+
+    #[tokio::test]
+    async fn test_failed_migration_rolls_back() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_migrations_table(&conn).unwrap();
+
+        let migration = Migration {
+            name: "Half applied",
+            sql: "CREATE TABLE half_applied (id INTEGER); SELECT * FROM missing_table;",
+        };
+        assert!(run_migration(&conn, 1, &migration).is_err());
+
+        assert_eq!(get_current_migration_version(&conn).unwrap(), 0);
+        let tables: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM information_schema.tables WHERE table_name = 'half_applied'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 0);
     }
 }
\ No newline at end of file
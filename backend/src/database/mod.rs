@@ -1,11 +1,20 @@
 pub mod connection;
+pub mod filter;
 pub mod migrations;
+pub mod params;
+pub mod pool;
 pub mod queries;
+pub mod sql_guard;
+pub mod stream;
+pub mod timeout;
+pub mod values;
+
+pub use pool::{DatabasePool, PoolOptions, PooledConnection};
 
 use std::sync::Arc;
 use duckdb::{Connection, Result as DuckResult};
 use tokio::sync::Mutex;
-use tracing::{info, error};
+use tracing::info;
 
 pub type DatabaseConnection = Arc<Mutex<Connection>>;
 
@@ -22,38 +31,30 @@ pub async fn init(database_path: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Database named `test.db` in `dir`, with every migration applied
+#[cfg(test)]
+pub async fn test_pool(dir: &std::path::Path) -> DatabasePool {
+    let db_path = dir.join("test.db");
+    init(db_path.to_str().unwrap()).await.unwrap();
+    DatabasePool::new(db_path.to_str().unwrap()).unwrap()
+}
+
 /// Create a new database connection
 pub fn create_connection(database_path: &str) -> DuckResult<DatabaseConnection> {
     let conn = Connection::open(database_path)?;
     Ok(Arc::new(Mutex::new(conn)))
 }
 
-/// Get a thread-safe database connection pool
-#[derive(Clone)]
-pub struct DatabasePool {
-    connection: DatabaseConnection,
-}
-
-impl DatabasePool {
-    pub fn new(database_path: &str) -> DuckResult<Self> {
-        let connection = create_connection(database_path)?;
-        Ok(Self { connection })
-    }
-
-    pub fn get_connection(&self) -> DatabaseConnection {
-        Arc::clone(&self.connection)
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use tempfile::TempDir;
 
     #[tokio::test]
     async fn test_database_init() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap();
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
         
         let result = init(db_path).await;
         assert!(result.is_ok());
@@ -61,14 +62,14 @@ mod tests {
 
     #[tokio::test]
     async fn test_database_pool() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap();
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
         
         let pool = DatabasePool::new(db_path).unwrap();
-        let conn = pool.get_connection();
         
         // Test basic query
-        let conn_guard = conn.lock().await;
+        let conn_guard = pool.get_connection().await.unwrap();
         let result: i32 = conn_guard.query_row("SELECT 1", [], |row| row.get(0)).unwrap();
         assert_eq!(result, 1);
     }
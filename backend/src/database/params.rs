@@ -0,0 +1,480 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use duckdb::{
+    types::{Decimal, TimeUnit, Value},
+    Connection, Statement,
+};
+use serde_json::Value as JsonValue;
+use sqlparser::{
+    dialect::DuckDbDialect,
+    keywords::Keyword,
+    tokenizer::{Token, TokenWithLocation, Tokenizer},
+};
+
+use crate::utils::error::{AppError, AppResult};
+
+/// Prepare `sql` and resolve `params` for it, expanding list parameters first.
+///
+/// This is the entry point for user-supplied parameters; see
+/// [`expand_lists`] for how lists are bound.
+pub fn prepare_with_params<'c>(
+    conn: &'c Connection,
+    sql: &str,
+    params: Option<&JsonValue>,
+) -> AppResult<(Statement<'c>, Vec<Value>)> {
+    let stmt = match expand_lists(sql, params)? {
+        Some((sql, params)) => {
+            let stmt = conn.prepare(&sql)?;
+            let values = resolve_params(&stmt, Some(&params))?;
+            return Ok((stmt, values));
+        }
+        None => conn.prepare(sql)?,
+    };
+    let values = resolve_params(&stmt, params)?;
+    Ok((stmt, values))
+}
+
+/// Rewrite list parameters into one placeholder per element.
+///
+/// DuckDB cannot bind a LIST value to a placeholder, so a list bound inside
+/// `IN (...)` expands to `IN (?, ?, ...)` and anywhere else to a list
+/// expression `[?, ?, ...]`. Every placeholder is renumbered positionally and
+/// the returned array holds the values in placeholder order, with list
+/// elements bound individually. Returns `None` when no parameter is a list.
+pub fn expand_lists(sql: &str, params: Option<&JsonValue>) -> AppResult<Option<(String, JsonValue)>> {
+    let has_list = match params {
+        Some(JsonValue::Array(values)) => values.iter().any(|v| list_items(v).is_some()),
+        Some(JsonValue::Object(map)) => map.values().any(|v| list_items(v).is_some()),
+        _ => false,
+    };
+    if !has_list {
+        return Ok(None);
+    }
+
+    let tokens = Tokenizer::new(&DuckDbDialect {}, sql)
+        .tokenize_with_location()
+        .map_err(|e| AppError::validation(format!("Invalid SQL: {}", e)))?;
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(sql.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let offset_of = |token: &TokenWithLocation| {
+        let start = line_starts[token.location.line as usize - 1];
+        sql[start..]
+            .char_indices()
+            .nth(token.location.column as usize - 1)
+            .map_or(sql.len(), |(i, _)| start + i)
+    };
+
+    let mut rewritten = String::with_capacity(sql.len());
+    let mut copied = 0;
+    let mut values = Vec::new();
+    let mut used = HashSet::new();
+    let mut positional = 0;
+
+    for (idx, token) in tokens.iter().enumerate() {
+        let Token::Placeholder(placeholder) = &token.token else {
+            continue;
+        };
+
+        let key = match placeholder.trim_start_matches(['?', '$']) {
+            "" => {
+                positional += 1;
+                positional.to_string()
+            }
+            key => key.to_string(),
+        };
+        let value = match params {
+            Some(JsonValue::Array(items)) => {
+                let position: usize = key.parse().map_err(|_| {
+                    AppError::validation(format!("Named parameter {} requires an object of parameters", placeholder))
+                })?;
+                items
+                    .get(position.wrapping_sub(1))
+                    .ok_or_else(|| AppError::validation(format!("Missing value for parameter: {}", key)))?
+            }
+            Some(JsonValue::Object(map)) => map
+                .get(&key)
+                .or_else(|| map.get(&format!("${}", key)))
+                .ok_or_else(|| AppError::validation(format!("Missing value for parameter: {}", key)))?,
+            _ => unreachable!("only arrays and objects can hold lists"),
+        };
+        used.insert(key);
+
+        let start = offset_of(token);
+        rewritten.push_str(&sql[copied..start]);
+        copied = start + placeholder.len();
+
+        match list_items(value) {
+            // `x IN (NULL)` matches nothing, like an empty list
+            Some([]) if in_list_position(&tokens, idx) => rewritten.push_str("NULL"),
+            Some(items) if in_list_position(&tokens, idx) => {
+                let parts = items.iter().map(|item| expand_value(item, &mut values)).collect::<Vec<_>>();
+                rewritten.push_str(&parts.join(", "));
+            }
+            _ => rewritten.push_str(&expand_value(value, &mut values)),
+        }
+    }
+    rewritten.push_str(&sql[copied..]);
+
+    match params {
+        Some(JsonValue::Array(items)) if items.len() != used.len() => Err(AppError::validation(format!(
+            "Query expects {} parameter(s) but {} were provided",
+            used.len(),
+            items.len()
+        ))),
+        Some(JsonValue::Object(map)) => match map.keys().find(|key| !used.contains(key.trim_start_matches('$'))) {
+            Some(unknown) => Err(AppError::validation(format!("Unknown query parameter: {}", unknown))),
+            None => Ok(Some((rewritten, JsonValue::Array(values)))),
+        },
+        _ => Ok(Some((rewritten, JsonValue::Array(values)))),
+    }
+}
+
+/// The elements of a plain JSON array or a `{"type": "list", ...}` value
+fn list_items(value: &JsonValue) -> Option<&[JsonValue]> {
+    match value {
+        JsonValue::Array(items) => Some(items),
+        JsonValue::Object(map) if map.get("type").and_then(|t| t.as_str()) == Some("list") => {
+            map.get("value").and_then(|v| v.as_array()).map(|items| items.as_slice())
+        }
+        _ => None,
+    }
+}
+
+/// Placeholders for `value`, pushing the values they bind
+fn expand_value(value: &JsonValue, values: &mut Vec<JsonValue>) -> String {
+    match list_items(value) {
+        Some(items) => {
+            let parts = items.iter().map(|item| expand_value(item, values)).collect::<Vec<_>>();
+            format!("[{}]", parts.join(", "))
+        }
+        None => {
+            values.push(value.clone());
+            "?".to_string()
+        }
+    }
+}
+
+/// Whether the placeholder at `idx` is the sole item of an `IN (...)` list
+fn in_list_position(tokens: &[TokenWithLocation], idx: usize) -> bool {
+    let significant = |token: &&TokenWithLocation| !matches!(token.token, Token::Whitespace(_));
+    let mut before = tokens[..idx].iter().rev().filter(significant);
+    let mut after = tokens[idx + 1..].iter().filter(significant);
+
+    matches!(before.next().map(|t| &t.token), Some(Token::LParen))
+        && matches!(before.next().map(|t| &t.token), Some(Token::Word(w)) if w.keyword == Keyword::IN)
+        && matches!(after.next().map(|t| &t.token), Some(Token::RParen))
+}
+
+/// Resolve request parameters into the values expected by a prepared statement,
+/// ordered by parameter index.
+///
+/// `params` may be a JSON object keyed by parameter name (`$region` binds the
+/// `region` key, `?` placeholders bind `"1"`, `"2"`, ...) or a JSON array bound
+/// positionally.
+pub fn resolve_params(stmt: &Statement, params: Option<&JsonValue>) -> AppResult<Vec<Value>> {
+    let expected = stmt.parameter_count();
+
+    match params {
+        None | Some(JsonValue::Null) => {
+            if expected > 0 {
+                return Err(AppError::validation(format!(
+                    "Query expects {} parameter(s) but none were provided",
+                    expected
+                )));
+            }
+            Ok(Vec::new())
+        }
+        Some(JsonValue::Array(values)) => {
+            if values.len() != expected {
+                return Err(AppError::validation(format!(
+                    "Query expects {} parameter(s) but {} were provided",
+                    expected,
+                    values.len()
+                )));
+            }
+            values.iter().map(json_to_value).collect()
+        }
+        Some(JsonValue::Object(map)) => {
+            let mut names = Vec::with_capacity(expected);
+            let mut values = Vec::with_capacity(expected);
+            for idx in 1..=expected {
+                let name = stmt.parameter_name(idx)?;
+                let value = map
+                    .get(&name)
+                    .or_else(|| map.get(&format!("${}", name)))
+                    .ok_or_else(|| AppError::validation(format!("Missing value for parameter: {}", name)))?;
+                values.push(json_to_value(value)?);
+                names.push(name);
+            }
+
+            if let Some(unknown) = map
+                .keys()
+                .find(|key| !names.iter().any(|name| name == key.trim_start_matches('$')))
+            {
+                return Err(AppError::validation(format!("Unknown query parameter: {}", unknown)));
+            }
+
+            Ok(values)
+        }
+        Some(_) => Err(AppError::validation(
+            "Query parameters must be a JSON object or array",
+        )),
+    }
+}
+
+/// Convert a JSON parameter value into a DuckDB value.
+///
+/// Plain JSON scalars map to their natural DuckDB types. Lists cannot be
+/// bound directly and must go through [`expand_lists`]. Typed values can be requested explicitly with an object of the form
+/// `{"type": "date", "value": "2024-01-31"}`; supported types are `date`,
+/// `timestamp`, `time`, `decimal`, `integer`, `double`, `varchar`, `boolean`
+/// and `list`.
+pub fn json_to_value(value: &JsonValue) -> AppResult<Value> {
+    match value {
+        JsonValue::Null => Ok(Value::Null),
+        JsonValue::Bool(b) => Ok(Value::Boolean(*b)),
+        JsonValue::Number(n) => Ok(number_to_value(n)),
+        JsonValue::String(s) => Ok(Value::Text(s.clone())),
+        JsonValue::Array(_) => Err(list_unsupported()),
+        JsonValue::Object(map) => {
+            let type_name = map.get("type").and_then(|t| t.as_str()).ok_or_else(|| {
+                AppError::validation("Object parameters must have the form {\"type\": ..., \"value\": ...}")
+            })?;
+            let inner = map
+                .get("value")
+                .ok_or_else(|| AppError::validation(format!("Typed parameter '{}' is missing a value", type_name)))?;
+            typed_value(type_name, inner)
+        }
+    }
+}
+
+fn number_to_value(n: &serde_json::Number) -> Value {
+    if let Some(i) = n.as_i64() {
+        Value::BigInt(i)
+    } else if let Some(u) = n.as_u64() {
+        Value::UBigInt(u)
+    } else {
+        Value::Double(n.as_f64().unwrap_or(f64::NAN))
+    }
+}
+
+fn typed_value(type_name: &str, value: &JsonValue) -> AppResult<Value> {
+    if value.is_null() {
+        return Ok(Value::Null);
+    }
+
+    match type_name.to_lowercase().as_str() {
+        "date" => {
+            let date = parse_date(expect_str(type_name, value)?)?;
+            let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+            Ok(Value::Date32((date - epoch).num_days() as i32))
+        }
+        "timestamp" => {
+            let timestamp = parse_timestamp(expect_str(type_name, value)?)?;
+            Ok(Value::Timestamp(TimeUnit::Microsecond, timestamp.and_utc().timestamp_micros()))
+        }
+        "time" => {
+            let raw = expect_str(type_name, value)?;
+            let time = NaiveTime::parse_from_str(raw, "%H:%M:%S%.f")
+                .or_else(|_| NaiveTime::parse_from_str(raw, "%H:%M"))
+                .map_err(|_| AppError::validation(format!("Invalid time parameter: {}", raw)))?;
+            let micros = time.num_seconds_from_midnight() as i64 * 1_000_000 + (time.nanosecond() / 1_000) as i64;
+            Ok(Value::Time64(TimeUnit::Microsecond, micros))
+        }
+        "decimal" => {
+            let raw = match value {
+                JsonValue::String(s) => s.clone(),
+                JsonValue::Number(n) => n.to_string(),
+                _ => return Err(AppError::validation("Decimal parameters must be a string or number")),
+            };
+            Ok(Value::Decimal(parse_decimal(&raw)?))
+        }
+        "integer" | "bigint" => value
+            .as_i64()
+            .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+            .map(Value::BigInt)
+            .ok_or_else(|| AppError::validation(format!("Invalid integer parameter: {}", value))),
+        "double" => value
+            .as_f64()
+            .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+            .map(Value::Double)
+            .ok_or_else(|| AppError::validation(format!("Invalid double parameter: {}", value))),
+        "varchar" | "text" => match value {
+            JsonValue::String(s) => Ok(Value::Text(s.clone())),
+            other => Ok(Value::Text(other.to_string())),
+        },
+        "boolean" => value
+            .as_bool()
+            .map(Value::Boolean)
+            .ok_or_else(|| AppError::validation(format!("Invalid boolean parameter: {}", value))),
+        "list" => match value {
+            JsonValue::Array(_) => Err(list_unsupported()),
+            _ => Err(AppError::validation("List parameters must be a JSON array")),
+        },
+        other => Err(AppError::validation(format!("Unsupported parameter type: {}", other))),
+    }
+}
+
+fn expect_str<'a>(type_name: &str, value: &'a JsonValue) -> AppResult<&'a str> {
+    value
+        .as_str()
+        .ok_or_else(|| AppError::validation(format!("{} parameters must be strings", type_name)))
+}
+
+fn parse_date(raw: &str) -> AppResult<NaiveDate> {
+    NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+        .map_err(|_| AppError::validation(format!("Invalid date parameter: {}", raw)))
+}
+
+pub(crate) fn parse_timestamp(raw: &str) -> AppResult<NaiveDateTime> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(dt.naive_utc());
+    }
+    NaiveDateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(raw, "%Y-%m-%dT%H:%M:%S%.f"))
+        .or_else(|_| parse_date(raw).map(|d| d.and_hms_opt(0, 0, 0).unwrap()).map_err(|_| ()))
+        .map_err(|_| AppError::validation(format!("Invalid timestamp parameter: {}", raw)))
+}
+
+fn parse_decimal(raw: &str) -> AppResult<Decimal> {
+    let invalid = || AppError::validation(format!("Invalid decimal parameter: {}", raw));
+
+    let trimmed = raw.trim();
+    let (negative, unsigned) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let (int_part, frac_part) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    if int_part.is_empty() && frac_part.is_empty()
+        || !int_part.chars().chain(frac_part.chars()).all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+
+    let digits = format!("{}{}", int_part, frac_part);
+    let digits = digits.trim_start_matches('0');
+    let mut scaled: i128 = if digits.is_empty() { 0 } else { digits.parse().map_err(|_| invalid())? };
+    if negative {
+        scaled = -scaled;
+    }
+
+    let scale = frac_part.len() as u8;
+    let width = (digits.len() as u8).max(scale).max(1);
+    Decimal::new(width, scale, scaled).map_err(|e| AppError::validation(format!("Invalid decimal parameter {}: {}", raw, e)))
+}
+
+fn list_unsupported() -> AppError {
+    AppError::validation("List parameters can only be bound to query placeholders")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use duckdb::Connection;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_scalar_conversion() {
+        assert_eq!(json_to_value(&json!(null)).unwrap(), Value::Null);
+        assert_eq!(json_to_value(&json!(true)).unwrap(), Value::Boolean(true));
+        assert_eq!(json_to_value(&json!(42)).unwrap(), Value::BigInt(42));
+        assert_eq!(json_to_value(&json!(1.5)).unwrap(), Value::Double(1.5));
+        assert_eq!(json_to_value(&json!("EU")).unwrap(), Value::Text("EU".to_string()));
+    }
+
+    #[test]
+    fn test_typed_conversion() {
+        assert_eq!(
+            json_to_value(&json!({"type": "date", "value": "1970-01-02"})).unwrap(),
+            Value::Date32(1)
+        );
+
+        match json_to_value(&json!({"type": "decimal", "value": "-12.340"})).unwrap() {
+            Value::Decimal(d) => {
+                assert_eq!(d.scale(), 3);
+                assert_eq!(d.value(), -12340);
+            }
+            other => panic!("Expected decimal, got {:?}", other),
+        }
+
+        assert!(json_to_value(&json!({"type": "date", "value": "31/01/2024"})).is_err());
+        assert!(json_to_value(&json!({"type": "geometry", "value": "POINT(0 0)"})).is_err());
+    }
+
+    #[test]
+    fn test_bind_named_and_positional() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE sales (region VARCHAR, sold_on DATE, amount DECIMAL(10, 2));
+            INSERT INTO sales VALUES
+                ('EU', '2024-01-01', 10.50),
+                ('US', '2024-01-02', 20.00),
+                ('EU', '2024-02-01', 5.25);
+        ").unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT COUNT(*) FROM sales WHERE region = $region AND sold_on >= $since")
+            .unwrap();
+        let values = resolve_params(
+            &stmt,
+            Some(&json!({"region": "EU", "since": {"type": "date", "value": "2024-01-15"}})),
+        )
+        .unwrap();
+        let count: i64 = stmt.query_row(duckdb::params_from_iter(values), |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+
+        let (mut stmt, values) = prepare_with_params(
+            &conn,
+            "SELECT COUNT(*) FROM sales WHERE amount > ? AND list_contains(?::VARCHAR[], region)",
+            Some(&json!([{"type": "decimal", "value": "6.00"}, ["EU", "APAC"]])),
+        )
+        .unwrap();
+        let count: i64 = stmt.query_row(duckdb::params_from_iter(values), |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_bind_list_elements() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE sales (region VARCHAR, sold_on DATE);
+            INSERT INTO sales VALUES ('EU', '2024-01-01'), ('US', '2024-01-02'), ('APAC', '2024-02-01');
+        ").unwrap();
+
+        let count = |sql: &str, params: JsonValue| -> AppResult<i64> {
+            let (mut stmt, values) = prepare_with_params(&conn, sql, Some(&params))?;
+            Ok(stmt.query_row(duckdb::params_from_iter(values), |row| row.get(0))?)
+        };
+
+        let sql = "SELECT COUNT(*) FROM sales WHERE region IN ($regions) AND region <> $skip";
+        assert_eq!(count(sql, json!({"regions": ["EU", "US", "it's"], "skip": "US"})).unwrap(), 1);
+        assert_eq!(count(sql, json!({"regions": [], "skip": "US"})).unwrap(), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM sales WHERE region IN (?)", json!([["EU", "APAC"]])).unwrap(), 2);
+
+        let dates = json!({"days": {"type": "list", "value": [
+            {"type": "date", "value": "2024-01-02"},
+            {"type": "date", "value": "2024-02-01"}
+        ]}});
+        assert_eq!(count("SELECT COUNT(*) FROM sales WHERE sold_on IN ($days)", dates).unwrap(), 2);
+
+        // Placeholder-like text in strings is left alone
+        assert_eq!(count("SELECT COUNT(*) FROM sales WHERE region IN (?) OR region = '?'", json!([["US"]])).unwrap(), 1);
+
+        assert!(count(sql, json!({"regions": ["EU"]})).is_err());
+        assert!(count(sql, json!({"regions": ["EU"], "skip": "US", "extra": 1})).is_err());
+        assert!(json_to_value(&json!(["EU"])).is_err());
+    }
+
+    #[test]
+    fn test_parameter_mismatch() {
+        let conn = Connection::open_in_memory().unwrap();
+        let stmt = conn.prepare("SELECT $a::INTEGER").unwrap();
+
+        assert!(resolve_params(&stmt, None).is_err());
+        assert!(resolve_params(&stmt, Some(&json!({"b": 1}))).is_err());
+        assert!(resolve_params(&stmt, Some(&json!({"a": 1, "b": 2}))).is_err());
+        assert!(resolve_params(&stmt, Some(&json!({"$a": 1}))).is_ok());
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use duckdb::{Connection, Result as DuckResult};
+use tokio::sync::{Mutex, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, info, warn};
+
+use crate::utils::error::{AppError, AppResult};
+
+/// Sizing and checkout behaviour of a [`DatabasePool`]
+#[derive(Debug, Clone)]
+pub struct PoolOptions {
+    /// Number of reader connections
+    pub size: usize,
+    /// How long a request waits for a free connection before failing
+    pub checkout_timeout: Duration,
+    /// How long a request waits for the writer before failing. The writer is
+    /// held for the whole of a bulk load, so this is much longer than
+    /// `checkout_timeout` to let writes queue behind a load instead of
+    /// failing with 503.
+    pub writer_checkout_timeout: Duration,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            size: 8,
+            checkout_timeout: Duration::from_secs(5),
+            writer_checkout_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Pool of DuckDB connections to one database.
+///
+/// Every connection is a `try_clone` of the first, so they share the same
+/// database instance and see each other's committed writes. Readers are
+/// checked out from a fixed set of connections; ingestion and DDL go through
+/// a single writer connection so bulk loads are serialized without blocking
+/// dashboard reads.
+#[derive(Clone)]
+pub struct DatabasePool {
+    readers: Arc<StdMutex<Vec<Connection>>>,
+    available: Arc<Semaphore>,
+    writer: Arc<Mutex<Connection>>,
+    options: PoolOptions,
+}
+
+impl DatabasePool {
+    pub fn new(database_path: &str) -> DuckResult<Self> {
+        Self::with_options(database_path, PoolOptions::default())
+    }
+
+    pub fn with_options(database_path: &str, options: PoolOptions) -> DuckResult<Self> {
+        let size = options.size.max(1);
+        info!("Opening database pool with {} readers at: {}", size, database_path);
+
+        let writer = Connection::open(database_path)?;
+        let readers = (0..size)
+            .map(|_| writer.try_clone())
+            .collect::<DuckResult<Vec<_>>>()?;
+
+        Ok(Self {
+            readers: Arc::new(StdMutex::new(readers)),
+            available: Arc::new(Semaphore::new(size)),
+            writer: Arc::new(Mutex::new(writer)),
+            options: PoolOptions { size, ..options },
+        })
+    }
+
+    /// Check out a reader connection, waiting up to the checkout timeout
+    pub async fn get_connection(&self) -> AppResult<PooledConnection> {
+        let permit = tokio::time::timeout(
+            self.options.checkout_timeout,
+            Arc::clone(&self.available).acquire_owned(),
+        )
+        .await
+        .map_err(|_| {
+            warn!("Timed out waiting for a database connection");
+            AppError::PoolExhausted
+        })?
+        .map_err(|_| AppError::internal("Database pool is closed"))?;
+
+        let conn = self
+            .readers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .ok_or_else(|| AppError::internal("Database pool has no idle connection"))?;
+
+        debug!("Checked out reader connection");
+        Ok(PooledConnection {
+            inner: Lease::Reader {
+                conn: Some(conn),
+                idle: Arc::clone(&self.readers),
+                _permit: permit,
+            },
+        })
+    }
+
+    /// Check out the writer connection used for ingestion and schema changes,
+    /// waiting up to the writer checkout timeout
+    pub async fn get_writer(&self) -> AppResult<PooledConnection> {
+        let guard = tokio::time::timeout(
+            self.options.writer_checkout_timeout,
+            Arc::clone(&self.writer).lock_owned(),
+        )
+        .await
+        .map_err(|_| {
+            warn!("Timed out waiting for the database writer");
+            AppError::PoolExhausted
+        })?;
+
+        debug!("Checked out writer connection");
+        Ok(PooledConnection {
+            inner: Lease::Writer(guard),
+        })
+    }
+
+    /// Check out the writer only if it is free right now, for best-effort
+    /// writes that should not queue behind a bulk load
+    pub fn try_get_writer(&self) -> Option<PooledConnection> {
+        let guard = Arc::clone(&self.writer).try_lock_owned().ok()?;
+        debug!("Checked out idle writer connection");
+        Some(PooledConnection {
+            inner: Lease::Writer(guard),
+        })
+    }
+
+    /// Number of reader connections that are not checked out
+    pub fn idle_connections(&self) -> usize {
+        self.available.available_permits()
+    }
+
+    pub fn size(&self) -> usize {
+        self.options.size
+    }
+}
+
+/// Connection checked out from a [`DatabasePool`]; returned to the pool on drop
+pub struct PooledConnection {
+    inner: Lease,
+}
+
+enum Lease {
+    Reader {
+        conn: Option<Connection>,
+        idle: Arc<StdMutex<Vec<Connection>>>,
+        // Released after the connection is back in `idle`
+        _permit: OwnedSemaphorePermit,
+    },
+    Writer(OwnedMutexGuard<Connection>),
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match &self.inner {
+            Lease::Reader { conn, .. } => conn.as_ref().expect("connection present until drop"),
+            Lease::Writer(guard) => guard,
+        }
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        match &mut self.inner {
+            Lease::Reader { conn, .. } => conn.as_mut().expect("connection present until drop"),
+            Lease::Writer(guard) => guard,
+        }
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Lease::Reader { conn, idle, .. } = &mut self.inner {
+            if let Some(conn) = conn.take() {
+                idle.lock().unwrap_or_else(|e| e.into_inner()).push(conn);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn test_pool(size: usize) -> (TempDir, DatabasePool) {
+        let dir = TempDir::new().unwrap();
+        let options = PoolOptions {
+            size,
+            checkout_timeout: Duration::from_millis(100),
+            writer_checkout_timeout: Duration::from_millis(100),
+        };
+        let pool = DatabasePool::with_options(dir.path().join("test.db").to_str().unwrap(), options).unwrap();
+        (dir, pool)
+    }
+
+    #[tokio::test]
+    async fn test_readers_are_concurrent() {
+        let (_file, pool) = test_pool(2);
+
+        let a = pool.get_connection().await.unwrap();
+        let b = pool.get_connection().await.unwrap();
+        assert_eq!(pool.idle_connections(), 0);
+
+        let one: i32 = a.query_row("SELECT 1", [], |row| row.get(0)).unwrap();
+        let two: i32 = b.query_row("SELECT 2", [], |row| row.get(0)).unwrap();
+        assert_eq!((one, two), (1, 2));
+
+        assert!(matches!(pool.get_connection().await, Err(AppError::PoolExhausted)));
+
+        drop(a);
+        assert_eq!(pool.idle_connections(), 1);
+        assert!(pool.get_connection().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_writer_is_visible_to_readers() {
+        let (_file, pool) = test_pool(1);
+
+        let writer = pool.get_writer().await.unwrap();
+        writer.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (7);").unwrap();
+        assert!(matches!(pool.get_writer().await, Err(AppError::PoolExhausted)));
+        drop(writer);
+
+        let reader = pool.get_connection().await.unwrap();
+        let x: i32 = reader.query_row("SELECT x FROM t", [], |row| row.get(0)).unwrap();
+        assert_eq!(x, 7);
+    }
+}
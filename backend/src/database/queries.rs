@@ -1,7 +1,16 @@
-use duckdb::{Connection, Result as DuckResult, params};
+use std::collections::HashSet;
+
+use duckdb::{Connection, Result as DuckResult, params, params_from_iter};
 use serde_json::Value as JsonValue;
-use crate::models::{DataSource, DashboardConfig, QueryResult};
-use tracing::{debug, error};
+use crate::models::{
+    ColumnSchema, DataSource, DashboardConfig, DerivedConfig, ExternalDatabase, ExternalTable, Filter, FolderWatch,
+    IncrementalConfig, IngestJob, QueryCache, QueryResult, RejectedRecords, RemoteSource, SourceRefresh, SourceVersion,
+    source_table, version_table,
+};
+use crate::utils::error::{AppError, AppResult};
+use tracing::debug;
+
+use super::{params::prepare_with_params, sql_guard::check_read_only, values::collect_rows};
 
 /// Data source queries
 pub struct DataSourceQueries;
@@ -11,8 +20,8 @@ impl DataSourceQueries {
         debug!("Creating data source: {}", data_source.id);
         
         conn.execute(
-            "INSERT INTO data_sources (id, name, type, file_path, schema_info, row_count, size_bytes) 
-             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            "INSERT INTO data_sources (id, name, type, file_path, schema_info, row_count, size_bytes, content_hash) 
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
             params![
                 data_source.id,
                 data_source.name,
@@ -20,7 +29,8 @@ impl DataSourceQueries {
                 data_source.file_path,
                 serde_json::to_string(&data_source.schema).unwrap_or_default(),
                 data_source.row_count,
-                data_source.size_bytes
+                data_source.size_bytes,
+                data_source.content_hash
             ],
         )?;
         
@@ -31,8 +41,16 @@ impl DataSourceQueries {
         debug!("Getting data source by id: {}", id);
         
         let mut stmt = conn.prepare(
-            "SELECT id, name, type, file_path, schema_info, row_count, size_bytes, created_at, updated_at 
-             FROM data_sources WHERE id = ?"
+            "SELECT id, name, type, file_path, schema_info, row_count, size_bytes, created_at, updated_at,
+                    CAST(i.config AS VARCHAR), CAST(d.config AS VARCHAR), (
+                        SELECT string_agg(depends_on, ',' ORDER BY depends_on) FROM source_dependencies AS s
+                        WHERE s.data_source_id = data_sources.id
+                    ), CAST(r.records AS VARCHAR), content_hash
+             FROM data_sources
+             LEFT JOIN incremental_configs AS i ON i.data_source_id = id
+             LEFT JOIN derived_sources AS d ON d.data_source_id = id
+             LEFT JOIN source_rejects AS r ON r.data_source_id = id
+             WHERE id = ?"
         )?;
         
         let mut rows = stmt.query(params![id])?;
@@ -49,6 +67,11 @@ impl DataSourceQueries {
                 schema,
                 row_count: row.get(5)?,
                 size_bytes: row.get(6)?,
+                content_hash: row.get(13)?,
+                rejected_records: rejected_records(row.get(12)?),
+                incremental: incremental_config(row.get(9)?),
+                derived: derived_config(row.get(10)?),
+                dependencies: dependency_ids(row.get(11)?),
                 created_at: chrono::Utc::now(), // TODO: Parse from database
                 updated_at: chrono::Utc::now(), // TODO: Parse from database
             }))
@@ -61,8 +84,16 @@ impl DataSourceQueries {
         debug!("Listing all data sources");
         
         let mut stmt = conn.prepare(
-            "SELECT id, name, type, file_path, schema_info, row_count, size_bytes, created_at, updated_at 
-             FROM data_sources ORDER BY created_at DESC"
+            "SELECT id, name, type, file_path, schema_info, row_count, size_bytes, created_at, updated_at,
+                    CAST(i.config AS VARCHAR), CAST(d.config AS VARCHAR), (
+                        SELECT string_agg(depends_on, ',' ORDER BY depends_on) FROM source_dependencies AS s
+                        WHERE s.data_source_id = data_sources.id
+                    ), CAST(r.records AS VARCHAR), content_hash
+             FROM data_sources
+             LEFT JOIN incremental_configs AS i ON i.data_source_id = id
+             LEFT JOIN derived_sources AS d ON d.data_source_id = id
+             LEFT JOIN source_rejects AS r ON r.data_source_id = id
+             ORDER BY created_at DESC"
         )?;
         
         let rows = stmt.query_map([], |row| {
@@ -77,6 +108,11 @@ impl DataSourceQueries {
                 schema,
                 row_count: row.get(5)?,
                 size_bytes: row.get(6)?,
+                content_hash: row.get(13)?,
+                rejected_records: rejected_records(row.get(12)?),
+                incremental: incremental_config(row.get(9)?),
+                derived: derived_config(row.get(10)?),
+                dependencies: dependency_ids(row.get(11)?),
                 created_at: chrono::Utc::now(), // TODO: Parse from database
                 updated_at: chrono::Utc::now(), // TODO: Parse from database
             })
@@ -93,10 +129,29 @@ impl DataSourceQueries {
     pub fn delete(conn: &Connection, id: &str) -> DuckResult<bool> {
         debug!("Deleting data source: {}", id);
         
+        conn.execute("DELETE FROM incremental_configs WHERE data_source_id = ?", params![id])?;
+        conn.execute("DELETE FROM source_rejects WHERE data_source_id = ?", params![id])?;
         let rows_affected = conn.execute("DELETE FROM data_sources WHERE id = ?", params![id])?;
         Ok(rows_affected > 0)
     }
 
+    /// DuckDB table names of every registered data source and of their
+    /// versions
+    pub fn table_names(conn: &Connection) -> DuckResult<Vec<String>> {
+        let mut stmt = conn.prepare("SELECT id FROM data_sources")?;
+        let ids = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut names = ids
+            .map(|id| id.map(|id| source_table(&id)))
+            .collect::<DuckResult<Vec<_>>>()?;
+
+        let mut stmt = conn.prepare("SELECT data_source_id, version FROM source_versions")?;
+        let versions = stmt.query_map([], |row| Ok(version_table(&row.get::<_, String>(0)?, row.get(1)?)))?;
+        for name in versions {
+            names.push(name?);
+        }
+        Ok(names)
+    }
+
     pub fn update_stats(conn: &Connection, id: &str, row_count: i64, size_bytes: i64) -> DuckResult<()> {
         debug!("Updating stats for data source {}: {} rows, {} bytes", id, row_count, size_bytes);
         
@@ -107,6 +162,100 @@ impl DataSourceQueries {
         
         Ok(())
     }
+
+    /// Record the SHA-256 of the file a data source's rows were last loaded
+    /// from, or `None` once they no longer match a single file
+    pub fn update_content_hash(conn: &Connection, id: &str, content_hash: Option<&str>) -> DuckResult<()> {
+        conn.execute(
+            "UPDATE data_sources SET content_hash = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            params![content_hash, id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Record where a data source's rows come from
+    pub fn update_origin(conn: &Connection, id: &str, r#type: &str, file_path: &str) -> DuckResult<()> {
+        debug!("Updating origin of data source {}: {} {}", id, r#type, file_path);
+
+        conn.execute(
+            "UPDATE data_sources SET type = ?, file_path = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            params![r#type, file_path, id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Store a data source's incremental config, or stop loading it
+    /// incrementally with `None`
+    pub fn set_incremental(conn: &Connection, id: &str, config: Option<&IncrementalConfig>) -> AppResult<()> {
+        debug!("Setting incremental config of data source {}: {:?}", id, config);
+
+        conn.execute("DELETE FROM incremental_configs WHERE data_source_id = ?", params![id])?;
+        if let Some(config) = config {
+            conn.execute(
+                "INSERT INTO incremental_configs (data_source_id, config) VALUES (?, ?)",
+                params![id, serde_json::to_string(config)?],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Store the records the last load of a data source rejected, or forget
+    /// them with `None`
+    pub fn set_rejects(conn: &Connection, id: &str, rejected: Option<&RejectedRecords>) -> AppResult<()> {
+        conn.execute("DELETE FROM source_rejects WHERE data_source_id = ?", params![id])?;
+        if let Some(rejected) = rejected {
+            conn.execute(
+                "INSERT INTO source_rejects (data_source_id, records) VALUES (?, ?)",
+                params![id, serde_json::to_string(rejected)?],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn update_schema(conn: &Connection, id: &str, schema: &[ColumnSchema]) -> DuckResult<()> {
+        debug!("Updating schema for data source {}: {} columns", id, schema.len());
+
+        conn.execute(
+            "UPDATE data_sources SET schema_info = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            params![serde_json::to_string(schema).unwrap_or_default(), id],
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Stored dashboard filters; legacy forms are converted by `Filter`'s parser
+/// and anything unreadable is an error rather than silently dropped
+fn dashboard_filters(filters_json: Option<String>) -> DuckResult<Option<Filter>> {
+    match filters_json {
+        Some(json) => serde_json::from_str(&json)
+            .map_err(|e| duckdb::Error::FromSqlConversionFailure(3, duckdb::types::Type::Text, Box::new(e))),
+        None => Ok(None),
+    }
+}
+
+/// Incremental config stored alongside a data source, if any
+fn incremental_config(config: Option<String>) -> Option<IncrementalConfig> {
+    config.and_then(|config| serde_json::from_str(&config).ok())
+}
+
+/// Records rejected by a data source's last load, if any
+fn rejected_records(records: Option<String>) -> Option<RejectedRecords> {
+    records.and_then(|records| serde_json::from_str(&records).ok())
+}
+
+/// Definition of a derived data source, if it is one
+fn derived_config(config: Option<String>) -> Option<DerivedConfig> {
+    config.and_then(|config| serde_json::from_str(&config).ok())
+}
+
+/// Data source ids aggregated as a comma-separated list
+fn dependency_ids(ids: Option<String>) -> Vec<String> {
+    ids.map(|ids| ids.split(',').map(str::to_string).collect()).unwrap_or_default()
 }
 
 /// Dashboard configuration queries
@@ -150,7 +299,7 @@ impl DashboardQueries {
                 id: row.get(0)?,
                 name: row.get(1)?,
                 layout: serde_json::from_str(&layout_json).unwrap_or_default(),
-                filters: filters_json.and_then(|f| serde_json::from_str(&f).ok()),
+                filters: dashboard_filters(filters_json)?,
                 data_source_id: row.get(4)?,
                 refresh_interval: row.get(5)?,
                 created_at: chrono::Utc::now(), // TODO: Parse from database
@@ -177,7 +326,7 @@ impl DashboardQueries {
                 id: row.get(0)?,
                 name: row.get(1)?,
                 layout: serde_json::from_str(&layout_json).unwrap_or_default(),
-                filters: filters_json.and_then(|f| serde_json::from_str(&f).ok()),
+                filters: dashboard_filters(filters_json)?,
                 data_source_id: row.get(4)?,
                 refresh_interval: row.get(5)?,
                 created_at: chrono::Utc::now(), // TODO: Parse from database
@@ -221,57 +370,610 @@ impl DashboardQueries {
     }
 }
 
+/// Ingestion job queries
+pub struct JobQueries;
+
+impl JobQueries {
+    pub fn create(conn: &Connection, job: &IngestJob) -> AppResult<()> {
+        debug!("Creating ingest job: {}", job.id);
+
+        conn.execute(
+            "INSERT INTO ingest_jobs (id, status, data_source_id, job_info) VALUES (?, ?, ?, ?)",
+            params![job.id, job.status.as_str(), job.data_source_id, serde_json::to_string(job)?],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn update(conn: &Connection, job: &IngestJob) -> AppResult<()> {
+        debug!("Updating ingest job {}: {}", job.id, job.status.as_str());
+
+        conn.execute(
+            "UPDATE ingest_jobs SET status = ?, job_info = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            params![job.status.as_str(), serde_json::to_string(job)?, job.id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_by_id(conn: &Connection, id: &str) -> AppResult<Option<IngestJob>> {
+        let mut stmt = conn.prepare("SELECT CAST(job_info AS VARCHAR) FROM ingest_jobs WHERE id = ?")?;
+        let mut rows = stmt.query(params![id])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(serde_json::from_str(&row.get::<_, String>(0)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Most recently created jobs first
+    pub fn list_recent(conn: &Connection, limit: usize) -> AppResult<Vec<IngestJob>> {
+        let mut stmt = conn.prepare(
+            "SELECT CAST(job_info AS VARCHAR) FROM ingest_jobs ORDER BY created_at DESC LIMIT ?"
+        )?;
+        let rows = stmt.query_map(params![limit as i64], |row| row.get::<_, String>(0))?;
+
+        rows.map(|info| Ok(serde_json::from_str(&info?)?)).collect()
+    }
+
+    /// Jobs that were queued or running, e.g. when the server stopped
+    pub fn list_unfinished(conn: &Connection) -> AppResult<Vec<IngestJob>> {
+        let mut stmt = conn.prepare(
+            "SELECT CAST(job_info AS VARCHAR) FROM ingest_jobs WHERE status IN ('queued', 'running')"
+        )?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        rows.map(|info| Ok(serde_json::from_str(&info?)?)).collect()
+    }
+}
+
+/// Folder watch queries
+pub struct WatchQueries;
+
+impl WatchQueries {
+    pub fn create(conn: &Connection, watch: &FolderWatch) -> AppResult<()> {
+        debug!("Creating folder watch {} on {}", watch.id, watch.path);
+
+        conn.execute(
+            "INSERT INTO folder_watches (id, data_source_id, watch_info) VALUES (?, ?, ?)",
+            params![watch.id, watch.data_source_id, serde_json::to_string(watch)?],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn update(conn: &Connection, watch: &FolderWatch) -> AppResult<()> {
+        conn.execute(
+            "UPDATE folder_watches SET data_source_id = ?, watch_info = ? WHERE id = ?",
+            params![watch.data_source_id, serde_json::to_string(watch)?, watch.id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_by_id(conn: &Connection, id: &str) -> AppResult<Option<FolderWatch>> {
+        let mut stmt = conn.prepare("SELECT CAST(watch_info AS VARCHAR) FROM folder_watches WHERE id = ?")?;
+        let mut rows = stmt.query(params![id])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(serde_json::from_str(&row.get::<_, String>(0)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_all(conn: &Connection) -> AppResult<Vec<FolderWatch>> {
+        let mut stmt = conn.prepare("SELECT CAST(watch_info AS VARCHAR) FROM folder_watches ORDER BY created_at")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        rows.map(|info| Ok(serde_json::from_str(&info?)?)).collect()
+    }
+
+    /// Delete a watch and its record of seen files
+    pub fn delete(conn: &Connection, id: &str) -> DuckResult<bool> {
+        debug!("Deleting folder watch: {}", id);
+
+        conn.execute("DELETE FROM watched_files WHERE watch_id = ?", params![id])?;
+        let rows_affected = conn.execute("DELETE FROM folder_watches WHERE id = ?", params![id])?;
+        Ok(rows_affected > 0)
+    }
+
+    /// Delete the watches loading into a data source
+    pub fn delete_for_source(conn: &Connection, data_source_id: &str) -> DuckResult<usize> {
+        conn.execute(
+            "DELETE FROM watched_files WHERE watch_id IN (SELECT id FROM folder_watches WHERE data_source_id = ?)",
+            params![data_source_id],
+        )?;
+        conn.execute("DELETE FROM folder_watches WHERE data_source_id = ?", params![data_source_id])
+    }
+
+    /// Paths of the files a watch has already seen
+    pub fn seen_files(conn: &Connection, watch_id: &str) -> DuckResult<HashSet<String>> {
+        let mut stmt = conn.prepare("SELECT path FROM watched_files WHERE watch_id = ?")?;
+        let rows = stmt.query_map(params![watch_id], |row| row.get::<_, String>(0))?;
+        rows.collect()
+    }
+
+    /// Record a file as seen, with the job loading it if there is one
+    pub fn mark_seen(
+        conn: &Connection,
+        watch_id: &str,
+        path: &str,
+        size_bytes: i64,
+        job_id: Option<&str>,
+    ) -> DuckResult<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO watched_files (watch_id, path, size_bytes, job_id) VALUES (?, ?, ?, ?)",
+            params![watch_id, path, size_bytes, job_id],
+        )?;
+        Ok(())
+    }
+}
+
+/// Remote data source and refresh history queries
+pub struct RemoteQueries;
+
+impl RemoteQueries {
+    pub fn create(conn: &Connection, remote: &RemoteSource) -> AppResult<()> {
+        debug!("Registering remote source {} at {}", remote.data_source_id, remote.url);
+
+        conn.execute(
+            "INSERT INTO remote_sources (data_source_id, remote_info, next_refresh_at) VALUES (?, ?, CAST(? AS TIMESTAMP))",
+            params![remote.data_source_id, serde_json::to_string(remote)?, timestamp(remote)],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn update(conn: &Connection, remote: &RemoteSource) -> AppResult<()> {
+        conn.execute(
+            "UPDATE remote_sources SET remote_info = ?, next_refresh_at = CAST(? AS TIMESTAMP) WHERE data_source_id = ?",
+            params![serde_json::to_string(remote)?, timestamp(remote), remote.data_source_id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get(conn: &Connection, data_source_id: &str) -> AppResult<Option<RemoteSource>> {
+        let mut stmt = conn.prepare("SELECT CAST(remote_info AS VARCHAR) FROM remote_sources WHERE data_source_id = ?")?;
+        let mut rows = stmt.query(params![data_source_id])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(serde_json::from_str(&row.get::<_, String>(0)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remote sources whose scheduled refresh is at or before `now`
+    pub fn list_due(conn: &Connection, now: chrono::DateTime<chrono::Utc>) -> AppResult<Vec<RemoteSource>> {
+        let mut stmt = conn.prepare(
+            "SELECT CAST(remote_info AS VARCHAR) FROM remote_sources
+             WHERE next_refresh_at <= CAST(? AS TIMESTAMP) ORDER BY next_refresh_at",
+        )?;
+        let rows = stmt.query_map(params![now.naive_utc().to_string()], |row| row.get::<_, String>(0))?;
+
+        rows.map(|info| Ok(serde_json::from_str(&info?)?)).collect()
+    }
+
+    /// Forget a data source's remote origin and its refresh history
+    pub fn delete_for_source(conn: &Connection, data_source_id: &str) -> DuckResult<()> {
+        conn.execute("DELETE FROM source_refreshes WHERE data_source_id = ?", params![data_source_id])?;
+        conn.execute("DELETE FROM remote_sources WHERE data_source_id = ?", params![data_source_id])?;
+        Ok(())
+    }
+
+    pub fn add_refresh(conn: &Connection, refresh: &SourceRefresh) -> AppResult<()> {
+        conn.execute(
+            "INSERT INTO source_refreshes (id, data_source_id, status, refresh_info, started_at)
+             VALUES (?, ?, ?, ?, CAST(? AS TIMESTAMP))",
+            params![
+                refresh.id,
+                refresh.data_source_id,
+                refresh.status.as_str(),
+                serde_json::to_string(refresh)?,
+                refresh.started_at.naive_utc().to_string()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// A data source's most recent refreshes, newest first
+    pub fn list_refreshes(conn: &Connection, data_source_id: &str, limit: usize) -> AppResult<Vec<SourceRefresh>> {
+        let mut stmt = conn.prepare(
+            "SELECT CAST(refresh_info AS VARCHAR) FROM source_refreshes
+             WHERE data_source_id = ? ORDER BY started_at DESC LIMIT ?",
+        )?;
+        let rows = stmt.query_map(params![data_source_id, limit as i64], |row| row.get::<_, String>(0))?;
+
+        rows.map(|info| Ok(serde_json::from_str(&info?)?)).collect()
+    }
+}
+
+/// `next_refresh_at` as a UTC timestamp literal
+fn timestamp(remote: &RemoteSource) -> Option<String> {
+    remote.next_refresh_at.map(|at| at.naive_utc().to_string())
+}
+
+/// External database queries
+pub struct ExternalDatabaseQueries;
+
+impl ExternalDatabaseQueries {
+    /// Store a database and its tables. The tables are stored by data source,
+    /// not in `database_info`.
+    pub fn create(conn: &Connection, database: &ExternalDatabase) -> AppResult<()> {
+        debug!("Registering external database {} at {}", database.id, database.location());
+
+        let info = ExternalDatabase { tables: Vec::new(), ..database.clone() };
+        conn.execute(
+            "INSERT INTO external_databases (id, database_info) VALUES (?, ?)",
+            params![database.id, serde_json::to_string(&info)?],
+        )?;
+        for table in &database.tables {
+            conn.execute(
+                "INSERT INTO external_tables (data_source_id, database_id, schema_name, table_name) VALUES (?, ?, ?, ?)",
+                params![table.data_source_id, database.id, table.schema, table.name],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_by_id(conn: &Connection, id: &str) -> AppResult<Option<ExternalDatabase>> {
+        let mut stmt = conn.prepare("SELECT CAST(database_info AS VARCHAR) FROM external_databases WHERE id = ?")?;
+        let mut rows = stmt.query(params![id])?;
+
+        let mut database: ExternalDatabase = match rows.next()? {
+            Some(row) => serde_json::from_str(&row.get::<_, String>(0)?)?,
+            None => return Ok(None),
+        };
+        database.tables = Self::tables(conn, id)?;
+        Ok(Some(database))
+    }
+
+    pub fn list_all(conn: &Connection) -> AppResult<Vec<ExternalDatabase>> {
+        let mut stmt = conn.prepare("SELECT CAST(database_info AS VARCHAR) FROM external_databases ORDER BY created_at")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        rows.map(|info| {
+            let mut database: ExternalDatabase = serde_json::from_str(&info?)?;
+            database.tables = Self::tables(conn, &database.id)?;
+            Ok(database)
+        })
+        .collect()
+    }
+
+    /// Delete a database and its record of tables
+    pub fn delete(conn: &Connection, id: &str) -> DuckResult<bool> {
+        debug!("Deleting external database: {}", id);
+
+        conn.execute("DELETE FROM external_tables WHERE database_id = ?", params![id])?;
+        let rows_affected = conn.execute("DELETE FROM external_databases WHERE id = ?", params![id])?;
+        Ok(rows_affected > 0)
+    }
+
+    /// Forget the external table a data source queries
+    pub fn delete_table(conn: &Connection, data_source_id: &str) -> DuckResult<()> {
+        conn.execute("DELETE FROM external_tables WHERE data_source_id = ?", params![data_source_id])?;
+        Ok(())
+    }
+
+    fn tables(conn: &Connection, database_id: &str) -> DuckResult<Vec<ExternalTable>> {
+        let mut stmt = conn.prepare(
+            "SELECT data_source_id, schema_name, table_name FROM external_tables
+             WHERE database_id = ? ORDER BY schema_name, table_name",
+        )?;
+        let rows = stmt.query_map(params![database_id], |row| {
+            Ok(ExternalTable {
+                data_source_id: row.get(0)?,
+                schema: row.get(1)?,
+                name: row.get(2)?,
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+/// Data source version queries
+pub struct VersionQueries;
+
+impl VersionQueries {
+    pub fn create(conn: &Connection, version: &SourceVersion) -> AppResult<()> {
+        debug!("Recording version {} of data source {}", version.version, version.data_source_id);
+
+        conn.execute(
+            "INSERT INTO source_versions (data_source_id, version, version_info, created_at)
+             VALUES (?, ?, ?, CAST(? AS TIMESTAMP))",
+            params![
+                version.data_source_id,
+                version.version,
+                serde_json::to_string(version)?,
+                version.created_at.naive_utc().to_string()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Number the next version of a data source gets
+    pub fn next_version(conn: &Connection, data_source_id: &str) -> DuckResult<i64> {
+        conn.query_row(
+            "SELECT COALESCE(MAX(version), 0) + 1 FROM source_versions WHERE data_source_id = ?",
+            params![data_source_id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn get(conn: &Connection, data_source_id: &str, version: i64) -> AppResult<Option<SourceVersion>> {
+        let mut stmt = conn.prepare(
+            "SELECT CAST(version_info AS VARCHAR) FROM source_versions WHERE data_source_id = ? AND version = ?",
+        )?;
+        let mut rows = stmt.query(params![data_source_id, version])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(serde_json::from_str(&row.get::<_, String>(0)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The version of a data source current at `at`: the last one taken at
+    /// or before it
+    pub fn at(conn: &Connection, data_source_id: &str, at: chrono::DateTime<chrono::Utc>) -> AppResult<Option<SourceVersion>> {
+        let mut stmt = conn.prepare(
+            "SELECT CAST(version_info AS VARCHAR) FROM source_versions
+             WHERE data_source_id = ? AND created_at <= CAST(? AS TIMESTAMP)
+             ORDER BY version DESC LIMIT 1",
+        )?;
+        let mut rows = stmt.query(params![data_source_id, at.naive_utc().to_string()])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(serde_json::from_str(&row.get::<_, String>(0)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// A data source's versions, newest first
+    pub fn list(conn: &Connection, data_source_id: &str) -> AppResult<Vec<SourceVersion>> {
+        let mut stmt = conn.prepare(
+            "SELECT CAST(version_info AS VARCHAR) FROM source_versions
+             WHERE data_source_id = ? ORDER BY version DESC",
+        )?;
+        let rows = stmt.query_map(params![data_source_id], |row| row.get::<_, String>(0))?;
+
+        rows.map(|info| Ok(serde_json::from_str(&info?)?)).collect()
+    }
+
+    /// Data sources with at least one version, including deleted ones
+    pub fn source_ids(conn: &Connection) -> DuckResult<Vec<String>> {
+        let mut stmt = conn.prepare("SELECT DISTINCT data_source_id FROM source_versions ORDER BY data_source_id")?;
+        let ids = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        ids.collect()
+    }
+
+    /// Forget a version. Its table is dropped by the caller.
+    pub fn delete(conn: &Connection, data_source_id: &str, version: i64) -> DuckResult<()> {
+        conn.execute(
+            "DELETE FROM source_versions WHERE data_source_id = ? AND version = ?",
+            params![data_source_id, version],
+        )?;
+        Ok(())
+    }
+}
+
+/// Derived data source and dependency queries
+pub struct DerivedQueries;
+
+impl DerivedQueries {
+    /// Store the definition of a derived source and the sources it reads
+    pub fn create(conn: &Connection, data_source_id: &str, config: &DerivedConfig, dependencies: &[String]) -> AppResult<()> {
+        debug!("Registering derived source {} over {:?}", data_source_id, dependencies);
+
+        conn.execute(
+            "INSERT INTO derived_sources (data_source_id, config, refresh_on_change, next_refresh_at)
+             VALUES (?, ?, ?, CAST(? AS TIMESTAMP))",
+            params![
+                data_source_id,
+                serde_json::to_string(config)?,
+                config.refresh_on_change,
+                config.next_refresh_at.map(|at| at.naive_utc().to_string())
+            ],
+        )?;
+        for depends_on in dependencies {
+            conn.execute(
+                "INSERT INTO source_dependencies (data_source_id, depends_on) VALUES (?, ?)",
+                params![data_source_id, depends_on],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Store a derived source's config after a refresh attempt, successful
+    /// or not
+    pub fn update(conn: &Connection, data_source_id: &str, config: &DerivedConfig) -> AppResult<()> {
+        conn.execute(
+            "UPDATE derived_sources SET config = ?, next_refresh_at = CAST(? AS TIMESTAMP), checked_at = CURRENT_TIMESTAMP
+             WHERE data_source_id = ?",
+            params![
+                serde_json::to_string(config)?,
+                config.next_refresh_at.map(|at| at.naive_utc().to_string()),
+                data_source_id
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Derived sources whose scheduled refresh is at or before `now`
+    pub fn list_due(conn: &Connection, now: chrono::DateTime<chrono::Utc>) -> DuckResult<Vec<String>> {
+        let mut stmt = conn.prepare(
+            "SELECT data_source_id FROM derived_sources
+             WHERE next_refresh_at <= CAST(? AS TIMESTAMP) ORDER BY next_refresh_at",
+        )?;
+        let ids = stmt.query_map(params![now.naive_utc().to_string()], |row| row.get::<_, String>(0))?;
+        ids.collect()
+    }
+
+    /// Derived sources refreshed on change with a dependency, direct or
+    /// through other derived sources, updated since their last refresh
+    /// attempt
+    pub fn list_stale(conn: &Connection) -> DuckResult<Vec<String>> {
+        let mut stmt = conn.prepare(
+            "WITH RECURSIVE upstream (data_source_id, depends_on) AS (
+                 SELECT data_source_id, depends_on FROM source_dependencies
+                 UNION
+                 SELECT u.data_source_id, s.depends_on FROM upstream AS u
+                 JOIN source_dependencies AS s ON s.data_source_id = u.depends_on
+             )
+             SELECT DISTINCT d.data_source_id FROM derived_sources AS d
+             JOIN upstream AS u ON u.data_source_id = d.data_source_id
+             JOIN data_sources AS dependency ON dependency.id = u.depends_on
+             WHERE d.refresh_on_change AND dependency.updated_at > d.checked_at
+             ORDER BY d.data_source_id",
+        )?;
+        let ids = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        ids.collect()
+    }
+
+    /// Derived sources reading a data source directly
+    pub fn dependents(conn: &Connection, data_source_id: &str) -> DuckResult<Vec<String>> {
+        let mut stmt = conn.prepare(
+            "SELECT data_source_id FROM source_dependencies WHERE depends_on = ? ORDER BY data_source_id",
+        )?;
+        let ids = stmt.query_map(params![data_source_id], |row| row.get::<_, String>(0))?;
+        ids.collect()
+    }
+
+    /// Forget a derived source's definition and dependencies
+    pub fn delete_for_source(conn: &Connection, data_source_id: &str) -> DuckResult<()> {
+        conn.execute("DELETE FROM source_dependencies WHERE data_source_id = ?", params![data_source_id])?;
+        conn.execute("DELETE FROM derived_sources WHERE data_source_id = ?", params![data_source_id])?;
+        Ok(())
+    }
+}
+
+/// Query result cache queries
+pub struct QueryCacheQueries;
+
+impl QueryCacheQueries {
+    /// Get the unexpired cached result for a query hash
+    pub fn get_valid(conn: &Connection, query_hash: &str) -> DuckResult<Option<QueryResult>> {
+        debug!("Looking up cached result: {}", query_hash);
+
+        let mut stmt = conn.prepare(
+            "SELECT CAST(result_data AS VARCHAR) FROM query_cache
+             WHERE query_hash = ? AND expires_at > CAST(now() AS TIMESTAMP)"
+        )?;
+
+        let mut rows = stmt.query(params![query_hash])?;
+
+        if let Some(row) = rows.next()? {
+            let result_json: String = row.get(0)?;
+            Ok(serde_json::from_str(&result_json).ok())
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Store a cache entry, replacing any previous entry for the same hash
+    pub fn upsert(conn: &Connection, entry: &QueryCache, source_ids: &[String]) -> DuckResult<()> {
+        debug!("Caching result for query hash: {}", entry.query_hash);
+
+        let ttl_seconds = (entry.expires_at - entry.created_at).num_seconds();
+
+        conn.execute("DELETE FROM query_cache WHERE query_hash = ?", params![entry.query_hash])?;
+        conn.execute(
+            "INSERT INTO query_cache (id, query_hash, query_sql, result_data, data_source_id, source_ids, created_at, expires_at)
+             VALUES (?, ?, ?, ?, ?, string_split(?, ','), CAST(now() AS TIMESTAMP), CAST(now() AS TIMESTAMP) + to_seconds(?))",
+            params![
+                entry.id,
+                entry.query_hash,
+                entry.query_sql,
+                serde_json::to_string(&entry.result_data).unwrap_or_default(),
+                entry.data_source_id,
+                source_ids.join(","),
+                ttl_seconds
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Drop every entry that was computed from the given data source
+    pub fn delete_for_source(conn: &Connection, data_source_id: &str) -> DuckResult<usize> {
+        debug!("Invalidating cached results for data source: {}", data_source_id);
+
+        conn.execute(
+            "DELETE FROM query_cache WHERE data_source_id = ? OR list_contains(source_ids, ?)",
+            params![data_source_id, data_source_id],
+        )
+    }
+
+    pub fn purge_expired(conn: &Connection) -> DuckResult<usize> {
+        conn.execute(
+            "DELETE FROM query_cache WHERE expires_at <= CAST(now() AS TIMESTAMP)",
+            [],
+        )
+    }
+
+    pub fn clear(conn: &Connection) -> DuckResult<usize> {
+        conn.execute("DELETE FROM query_cache", [])
+    }
+
+    pub fn count(conn: &Connection) -> DuckResult<i64> {
+        conn.query_row("SELECT COUNT(*) FROM query_cache", [], |row| row.get(0))
+    }
+}
+
 /// Analytics and query operations
 pub struct AnalyticsQueries;
 
 impl AnalyticsQueries {
-    /// Execute a custom SQL query on a data source
-    pub fn execute_custom_query(conn: &Connection, table_name: &str, sql: &str) -> DuckResult<QueryResult> {
+    /// Execute a custom SQL query on a data source, binding `params` to the
+    /// statement's named (`$name`) or positional (`?`) parameters
+    pub fn execute_custom_query(
+        conn: &Connection,
+        table_name: &str,
+        sql: &str,
+        params: Option<&JsonValue>,
+    ) -> AppResult<QueryResult> {
         debug!("Executing custom query on table {}: {}", table_name, sql);
         
-        // Validate and sanitize the query (basic protection)
-        if sql.to_lowercase().contains("drop") || sql.to_lowercase().contains("delete") {
-            return Err(duckdb::Error::ToSqlConversionFailure(Box::new(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "Destructive operations are not allowed"
-            ))));
-        }
+        check_read_only(sql, &DataSourceQueries::table_names(conn)?)?;
 
-        let mut stmt = conn.prepare(sql)?;
-        
-        let column_count = stmt.column_count();
-        let column_names: Vec<String> = (0..column_count)
-            .map(|i| stmt.column_name(i).map_or("unknown".to_string(), |v| v.to_string()))
-            .collect();
-        
-        let mut rows = stmt.query([])?;
-        let mut data = Vec::new();
-
-        while let Some(row) = rows.next()? {
-            let mut row_data = Vec::new();
-            for i in 0..column_count {
-                let value: JsonValue = match row.get_ref(i)? {
-                    duckdb::types::ValueRef::Null => JsonValue::Null,
-                    duckdb::types::ValueRef::Int(i) => JsonValue::Number(i.into()),
-                    duckdb::types::ValueRef::BigInt(i) => JsonValue::Number(i.into()),
-                    duckdb::types::ValueRef::Double(f) => JsonValue::Number(
-                        serde_json::Number::from_f64(f).unwrap_or_else(|| serde_json::Number::from(0))
-                    ),
-                    duckdb::types::ValueRef::Text(s) => JsonValue::String(String::from_utf8_lossy(s).to_string()),
-                    duckdb::types::ValueRef::Blob(_) => JsonValue::String("BLOB".to_string()),
-                    _ => JsonValue::String("UNKNOWN".to_string()),
-                };
-                row_data.push(value);
-            }
-            data.push(row_data);
-        }
+        let (mut stmt, values) = prepare_with_params(conn, sql, params)?;
         
-        let row_count = data.len();
-        Ok(QueryResult {
-            columns: column_names,
-            data,
-            row_count,
-        })
+        let rows = stmt.query(params_from_iter(values))?;
+        Ok(collect_rows(rows, None)?)
+    }
+
+    /// Write the rows of `select_sql` to `file_path` with `COPY ... TO`,
+    /// returning the number of rows written
+    pub fn copy_to_file(
+        conn: &Connection,
+        select_sql: &str,
+        params: Option<&JsonValue>,
+        format: &str,
+        file_path: &str,
+    ) -> AppResult<i64> {
+        debug!("Copying query result to {} as {}", file_path, format);
+
+        let options = match format.to_lowercase().as_str() {
+            "csv" => "FORMAT CSV, HEADER",
+            "parquet" => "FORMAT PARQUET",
+            "json" => "FORMAT JSON",
+            _ => return Err(AppError::bad_request(format!("Unsupported export format: {}", format))),
+        };
+
+        let copy_sql = format!(
+            "COPY ({}) TO '{}' ({})",
+            select_sql,
+            file_path.replace('\'', "''"),
+            options
+        );
+
+        // COPY reports the number of rows written as its only result
+        let (mut stmt, values) = prepare_with_params(conn, &copy_sql, params)?;
+        Ok(stmt.query_row(params_from_iter(values), |row| row.get(0))?)
     }
 
     /// Get basic statistics for a table
@@ -294,13 +996,14 @@ impl AnalyticsQueries {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use tempfile::TempDir;
     use crate::models::ColumnSchema;
 
     #[test]
     fn test_data_source_queries() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap();
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
         let conn = Connection::open(db_path).unwrap();
         
         // Setup tables
@@ -314,7 +1017,28 @@ mod tests {
                 row_count BIGINT DEFAULT 0,
                 size_bytes BIGINT DEFAULT 0,
                 created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                content_hash VARCHAR
+            );
+            CREATE TABLE incremental_configs (
+                data_source_id VARCHAR PRIMARY KEY,
+                config JSON NOT NULL
+            );
+            CREATE TABLE derived_sources (
+                data_source_id VARCHAR PRIMARY KEY,
+                config JSON NOT NULL,
+                refresh_on_change BOOLEAN NOT NULL DEFAULT false,
+                checked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                next_refresh_at TIMESTAMP
+            );
+            CREATE TABLE source_dependencies (
+                data_source_id VARCHAR NOT NULL,
+                depends_on VARCHAR NOT NULL,
+                PRIMARY KEY (data_source_id, depends_on)
+            );
+            CREATE TABLE source_rejects (
+                data_source_id VARCHAR PRIMARY KEY,
+                records JSON NOT NULL
             );
         ").unwrap();
         
@@ -330,10 +1054,17 @@ mod tests {
                     nullable: false,
                     unique: true,
                     primary_key: true,
+                    confidence: None,
+                    overridden: false,
                 }
             ],
             row_count: 1000,
             size_bytes: 50000,
+            content_hash: Some("abc123".to_string()),
+            rejected_records: None,
+            incremental: None,
+            derived: None,
+            dependencies: Vec::new(),
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         };
@@ -343,8 +1074,9 @@ mod tests {
         
         // Test get by id
         let retrieved = DataSourceQueries::get_by_id(&conn, "test-id").unwrap();
-        assert!(retrieved.is_some());
-        assert_eq!(retrieved.unwrap().name, "Test Source");
+        let retrieved = retrieved.unwrap();
+        assert_eq!(retrieved.name, "Test Source");
+        assert_eq!(retrieved.content_hash.as_deref(), Some("abc123"));
         
         // Test list all
         let all_sources = DataSourceQueries::list_all(&conn).unwrap();
@@ -354,6 +1086,10 @@ mod tests {
         DataSourceQueries::update_stats(&conn, "test-id", 2000, 100000).unwrap();
         let updated = DataSourceQueries::get_by_id(&conn, "test-id").unwrap().unwrap();
         assert_eq!(updated.row_count, 2000);
+
+        DataSourceQueries::update_content_hash(&conn, "test-id", None).unwrap();
+        let updated = DataSourceQueries::get_by_id(&conn, "test-id").unwrap().unwrap();
+        assert!(updated.content_hash.is_none());
         
         // Test delete
         let deleted = DataSourceQueries::delete(&conn, "test-id").unwrap();
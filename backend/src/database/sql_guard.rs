@@ -0,0 +1,459 @@
+use std::{collections::HashSet, ops::ControlFlow};
+
+use sqlparser::{
+    ast::{visit_relations, Expr, ObjectName, Query, SetExpr, Statement, TableFactor, Visit, Visitor},
+    dialect::DuckDbDialect,
+    parser::Parser,
+};
+use thiserror::Error;
+
+/// Functions user queries may call: pure scalar, aggregate and window
+/// functions. Anything else — file readers, `getenv`, `current_setting`,
+/// `query`, catalog functions, extensions — is refused.
+const ALLOWED_FUNCTIONS: &[&str] = &[
+    // Aggregates
+    "any_value", "approx_count_distinct", "approx_quantile", "arg_max", "arg_min", "argmax", "argmin",
+    "array_agg", "avg", "bit_and", "bit_or", "bit_xor", "bool_and", "bool_or", "corr", "count",
+    "count_if", "covar_pop", "covar_samp", "entropy", "favg", "first", "fsum", "geomean",
+    "group_concat", "grouping", "histogram", "kurtosis", "last", "list", "mad", "max", "max_by",
+    "mean", "median", "min", "min_by", "mode", "product", "quantile", "quantile_cont",
+    "quantile_disc", "regr_avgx", "regr_avgy", "regr_count", "regr_intercept", "regr_r2",
+    "regr_slope", "regr_sxx", "regr_sxy", "regr_syy", "skewness", "stddev", "stddev_pop",
+    "stddev_samp", "string_agg", "sum", "var_pop", "var_samp", "variance",
+    // Window functions
+    "cume_dist", "dense_rank", "first_value", "lag", "last_value", "lead", "nth_value", "ntile",
+    "percent_rank", "rank", "row_number",
+    // Numeric
+    "abs", "acos", "asin", "atan", "atan2", "bit_count", "cbrt", "ceil", "ceiling", "cos", "cot",
+    "degrees", "even", "exp", "factorial", "floor", "gamma", "gcd", "greatest", "isfinite",
+    "isinf", "isnan", "lcm", "least", "lgamma", "ln", "log", "log10", "log2", "mod", "pi", "pow",
+    "power", "radians", "round", "round_even", "sign", "signbit", "sin", "sqrt", "tan", "trunc",
+    // Text
+    "array_to_string", "ascii", "bar", "chr", "concat", "concat_ws", "contains",
+    "damerau_levenshtein", "ends_with", "format", "hamming", "hash", "ilike_escape", "instr",
+    "jaccard", "jaro_similarity", "jaro_winkler_similarity", "lcase", "left", "len", "length",
+    "levenshtein", "like_escape", "lower", "lpad", "ltrim", "md5", "nfc_normalize",
+    "not_ilike_escape", "not_like_escape", "position", "prefix", "printf", "regexp_extract",
+    "regexp_extract_all", "regexp_full_match", "regexp_matches", "regexp_replace",
+    "regexp_split_to_array", "repeat", "replace", "reverse", "right", "rpad", "rtrim", "sha256",
+    "split_part", "starts_with", "str_split", "strip_accents", "string_split", "strlen", "strpos",
+    "substr", "substring", "suffix", "translate", "trim", "ucase", "unicode", "upper",
+    // Dates and times
+    "age", "century", "current_date", "current_time", "current_timestamp", "date_add", "date_diff",
+    "date_part", "date_sub", "date_trunc", "datediff", "datepart", "datesub", "datetrunc", "day",
+    "dayname", "dayofmonth", "dayofweek", "dayofyear", "decade", "epoch", "epoch_ms", "epoch_ns",
+    "epoch_us", "era", "extract", "hour", "isodow", "isoyear", "last_day", "make_date",
+    "make_time", "make_timestamp", "microsecond", "millennium", "millisecond", "minute", "month",
+    "monthname", "now", "quarter", "second", "strftime", "strptime", "time_bucket", "timezone",
+    "to_centuries", "to_days", "to_decades", "to_hours", "to_microseconds", "to_millennia",
+    "to_milliseconds", "to_minutes", "to_months", "to_seconds", "to_timestamp", "to_weeks",
+    "to_years", "today", "try_strptime", "week", "weekday", "weekofyear", "year", "yearweek",
+    // Conditionals and types
+    "coalesce", "if", "ifnull", "nullif", "typeof",
+    // Lists, structs and maps
+    "array_concat", "array_contains", "array_distinct", "array_extract", "array_has",
+    "array_length", "array_position", "array_slice", "array_sort", "cardinality", "element_at",
+    "flatten", "generate_series", "list_aggregate", "list_any_value", "list_append", "list_avg",
+    "list_concat", "list_contains", "list_count", "list_distinct", "list_element", "list_extract",
+    "list_filter", "list_has", "list_has_all", "list_has_any", "list_max", "list_min",
+    "list_position", "list_prepend", "list_reduce", "list_reverse", "list_reverse_sort",
+    "list_slice", "list_sort", "list_sum", "list_transform", "list_unique", "list_value", "map",
+    "map_entries", "map_extract", "map_from_entries", "map_keys", "map_values", "range", "row",
+    "struct_extract", "struct_pack", "unnest",
+    // JSON
+    "json_array", "json_array_length", "json_contains", "json_extract", "json_extract_path",
+    "json_extract_path_text", "json_extract_string", "json_keys", "json_object", "json_type",
+    "json_valid", "json_value", "to_json",
+];
+
+/// Internal tables and system schemas, refused even when a CTE or alias
+/// shares their name
+const INTERNAL_TABLES: &[&str] = &[
+    "__migrations",
+    "analytics_metrics",
+    "dashboard_configs",
+    "data_sources",
+    "derived_sources",
+    "external_databases",
+    "external_tables",
+    "folder_watches",
+    "incremental_configs",
+    "ingest_jobs",
+    "query_cache",
+    "remote_sources",
+    "source_dependencies",
+    "source_refreshes",
+    "source_versions",
+    "system_stats",
+    "watched_files",
+];
+
+const SYSTEM_SCHEMAS: &[&str] = &["information_schema", "pg_catalog"];
+
+/// Reason a user-supplied statement was refused
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SqlRejection {
+    #[error("Could not parse SQL: {0}")]
+    Parse(String),
+
+    #[error("Expected a single statement, found {0}")]
+    StatementCount(usize),
+
+    #[error("{0} statements are not allowed")]
+    NotReadOnly(String),
+
+    #[error("Table {0} is not a registered data source")]
+    UnknownTable(String),
+
+    #[error("Function {0} is not allowed")]
+    ForbiddenFunction(String),
+}
+
+impl SqlRejection {
+    /// The construct that caused the rejection
+    pub fn construct(&self) -> String {
+        match self {
+            SqlRejection::Parse(msg) => msg.clone(),
+            SqlRejection::StatementCount(count) => count.to_string(),
+            SqlRejection::NotReadOnly(kind) => kind.clone(),
+            SqlRejection::UnknownTable(table) => table.clone(),
+            SqlRejection::ForbiddenFunction(name) => name.clone(),
+        }
+    }
+}
+
+/// Accept `sql` only if it is one read-only SELECT/WITH statement whose
+/// tables are all in `allowed_tables` (or CTEs in scope where they are read)
+/// and whose functions are all in the allowlist
+pub fn check_read_only(sql: &str, allowed_tables: &[String]) -> Result<(), SqlRejection> {
+    guard(sql, allowed_tables).map(|_| ())
+}
+
+/// Check `sql` like [`check_read_only`], returning the tables of
+/// `allowed_tables` it reads, in order of first use
+pub fn referenced_tables(sql: &str, allowed_tables: &[String]) -> Result<Vec<String>, SqlRejection> {
+    guard(sql, allowed_tables).map(|guard| guard.referenced)
+}
+
+/// Whether `sql` reads `table` under a schema or catalog name, e.g.
+/// `main.data_source_abc`, which a CTE named after the table does not shadow
+pub fn reads_qualified(sql: &str, table: &str) -> bool {
+    let Ok(statements) = Parser::parse_sql(&DuckDbDialect {}, sql) else {
+        return false;
+    };
+    visit_relations(&statements, |relation| match relation.0.split_last() {
+        Some((name, qualifiers)) if !qualifiers.is_empty() && name.value.eq_ignore_ascii_case(table) => {
+            ControlFlow::Break(())
+        }
+        _ => ControlFlow::Continue(()),
+    })
+    .is_break()
+}
+
+fn guard(sql: &str, allowed_tables: &[String]) -> Result<ReadOnlyGuard, SqlRejection> {
+    let statements = Parser::parse_sql(&DuckDbDialect {}, sql)
+        .map_err(|e| SqlRejection::Parse(e.to_string()))?;
+
+    if statements.len() != 1 {
+        return Err(SqlRejection::StatementCount(statements.len()));
+    }
+
+    let statement = &statements[0];
+    if !matches!(statement, Statement::Query(_)) {
+        return Err(SqlRejection::NotReadOnly(statement_kind(statement)));
+    }
+
+    let mut guard = ReadOnlyGuard {
+        allowed: allowed_tables.iter().map(|t| t.to_lowercase()).collect(),
+        scopes: Vec::new(),
+        depth: 0,
+        referenced: Vec::new(),
+    };
+
+    match statement.visit(&mut guard) {
+        ControlFlow::Break(rejection) => Err(rejection),
+        ControlFlow::Continue(()) => Ok(guard),
+    }
+}
+
+/// Leading keyword(s) of a statement, e.g. `COPY` or `CREATE TABLE`
+fn statement_kind(statement: &Statement) -> String {
+    let text = statement.to_string();
+    let mut words = text.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(first), Some(second)) if first.eq_ignore_ascii_case("create") => {
+            format!("{} {}", first, second).to_uppercase()
+        }
+        (Some(first), _) => first.to_uppercase(),
+        _ => "Empty".to_string(),
+    }
+}
+
+struct ReadOnlyGuard {
+    allowed: HashSet<String>,
+    scopes: Vec<CteScope>, // one per query being visited, innermost last
+    depth: usize,
+    referenced: Vec<String>, // allowed tables read, not counting CTEs
+}
+
+/// CTEs defined by the WITH clause of one query.
+///
+/// A CTE is only in scope in that query's body and in the CTEs defined after
+/// it (or in itself, for WITH RECURSIVE); `visible` is how many of `names`
+/// are in scope at the point being visited.
+struct CteScope {
+    names: Vec<String>,
+    queries: Vec<*const Query>,
+    recursive: bool,
+    visible: usize,
+}
+
+impl ReadOnlyGuard {
+    fn is_cte(&self, table: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.names[..scope.visible].iter().any(|name| name == table))
+    }
+
+    fn is_allowed_table(&mut self, name: &ObjectName) -> bool {
+        let parts: Vec<String> = name.0.iter().map(|ident| ident.value.to_lowercase()).collect();
+        if parts.iter().any(|part| is_internal(part)) {
+            return false;
+        }
+        let table = match parts.as_slice() {
+            [table] if self.is_cte(table) => return true,
+            [table] => table,
+            [schema, table] if schema == "main" => table,
+            _ => return false,
+        };
+        if !self.allowed.contains(table) {
+            return false;
+        }
+        if !self.referenced.contains(table) {
+            self.referenced.push(table.clone());
+        }
+        true
+    }
+}
+
+fn is_internal(name: &str) -> bool {
+    INTERNAL_TABLES.contains(&name)
+        || SYSTEM_SCHEMAS.contains(&name)
+        || ["duckdb_", "pg_", "sqlite_"].iter().any(|prefix| name.starts_with(prefix))
+}
+
+impl Visitor for ReadOnlyGuard {
+    type Break = SqlRejection;
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<Self::Break> {
+        // Only the top-level query may be a statement; anything nested
+        // (e.g. a data-modifying CTE) is a write
+        self.depth += 1;
+        if self.depth > 1 {
+            return ControlFlow::Break(SqlRejection::NotReadOnly(statement_kind(statement)));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        // Entering one of the enclosing query's CTEs narrows its scope to the
+        // CTEs defined before this one
+        if let Some(scope) = self.scopes.last_mut() {
+            if let Some(idx) = scope.queries.iter().position(|q| std::ptr::eq(*q, query)) {
+                scope.visible = if scope.recursive { idx + 1 } else { idx };
+            }
+        }
+
+        let (names, queries, recursive) = match &query.with {
+            Some(with) => (
+                with.cte_tables.iter().map(|cte| cte.alias.name.value.to_lowercase()).collect(),
+                with.cte_tables.iter().map(|cte| &*cte.query as *const Query).collect(),
+                with.recursive,
+            ),
+            None => (Vec::new(), Vec::new(), false),
+        };
+        if let Some(name) = names.iter().find(|name| is_internal(name)) {
+            return ControlFlow::Break(SqlRejection::UnknownTable(name.clone()));
+        }
+        self.scopes.push(CteScope {
+            visible: names.len(),
+            names,
+            queries,
+            recursive,
+        });
+
+        if let SetExpr::Select(select) = query.body.as_ref() {
+            if select.into.is_some() {
+                return ControlFlow::Break(SqlRejection::NotReadOnly("SELECT INTO".to_string()));
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        self.scopes.pop();
+        // Back in the enclosing query, whose later CTEs and body see all of
+        // its CTEs again
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.queries.iter().any(|q| std::ptr::eq(*q, query)) {
+                scope.visible = scope.names.len();
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<Self::Break> {
+        match table_factor {
+            TableFactor::Table { name, args: Some(_), .. } | TableFactor::Function { name, .. } => {
+                ControlFlow::Break(SqlRejection::ForbiddenFunction(name.to_string()))
+            }
+            TableFactor::TableFunction { expr, .. } => {
+                ControlFlow::Break(SqlRejection::ForbiddenFunction(expr.to_string()))
+            }
+            _ => ControlFlow::Continue(()),
+        }
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<Self::Break> {
+        if self.is_allowed_table(relation) {
+            ControlFlow::Continue(())
+        } else {
+            ControlFlow::Break(SqlRejection::UnknownTable(relation.to_string()))
+        }
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        if let Expr::Function(function) = expr {
+            let name = function
+                .name
+                .0
+                .last()
+                .map(|ident| ident.value.to_lowercase())
+                .unwrap_or_default();
+            if !ALLOWED_FUNCTIONS.contains(&name.as_str()) {
+                return ControlFlow::Break(SqlRejection::ForbiddenFunction(function.name.to_string()));
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed() -> Vec<String> {
+        vec!["data_source_abc".to_string()]
+    }
+
+    #[test]
+    fn test_allows_read_only_queries() {
+        assert!(check_read_only("SELECT 1", &allowed()).is_ok());
+        assert!(check_read_only("SELECT deleted_at, dropped FROM data_source_abc", &allowed()).is_ok());
+        assert!(check_read_only(
+            "WITH recent AS (SELECT * FROM main.data_source_abc) SELECT COUNT(*) FROM recent",
+            &allowed()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_rejects_writes_and_side_effects() {
+        assert_eq!(
+            check_read_only("COPY data_source_abc TO '/tmp/out.csv'", &allowed()),
+            Err(SqlRejection::NotReadOnly("COPY".to_string()))
+        );
+        assert_eq!(
+            check_read_only("DROP TABLE data_source_abc", &allowed()),
+            Err(SqlRejection::NotReadOnly("DROP".to_string()))
+        );
+        assert_eq!(
+            check_read_only("SELECT 1; SELECT 2", &allowed()),
+            Err(SqlRejection::StatementCount(2))
+        );
+    }
+
+    #[test]
+    fn test_rejects_unregistered_tables_and_file_access() {
+        assert_eq!(
+            check_read_only("SELECT * FROM data_sources", &allowed()),
+            Err(SqlRejection::UnknownTable("data_sources".to_string()))
+        );
+        assert!(matches!(
+            check_read_only("SELECT * FROM read_csv('/etc/passwd')", &allowed()),
+            Err(SqlRejection::ForbiddenFunction(_))
+        ));
+        assert!(matches!(
+            check_read_only("SELECT getenv('HOME')", &allowed()),
+            Err(SqlRejection::ForbiddenFunction(_))
+        ));
+    }
+
+    #[test]
+    fn test_ctes_are_scoped_to_their_query() {
+        let rejected = |sql: &str| check_read_only(sql, &allowed()).is_err();
+
+        // A CTE defined in a subquery does not shadow tables outside it
+        assert!(rejected("SELECT * FROM (WITH remote_sources AS (SELECT 1) SELECT 1) t, remote_sources"));
+        assert!(rejected("SELECT * FROM (WITH secrets AS (SELECT 1) SELECT 1) t, secrets"));
+        assert!(rejected("SELECT (WITH x AS (SELECT 1) SELECT 1), * FROM x"));
+        // Nor does a later CTE in an earlier one
+        assert!(rejected("WITH a AS (SELECT * FROM b), b AS (SELECT 1) SELECT * FROM a"));
+        // Internal tables are refused even under a CTE of the same name
+        assert!(rejected("WITH data_sources AS (SELECT 1) SELECT * FROM data_sources"));
+        assert!(rejected("SELECT * FROM main.remote_sources"));
+        assert!(rejected("SELECT * FROM information_schema.tables"));
+
+        assert!(!rejected("WITH a AS (SELECT 1 AS x), b AS (SELECT * FROM a) SELECT * FROM b"));
+        assert!(!rejected(
+            "WITH a AS (SELECT * FROM data_source_abc) SELECT * FROM a WHERE x IN (SELECT x FROM a)"
+        ));
+        assert!(!rejected(
+            "WITH RECURSIVE r AS (SELECT 1 AS n UNION ALL SELECT n + 1 FROM r WHERE n < 3) SELECT * FROM r"
+        ));
+    }
+
+    #[test]
+    fn test_only_allowlisted_functions() {
+        let rejected = |sql: &str| matches!(check_read_only(sql, &allowed()), Err(SqlRejection::ForbiddenFunction(_)));
+
+        assert!(rejected("SELECT current_setting('s3_secret_access_key')"));
+        assert!(rejected("SELECT * FROM data_source_abc WHERE pg_catalog.current_setting('x') IS NULL"));
+        assert!(rejected("SELECT load_extension('httpfs')"));
+        assert!(rejected("SELECT * FROM duckdb_settings()"));
+
+        assert!(check_read_only(
+            "SELECT date_trunc('month', sold_at), SUM(amount), quantile_cont(amount, 0.5),
+                    ROW_NUMBER() OVER (ORDER BY 1), coalesce(lower(region), 'n/a')
+             FROM data_source_abc GROUP BY 1",
+            &allowed()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_referenced_tables() {
+        let allowed = vec!["data_source_abc".to_string(), "data_source_def".to_string()];
+        assert_eq!(
+            referenced_tables(
+                "WITH data_source_def AS (SELECT * FROM data_source_abc)
+                 SELECT * FROM data_source_def JOIN main.Data_Source_Abc USING (id)",
+                &allowed
+            ),
+            Ok(vec!["data_source_abc".to_string()])
+        );
+        assert_eq!(referenced_tables("SELECT 1", &allowed), Ok(Vec::new()));
+        assert!(referenced_tables("SELECT * FROM data_sources", &allowed).is_err());
+    }
+
+    #[test]
+    fn test_reads_qualified() {
+        assert!(reads_qualified("SELECT * FROM main.data_source_abc", "data_source_abc"));
+        assert!(reads_qualified(
+            "SELECT * FROM t WHERE id IN (SELECT id FROM dashboard.main.DATA_SOURCE_ABC)",
+            "data_source_abc"
+        ));
+        assert!(!reads_qualified("SELECT * FROM data_source_abc", "data_source_abc"));
+        assert!(!reads_qualified("SELECT * FROM main.data_source_def", "data_source_abc"));
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use arrow_ipc::writer::StreamWriter;
+use arrow_json::LineDelimitedWriter;
+use axum::http::{header::ACCEPT, HeaderMap};
+use bytes::Bytes;
+use duckdb::{arrow::record_batch::RecordBatch, params_from_iter, types::Value, Statement};
+use futures_util::Stream;
+use serde_json::Value as JsonValue;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use super::{params::prepare_with_params, timeout::QueryDeadline, PooledConnection};
+use crate::utils::error::{AppError, AppResult};
+
+/// Encoded bytes buffered before a chunk is handed to the response body
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks queued between the DuckDB thread and the response body. Bounds
+/// memory when the client reads slower than DuckDB produces batches.
+const CHANNEL_CAPACITY: usize = 8;
+
+/// Wire format of a streamed query result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// One JSON object per row, newline delimited
+    Ndjson,
+    /// Arrow IPC streaming format
+    ArrowIpc,
+}
+
+impl StreamFormat {
+    /// Pick a streaming format from the `Accept` header, or `None` for a
+    /// regular JSON response
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let accept = headers.get(ACCEPT)?.to_str().ok()?;
+        accept
+            .split(',')
+            .map(|media| media.split(';').next().unwrap_or("").trim())
+            .find_map(|media| match media {
+                "application/x-ndjson" | "application/ndjson" => Some(Self::Ndjson),
+                "application/vnd.apache.arrow.stream" => Some(Self::ArrowIpc),
+                _ => None,
+            })
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Ndjson => "application/x-ndjson",
+            Self::ArrowIpc => "application/vnd.apache.arrow.stream",
+        }
+    }
+}
+
+/// Run `sql` on a blocking thread and stream its Arrow batches encoded as
+/// `format`.
+///
+/// The connection is held until the stream ends or the receiver is dropped.
+/// Batches are fetched from DuckDB as they are encoded, so `timeout` bounds
+/// the whole stream, including time spent waiting on a slow client. Errors
+/// after the first chunk abort the stream.
+pub fn stream_query(
+    conn: PooledConnection,
+    sql: String,
+    params: Option<JsonValue>,
+    format: StreamFormat,
+    timeout: Duration,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+    tokio::task::spawn_blocking(move || {
+        let sink = ChunkSender::new(tx.clone());
+        if let Err(e) = write_batches(&conn, &sql, params.as_ref(), format, timeout, sink) {
+            warn!("Streaming query failed: {}", e);
+            let _ = tx.blocking_send(Err(io::Error::other(e.to_string())));
+        }
+    });
+
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
+
+fn write_batches(
+    conn: &PooledConnection,
+    sql: &str,
+    params: Option<&JsonValue>,
+    format: StreamFormat,
+    timeout: Duration,
+    sink: ChunkSender,
+) -> AppResult<()> {
+    debug!("Streaming query as {:?}: {}", format, sql);
+
+    let (mut stmt, values) = prepare_with_params(conn, sql, params)?;
+
+    let deadline = QueryDeadline::start(conn, timeout);
+    deadline.finish(encode_batches(&mut stmt, values, format, sink))
+}
+
+fn encode_batches(
+    stmt: &mut Statement<'_>,
+    values: Vec<Value>,
+    format: StreamFormat,
+    sink: ChunkSender,
+) -> AppResult<()> {
+    let schema = stmt.stream_arrow(params_from_iter(values))?.get_schema();
+
+    match format {
+        StreamFormat::ArrowIpc => {
+            let mut writer = StreamWriter::try_new(sink, &schema).map_err(arrow_error)?;
+            while let Some(batch) = next_batch(stmt)? {
+                writer.write(&batch).map_err(arrow_error)?;
+            }
+            writer.finish().map_err(arrow_error)?;
+        }
+        StreamFormat::Ndjson => {
+            let mut writer = LineDelimitedWriter::new(sink);
+            while let Some(batch) = next_batch(stmt)? {
+                writer.write(&batch).map_err(arrow_error)?;
+            }
+            writer.finish().map_err(arrow_error)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetch the next chunk of a streaming statement, surfacing fetch errors
+/// (including an interrupt) instead of panicking like the `Arrow` iterator
+fn next_batch(stmt: &Statement<'_>) -> AppResult<Option<RecordBatch>> {
+    Ok(stmt.step()?.map(|array| RecordBatch::from(&array)))
+}
+
+fn arrow_error(e: duckdb::arrow::error::ArrowError) -> AppError {
+    AppError::internal(format!("Failed to encode result batch: {}", e))
+}
+
+/// `Write` adapter that forwards encoded bytes to the response body in
+/// [`CHUNK_SIZE`] chunks, blocking while the channel is full
+struct ChunkSender {
+    buffer: Vec<u8>,
+    tx: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl ChunkSender {
+    fn new(tx: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self {
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            tx,
+        }
+    }
+
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE)));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+impl Drop for ChunkSender {
+    fn drop(&mut self) {
+        let _ = self.send_buffer();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use futures_util::StreamExt;
+    use tempfile::TempDir;
+
+    use crate::database::DatabasePool;
+
+    #[test]
+    fn test_format_from_accept_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(StreamFormat::from_headers(&headers), None);
+
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        assert_eq!(StreamFormat::from_headers(&headers), None);
+
+        headers.insert(ACCEPT, HeaderValue::from_static("application/x-ndjson; charset=utf-8"));
+        assert_eq!(StreamFormat::from_headers(&headers), Some(StreamFormat::Ndjson));
+
+        headers.insert(ACCEPT, HeaderValue::from_static("text/html, application/vnd.apache.arrow.stream"));
+        assert_eq!(StreamFormat::from_headers(&headers), Some(StreamFormat::ArrowIpc));
+    }
+
+    #[tokio::test]
+    async fn test_stream_ndjson() {
+        let dir = TempDir::new().unwrap();
+        let pool = DatabasePool::new(dir.path().join("test.db").to_str().unwrap()).unwrap();
+        let conn = pool.get_connection().await.unwrap();
+
+        let chunks: Vec<_> = stream_query(
+            conn,
+            "SELECT range AS n FROM range(3)".to_string(),
+            None,
+            StreamFormat::Ndjson,
+            Duration::from_secs(10),
+        )
+        .collect()
+        .await;
+
+        let body: Vec<u8> = chunks.into_iter().flat_map(|chunk| chunk.unwrap().to_vec()).collect();
+        assert_eq!(String::from_utf8(body).unwrap(), "{\"n\":0}\n{\"n\":1}\n{\"n\":2}\n");
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use duckdb::Connection;
+use tracing::warn;
+
+use crate::utils::error::{AppError, AppResult};
+
+/// Watchdog that interrupts the query running on a connection once a deadline
+/// passes.
+///
+/// The watchdog runs on its own thread because DuckDB queries execute
+/// synchronously on the task that holds the connection. Dropping the guard
+/// cancels the watchdog.
+pub struct QueryDeadline {
+    cancel: Option<mpsc::Sender<()>>,
+    expired: Arc<AtomicBool>,
+    watchdog: Option<JoinHandle<()>>,
+}
+
+impl QueryDeadline {
+    /// Start a deadline for the next query executed on `conn`
+    pub fn start(conn: &Connection, timeout: Duration) -> Self {
+        let interrupt = conn.interrupt_handle();
+        let expired = Arc::new(AtomicBool::new(false));
+        let (cancel, cancelled) = mpsc::channel::<()>();
+
+        let flag = Arc::clone(&expired);
+        let watchdog = std::thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = cancelled.recv_timeout(timeout) {
+                warn!("Query exceeded timeout of {:?}, interrupting", timeout);
+                flag.store(true, Ordering::SeqCst);
+                interrupt.interrupt();
+            }
+        });
+
+        Self {
+            cancel: Some(cancel),
+            expired,
+            watchdog: Some(watchdog),
+        }
+    }
+
+    /// Whether the deadline passed and the query was interrupted
+    pub fn expired(&self) -> bool {
+        self.expired.load(Ordering::SeqCst)
+    }
+
+    /// Stop the watchdog and map a failure caused by the interrupt to
+    /// [`AppError::QueryTimeout`]
+    pub fn finish<T>(mut self, result: AppResult<T>) -> AppResult<T> {
+        self.stop();
+        match result {
+            Err(_) if self.expired() => Err(AppError::QueryTimeout),
+            other => other,
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(());
+        }
+        if let Some(watchdog) = self.watchdog.take() {
+            let _ = watchdog.join();
+        }
+    }
+}
+
+impl Drop for QueryDeadline {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Resolve the timeout for a query: the configured limit, optionally lowered
+/// by a per-request value. Requests cannot raise the configured limit.
+pub fn effective_timeout(configured_seconds: u64, requested: Option<Duration>) -> Duration {
+    let configured = Duration::from_secs(configured_seconds);
+    match requested {
+        Some(requested) if requested < configured => requested,
+        _ => configured,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_timeout() {
+        assert_eq!(effective_timeout(30, None), Duration::from_secs(30));
+        assert_eq!(effective_timeout(30, Some(Duration::from_secs(5))), Duration::from_secs(5));
+        assert_eq!(effective_timeout(30, Some(Duration::from_secs(60))), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_deadline_interrupts_long_query() {
+        let conn = Connection::open_in_memory().unwrap();
+        let deadline = QueryDeadline::start(&conn, Duration::from_millis(100));
+
+        let result = conn
+            .query_row(
+                "SELECT COUNT(*) FROM range(1000000000) a, range(1000) b WHERE a.range + b.range = -1",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .map_err(AppError::from);
+
+        assert!(matches!(deadline.finish(result), Err(AppError::QueryTimeout)));
+
+        // The connection stays usable after the interrupt
+        let one: i32 = conn.query_row("SELECT 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(one, 1);
+    }
+
+    #[test]
+    fn test_deadline_not_reached() {
+        let conn = Connection::open_in_memory().unwrap();
+        let deadline = QueryDeadline::start(&conn, Duration::from_secs(10));
+        let result = conn
+            .query_row("SELECT 42", [], |row| row.get::<_, i32>(0))
+            .map_err(AppError::from);
+
+        assert_eq!(deadline.finish(result).unwrap(), 42);
+    }
+}
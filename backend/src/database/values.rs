@@ -0,0 +1,345 @@
+use chrono::{DateTime, NaiveDate, NaiveTime};
+use duckdb::{
+    core::{LogicalTypeHandle, LogicalTypeId},
+    types::{TimeUnit, Value, ValueRef},
+    Result as DuckResult, Rows, Statement,
+};
+use serde_json::{Map, Number, Value as JsonValue};
+
+use crate::models::QueryResult;
+
+/// Collect the remaining rows of an executed statement into a [`QueryResult`],
+/// stopping after `limit` rows when one is given and flagging the result as
+/// truncated if more rows existed.
+pub fn collect_rows(mut rows: Rows<'_>, limit: Option<usize>) -> DuckResult<QueryResult> {
+    let (columns, column_types) = match rows.as_ref() {
+        Some(stmt) => column_metadata(stmt),
+        None => (Vec::new(), Vec::new()),
+    };
+    let tz_aware: Vec<bool> = column_types.iter().map(|t| t.starts_with("TIMESTAMP WITH TIME ZONE")).collect();
+
+    let mut data = Vec::new();
+    let mut truncated = false;
+    while let Some(row) = rows.next()? {
+        // The row past the limit is fetched only to tell whether any were cut
+        if limit.is_some_and(|limit| data.len() >= limit) {
+            truncated = true;
+            break;
+        }
+
+        let mut row_data = Vec::with_capacity(columns.len());
+        for (i, tz) in tz_aware.iter().enumerate() {
+            let value = row.get_ref(i)?;
+            row_data.push(match value {
+                ValueRef::Timestamp(unit, ts) => timestamp_to_json(unit, ts, *tz),
+                other => value_ref_to_json(other),
+            });
+        }
+        data.push(row_data);
+    }
+
+    let mut result = QueryResult::new(columns, data).with_column_types(column_types);
+    result.truncated = truncated;
+    Ok(result)
+}
+
+/// Column names and DuckDB type names of an executed statement
+pub fn column_metadata(stmt: &Statement<'_>) -> (Vec<String>, Vec<String>) {
+    let columns = stmt.column_names();
+    let types = (0..columns.len())
+        .map(|i| logical_type_name(&stmt.column_logical_type(i)))
+        .collect();
+    (columns, types)
+}
+
+/// Render a logical type the way DuckDB spells it, e.g. `DECIMAL(18,3)`,
+/// `VARCHAR[]` or `STRUCT(a INTEGER, b VARCHAR)`.
+pub fn logical_type_name(logical_type: &LogicalTypeHandle) -> String {
+    match logical_type.id() {
+        LogicalTypeId::Boolean => "BOOLEAN".to_string(),
+        LogicalTypeId::Tinyint => "TINYINT".to_string(),
+        LogicalTypeId::Smallint => "SMALLINT".to_string(),
+        LogicalTypeId::Integer => "INTEGER".to_string(),
+        LogicalTypeId::Bigint => "BIGINT".to_string(),
+        LogicalTypeId::Hugeint => "HUGEINT".to_string(),
+        LogicalTypeId::UTinyint => "UTINYINT".to_string(),
+        LogicalTypeId::USmallint => "USMALLINT".to_string(),
+        LogicalTypeId::UInteger => "UINTEGER".to_string(),
+        LogicalTypeId::UBigint => "UBIGINT".to_string(),
+        LogicalTypeId::UHugeint => "UHUGEINT".to_string(),
+        LogicalTypeId::Float => "FLOAT".to_string(),
+        LogicalTypeId::Double => "DOUBLE".to_string(),
+        LogicalTypeId::Decimal => format!(
+            "DECIMAL({},{})",
+            logical_type.decimal_width(),
+            logical_type.decimal_scale()
+        ),
+        LogicalTypeId::Varchar | LogicalTypeId::StringLiteral => "VARCHAR".to_string(),
+        LogicalTypeId::Blob => "BLOB".to_string(),
+        LogicalTypeId::Bit => "BIT".to_string(),
+        LogicalTypeId::Date => "DATE".to_string(),
+        LogicalTypeId::Time | LogicalTypeId::TimeNs => "TIME".to_string(),
+        LogicalTypeId::TimeTZ => "TIME WITH TIME ZONE".to_string(),
+        LogicalTypeId::Timestamp => "TIMESTAMP".to_string(),
+        LogicalTypeId::TimestampS => "TIMESTAMP_S".to_string(),
+        LogicalTypeId::TimestampMs => "TIMESTAMP_MS".to_string(),
+        LogicalTypeId::TimestampNs => "TIMESTAMP_NS".to_string(),
+        LogicalTypeId::TimestampTZ => "TIMESTAMP WITH TIME ZONE".to_string(),
+        LogicalTypeId::Interval => "INTERVAL".to_string(),
+        LogicalTypeId::Uuid => "UUID".to_string(),
+        LogicalTypeId::Enum => "ENUM".to_string(),
+        LogicalTypeId::IntegerLiteral => "INTEGER".to_string(),
+        LogicalTypeId::Bignum => "BIGNUM".to_string(),
+        LogicalTypeId::SqlNull => "NULL".to_string(),
+        LogicalTypeId::Geometry => "GEOMETRY".to_string(),
+        LogicalTypeId::List => format!("{}[]", logical_type_name(&logical_type.child(0))),
+        LogicalTypeId::Array => format!("{}[]", logical_type_name(&logical_type.child(0))),
+        LogicalTypeId::Map => format!(
+            "MAP({}, {})",
+            logical_type_name(&logical_type.child(0)),
+            logical_type_name(&logical_type.child(1))
+        ),
+        id @ (LogicalTypeId::Struct | LogicalTypeId::Union) => {
+            let fields: Vec<String> = (0..logical_type.num_children())
+                .map(|i| format!("{} {}", logical_type.child_name(i), logical_type_name(&logical_type.child(i))))
+                .collect();
+            let keyword = if id == LogicalTypeId::Struct { "STRUCT" } else { "UNION" };
+            format!("{}({})", keyword, fields.join(", "))
+        }
+        other => format!("{:?}", other).to_uppercase(),
+    }
+}
+
+/// Convert a borrowed DuckDB value into JSON.
+///
+/// Numbers stay numbers (HUGEINT and wide DECIMAL values that do not fit a
+/// JSON number become strings), temporal values become ISO 8601 strings,
+/// intervals become `{months, days, micros}` objects, BLOBs are hex encoded and
+/// LIST/ARRAY, STRUCT and MAP values are converted recursively.
+pub fn value_ref_to_json(value: ValueRef<'_>) -> JsonValue {
+    match value {
+        ValueRef::Null => JsonValue::Null,
+        ValueRef::Boolean(b) => JsonValue::Bool(b),
+        ValueRef::TinyInt(n) => n.into(),
+        ValueRef::SmallInt(n) => n.into(),
+        ValueRef::Int(n) => n.into(),
+        ValueRef::BigInt(n) => n.into(),
+        ValueRef::UTinyInt(n) => n.into(),
+        ValueRef::USmallInt(n) => n.into(),
+        ValueRef::UInt(n) => n.into(),
+        ValueRef::UBigInt(n) => n.into(),
+        ValueRef::HugeInt(n) => i64::try_from(n).map_or_else(|_| JsonValue::String(n.to_string()), Into::into),
+        ValueRef::UHugeInt(n) => u64::try_from(n).map_or_else(|_| JsonValue::String(n.to_string()), Into::into),
+        ValueRef::Float(f) => float_to_json(f as f64),
+        ValueRef::Double(f) => float_to_json(f),
+        ValueRef::Decimal(d) => {
+            let text = d.to_string();
+            // f64 holds 15 significant digits exactly; wider values keep full
+            // precision as strings.
+            if d.width() <= 15 {
+                text.parse::<f64>().map_or(JsonValue::String(text), float_to_json)
+            } else {
+                JsonValue::String(text)
+            }
+        }
+        ValueRef::Text(s) => JsonValue::String(String::from_utf8_lossy(s).to_string()),
+        ValueRef::Blob(b) | ValueRef::Geometry(b) => JsonValue::String(hex_encode(b)),
+        ValueRef::Date32(days) => date_to_json(days),
+        ValueRef::Time64(unit, t) => time_to_json(unit.to_micros(t)),
+        ValueRef::Timestamp(unit, ts) => timestamp_to_json(unit, ts, false),
+        ValueRef::Interval { months, days, nanos } => interval_to_json(months, days, nanos),
+        nested => value_to_json(nested.to_owned()),
+    }
+}
+
+/// Convert an owned DuckDB value into JSON; see [`value_ref_to_json`].
+pub fn value_to_json(value: Value) -> JsonValue {
+    match value {
+        Value::Null => JsonValue::Null,
+        Value::Boolean(b) => JsonValue::Bool(b),
+        Value::TinyInt(n) => value_ref_to_json(ValueRef::TinyInt(n)),
+        Value::SmallInt(n) => value_ref_to_json(ValueRef::SmallInt(n)),
+        Value::Int(n) => value_ref_to_json(ValueRef::Int(n)),
+        Value::BigInt(n) => value_ref_to_json(ValueRef::BigInt(n)),
+        Value::HugeInt(n) => value_ref_to_json(ValueRef::HugeInt(n)),
+        Value::UHugeInt(n) => value_ref_to_json(ValueRef::UHugeInt(n)),
+        Value::UTinyInt(n) => value_ref_to_json(ValueRef::UTinyInt(n)),
+        Value::USmallInt(n) => value_ref_to_json(ValueRef::USmallInt(n)),
+        Value::UInt(n) => value_ref_to_json(ValueRef::UInt(n)),
+        Value::UBigInt(n) => value_ref_to_json(ValueRef::UBigInt(n)),
+        Value::Float(f) => float_to_json(f as f64),
+        Value::Double(f) => float_to_json(f),
+        Value::Decimal(d) => value_ref_to_json(ValueRef::Decimal(d)),
+        Value::Timestamp(unit, ts) => timestamp_to_json(unit, ts, false),
+        Value::Text(s) | Value::Enum(s) => JsonValue::String(s),
+        Value::Blob(b) | Value::Geometry(b) => JsonValue::String(hex_encode(&b)),
+        Value::Date32(days) => date_to_json(days),
+        Value::Time64(unit, t) => time_to_json(unit.to_micros(t)),
+        Value::Interval { months, days, nanos } => interval_to_json(months, days, nanos),
+        Value::List(items) | Value::Array(items) => JsonValue::Array(items.into_iter().map(value_to_json).collect()),
+        Value::Struct(fields) => JsonValue::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), value_to_json(value.clone())))
+                .collect(),
+        ),
+        Value::Map(entries) => {
+            // Maps keyed by strings read naturally as objects; anything else is
+            // kept as a list of key/value pairs.
+            if entries.keys().all(|k| matches!(k, Value::Text(_) | Value::Enum(_))) {
+                let mut object = Map::new();
+                for (key, value) in entries.iter() {
+                    if let Value::Text(k) | Value::Enum(k) = key {
+                        object.insert(k.clone(), value_to_json(value.clone()));
+                    }
+                }
+                JsonValue::Object(object)
+            } else {
+                JsonValue::Array(
+                    entries
+                        .iter()
+                        .map(|(k, v)| serde_json::json!({"key": value_to_json(k.clone()), "value": value_to_json(v.clone())}))
+                        .collect(),
+                )
+            }
+        }
+        Value::Union(inner) => value_to_json(*inner),
+        #[allow(unreachable_patterns)]
+        other => JsonValue::String(format!("{:?}", other)),
+    }
+}
+
+fn float_to_json(f: f64) -> JsonValue {
+    // NaN and infinities have no JSON representation
+    Number::from_f64(f).map_or(JsonValue::Null, JsonValue::Number)
+}
+
+fn date_to_json(days: i32) -> JsonValue {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    epoch
+        .checked_add_signed(chrono::Duration::days(days as i64))
+        .map_or_else(|| JsonValue::from(days), |d| JsonValue::String(d.format("%Y-%m-%d").to_string()))
+}
+
+fn time_to_json(micros: i64) -> JsonValue {
+    let secs = micros.div_euclid(1_000_000) as u32;
+    let nanos = (micros.rem_euclid(1_000_000) * 1_000) as u32;
+    NaiveTime::from_num_seconds_from_midnight_opt(secs, nanos)
+        .map_or_else(|| JsonValue::from(micros), |t| JsonValue::String(t.format("%H:%M:%S%.f").to_string()))
+}
+
+fn timestamp_to_json(unit: TimeUnit, ts: i64, tz_aware: bool) -> JsonValue {
+    let (secs, nanos) = match unit {
+        TimeUnit::Second => (ts, 0),
+        TimeUnit::Millisecond => (ts.div_euclid(1_000), ts.rem_euclid(1_000) * 1_000_000),
+        TimeUnit::Microsecond => (ts.div_euclid(1_000_000), ts.rem_euclid(1_000_000) * 1_000),
+        TimeUnit::Nanosecond => (ts.div_euclid(1_000_000_000), ts.rem_euclid(1_000_000_000)),
+    };
+    match DateTime::from_timestamp(secs, nanos as u32) {
+        Some(dt) if tz_aware => JsonValue::String(dt.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)),
+        Some(dt) => JsonValue::String(dt.naive_utc().format("%Y-%m-%dT%H:%M:%S%.f").to_string()),
+        // Infinite timestamps and other out of range values
+        None => JsonValue::from(ts),
+    }
+}
+
+fn interval_to_json(months: i32, days: i32, nanos: i64) -> JsonValue {
+    serde_json::json!({
+        "months": months,
+        "days": days,
+        "micros": nanos / 1_000,
+    })
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(2 + bytes.len() * 2);
+    out.push_str("\\x");
+    for b in bytes {
+        out.push_str(&format!("{:02x}", b));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use duckdb::Connection;
+    use serde_json::json;
+
+    fn query(sql: &str) -> QueryResult {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut stmt = conn.prepare(sql).unwrap();
+        let rows = stmt.query([]).unwrap();
+        collect_rows(rows, None).unwrap()
+    }
+
+    #[test]
+    fn test_scalar_types() {
+        let result = query("
+            SELECT
+                true AS flag,
+                42::TINYINT AS tiny,
+                170141183460469231731687303715884105727::HUGEINT AS huge,
+                12.50::DECIMAL(10, 2) AS amount,
+                1.5::FLOAT AS ratio,
+                DATE '2024-03-01' AS day,
+                TIME '13:45:00' AS at,
+                TIMESTAMP '2024-03-01 13:45:00.5' AS ts,
+                INTERVAL 1 MONTH + INTERVAL 2 DAY AS span,
+                'abc'::BLOB AS raw
+        ");
+
+        assert_eq!(result.columns.len(), 10);
+        assert_eq!(result.column_types[0], "BOOLEAN");
+        assert_eq!(result.column_types[3], "DECIMAL(10,2)");
+        assert_eq!(result.column_types[7], "TIMESTAMP");
+
+        let row = &result.data[0];
+        assert_eq!(row[0], json!(true));
+        assert_eq!(row[1], json!(42));
+        assert_eq!(row[2], json!("170141183460469231731687303715884105727"));
+        assert_eq!(row[3], json!(12.5));
+        assert_eq!(row[4], json!(1.5));
+        assert_eq!(row[5], json!("2024-03-01"));
+        assert_eq!(row[6], json!("13:45:00"));
+        assert_eq!(row[7], json!("2024-03-01T13:45:00.500"));
+        assert_eq!(row[8], json!({"months": 1, "days": 2, "micros": 0}));
+        assert_eq!(row[9], json!("\\x616263"));
+    }
+
+    #[test]
+    fn test_nested_types() {
+        let result = query("
+            SELECT
+                [1, 2, NULL] AS list,
+                {'name': 'a', 'score': 1.5} AS rec,
+                MAP {'k': 1} AS dict,
+                'b'::ENUM ('a', 'b') AS choice
+        ");
+
+        assert_eq!(result.column_types[0], "INTEGER[]");
+        assert_eq!(result.column_types[1], "STRUCT(name VARCHAR, score DECIMAL(2,1))");
+
+        let row = &result.data[0];
+        assert_eq!(row[0], json!([1, 2, null]));
+        assert_eq!(row[1], json!({"name": "a", "score": 1.5}));
+        assert_eq!(row[2], json!({"k": 1}));
+        assert_eq!(row[3], json!("b"));
+    }
+
+    #[test]
+    fn test_collect_rows_limit() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut stmt = conn.prepare("SELECT * FROM range(10)").unwrap();
+        let rows = stmt.query([]).unwrap();
+        let result = collect_rows(rows, Some(3)).unwrap();
+        assert_eq!(result.row_count, 3);
+        assert!(result.truncated);
+
+        let mut stmt = conn.prepare("SELECT * FROM range(3)").unwrap();
+        let rows = stmt.query([]).unwrap();
+        let result = collect_rows(rows, Some(3)).unwrap();
+        assert_eq!(result.row_count, 3);
+        assert!(!result.truncated);
+    }
+}
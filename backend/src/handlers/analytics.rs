@@ -1,125 +1,157 @@
-use axum::{extract::State, response::Json, extract::Path};
+use axum::{
+    body::Body,
+    extract::{Path, Request, State},
+    http::{header::{CONTENT_DISPOSITION, CONTENT_TYPE}, HeaderMap, HeaderValue},
+    response::{IntoResponse, Json, Response},
+};
+use duckdb::Connection;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
 use tracing::{debug, info};
 
 use crate::{
-    database::queries::AnalyticsQueries,
+    database::{
+        queries::{AnalyticsQueries, DataSourceQueries},
+        sql_guard::check_read_only,
+        stream::{stream_query, StreamFormat},
+        timeout::{effective_timeout, QueryDeadline},
+    },
     AppState,
-    models::{QueryRequest, QueryResult, AggregationRequest, AggregationResult, ExportRequest, ExportResult, MetricsRequest, MetricsResult},
-    utils::error::AppResult,
+    services::{
+        aggregation::{build_aggregation, referenced_columns},
+        analytics::AnalyticsService,
+        query_cache::CacheKey,
+        versions::{self, ResolvedSource},
+    },
+    models::{
+        QueryRequest, AggregationRequest, AggregationResult, AggregationSummary, ExportRequest, ExportResult, MetricsResult,
+        CorrelationRequest, CorrelationResult, DataQualityReport, MovingAverageRequest, OutlierRequest,
+        QueryResult, StatisticsRequest, StatisticsResult, TimeSeriesRequest, source_table,
+    },
+    utils::error::{AppError, AppResult},
 };
 
 /// Execute a custom SQL query
+///
+/// Responds with a `QueryResult` document unless the `Accept` header asks for
+/// NDJSON or an Arrow IPC stream, in which case rows are streamed uncached.
+/// A `data_source_id` of `id@version` or `id@<timestamp>` makes the query
+/// read that version wherever it names the source's table.
 pub async fn execute_query(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(request): Json<QueryRequest>,
-) -> AppResult<Json<QueryResult>> {
+) -> AppResult<Response> {
     info!("Executing custom query");
     debug!("Query: {}", request.sql);
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
-    
-    let table_name = if let Some(source_id) = &request.data_source_id {
-        format!("data_source_{}", source_id.replace('-', "_"))
-    } else {
-        "main".to_string()
+    let conn_guard = state.db_pool.get_connection().await?;
+
+    let mut table_name = match &request.data_source_id {
+        Some(source_id) => source_table(source_id),
+        None => "main".to_string(),
     };
+    let mut sql = request.sql.clone();
+    let mut cache_key = Some(CacheKey::new(&request.sql, request.params.as_ref(), request.data_source_id.as_deref()));
+    if let Some(reference) = request.data_source_id.as_deref().filter(|id| id.contains('@')) {
+        let source = versions::resolve(&conn_guard, reference)?;
+        sql = versions::pin_version(&request.sql, &source)?;
+        cache_key = versions::cache_key(&conn_guard, &request.sql, request.params.as_ref(), &source)?;
+        table_name = source.table_name;
+    }
+
+    if let Some(format) = StreamFormat::from_headers(&headers) {
+        check_read_only(&sql, &DataSourceQueries::table_names(&conn_guard)?)?;
 
-    let result = AnalyticsQueries::execute_custom_query(&conn_guard, &table_name, &request.sql)?;
+        let timeout = effective_timeout(state.config.query_timeout, request.timeout());
+        let body = Body::from_stream(stream_query(conn_guard, sql, request.params, format, timeout));
+        return Ok(([(CONTENT_TYPE, format.content_type())], body).into_response());
+    }
+
+    let cache_key = cache_key.filter(|_| request.cache.unwrap_or(true));
+    if let Some(cache_key) = &cache_key {
+        if let Some(cached) = state.query_cache.get(&conn_guard, cache_key) {
+            return Ok(Json(cached).into_response());
+        }
+    }
+
+    let timeout = effective_timeout(state.config.query_timeout, request.timeout());
+    let deadline = QueryDeadline::start(&conn_guard, timeout);
+    let result = deadline.finish(AnalyticsQueries::execute_custom_query(
+        &conn_guard,
+        &table_name,
+        &sql,
+        request.params.as_ref(),
+    ))?;
+
+    if let Some(cache_key) = &cache_key {
+        state.query_cache.store(&state.db_pool, cache_key, &result, None);
+    }
     
-    Ok(Json(result))
+    Ok(Json(result).into_response())
 }
 
 /// Run aggregation operations
+///
+/// `aggregations` carries each operation's grand total over all filtered
+/// rows, independent of grouping, `having` and `limit`.
 pub async fn run_aggregation(
     State(state): State<AppState>,
     Json(request): Json<AggregationRequest>,
 ) -> AppResult<Json<AggregationResult>> {
     info!("Running aggregation for source: {}", request.data_source_id);
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
-    
-    let table_name = format!("data_source_{}", request.data_source_id.replace('-', "_"));
-    
-    // Build aggregation query
-    let mut select_parts = Vec::new();
-    let mut agg_summaries = Vec::new();
-    
-    for op in &request.operations {
-        let alias = op.get_alias();
-        let sql_op = match op.operation.as_str() {
-            "sum" => format!("SUM({})", op.field),
-            "avg" => format!("AVG({})", op.field),
-            "count" => format!("COUNT({})", op.field),
-            "min" => format!("MIN({})", op.field),
-            "max" => format!("MAX({})", op.field),
-            "distinct_count" => format!("COUNT(DISTINCT {})", op.field),
-            _ => return Err(crate::utils::error::AppError::bad_request(
-                format!("Unsupported aggregation operation: {}", op.operation)
-            )),
-        };
-        
-        select_parts.push(format!("{} AS {}", sql_op, alias));
-        agg_summaries.push(crate::models::AggregationSummary {
+    let source = resolve_source(&state, &request.data_source_id, &referenced_columns(&request)).await?;
+    let query = build_aggregation(&source.table_name, &request)?;
+    debug!("Executing aggregation query: {}", query.sql);
+
+    let conn_guard = state.db_pool.get_connection().await?;
+    let result = cached_query(&state, &conn_guard, &source, &query.sql, &query.params)?;
+    let totals = cached_query(&state, &conn_guard, &source, &query.totals_sql, &query.totals_params)?;
+
+    let grand_totals = totals.data.into_iter().next().unwrap_or_default();
+    let aggregations = request
+        .operations
+        .iter()
+        .enumerate()
+        .map(|(i, op)| AggregationSummary {
             field: op.field.clone(),
             operation: op.operation.clone(),
-            result: serde_json::Value::Null, // Will be filled after query
-        });
-    }
-    
-    // Add group by fields
-    if let Some(group_by) = &request.group_by {
-        for field in group_by {
-            select_parts.insert(0, field.clone());
-        }
-    }
-    
-    let mut query = format!("SELECT {} FROM {}", select_parts.join(", "), table_name);
-    
-    // Add filters
-    if let Some(filters) = &request.filters {
-        if let Some(filter_obj) = filters.as_object() {
-            let mut conditions = Vec::new();
-            for (field, value) in filter_obj {
-                if let Some(str_value) = value.as_str() {
-                    conditions.push(format!("{} LIKE '%{}%'", field, str_value));
-                } else if let Some(num_value) = value.as_f64() {
-                    conditions.push(format!("{} = {}", field, num_value));
-                }
-            }
-            if !conditions.is_empty() {
-                query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
-            }
-        }
-    }
-    
-    // Add group by clause
-    if let Some(group_by) = &request.group_by {
-        if !group_by.is_empty() {
-            query.push_str(&format!(" GROUP BY {}", group_by.join(", ")));
-        }
-    }
-    
-    // Add limit
-    if let Some(limit) = request.limit {
-        query.push_str(&format!(" LIMIT {}", limit));
-    }
-    
-    debug!("Executing aggregation query: {}", query);
-    
-    let result = AnalyticsQueries::execute_custom_query(&conn_guard, &table_name, &query)?;
+            result: grand_totals.get(i).cloned().unwrap_or(serde_json::Value::Null),
+        })
+        .collect();
     
     let agg_result = AggregationResult {
         columns: result.columns,
         data: result.data,
         row_count: result.row_count,
-        aggregations: agg_summaries,
+        aggregations,
     };
     
     Ok(Json(agg_result))
 }
 
+/// Run a generated query on a data source through the query cache
+fn cached_query(
+    state: &AppState,
+    conn: &Connection,
+    source: &ResolvedSource,
+    sql: &str,
+    params: &serde_json::Value,
+) -> AppResult<QueryResult> {
+    let cache_key = versions::cache_key(conn, sql, Some(params), source)?;
+    if let Some(cached) = cache_key.as_ref().and_then(|key| state.query_cache.get(conn, key)) {
+        return Ok(cached);
+    }
+
+    let deadline = QueryDeadline::start(conn, effective_timeout(state.config.query_timeout, None));
+    let result = deadline.finish(AnalyticsQueries::execute_custom_query(conn, &source.table_name, sql, Some(params)))?;
+    if let Some(cache_key) = &cache_key {
+        state.query_cache.store(&state.db_pool, cache_key, &result, None);
+    }
+    Ok(result)
+}
+
 /// Get predefined metrics for a data source
 pub async fn get_metrics(
     State(state): State<AppState>,
@@ -127,10 +159,9 @@ pub async fn get_metrics(
 ) -> AppResult<Json<MetricsResult>> {
     info!("Getting metrics for data source: {}", id);
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
+    let conn_guard = state.db_pool.get_connection().await?;
     
-    let table_name = format!("data_source_{}", id.replace('-', "_"));
+    let table_name = source_table(&id);
     
     // Get basic table statistics
     let stats = AnalyticsQueries::get_table_stats(&conn_guard, &table_name)?;
@@ -159,21 +190,194 @@ pub async fn get_metrics(
     Ok(Json(result))
 }
 
-/// Export data
+/// Export data to a file in the exports directory
 pub async fn export_data(
     State(state): State<AppState>,
     Json(request): Json<ExportRequest>,
 ) -> AppResult<Json<ExportResult>> {
     info!("Exporting data in format: {}", request.format);
 
-    // For now, return a placeholder response
-    let result = ExportResult {
-        file_url: "/exports/data.csv".to_string(),
-        file_size: 1024,
-        row_count: 100,
-        format: request.format,
-        expires_at: chrono::Utc::now() + chrono::Duration::hours(24),
-    };
+    let conn_guard = state.db_pool.get_connection().await?;
+
+    let deadline = QueryDeadline::start(&conn_guard, effective_timeout(state.config.query_timeout, None));
+    let result = deadline.finish(state.exports.export(&conn_guard, &request))?;
     
     Ok(Json(result))
-}
\ No newline at end of file
+}
+
+/// Download a previously exported file until it expires
+pub async fn download_export(
+    State(state): State<AppState>,
+    Path(file_name): Path<String>,
+    request: Request,
+) -> AppResult<Response> {
+    debug!("Downloading export: {}", file_name);
+
+    let path = state
+        .exports
+        .resolve(&file_name)
+        .ok_or_else(|| AppError::not_found(format!("Export not found or expired: {}", file_name)))?;
+
+    let response = ServeFile::new(path)
+        .oneshot(request)
+        .await
+        .map_err(|e| AppError::internal(format!("Failed to serve export: {}", e)))?;
+
+    let mut response = response.map(Body::new);
+    if let Ok(disposition) = HeaderValue::from_str(&format!("attachment; filename=\"{}\"", file_name)) {
+        response.headers_mut().insert(CONTENT_DISPOSITION, disposition);
+    }
+    Ok(response)
+}
+
+/// Look up a data source, or a version of it as `id@version` or
+/// `id@<timestamp>`, and check that `columns` exist in its schema,
+/// returning the table to read
+async fn resolve_columns(state: &AppState, data_source_id: &str, columns: &[&str]) -> AppResult<String> {
+    Ok(resolve_source(state, data_source_id, columns).await?.table_name)
+}
+
+/// Resolve a data source or one of its versions, checking it has `columns`
+async fn resolve_source(state: &AppState, data_source_id: &str, columns: &[&str]) -> AppResult<ResolvedSource> {
+    let conn_guard = state.db_pool.get_connection().await?;
+    let source = versions::resolve(&conn_guard, data_source_id)?;
+
+    for column in columns {
+        if !source.schema.iter().any(|c| c.name == *column) {
+            return Err(AppError::validation(format!(
+                "Unknown column {} in data source {}",
+                column, data_source_id
+            )));
+        }
+    }
+
+    Ok(source)
+}
+
+fn analytics_service(state: &AppState) -> AnalyticsService {
+    AnalyticsService::new(state.db_pool.clone())
+        .with_query_timeout(effective_timeout(state.config.query_timeout, None))
+}
+
+/// Summary statistics for a numeric column
+pub async fn column_statistics(
+    State(state): State<AppState>,
+    Json(request): Json<StatisticsRequest>,
+) -> AppResult<Json<StatisticsResult>> {
+    info!("Calculating statistics for {}.{}", request.data_source_id, request.column);
+
+    let table_name = resolve_columns(&state, &request.data_source_id, &[request.column.as_str()]).await?;
+    let statistics = analytics_service(&state)
+        .calculate_statistics(&table_name, &request.column)
+        .await?;
+
+    Ok(Json(StatisticsResult {
+        data_source_id: request.data_source_id,
+        column: request.column,
+        statistics,
+    }))
+}
+
+/// Aggregate a value column over truncated time buckets
+pub async fn time_series(
+    State(state): State<AppState>,
+    Json(request): Json<TimeSeriesRequest>,
+) -> AppResult<Json<QueryResult>> {
+    info!("Running time series for source: {}", request.data_source_id);
+
+    let table_name = resolve_columns(
+        &state,
+        &request.data_source_id,
+        &[request.time_column.as_str(), request.value_column.as_str()],
+    )
+    .await?;
+    let result = analytics_service(&state)
+        .time_series_aggregation(
+            &table_name,
+            &request.time_column,
+            &request.value_column,
+            &request.interval,
+            &request.aggregation,
+        )
+        .await?;
+
+    Ok(Json(result))
+}
+
+/// Rows whose value is an outlier by IQR or z-score
+pub async fn outliers(
+    State(state): State<AppState>,
+    Json(request): Json<OutlierRequest>,
+) -> AppResult<Json<QueryResult>> {
+    info!("Detecting outliers in {}.{}", request.data_source_id, request.column);
+
+    let table_name = resolve_columns(&state, &request.data_source_id, &[request.column.as_str()]).await?;
+    let result = analytics_service(&state)
+        .detect_outliers(&table_name, &request.column, &request.method, request.threshold)
+        .await?;
+
+    Ok(Json(result))
+}
+
+/// Pairwise correlation between numeric columns
+pub async fn correlation(
+    State(state): State<AppState>,
+    Json(request): Json<CorrelationRequest>,
+) -> AppResult<Json<CorrelationResult>> {
+    info!("Calculating correlation matrix for source: {}", request.data_source_id);
+
+    if request.columns.len() < 2 {
+        return Err(AppError::validation("Correlation needs at least two columns"));
+    }
+
+    let columns: Vec<&str> = request.columns.iter().map(String::as_str).collect();
+    let table_name = resolve_columns(&state, &request.data_source_id, &columns).await?;
+    let matrix = analytics_service(&state)
+        .correlation_matrix(&table_name, request.columns)
+        .await?;
+
+    Ok(Json(CorrelationResult {
+        data_source_id: request.data_source_id,
+        matrix,
+    }))
+}
+
+/// Null counts and types for every column of a data source
+pub async fn data_quality(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<DataQualityReport>> {
+    info!("Generating data quality report for source: {}", id);
+
+    let table_name = resolve_columns(&state, &id, &[]).await?;
+    let columns = analytics_service(&state).data_quality_report(&table_name).await?;
+
+    Ok(Json(DataQualityReport {
+        data_source_id: id,
+        columns,
+    }))
+}
+
+/// Rolling average of a value column ordered by another column
+pub async fn moving_average(
+    State(state): State<AppState>,
+    Json(request): Json<MovingAverageRequest>,
+) -> AppResult<Json<QueryResult>> {
+    info!("Calculating moving average for source: {}", request.data_source_id);
+
+    if request.window_size < 1 {
+        return Err(AppError::validation("window_size must be at least 1"));
+    }
+
+    let table_name = resolve_columns(
+        &state,
+        &request.data_source_id,
+        &[request.value_column.as_str(), request.order_column.as_str()],
+    )
+    .await?;
+    let result = analytics_service(&state)
+        .moving_average(&table_name, &request.value_column, &request.order_column, request.window_size)
+        .await?;
+
+    Ok(Json(result))
+}
@@ -2,7 +2,7 @@ use axum::{extract::{State, Path}, response::Json, http::StatusCode};
 use tracing::{debug, info};
 
 use crate::{
-    database::queries::DashboardQueries,
+    database::{filter::compile_filter, queries::DashboardQueries},
     AppState,
     models::{DashboardConfig, CreateDashboardRequest, UpdateDashboardRequest},
     utils::error::{AppError, AppResult},
@@ -14,8 +14,7 @@ pub async fn list_configs(
 ) -> AppResult<Json<Vec<DashboardConfig>>> {
     debug!("Listing all dashboard configurations");
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
+    let conn_guard = state.db_pool.get_connection().await?;
     let configs = DashboardQueries::list_all(&conn_guard)?;
 
     Ok(Json(configs))
@@ -28,16 +27,19 @@ pub async fn save_config(
 ) -> AppResult<Json<DashboardConfig>> {
     info!("Creating new dashboard configuration: {}", request.name);
 
+    // Reject filters that would fail when the dashboard is rendered
+    compile_filter(request.filters.as_ref())?;
+
     let config = DashboardConfig::new(
         uuid::Uuid::new_v4().to_string(),
         request.name,
     )
     .with_layout(request.layout)
+    .with_filters(request.filters)
     .with_data_source(request.data_source_id.unwrap_or_default())
     .with_refresh_interval(request.refresh_interval.unwrap_or(30));
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
+    let conn_guard = state.db_pool.get_writer().await?;
     DashboardQueries::create(&conn_guard, &config)?;
 
     info!("Dashboard configuration created successfully: {}", config.id);
@@ -52,8 +54,7 @@ pub async fn update_config(
 ) -> AppResult<Json<DashboardConfig>> {
     info!("Updating dashboard configuration: {}", id);
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
+    let conn_guard = state.db_pool.get_writer().await?;
     
     // Get existing config
     let mut config = DashboardQueries::get_by_id(&conn_guard, &id)?
@@ -67,6 +68,7 @@ pub async fn update_config(
         config.layout = layout;
     }
     if let Some(filters) = request.filters {
+        compile_filter(Some(&filters))?;
         config.filters = Some(filters);
     }
     if let Some(data_source_id) = request.data_source_id {
@@ -92,8 +94,7 @@ pub async fn delete_config(
 ) -> AppResult<StatusCode> {
     info!("Deleting dashboard configuration: {}", id);
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
+    let conn_guard = state.db_pool.get_writer().await?;
     
     let deleted = DashboardQueries::delete(&conn_guard, &id)?;
     
@@ -1,29 +1,55 @@
 use axum::{
-    extract::{Multipart, Path, Query, State},
+    extract::{multipart::Field, Multipart, Path, Query, State},
     http::StatusCode,
-    response::Json,
+    response::{IntoResponse, Json, Response},
 };
+use duckdb::{params_from_iter, Connection};
+use serde::Deserialize;
 use std::collections::HashMap;
-use tracing::{debug, error, info};
+use tracing::{debug, info};
 
 use crate::{
-    database::{queries::DataSourceQueries, DatabasePool},
-    models::{DataSource, DataPreviewRequest, DataPreviewResponse},
-    services::file_processor::FileProcessor,
+    database::{
+        filter::compile_filter,
+        params::resolve_params,
+        queries::{DataSourceQueries, DerivedQueries, ExternalDatabaseQueries, RemoteQueries, WatchQueries},
+        timeout::{effective_timeout, QueryDeadline},
+        values::collect_rows,
+    },
+    models::{
+        ArchiveMode, CsvInspection, DataSource, DataPreviewRequest, DataPreviewResponse, IncrementalConfig,
+        IncrementalConfigRequest, JsonNesting, LocalMode, LocalSourceRequest, SheetInfo, XlsxOptions, source_table,
+    },
+    services::{
+        archive::unpack_uploads,
+        derived,
+        file_processor::watermark,
+        ingest::prepare_uploads,
+        jobs::IngestRequest,
+        local_files::{list_files, resolve_pattern},
+        rejects,
+        upload_spool::{spool_field, SpooledUpload},
+        versions,
+        xlsx::list_sheets,
+    },
     utils::error::{AppError, AppResult},
     AppState,
 };
 
 
-/// Upload a data file
-pub async fn upload_data(
-    State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> AppResult<Json<DataSource>> {
-    info!("Starting file upload");
-
-    let mut file_name = None;
-    let mut file_data = None;
+/// Spool the `file` field to disk and read the optional `column_types` JSON
+/// object, `mode`, comma-separated `key_columns`, `archive_mode`,
+/// `json_nesting`, `max_reject_ratio` and the workbook options `sheet`,
+/// `range`, `header` and `all_sheets` from a multipart upload
+async fn read_upload_form(mut multipart: Multipart) -> AppResult<IngestRequest> {
+    let mut upload = None;
+    let mut column_types = HashMap::new();
+    let mut mode = None;
+    let mut key_columns = Vec::new();
+    let mut archive_mode = ArchiveMode::default();
+    let mut xlsx = XlsxOptions::default();
+    let mut json_nesting = JsonNesting::default();
+    let mut max_reject_ratio = None;
 
     // Process multipart form data
     while let Some(field) = multipart.next_field().await.map_err(|e| {
@@ -33,39 +59,278 @@ pub async fn upload_data(
         
         match name.as_str() {
             "file" => {
-                file_name = field.file_name().map(|s| s.to_string());
-                file_data = Some(field.bytes().await.map_err(|e| {
-                    AppError::file_upload(format!("Failed to read file data: {}", e))
+                upload = Some(spool_field(field, &std::env::temp_dir()).await?);
+            }
+            "column_types" => {
+                let text = field_text(field, "column types").await?;
+                column_types = serde_json::from_str(&text).map_err(|e| {
+                    AppError::bad_request(format!("column_types must be a JSON object of column name to type: {}", e))
+                })?;
+            }
+            "mode" => {
+                let text = field_text(field, "load mode").await?;
+                mode = Some(serde_json::from_value(serde_json::Value::String(text.trim().to_lowercase())).map_err(|_| {
+                    AppError::bad_request(format!("mode must be append, replace or upsert, got: {}", text))
                 })?);
             }
+            "key_columns" => {
+                let text = field_text(field, "key columns").await?;
+                key_columns = text
+                    .split(',')
+                    .map(|k| k.trim().to_string())
+                    .filter(|k| !k.is_empty())
+                    .collect();
+            }
+            "archive_mode" => {
+                let text = field_text(field, "archive mode").await?;
+                archive_mode = serde_json::from_value(serde_json::Value::String(text.trim().to_lowercase())).map_err(|_| {
+                    AppError::bad_request(format!("archive_mode must be combine or separate, got: {}", text))
+                })?;
+            }
+            "json_nesting" => {
+                let text = field_text(field, "JSON nesting").await?;
+                json_nesting = serde_json::from_value(serde_json::Value::String(text.trim().to_lowercase())).map_err(|_| {
+                    AppError::bad_request(format!("json_nesting must be nested or flatten, got: {}", text))
+                })?;
+            }
+            "max_reject_ratio" => {
+                let text = field_text(field, "max reject ratio").await?;
+                let ratio = text
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|r| (0.0..=1.0).contains(r))
+                    .ok_or_else(|| {
+                        AppError::bad_request(format!("max_reject_ratio must be a number from 0 to 1, got: {}", text))
+                    })?;
+                max_reject_ratio = Some(ratio);
+            }
+            "sheet" => {
+                let text = field_text(field, "sheet").await?;
+                xlsx.sheet = Some(text.trim().to_string()).filter(|s| !s.is_empty());
+            }
+            "range" => {
+                let text = field_text(field, "cell range").await?;
+                xlsx.range = Some(text.trim().to_string()).filter(|s| !s.is_empty());
+            }
+            "header" => {
+                let text = field_text(field, "header").await?;
+                xlsx.header = Some(parse_bool(&text, "header")?);
+            }
+            "all_sheets" => {
+                let text = field_text(field, "all_sheets").await?;
+                xlsx.all_sheets = parse_bool(&text, "all_sheets")?;
+            }
             _ => {
                 debug!("Ignoring unknown field: {}", name);
             }
         }
     }
 
-    let file_name = file_name.ok_or_else(|| AppError::bad_request("No file provided"))?;
-    let file_data = file_data.ok_or_else(|| AppError::bad_request("No file data provided"))?;
+    let upload = upload.ok_or_else(|| AppError::bad_request("No file provided"))?;
 
-    if file_data.is_empty() {
+    if upload.size == 0 {
         return Err(AppError::bad_request("Empty file provided"));
     }
 
-    info!("Processing uploaded file: {} ({} bytes)", file_name, file_data.len());
+    let mut request = IngestRequest::new(upload.file_name.clone(), vec![upload]);
+    request.column_types = column_types;
+    request.mode = mode;
+    request.key_columns = key_columns;
+    request.archive_mode = archive_mode;
+    request.xlsx = xlsx;
+    request.json_nesting = json_nesting;
+    request.max_reject_ratio = max_reject_ratio;
+    Ok(request)
+}
+
+async fn field_text(field: Field<'_>, what: &str) -> AppResult<String> {
+    field
+        .text()
+        .await
+        .map_err(|e| AppError::file_upload(format!("Failed to read {}: {}", what, e)))
+}
 
-    // Process the file
-    let data_source = state.file_processor.process_file(
-        file_name,
-        file_data.to_vec(),
-    ).await?;
+fn parse_bool(text: &str, name: &str) -> AppResult<bool> {
+    match text.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(AppError::bad_request(format!("{} must be true or false, got: {}", name, text))),
+    }
+}
 
-    // Save to database
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
-    DataSourceQueries::create(&conn_guard, &data_source)?;
+#[derive(Debug, Deserialize)]
+pub struct UploadParams {
+    /// Respond with the queued job instead of waiting for it to finish
+    #[serde(default)]
+    pub background: bool,
+}
 
-    info!("File upload completed successfully: {}", data_source.id);
-    Ok(Json(data_source))
+/// Upload a data file
+///
+/// Gzip and zstd files are decompressed. The data files of a zip or tar
+/// archive are loaded into one source each (`archive_mode=separate`) or
+/// appended into a single source named after the archive
+/// (`archive_mode=combine`). Excel workbooks load their first sheet, the
+/// `sheet` chosen, or one source per sheet with `all_sheets`. Responds with
+/// the list of data sources created, even when there is one.
+///
+/// The upload runs as an ingestion job. With `?background=true` the queued
+/// job is returned at once (202) and progress is published on `/ws`.
+pub async fn upload_data(
+    State(state): State<AppState>,
+    Query(params): Query<UploadParams>,
+    multipart: Multipart,
+) -> AppResult<Response> {
+    info!("Starting file upload");
+
+    let request = read_upload_form(multipart).await?;
+
+    info!("Processing uploaded file: {}", request.source);
+
+    run_job(&state, request, params.background).await
+}
+
+/// Upload a data file into an existing data source, appending to, replacing
+/// or upserting into its rows (`mode`, append by default). Every data file
+/// of a compressed upload or archive, and every selected sheet of a
+/// workbook, is loaded in turn. Responds with the `LoadResult`, or with the
+/// queued job when `?background=true`.
+pub async fn upload_into_source(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<UploadParams>,
+    multipart: Multipart,
+) -> AppResult<Response> {
+    info!("Starting upload into data source: {}", id);
+
+    {
+        let conn_guard = state.db_pool.get_connection().await?;
+        DataSourceQueries::get_by_id(&conn_guard, &id)?
+            .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", id)))?;
+    }
+
+    let mut request = read_upload_form(multipart).await?;
+    request.data_source_id = Some(id);
+
+    run_job(&state, request, params.background).await
+}
+
+/// Load local files on the server as a data source, without uploading them.
+///
+/// `path` is a file or glob under one of the server's `--local-roots`. With
+/// `mode: "copy"` the files are loaded like an upload: into a new data
+/// source, every matching file appended into one, or into an existing source
+/// (`data_source_id`, `load_mode`). With `mode: "in_place"` a data source
+/// querying the files where they are is registered at once. Either way it
+/// responds with the list of data sources created, like an upload, or with
+/// the queued job when `?background=true`.
+pub async fn ingest_local(
+    State(state): State<AppState>,
+    Query(params): Query<UploadParams>,
+    Json(request): Json<LocalSourceRequest>,
+) -> AppResult<Response> {
+    info!("Ingesting local files: {} ({:?})", request.path, request.mode);
+
+    let pattern = resolve_pattern(&state.config.local_roots, &request.path)?;
+    let files = {
+        let conn_guard = state.db_pool.get_connection().await?;
+        list_files(&conn_guard, &state.config.local_roots, &pattern)?
+    };
+    if files.is_empty() {
+        return Err(AppError::not_found(format!("No files match {}", pattern)));
+    }
+
+    if request.mode == LocalMode::InPlace {
+        if request.data_source_id.is_some() {
+            return Err(AppError::bad_request("Files queried in place cannot be loaded into a data source"));
+        }
+        let mut data_source = state.file_processor.register_local(&pattern, &files).await?;
+        if let Some(name) = request.name {
+            data_source.name = name;
+        }
+
+        let conn_guard = state.db_pool.get_writer().await?;
+        DataSourceQueries::create(&conn_guard, &data_source)?;
+        return Ok((StatusCode::CREATED, Json(vec![data_source])).into_response());
+    }
+
+    let mut uploads = Vec::with_capacity(files.len());
+    for file in &files {
+        uploads.push(SpooledUpload::local(&file.path).await?);
+    }
+    let mut ingest = IngestRequest::new(pattern, uploads);
+    ingest.name = request.name;
+    ingest.column_types = request.column_types;
+    ingest.data_source_id = request.data_source_id;
+    ingest.mode = request.load_mode;
+    ingest.key_columns = request.key_columns;
+    ingest.archive_mode = ArchiveMode::Combine;
+    ingest.max_reject_ratio = request.max_reject_ratio;
+
+    run_job(&state, ingest, params.background).await
+}
+
+/// Queue an ingestion job, then respond with the job itself in the
+/// background, or with its result once it finishes
+async fn run_job(state: &AppState, request: IngestRequest, background: bool) -> AppResult<Response> {
+    let (job, outcome) = state.jobs.enqueue(request).await?;
+    if background {
+        return Ok((StatusCode::ACCEPTED, Json(job)).into_response());
+    }
+
+    let result = outcome
+        .await
+        .map_err(|_| AppError::internal(format!("Job {} was dropped", job.id)))??;
+    Ok(Json(result).into_response())
+}
+
+/// Infer the dialect and column types of a CSV file, or of the selected
+/// sheet of a workbook, without loading it
+pub async fn inspect_upload(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> AppResult<Json<CsvInspection>> {
+    let request = read_upload_form(multipart).await?;
+    info!("Inspecting uploaded file: {}", request.source);
+
+    // Compressed uploads and archives are inspected through their first CSV
+    let files = unpack_uploads(request.files, state.config.max_unpacked_size).await?;
+    let files = prepare_uploads(
+        files,
+        &HashMap::new(),
+        &request.xlsx,
+        request.json_nesting,
+        state.config.max_unpacked_size,
+    )
+    .await?;
+    let upload = files
+        .iter()
+        .map(|file| &file.upload)
+        .find(|file| file.extension() == "csv")
+        .ok_or_else(|| AppError::bad_request("Only CSV files and workbooks can be inspected"))?;
+
+    let inspection = state.file_processor.inspect_csv(upload).await?;
+    Ok(Json(inspection))
+}
+
+/// List the sheets of an uploaded Excel workbook, so one can be chosen
+/// before upload
+pub async fn list_upload_sheets(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> AppResult<Json<Vec<SheetInfo>>> {
+    let request = read_upload_form(multipart).await?;
+    let files = unpack_uploads(request.files, state.config.max_unpacked_size).await?;
+    let workbook = files
+        .into_iter()
+        .find(|file| file.extension() == "xlsx")
+        .ok_or_else(|| AppError::bad_request("Upload is not an Excel workbook"))?;
+
+    let sheets = tokio::task::spawn_blocking(move || list_sheets(&workbook.path))
+        .await
+        .map_err(|e| AppError::internal(format!("Workbook task failed: {}", e)))??;
+    Ok(Json(sheets))
 }
 
 /// List all data sources
@@ -74,39 +339,164 @@ pub async fn list_sources(
 ) -> AppResult<Json<Vec<DataSource>>> {
     debug!("Listing all data sources");
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
+    let conn_guard = state.db_pool.get_connection().await?;
     let sources = DataSourceQueries::list_all(&conn_guard)?;
 
     Ok(Json(sources))
 }
 
-/// Delete a data source
+#[derive(Debug, Deserialize)]
+pub struct DeleteSourceParams {
+    /// Keep the source's versions queryable and restorable, which are
+    /// otherwise dropped with it
+    #[serde(default)]
+    pub keep_versions: bool,
+    /// Also delete the derived sources reading the source, which otherwise
+    /// prevent its deletion
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+/// Delete a data source with its versions, or, with `?keep_versions=true`,
+/// leaving them queryable and restorable as retention allows. Deleting an
+/// already deleted source drops the versions it left behind. A source read by derived sources is only
+/// deleted with `?cascade=true`, which deletes them too.
 pub async fn delete_source(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    Query(params): Query<DeleteSourceParams>,
 ) -> AppResult<StatusCode> {
     info!("Deleting data source: {}", id);
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
-    
-    let deleted = DataSourceQueries::delete(&conn_guard, &id)?;
-    
-    if deleted {
-        // Also delete the actual table if it exists
-        let table_name = format!("data_source_{}", id.replace('-', "_"));
-        if let Err(e) = conn_guard.execute(&format!("DROP TABLE IF EXISTS {}", table_name), []) {
-            error!("Failed to drop table {}: {:?}", table_name, e);
+    let conn_guard = state.db_pool.get_writer().await?;
+    let dependents = derived::dependents(&conn_guard, &id)?;
+    if !dependents.is_empty() && !params.cascade {
+        return Err(AppError::validation(format!(
+            "Data source {} is read by derived sources {}; delete them first or pass cascade=true",
+            id,
+            dependents.join(", ")
+        )));
+    }
+
+    // Derived sources are deleted with the source or not at all
+    let mut deleted_ids = Vec::new();
+    conn_guard.execute_batch("BEGIN TRANSACTION")?;
+    let removed = dependents
+        .iter()
+        .try_for_each(|dependent| {
+            info!("Deleting derived source {} with {}", dependent, id);
+            let (deleted, _) = remove_source(&state, &conn_guard, dependent, params.keep_versions)?;
+            if deleted {
+                deleted_ids.push(dependent.as_str());
+            }
+            Ok(())
+        })
+        .and_then(|_| remove_source(&state, &conn_guard, &id, params.keep_versions));
+    let (deleted, purged) = match removed {
+        Ok(removed) => {
+            conn_guard.execute_batch("COMMIT")?;
+            removed
         }
-        
+        Err(e) => {
+            let _ = conn_guard.execute_batch("ROLLBACK");
+            return Err(e);
+        }
+    };
+
+    // Reject files cannot be rolled back, so they go once the rows are gone
+    if deleted {
+        deleted_ids.push(&id);
+    }
+    for deleted_id in deleted_ids {
+        rejects::remove_files(&state.exports, deleted_id);
+    }
+    if deleted {
         info!("Data source deleted successfully: {}", id);
         Ok(StatusCode::NO_CONTENT)
+    } else if purged > 0 {
+        info!("Dropped {} versions of deleted data source {}", purged, id);
+        Ok(StatusCode::NO_CONTENT)
     } else {
         Err(AppError::not_found(format!("Data source not found: {}", id)))
     }
 }
 
+/// Delete a data source with its table and everything recorded about it,
+/// returning whether it existed and how many of its versions were dropped.
+/// Runs inside the caller's transaction, which removes its reject files once
+/// committed.
+fn remove_source(state: &AppState, conn: &Connection, id: &str, keep_versions: bool) -> AppResult<(bool, usize)> {
+    let data_source = DataSourceQueries::get_by_id(conn, id)?;
+
+    // Cached results reference the source, so they must go first
+    state.query_cache.invalidate_source(conn, id)?;
+    WatchQueries::delete_for_source(conn, id)?;
+    RemoteQueries::delete_for_source(conn, id)?;
+    ExternalDatabaseQueries::delete_table(conn, id)?;
+    DerivedQueries::delete_for_source(conn, id)?;
+    let deleted = DataSourceQueries::delete(conn, id)?;
+    let purged = if keep_versions { 0 } else { versions::purge(conn, id)? };
+
+    if deleted {
+        // Also delete the actual table, or the view over files, an external
+        // table or other sources
+        let table_name = source_table(id);
+        let kind = match data_source {
+            Some(source) if source.is_view() => "VIEW",
+            _ => "TABLE",
+        };
+        conn.execute_batch(&format!("DROP {} IF EXISTS {}", kind, table_name))?;
+    }
+    Ok((deleted, purged))
+}
+
+/// Load a data source incrementally from now on. Appends and upserts into
+/// it, including refreshes of a remote source, keep only rows whose
+/// `watermark_column` is above the highest value already loaded.
+pub async fn set_incremental(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<IncrementalConfigRequest>,
+) -> AppResult<Json<DataSource>> {
+    info!("Loading data source {} incrementally on {}", id, request.watermark_column);
+
+    let conn_guard = state.db_pool.get_writer().await?;
+    let mut data_source = DataSourceQueries::get_by_id(&conn_guard, &id)?
+        .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", id)))?;
+    if let Some(reason) = data_source.load_refusal() {
+        return Err(AppError::validation(reason));
+    }
+    for column in std::iter::once(&request.watermark_column).chain(&request.key_columns) {
+        if !data_source.schema.iter().any(|c| &c.name == column) {
+            return Err(AppError::validation(format!("Column {} not found in data source {}", column, id)));
+        }
+    }
+
+    let table_name = source_table(&id);
+    let config = IncrementalConfig {
+        last_value: watermark(&conn_guard, &table_name, &request.watermark_column)?,
+        watermark_column: request.watermark_column,
+        key_columns: request.key_columns,
+        updated_at: Some(chrono::Utc::now()),
+    };
+    DataSourceQueries::set_incremental(&conn_guard, &id, Some(&config))?;
+    data_source.incremental = Some(config);
+
+    Ok(Json(data_source))
+}
+
+/// Stop loading a data source incrementally
+pub async fn clear_incremental(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn_guard = state.db_pool.get_writer().await?;
+    DataSourceQueries::get_by_id(&conn_guard, &id)?
+        .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", id)))?;
+    DataSourceQueries::set_incremental(&conn_guard, &id, None)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// Get schema for a data source
 pub async fn get_schema(
     State(state): State<AppState>,
@@ -114,8 +504,7 @@ pub async fn get_schema(
 ) -> AppResult<Json<DataSource>> {
     debug!("Getting schema for data source: {}", id);
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
+    let conn_guard = state.db_pool.get_connection().await?;
     
     let data_source = DataSourceQueries::get_by_id(&conn_guard, &id)?
         .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", id)))?;
@@ -123,84 +512,56 @@ pub async fn get_schema(
     Ok(Json(data_source))
 }
 
-/// Preview data from a data source
+/// Preview data from a data source, or from one of its versions as
+/// `id@version` or `id@<timestamp>`
 pub async fn preview_data(
     State(state): State<AppState>,
     Path(id): Path<String>,
-    Query(params): Query<HashMap<String, String>>,
+    Query(_params): Query<HashMap<String, String>>,
     Json(request): Json<DataPreviewRequest>,
 ) -> AppResult<Json<DataPreviewResponse>> {
     debug!("Previewing data for source: {}", id);
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
+    let conn_guard = state.db_pool.get_connection().await?;
     
-    // Verify data source exists
-    let data_source = DataSourceQueries::get_by_id(&conn_guard, &id)?
-        .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", id)))?;
+    // Verify the data source, or the `id@version` of it, exists
+    let source = versions::resolve(&conn_guard, &id)?;
 
-    let table_name = format!("data_source_{}", id.replace('-', "_"));
+    let table_name = source.table_name;
     let limit = request.limit.unwrap_or(1000).min(10000); // Max 10k rows for preview
     let offset = request.offset.unwrap_or(0);
 
     // Build query
-    let mut query = format!("SELECT * FROM {} LIMIT {} OFFSET {}", table_name, limit, offset);
-    
-    // Add filters if provided
-    if let Some(filters) = &request.filters {
-        if let Some(filter_obj) = filters.as_object() {
-            let mut conditions = Vec::new();
-            for (field, value) in filter_obj {
-                if let Some(str_value) = value.as_str() {
-                    conditions.push(format!("{} LIKE '%{}%'", field, str_value));
-                } else if let Some(num_value) = value.as_f64() {
-                    conditions.push(format!("{} = {}", field, num_value));
-                }
-            }
-            if !conditions.is_empty() {
-                query = format!("SELECT * FROM {} WHERE {} LIMIT {} OFFSET {}", 
-                    table_name, conditions.join(" AND "), limit, offset);
-            }
-        }
-    }
+    let filter = compile_filter(request.filters.as_ref())?;
+    let query = format!(
+        "SELECT * FROM {}{} LIMIT {} OFFSET {}",
+        table_name,
+        filter.where_clause(),
+        limit,
+        offset
+    );
 
     debug!("Executing preview query: {}", query);
 
     // Execute query
-    let mut stmt = conn_guard.prepare(&query)?;
-    
-    let column_count = stmt.column_count();
-    let columns: Vec<String> = (0..column_count)
-        .map(|i| stmt.column_name(i).map_or("unknown".to_string(), |v| v.to_string()))
-        .collect();
-    
-    let mut rows = stmt.query([])?;
-
-    let mut data = Vec::new();
-    while let Some(row) = rows.next()? {
-        let mut row_data = Vec::new();
-        for i in 0..column_count {
-            let value = match row.get_ref(i)? {
-                duckdb::types::ValueRef::Null => serde_json::Value::Null,
-                duckdb::types::ValueRef::Int(n) => serde_json::Value::Number(n.into()),
-                duckdb::types::ValueRef::BigInt(n) => serde_json::Value::Number(n.into()),
-                duckdb::types::ValueRef::Double(f) => serde_json::Value::Number(
-                    serde_json::Number::from_f64(f).unwrap_or_else(|| serde_json::Number::from(0))
-                ),
-                duckdb::types::ValueRef::Text(s) => serde_json::Value::String(String::from_utf8_lossy(s).to_string()),
-                duckdb::types::ValueRef::Blob(_) => serde_json::Value::String("BLOB".to_string()),
-                _ => serde_json::Value::String("UNKNOWN".to_string()),
-            };
-            row_data.push(value);
-        }
-        data.push(row_data);
-    }
+    let deadline = QueryDeadline::start(&conn_guard, effective_timeout(state.config.query_timeout, None));
+    let result = deadline.finish(
+        conn_guard
+            .prepare(&query)
+            .map_err(AppError::from)
+            .and_then(|mut stmt| {
+                let values = resolve_params(&stmt, Some(&filter.params_json()))?;
+                let rows = stmt.query(params_from_iter(values))?;
+                Ok(collect_rows(rows, None)?)
+            }),
+    )?;
 
     let response = DataPreviewResponse {
-        columns,
-        data: data.clone(),
-        total_rows: data_source.row_count,
-        preview_rows: data.len(),
+        columns: result.columns,
+        column_types: result.column_types,
+        total_rows: source.row_count,
+        preview_rows: result.row_count,
+        data: result.data,
     };
 
     Ok(Json(response))
@@ -211,43 +572,102 @@ mod tests {
     use super::*;
     use crate::database::DatabasePool;
     use crate::services::file_processor::FileProcessor;
-    use tempfile::NamedTempFile;
-
-    async fn create_test_state() -> AppState {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap();
+    use crate::services::folder_watch::WatchService;
+    use crate::services::export::ExportService;
+    use crate::services::external_db::ExternalDatabaseService;
+    use crate::services::jobs::JobService;
+    use crate::services::query_cache::QueryCacheService;
+    use crate::services::remote::RemoteService;
+    use crate::services::versions::VersionService;
+    use crate::services::derived::DerivedSourceService;
+    use crate::utils::config::Config;
+    use crate::utils::secrets::Secrets;
+    use tempfile::TempDir;
+
+    async fn create_test_state(dir: &TempDir) -> AppState {
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
         let db_pool = DatabasePool::new(db_path).unwrap();
         let file_processor = FileProcessor::new(db_pool.clone());
         
+        let query_cache = QueryCacheService::new(300);
+        let config = Config::new(db_path.to_string(), "127.0.0.1".to_string(), 3000);
+
+        let jobs = JobService::new(db_pool.clone(), file_processor.clone(), query_cache.clone(), config.max_unpacked_size);
+
         AppState {
+            watches: WatchService::new(
+                db_pool.clone(),
+                jobs.clone(),
+                config.local_roots.clone(),
+                std::time::Duration::from_secs(config.watch_interval),
+            ),
+            remote: RemoteService::new(db_pool.clone(), jobs.clone(), Secrets::ephemeral(), config.max_upload_size as u64).unwrap(),
+            databases: ExternalDatabaseService::new(
+                db_pool.clone(),
+                file_processor.clone(),
+                query_cache.clone(),
+                Secrets::ephemeral(),
+                config.local_roots.clone(),
+            ),
+            versions: VersionService::new(db_pool.clone(), query_cache.clone(), config.version_retention()),
+            derived: DerivedSourceService::new(db_pool.clone(), file_processor.clone(), query_cache.clone()),
+            jobs,
             db_pool,
             file_processor,
+            query_cache,
+            exports: ExportService::new(std::env::temp_dir().join("dashboard-test-exports"), 3600).unwrap(),
+            config,
         }
     }
 
     #[tokio::test]
     async fn test_list_sources_empty() {
-        let state = create_test_state().await;
+        let dir = TempDir::new().unwrap();
+        let state = create_test_state(&dir).await;
         
-        // Initialize database with tables
-        let conn = state.db_pool.get_connection();
-        let conn_guard = conn.lock().await;
-        conn_guard.execute_batch("
-            CREATE TABLE data_sources (
-                id VARCHAR PRIMARY KEY,
-                name VARCHAR NOT NULL,
-                type VARCHAR NOT NULL,
-                file_path VARCHAR,
-                schema_info TEXT,
-                row_count BIGINT DEFAULT 0,
-                size_bytes BIGINT DEFAULT 0,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            );
-        ").unwrap();
+        let conn_guard = state.db_pool.get_writer().await.unwrap();
+        crate::database::migrations::run_migrations(&conn_guard).await.unwrap();
         drop(conn_guard);
 
         let result = list_sources(State(state)).await.unwrap();
         assert_eq!(result.0.len(), 0);
     }
+    #[tokio::test]
+    async fn test_set_incremental() {
+        let dir = TempDir::new().unwrap();
+        let state = create_test_state(&dir).await;
+        let data_source = {
+            let conn_guard = state.db_pool.get_writer().await.unwrap();
+            crate::database::migrations::run_migrations(&conn_guard).await.unwrap();
+            let upload = SpooledUpload::from_bytes(
+                &std::env::temp_dir(),
+                "orders.csv".to_string(),
+                b"id,updated_at\n1,2024-01-01\n2,2024-01-02\n",
+            )
+            .await
+            .unwrap();
+            let data_source = state.file_processor.create_table(&conn_guard, &upload, &HashMap::new()).unwrap();
+            DataSourceQueries::create(&conn_guard, &data_source).unwrap();
+            data_source
+        };
+
+        let request = |watermark_column: &str| IncrementalConfigRequest {
+            watermark_column: watermark_column.to_string(),
+            key_columns: vec!["id".to_string()],
+        };
+        assert!(set_incremental(State(state.clone()), Path(data_source.id.clone()), Json(request("missing")))
+            .await
+            .is_err());
+
+        let updated = set_incremental(State(state.clone()), Path(data_source.id.clone()), Json(request("updated_at")))
+            .await
+            .unwrap();
+        let config = updated.0.incremental.unwrap();
+        assert_eq!(config.last_value.as_deref(), Some("2024-01-02"));
+
+        let conn_guard = state.db_pool.get_connection().await.unwrap();
+        let stored = DataSourceQueries::get_by_id(&conn_guard, &data_source.id).unwrap().unwrap();
+        assert_eq!(stored.incremental.unwrap().key_columns, vec!["id".to_string()]);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,59 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    models::{AttachDatabaseRequest, DataSource, ExternalDatabase},
+    utils::error::AppResult,
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct AttachDatabaseResponse {
+    pub database: ExternalDatabase,
+    pub data_sources: Vec<DataSource>,
+}
+
+/// List attached external databases, with passwords redacted
+pub async fn list_databases(State(state): State<AppState>) -> AppResult<Json<Vec<ExternalDatabase>>> {
+    let databases = state.databases.list().await?;
+    Ok(Json(databases.iter().map(ExternalDatabase::redacted).collect()))
+}
+
+/// Get an attached external database and its tables
+pub async fn get_database(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<ExternalDatabase>> {
+    Ok(Json(state.databases.get(&id).await?.redacted()))
+}
+
+/// Attach a SQLite or DuckDB file under the server's `--local-roots`, or a
+/// PostgreSQL database, read-only, registering its tables as data sources
+pub async fn attach_database(
+    State(state): State<AppState>,
+    Json(request): Json<AttachDatabaseRequest>,
+) -> AppResult<(StatusCode, Json<AttachDatabaseResponse>)> {
+    info!("Attaching {:?} database", request.kind);
+    let (database, data_sources) = state.databases.attach(request).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(AttachDatabaseResponse {
+            database: database.redacted(),
+            data_sources,
+        }),
+    ))
+}
+
+/// Detach an external database, deleting the data sources over its tables
+pub async fn delete_database(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<StatusCode> {
+    state.databases.delete(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,32 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use tracing::info;
+
+use crate::{
+    models::{CreateDerivedSourceRequest, DataSource},
+    utils::error::AppResult,
+    AppState,
+};
+
+/// Define a data source by a read-only SQL query over other data sources,
+/// as a view or, with `materialized`, a table refreshed on demand, on
+/// `schedule` or with `refresh_on_change` when a dependency is loaded into
+pub async fn create_derived(
+    State(state): State<AppState>,
+    Json(request): Json<CreateDerivedSourceRequest>,
+) -> AppResult<(StatusCode, Json<DataSource>)> {
+    info!("Creating derived source {}", request.name);
+    Ok((StatusCode::CREATED, Json(state.derived.create(request).await?)))
+}
+
+/// Recompute a materialized derived source now
+pub async fn refresh_derived(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<DataSource>> {
+    info!("Refreshing derived source {}", id);
+    Ok(Json(state.derived.refresh(&id).await?))
+}
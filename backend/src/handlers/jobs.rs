@@ -0,0 +1,46 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    models::IngestJob,
+    utils::error::AppResult,
+    AppState,
+};
+
+/// Most jobs listed by default
+const DEFAULT_JOB_LIMIT: usize = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct ListJobsParams {
+    pub limit: Option<usize>,
+}
+
+/// List recent ingestion jobs, newest first
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Query(params): Query<ListJobsParams>,
+) -> AppResult<Json<Vec<IngestJob>>> {
+    let limit = params.limit.unwrap_or(DEFAULT_JOB_LIMIT).min(1000);
+    Ok(Json(state.jobs.list(limit).await?))
+}
+
+/// Get an ingestion job
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<IngestJob>> {
+    Ok(Json(state.jobs.get(&id).await?))
+}
+
+/// Cancel a queued or running ingestion job
+pub async fn cancel_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<IngestJob>> {
+    info!("Cancelling job: {}", id);
+    Ok(Json(state.jobs.cancel(&id).await?))
+}
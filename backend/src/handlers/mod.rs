@@ -1,5 +1,11 @@
 pub mod analytics;
 pub mod dashboard;
 pub mod data;
+pub mod databases;
+pub mod derived;
+pub mod jobs;
+pub mod remote;
 pub mod system;
+pub mod versions;
+pub mod watches;
 pub mod websocket;
\ No newline at end of file
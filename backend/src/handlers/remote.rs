@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    models::{CreateRemoteSourceRequest, DataSource, RemoteSource, SourceRefresh},
+    utils::error::AppResult,
+    AppState,
+};
+
+/// Most refreshes listed by default
+const DEFAULT_REFRESH_LIMIT: usize = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct ListRefreshesParams {
+    pub limit: Option<usize>,
+}
+
+/// Load a CSV, JSON or Parquet payload from an HTTP(S) URL as a data source.
+/// With `schedule`, a cron expression, it is refreshed automatically.
+pub async fn register_remote(
+    State(state): State<AppState>,
+    Json(request): Json<CreateRemoteSourceRequest>,
+) -> AppResult<(StatusCode, Json<DataSource>)> {
+    info!("Registering remote source from {}", request.url);
+    Ok((StatusCode::CREATED, Json(state.remote.register(request).await?)))
+}
+
+/// Get where a remote data source is fetched from, with secrets redacted
+pub async fn get_remote(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<RemoteSource>> {
+    Ok(Json(state.remote.get(&id).await?.redacted()))
+}
+
+/// Fetch a remote data source now. Responds with the refresh, which records
+/// rather than raises a failed fetch or load.
+pub async fn refresh_source(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<SourceRefresh>> {
+    info!("Refreshing remote source {}", id);
+    Ok(Json(state.remote.refresh(&id).await?))
+}
+
+/// List a remote data source's refreshes, newest first
+pub async fn list_refreshes(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<ListRefreshesParams>,
+) -> AppResult<Json<Vec<SourceRefresh>>> {
+    let limit = params.limit.unwrap_or(DEFAULT_REFRESH_LIMIT).min(1000);
+    state.remote.get(&id).await?;
+    Ok(Json(state.remote.history(&id, limit).await?))
+}
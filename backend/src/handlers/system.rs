@@ -4,6 +4,7 @@ use tracing::debug;
 
 use crate::{
     database::connection::DatabaseInfo,
+    services::query_cache::CacheStats,
     AppState,
     utils::error::AppResult,
 };
@@ -21,6 +22,7 @@ pub struct SystemStats {
     pub memory_usage: i64,
     pub active_connections: i32,
     pub uptime_seconds: i64,
+    pub query_cache: CacheStats,
 }
 
 /// Health check endpoint
@@ -48,12 +50,18 @@ pub async fn get_stats(
     );
     let database_info = conn_manager.get_database_info().await?;
 
+    let query_cache = {
+        let conn_guard = state.db_pool.get_connection().await?;
+        state.query_cache.stats(&conn_guard)
+    };
+
     // TODO: Implement actual memory and connection tracking
     let stats = SystemStats {
         database: database_info,
         memory_usage: 0, // Placeholder
-        active_connections: 1, // Placeholder
+        active_connections: (state.db_pool.size() - state.db_pool.idle_connections()) as i32,
         uptime_seconds: 0, // Placeholder
+        query_cache,
     };
 
     Ok(Json(stats))
@@ -65,8 +73,7 @@ pub async fn optimize_database(
 ) -> AppResult<StatusCode> {
     debug!("Database optimization requested");
 
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
+    let conn_guard = state.db_pool.get_writer().await?;
     
     // Run VACUUM to optimize database
     conn_guard.execute("VACUUM", [])?;
@@ -0,0 +1,31 @@
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use tracing::info;
+
+use crate::{
+    models::{RestoreVersionRequest, RestoreVersionResponse, SourceVersion},
+    utils::error::AppResult,
+    AppState,
+};
+
+/// List the versions of a data source, newest first, including those of a
+/// deleted one
+pub async fn list_versions(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<Vec<SourceVersion>>> {
+    Ok(Json(state.versions.list(&id).await?))
+}
+
+/// Restore a data source to a `version`, or to the version current `at` a
+/// point in time, recording the restored rows as a new version
+pub async fn restore_version(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<RestoreVersionRequest>,
+) -> AppResult<Json<RestoreVersionResponse>> {
+    info!("Restoring data source {}", id);
+    Ok(Json(state.versions.restore(&id, request).await?))
+}
@@ -0,0 +1,36 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use tracing::info;
+
+use crate::{
+    models::{CreateWatchRequest, FolderWatch},
+    utils::error::AppResult,
+    AppState,
+};
+
+/// List folder watches
+pub async fn list_watches(State(state): State<AppState>) -> AppResult<Json<Vec<FolderWatch>>> {
+    Ok(Json(state.watches.list().await?))
+}
+
+/// Watch a folder for new files, loading each into a data source as it
+/// arrives. `path` is a glob under one of the server's `--local-roots`.
+pub async fn create_watch(
+    State(state): State<AppState>,
+    Json(request): Json<CreateWatchRequest>,
+) -> AppResult<(StatusCode, Json<FolderWatch>)> {
+    info!("Creating folder watch on {}", request.path);
+    Ok((StatusCode::CREATED, Json(state.watches.create(request).await?)))
+}
+
+/// Stop watching a folder. Files already loaded are kept.
+pub async fn delete_watch(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<StatusCode> {
+    state.watches.delete(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
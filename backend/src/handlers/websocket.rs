@@ -9,8 +9,19 @@ use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
 
 use crate::{
+    database::{
+        params::prepare_with_params,
+        queries::DataSourceQueries,
+        sql_guard::check_read_only,
+        timeout::{effective_timeout, QueryDeadline},
+        values::collect_rows,
+    },
+    models::{Filter, IngestJob},
     AppState, utils::error::AppResult};
 
+/// Maximum number of rows returned for a `query:execute` message
+const WEBSOCKET_ROW_LIMIT: usize = 1000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ClientMessage {
@@ -18,7 +29,7 @@ pub enum ClientMessage {
     DataSubscribe {
         #[serde(rename = "sourceId")]
         source_id: String,
-        filters: Option<serde_json::Value>,
+        filters: Option<Filter>,
     },
     #[serde(rename = "data:unsubscribe")]
     DataUnsubscribe {
@@ -30,6 +41,11 @@ pub enum ClientMessage {
         sql: String,
         params: Option<serde_json::Value>,
     },
+    #[serde(rename = "job:cancel")]
+    JobCancel {
+        #[serde(rename = "jobId")]
+        job_id: String,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +64,16 @@ pub enum ServerMessage {
         data: Vec<serde_json::Value>,
         error: Option<String>,
     },
+    /// An ingestion job was queued or made progress
+    #[serde(rename = "job:progress")]
+    JobProgress {
+        job: IngestJob,
+    },
+    /// An ingestion job finished, failed or was cancelled
+    #[serde(rename = "job:finished")]
+    JobFinished {
+        job: IngestJob,
+    },
     #[serde(rename = "system:status")]
     SystemStatus {
         memory: i64,
@@ -90,6 +116,30 @@ async fn handle_websocket(socket: WebSocket, state: AppState) {
         error!("Failed to send initial system status: {}", e);
     }
     
+    // Forward ingestion job updates
+    let mut jobs = state.jobs.subscribe();
+    let jobs_tx = tx.clone();
+    let jobs_task = tokio::spawn(async move {
+        loop {
+            let job = match jobs.recv().await {
+                Ok(job) => job,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("WebSocket client missed {} job updates", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let message = if job.status.is_finished() {
+                ServerMessage::JobFinished { job }
+            } else {
+                ServerMessage::JobProgress { job }
+            };
+            if jobs_tx.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    
     // Handle incoming messages
     let tx_clone = tx.clone();
     let mut recv_task = tokio::spawn(async move {
@@ -153,6 +203,7 @@ async fn handle_websocket(socket: WebSocket, state: AppState) {
             send_task.abort();
         }
     }
+    jobs_task.abort();
     
     info!("WebSocket connection closed");
 }
@@ -171,6 +222,12 @@ async fn handle_client_message(
     match client_msg {
         ClientMessage::DataSubscribe { source_id, filters } => {
             info!("Client subscribing to data source: {}", source_id);
+            // No rows are pushed yet, so there is nothing to filter
+            if filters.is_some_and(|filter| !matches!(filter, Filter::And { and } if and.is_empty())) {
+                return Err(crate::utils::error::AppError::validation(
+                    "Filters are not supported on data subscriptions",
+                ));
+            }
             subscriptions.insert(source_id.clone(), true);
             
             // TODO: Implement actual data subscription logic
@@ -190,13 +247,19 @@ async fn handle_client_message(
             subscriptions.remove(&source_id);
         }
         
+        ClientMessage::JobCancel { job_id } => {
+            info!("Client cancelling job: {}", job_id);
+            // The cancelled job arrives as a job:finished message
+            state.jobs.cancel(&job_id).await?;
+        }
+        
         ClientMessage::QueryExecute { sql, params } => {
             info!("Client executing query: {}", sql);
             
             // Execute the query
             let query_id = uuid::Uuid::new_v4().to_string();
             
-            match execute_websocket_query(state, &sql).await {
+            match execute_websocket_query(state, &sql, params.as_ref()).await {
                 Ok(data) => {
                     let result_msg = ServerMessage::QueryResult {
                         query_id,
@@ -229,56 +292,34 @@ async fn handle_client_message(
 async fn execute_websocket_query(
     state: &AppState,
     sql: &str,
+    params: Option<&serde_json::Value>,
 ) -> AppResult<Vec<serde_json::Value>> {
-    let conn = state.db_pool.get_connection();
-    let conn_guard = conn.lock().await;
-    
-    // Basic SQL validation
-    let sql_lower = sql.to_lowercase();
-    if sql_lower.contains("drop") || sql_lower.contains("delete") || sql_lower.contains("insert") || sql_lower.contains("update") {
-        return Err(crate::utils::error::AppError::bad_request(
-            "Only SELECT queries are allowed via WebSocket"
-        ));
-    }
-    
-    let mut stmt = conn_guard.prepare(sql)?;
+    let conn_guard = state.db_pool.get_connection().await?;
     
-    let column_count = stmt.column_count();
-    let columns: Vec<String> = (0..column_count)
-        .map(|i| stmt.column_name(i).map_or("unknown".to_string(), |v| v.to_string()))
-        .collect();
-    
-    let mut rows = stmt.query([])?;
+    check_read_only(sql, &DataSourceQueries::table_names(&conn_guard)?)?;
     
-    let mut data = Vec::new();
+    let (mut stmt, values) = prepare_with_params(&conn_guard, sql, params)?;
     
-    while let Some(row) = rows.next()? {
-        let mut row_obj = serde_json::Map::new();
-        
-        for (i, column_name) in columns.iter().enumerate() {
-            let value = match row.get_ref(i)? {
-                duckdb::types::ValueRef::Null => serde_json::Value::Null,
-                duckdb::types::ValueRef::Int(n) => serde_json::Value::Number(n.into()),
-                duckdb::types::ValueRef::BigInt(n) => serde_json::Value::Number(n.into()),
-                duckdb::types::ValueRef::Double(f) => serde_json::Value::Number(
-                    serde_json::Number::from_f64(f).unwrap_or_else(|| serde_json::Number::from(0))
-                ),
-                duckdb::types::ValueRef::Text(s) => serde_json::Value::String(String::from_utf8_lossy(s).to_string()),
-                duckdb::types::ValueRef::Blob(_) => serde_json::Value::String("BLOB".to_string()),
-                _ => serde_json::Value::String("UNKNOWN".to_string()),
-            };
-            
-            row_obj.insert(column_name.clone(), value);
-        }
-        
-        data.push(serde_json::Value::Object(row_obj));
-        
-        // Limit results to prevent memory issues
-        if data.len() >= 1000 {
-            warn!("Query result truncated to 1000 rows");
-            break;
-        }
+    // Limit results to prevent memory issues
+    let deadline = QueryDeadline::start(&conn_guard, effective_timeout(state.config.query_timeout, None));
+    let result = deadline.finish(
+        stmt.query(duckdb::params_from_iter(values))
+            .and_then(|rows| collect_rows(rows, Some(WEBSOCKET_ROW_LIMIT)))
+            .map_err(crate::utils::error::AppError::from),
+    )?;
+    if result.truncated {
+        warn!("Query result truncated to {} rows", WEBSOCKET_ROW_LIMIT);
     }
     
+    let data = result
+        .data
+        .into_iter()
+        .map(|row| {
+            let row_obj: serde_json::Map<String, serde_json::Value> =
+                result.columns.iter().cloned().zip(row).collect();
+            serde_json::Value::Object(row_obj)
+        })
+        .collect();
+    
     Ok(data)
-}
\ No newline at end of file
+}
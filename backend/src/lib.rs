@@ -17,16 +17,29 @@ use tower_http::{
 };
 
 use crate::{
-    handlers::{dashboard, data, analytics, websocket, system},
+    handlers::{dashboard, data, databases, derived, analytics, jobs, remote, versions, watches, websocket, system},
     middleware::cors::create_cors_layer,
     database::DatabasePool,
-    services::file_processor::FileProcessor,
+    services::{
+        derived::DerivedSourceService, export::ExportService, external_db::ExternalDatabaseService, file_processor::FileProcessor,
+        folder_watch::WatchService, jobs::JobService, query_cache::QueryCacheService, remote::RemoteService, versions::VersionService,
+    },
+    utils::config::Config,
 };
 
 #[derive(Clone)]
 pub struct AppState {
     pub db_pool: DatabasePool,
     pub file_processor: FileProcessor,
+    pub query_cache: QueryCacheService,
+    pub exports: ExportService,
+    pub jobs: JobService,
+    pub watches: WatchService,
+    pub remote: RemoteService,
+    pub databases: ExternalDatabaseService,
+    pub versions: VersionService,
+    pub derived: DerivedSourceService,
+    pub config: Config,
 }
 
 /// Create the main application router with all routes and middleware
@@ -41,10 +54,36 @@ pub fn create_app() -> Router<AppState> {
         
         // Data management routes
         .route("/api/data/upload", post(data::upload_data))
+        .route("/api/data/upload/inspect", post(data::inspect_upload))
+        .route("/api/data/upload/sheets", post(data::list_upload_sheets))
         .route("/api/data/sources", get(data::list_sources))
         .route("/api/data/sources/:id", delete(data::delete_source))
+        .route("/api/data/sources/:id/upload", post(data::upload_into_source))
+        .route("/api/data/sources/:id/incremental", put(data::set_incremental))
+        .route("/api/data/sources/:id/incremental", delete(data::clear_incremental))
+        .route("/api/data/sources/:id/versions", get(versions::list_versions))
+        .route("/api/data/sources/:id/restore", post(versions::restore_version))
         .route("/api/data/schema/:id", get(data::get_schema))
         .route("/api/data/preview/:id", post(data::preview_data))
+        .route("/api/data/local", post(data::ingest_local))
+        .route("/api/data/watches", get(watches::list_watches))
+        .route("/api/data/watches", post(watches::create_watch))
+        .route("/api/data/watches/:id", delete(watches::delete_watch))
+        .route("/api/data/remote", post(remote::register_remote))
+        .route("/api/data/sources/:id/remote", get(remote::get_remote))
+        .route("/api/data/sources/:id/refresh", post(remote::refresh_source))
+        .route("/api/data/sources/:id/refreshes", get(remote::list_refreshes))
+        .route("/api/data/databases", get(databases::list_databases))
+        .route("/api/data/databases", post(databases::attach_database))
+        .route("/api/data/databases/:id", get(databases::get_database))
+        .route("/api/data/databases/:id", delete(databases::delete_database))
+        .route("/api/data/derived", post(derived::create_derived))
+        .route("/api/data/derived/:id/refresh", post(derived::refresh_derived))
+        
+        // Ingestion job routes
+        .route("/api/jobs", get(jobs::list_jobs))
+        .route("/api/jobs/:id", get(jobs::get_job))
+        .route("/api/jobs/:id/cancel", post(jobs::cancel_job))
         
         // Dashboard routes
         .route("/api/dashboard/configs", get(dashboard::list_configs))
@@ -57,6 +96,13 @@ pub fn create_app() -> Router<AppState> {
         .route("/api/analytics/aggregate", post(analytics::run_aggregation))
         .route("/api/analytics/metrics/:id", get(analytics::get_metrics))
         .route("/api/analytics/export", post(analytics::export_data))
+        .route("/api/analytics/exports/:file", get(analytics::download_export))
+        .route("/api/analytics/statistics", post(analytics::column_statistics))
+        .route("/api/analytics/timeseries", post(analytics::time_series))
+        .route("/api/analytics/outliers", post(analytics::outliers))
+        .route("/api/analytics/correlation", post(analytics::correlation))
+        .route("/api/analytics/quality/:id", get(analytics::data_quality))
+        .route("/api/analytics/moving-average", post(analytics::moving_average))
         
         // System routes
         .route("/api/system/health", get(system::health_check))
@@ -20,9 +20,60 @@ struct Cli {
     #[arg(long, default_value = "dashboard.db")]
     database_path: String,
 
+    /// File holding the key stored credentials are sealed with, created on
+    /// first start; defaults to the database path with `.key` appended
+    #[arg(long)]
+    secret_key_path: Option<String>,
+
     /// Log level
     #[arg(long, default_value = "info")]
     log_level: String,
+
+    /// Maximum query execution time in seconds
+    #[arg(long, default_value = "30")]
+    query_timeout: u64,
+
+    /// Number of pooled reader connections
+    #[arg(long, default_value = "8")]
+    db_pool_size: usize,
+
+    /// Seconds to wait for a free database connection
+    #[arg(long, default_value = "5")]
+    db_checkout_timeout: u64,
+
+    /// Seconds to wait for the database writer, which bulk loads hold until done
+    #[arg(long, default_value = "300")]
+    db_writer_checkout_timeout: u64,
+
+    /// Directory for exported files
+    #[arg(long, default_value = "exports")]
+    exports_dir: String,
+
+    /// Share of CSV rows that may fail to parse before an upload is rejected
+    #[arg(long, default_value = "0")]
+    max_reject_ratio: f64,
+
+    /// Comma-separated directories local files may be ingested and watched
+    /// from; local ingestion is disabled without any
+    #[arg(long, value_delimiter = ',')]
+    local_roots: Vec<String>,
+
+    /// Seconds between scans of watched folders
+    #[arg(long, default_value = "10")]
+    watch_interval: u64,
+
+    /// Snapshot every load of a data source as a version it can be queried at
+    /// and restored to
+    #[arg(long)]
+    versioning: bool,
+
+    /// Versions kept of each data source, 0 keeping all
+    #[arg(long, default_value = "10")]
+    max_versions: usize,
+
+    /// Days data source versions are kept, 0 keeping them until replaced
+    #[arg(long, default_value = "0")]
+    version_max_age_days: i64,
 }
 
 #[tokio::main]
@@ -37,20 +88,95 @@ async fn main() -> anyhow::Result<()> {
     info!("Starting DuckDB Dashboard Backend Server");
 
     // Initialize configuration
-    let config = Config::new(cli.database_path.clone(), cli.host.clone(), cli.port);
+    let mut config = Config::new(cli.database_path.clone(), cli.host.clone(), cli.port);
+    if let Some(secret_key_path) = &cli.secret_key_path {
+        config.secret_key_path = secret_key_path.clone();
+    }
+    config.query_timeout = cli.query_timeout;
+    config.db_pool_size = cli.db_pool_size;
+    config.db_checkout_timeout = cli.db_checkout_timeout;
+    config.db_writer_checkout_timeout = cli.db_writer_checkout_timeout;
+    config.exports_dir = cli.exports_dir.clone();
+    config.max_reject_ratio = cli.max_reject_ratio;
+    config.local_roots = cli.local_roots.clone();
+    config.watch_interval = cli.watch_interval;
+    config.versioning = cli.versioning;
+    config.max_versions = cli.max_versions;
+    config.version_max_age_days = cli.version_max_age_days;
     
     // Initialize database
     database::init(&cli.database_path).await?;
     info!("Database initialized at: {}", cli.database_path);
 
     // Create database pool and file processor
-    let db_pool = database::DatabasePool::new(&cli.database_path)?;
-    let file_processor = duckdb_dashboard_backend::services::file_processor::FileProcessor::new(db_pool.clone());
+    let db_pool = database::DatabasePool::with_options(&cli.database_path, config.pool_options())?;
+    let query_cache = duckdb_dashboard_backend::services::query_cache::QueryCacheService::new(config.cache_ttl);
+    let exports = duckdb_dashboard_backend::services::export::ExportService::new(&config.exports_dir, config.export_ttl)?;
+    exports.spawn_sweeper();
+    let mut file_processor = duckdb_dashboard_backend::services::file_processor::FileProcessor::new(db_pool.clone())
+        .with_reject_files(exports.clone())
+        .with_max_reject_ratio(config.max_reject_ratio);
+    if config.versioning {
+        file_processor = file_processor.with_version_retention(config.version_retention());
+    }
+    let versions = duckdb_dashboard_backend::services::versions::VersionService::new(
+        db_pool.clone(),
+        query_cache.clone(),
+        config.version_retention(),
+    );
+    versions.spawn_sweeper();
+    let jobs = duckdb_dashboard_backend::services::jobs::JobService::new(
+        db_pool.clone(),
+        file_processor.clone(),
+        query_cache.clone(),
+        config.max_unpacked_size,
+    );
+    jobs.start().await?;
+    let watches = duckdb_dashboard_backend::services::folder_watch::WatchService::new(
+        db_pool.clone(),
+        jobs.clone(),
+        config.local_roots.clone(),
+        std::time::Duration::from_secs(config.watch_interval),
+    );
+    watches.spawn();
+    let secrets = duckdb_dashboard_backend::utils::secrets::Secrets::from_key_file(std::path::Path::new(&config.secret_key_path))?;
+    let remote = duckdb_dashboard_backend::services::remote::RemoteService::new(
+        db_pool.clone(),
+        jobs.clone(),
+        secrets.clone(),
+        config.max_upload_size as u64,
+    )?;
+    remote.spawn();
+    let databases = duckdb_dashboard_backend::services::external_db::ExternalDatabaseService::new(
+        db_pool.clone(),
+        file_processor.clone(),
+        query_cache.clone(),
+        secrets,
+        config.local_roots.clone(),
+    );
+    let attached = databases.restore().await?;
+    info!("Attached {} external databases", attached);
+    let derived = duckdb_dashboard_backend::services::derived::DerivedSourceService::new(
+        db_pool.clone(),
+        file_processor.clone(),
+        query_cache.clone(),
+    )
+    .with_query_timeout(std::time::Duration::from_secs(config.query_timeout));
+    derived.spawn();
     
     // Create application state
     let state = AppState {
         db_pool,
         file_processor,
+        query_cache,
+        exports,
+        jobs,
+        watches,
+        remote,
+        databases,
+        versions,
+        derived,
+        config,
     };
 
     // Create the application
@@ -3,9 +3,7 @@ use axum::{
     http::StatusCode,
     middleware::Next,
     response::Response,
-    Json,
 };
-use serde_json::json;
 use tracing::warn;
 
 /// Middleware for validating request content types and sizes
@@ -1,12 +1,14 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 
+use super::filter::Filter;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DashboardConfig {
     pub id: String,
     pub name: String,
     pub layout: Vec<WidgetLayout>,
-    pub filters: Option<serde_json::Value>,
+    pub filters: Option<Filter>,
     pub data_source_id: Option<String>,
     pub refresh_interval: Option<i32>, // in seconds
     pub created_at: DateTime<Utc>,
@@ -154,7 +156,7 @@ pub struct FilterConfig {
 pub struct CreateDashboardRequest {
     pub name: String,
     pub layout: Vec<WidgetLayout>,
-    pub filters: Option<serde_json::Value>,
+    pub filters: Option<Filter>,
     pub data_source_id: Option<String>,
     pub refresh_interval: Option<i32>,
 }
@@ -163,7 +165,7 @@ pub struct CreateDashboardRequest {
 pub struct UpdateDashboardRequest {
     pub name: Option<String>,
     pub layout: Option<Vec<WidgetLayout>>,
-    pub filters: Option<serde_json::Value>,
+    pub filters: Option<Filter>,
     pub data_source_id: Option<String>,
     pub refresh_interval: Option<i32>,
 }
@@ -189,6 +191,12 @@ impl DashboardConfig {
         self
     }
 
+    pub fn with_filters(mut self, filters: Option<Filter>) -> Self {
+        self.filters = filters;
+        self.updated_at = Utc::now();
+        self
+    }
+
     pub fn with_data_source(mut self, data_source_id: String) -> Self {
         self.data_source_id = Some(data_source_id);
         self.updated_at = Utc::now();
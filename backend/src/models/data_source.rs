@@ -1,21 +1,91 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 
+use super::filter::Filter;
+
+/// Type of data sources that query local files in place through a view
+pub const LOCAL_SOURCE_TYPE: &str = "local";
+
+/// Type of data sources that query a table of an attached external database
+/// through a view
+pub const DATABASE_SOURCE_TYPE: &str = "database";
+
+/// Type of data sources defined by a SQL query over other data sources
+pub const DERIVED_SOURCE_TYPE: &str = "derived";
+
+/// DuckDB table or view holding the rows of a data source
+pub fn source_table(data_source_id: &str) -> String {
+    format!("data_source_{}", data_source_id.replace('-', "_"))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataSource {
     pub id: String,
     pub name: String,
     #[serde(rename = "type")]
-    pub r#type: String, // 'file' | 'database' | 'api'
+    pub r#type: String, // 'file' | 'local' | 'database' | 'api' | 'derived'
     pub file_path: Option<String>,
     pub schema: Vec<ColumnSchema>,
     pub row_count: i64,
     pub size_bytes: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>, // SHA-256 of the file the rows were last loaded from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected_records: Option<RejectedRecords>, // records skipped by the last load
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub incremental: Option<IncrementalConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub derived: Option<DerivedConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>, // ids of the data sources a derived source reads
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// SQL defining a derived data source, and how its table is kept current
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DerivedConfig {
+    pub sql: String, // reads other sources as data_source_<id>, like custom queries
+    pub materialized: bool, // stored as a table refreshed from `sql`, rather than a view
+    pub schedule: Option<String>, // cron expression refreshing a materialized source
+    #[serde(default)]
+    pub refresh_on_change: bool, // refresh a materialized source after its dependencies are loaded into
+    pub refreshed_at: Option<DateTime<Utc>>,
+    pub next_refresh_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateDerivedSourceRequest {
+    pub name: String,
+    pub sql: String,
+    #[serde(default)]
+    pub materialized: bool,
+    pub schedule: Option<String>,
+    #[serde(default)]
+    pub refresh_on_change: bool,
+}
+
+/// Appends and upserts into a data source keep only rows whose watermark
+/// column is above the highest value loaded so far
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IncrementalConfig {
+    pub watermark_column: String, // e.g. 'updated_at' or a monotonically increasing id
+    #[serde(default)]
+    pub key_columns: Vec<String>, // refreshes upsert on these when set, and append otherwise
+    pub last_value: Option<String>, // highest watermark loaded, as text
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncrementalConfigRequest {
+    pub watermark_column: String,
+    #[serde(default)]
+    pub key_columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ColumnSchema {
     pub name: String,
     #[serde(rename = "type")]
@@ -23,6 +93,142 @@ pub struct ColumnSchema {
     pub nullable: bool,
     pub unique: bool,
     pub primary_key: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>, // share of sampled values matching the inferred type
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub overridden: bool, // type chosen by the user; confidence stays that of the inferred type
+}
+
+/// How an upload into an existing data source combines with its rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadMode {
+    Append,
+    Replace,
+    /// Replace rows whose key columns match an uploaded row, append the rest
+    Upsert,
+}
+
+/// Whether the files of an archive upload become one data source or one each
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveMode {
+    Combine,
+    #[default]
+    Separate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadResult {
+    pub data_source: DataSource,
+    pub mode: LoadMode,
+    pub rows_loaded: i64,
+    pub rows_removed: i64, // existing rows dropped by replace or upsert
+    #[serde(default)]
+    pub rows_skipped: i64, // rows at or below the incremental watermark
+    #[serde(default)]
+    pub rows_without_watermark: i64, // rows dropped for lacking a watermark value
+    pub added_columns: Vec<String>,
+    pub widened_columns: Vec<String>,
+    #[serde(default)]
+    pub version: Option<i64>, // version of the data source the load created
+}
+
+/// How nested JSON objects are loaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonNesting {
+    /// Keep nested objects and arrays as STRUCT and LIST columns
+    #[default]
+    Nested,
+    /// Expand nested objects into `parent_child` columns; arrays stay LISTs
+    Flatten,
+}
+
+/// CSV rows and JSON records that could not be loaded, with the first few
+/// errors
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RejectedRecords {
+    pub count: usize,
+    #[serde(default)]
+    pub total: usize, // records read, rejected or not
+    pub samples: Vec<RejectedRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>, // download URLs listing every rejected CSV row, kept until the source is deleted
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RejectedRecord {
+    pub file: String,
+    pub record: usize, // line of a CSV or newline-delimited file, or position in an array, from 1
+    pub error: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>, // the offending value as written in the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>, // the whole line as written in the file
+}
+
+/// Whether local files are loaded into a table or queried where they are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalMode {
+    #[default]
+    Copy,
+    /// Register a view over the files, so queries always read their current
+    /// contents
+    InPlace,
+}
+
+/// Local files on the server to load as a data source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalSourceRequest {
+    pub path: String, // a file or glob, e.g. '/data/drops/*.parquet'
+    pub name: Option<String>, // defaults to the path
+    #[serde(default)]
+    pub mode: LocalMode,
+    pub data_source_id: Option<String>, // copy into an existing source instead
+    pub load_mode: Option<LoadMode>,
+    #[serde(default)]
+    pub key_columns: Vec<String>,
+    #[serde(default)]
+    pub column_types: HashMap<String, String>,
+    pub max_reject_ratio: Option<f64>,
+}
+
+/// Which part of an Excel workbook to load
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct XlsxOptions {
+    pub sheet: Option<String>, // defaults to the first sheet
+    pub range: Option<String>, // e.g. 'B2:F200', or 'B2' to read to the end of the sheet
+    pub header: Option<bool>, // detected when unset
+    #[serde(default)]
+    pub all_sheets: bool, // one data source per sheet
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SheetInfo {
+    pub name: String,
+    pub rows: usize,
+    pub columns: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsvDialect {
+    pub delimiter: String,
+    pub quote: String,
+    pub escape: String,
+    pub has_header: bool,
+    pub date_format: Option<String>,
+    pub timestamp_format: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsvInspection {
+    pub dialect: CsvDialect,
+    pub columns: Vec<ColumnSchema>,
+    pub sample_size: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,12 +242,14 @@ pub struct CreateDataSourceRequest {
 pub struct DataPreviewRequest {
     pub limit: Option<usize>,
     pub offset: Option<usize>,
-    pub filters: Option<serde_json::Value>,
+    pub filters: Option<Filter>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataPreviewResponse {
     pub columns: Vec<String>,
+    #[serde(default)]
+    pub column_types: Vec<String>,
     pub data: Vec<Vec<serde_json::Value>>,
     pub total_rows: i64,
     pub preview_rows: usize,
@@ -58,11 +266,39 @@ impl DataSource {
             schema: Vec::new(),
             row_count: 0,
             size_bytes: 0,
+            content_hash: None,
+            rejected_records: None,
+            incremental: None,
+            derived: None,
+            dependencies: Vec::new(),
             created_at: now,
             updated_at: now,
         }
     }
 
+    /// Whether the source is a view over data stored elsewhere, which cannot
+    /// be loaded into
+    pub fn is_view(&self) -> bool {
+        self.r#type == LOCAL_SOURCE_TYPE
+            || self.r#type == DATABASE_SOURCE_TYPE
+            || self.derived.as_ref().is_some_and(|derived| !derived.materialized)
+    }
+
+    pub fn is_derived(&self) -> bool {
+        self.r#type == DERIVED_SOURCE_TYPE
+    }
+
+    /// Why files cannot be loaded into the source, if they cannot
+    pub fn load_refusal(&self) -> Option<String> {
+        if self.is_derived() {
+            Some(format!("Data source {} is defined by SQL and cannot be loaded into", self.id))
+        } else if self.is_view() {
+            Some(format!("Data source {} is queried in place and cannot be loaded into", self.id))
+        } else {
+            None
+        }
+    }
+
     pub fn with_file_path(mut self, file_path: String) -> Self {
         self.file_path = Some(file_path);
         self
@@ -73,6 +309,11 @@ impl DataSource {
         self
     }
 
+    pub fn with_content_hash(mut self, content_hash: String) -> Self {
+        self.content_hash = Some(content_hash);
+        self
+    }
+
     pub fn with_stats(mut self, row_count: i64, size_bytes: i64) -> Self {
         self.row_count = row_count;
         self.size_bytes = size_bytes;
@@ -81,6 +322,69 @@ impl DataSource {
     }
 }
 
+impl LoadResult {
+    /// Fold in the result of loading a further file into the same source
+    pub fn merge(mut self, next: LoadResult) -> Self {
+        let rejected = self.data_source.rejected_records.take();
+        self.data_source = next.data_source;
+        self.data_source.rejected_records = RejectedRecords::combine(rejected, self.data_source.rejected_records.take());
+        self.rows_loaded += next.rows_loaded;
+        self.rows_removed += next.rows_removed;
+        self.rows_skipped += next.rows_skipped;
+        self.rows_without_watermark += next.rows_without_watermark;
+        self.version = next.version;
+        for column in next.added_columns {
+            if !self.added_columns.contains(&column) {
+                self.added_columns.push(column);
+            }
+        }
+        for column in next.widened_columns {
+            if !self.widened_columns.contains(&column) {
+                self.widened_columns.push(column);
+            }
+        }
+        self
+    }
+}
+
+impl RejectedRecords {
+    /// Most rejected records kept as samples
+    pub const MAX_SAMPLES: usize = 100;
+
+    pub fn push(&mut self, record: RejectedRecord) {
+        self.count += 1;
+        if self.samples.len() < Self::MAX_SAMPLES {
+            self.samples.push(record);
+        }
+    }
+
+    /// Share of the records read that were rejected
+    pub fn ratio(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.count as f64 / self.total as f64
+        }
+    }
+
+    /// Rejections of two files loaded into the same source
+    pub fn combine(first: Option<Self>, second: Option<Self>) -> Option<Self> {
+        match (first, second) {
+            (Some(mut first), Some(second)) => {
+                let count = first.count + second.count;
+                for record in second.samples {
+                    first.push(record);
+                }
+                first.count = count;
+                first.total += second.total;
+                first.files.extend(second.files);
+                Some(first)
+            }
+            (first, second) => first.or(second),
+        }
+    }
+}
+
 impl ColumnSchema {
     pub fn new(name: String, r#type: String) -> Self {
         Self {
@@ -89,6 +393,8 @@ impl ColumnSchema {
             nullable: true,
             unique: false,
             primary_key: false,
+            confidence: None,
+            overridden: false,
         }
     }
 
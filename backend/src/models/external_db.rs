@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+use crate::utils::{error::AppResult, secrets::Secrets};
+
+/// Replaces secrets in responses
+const REDACTED: &str = "********";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DatabaseKind {
+    Sqlite,
+    Duckdb,
+    Postgres,
+}
+
+/// A database attached read-only to DuckDB, each of whose tables is
+/// registered as a data source. The password is stored sealed, for
+/// attaching again on restart, and redacted in responses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalDatabase {
+    pub id: String,
+    pub name: String,
+    pub kind: DatabaseKind,
+    pub path: Option<String>, // SQLite or DuckDB file under the server's --local-roots
+    pub postgres: Option<PostgresConnection>,
+    #[serde(default)]
+    pub tables: Vec<ExternalTable>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostgresConnection {
+    pub host: String,
+    pub port: Option<u16>,
+    pub dbname: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub sslmode: Option<String>,
+}
+
+/// A table of an external database and the data source querying it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalTable {
+    pub data_source_id: String,
+    pub schema: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachDatabaseRequest {
+    pub name: Option<String>,
+    pub kind: DatabaseKind,
+    pub path: Option<String>,
+    pub postgres: Option<PostgresConnection>,
+    #[serde(default)]
+    pub tables: Vec<String>, // 'table' or 'schema.table'; every table when empty
+}
+
+impl ExternalDatabase {
+    pub fn new(name: String, kind: DatabaseKind, path: Option<String>, postgres: Option<PostgresConnection>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            kind,
+            path,
+            postgres,
+            tables: Vec::new(),
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Catalog name the database is attached under
+    pub fn alias(&self) -> String {
+        format!("external_{}", self.id.replace('-', "_"))
+    }
+
+    /// Where the database is, without credentials
+    pub fn location(&self) -> String {
+        match (&self.path, &self.postgres) {
+            (Some(path), _) => path.clone(),
+            (None, Some(postgres)) => {
+                let user = postgres.user.as_ref().map(|user| format!("{}@", user)).unwrap_or_default();
+                let port = postgres.port.map(|port| format!(":{}", port)).unwrap_or_default();
+                format!("postgres://{}{}{}/{}", user, postgres.host, port, postgres.dbname)
+            }
+            (None, None) => String::new(),
+        }
+    }
+
+    /// Copy safe to return to clients
+    pub fn redacted(&self) -> Self {
+        let mut database = self.clone();
+        if let Some(password) = database.postgres.as_mut().and_then(|postgres| postgres.password.as_mut()) {
+            *password = REDACTED.to_string();
+        }
+        database
+    }
+
+    /// Copy to store, with the password sealed
+    pub fn sealed(&self, secrets: &Secrets) -> AppResult<Self> {
+        self.map_password(|password| secrets.seal(password))
+    }
+
+    /// Copy of a stored database, with its password opened again
+    pub fn opened(&self, secrets: &Secrets) -> AppResult<Self> {
+        self.map_password(|password| secrets.open(password))
+    }
+
+    fn map_password(&self, map: impl Fn(&str) -> AppResult<String>) -> AppResult<Self> {
+        let mut database = self.clone();
+        if let Some(password) = database.postgres.as_mut().and_then(|postgres| postgres.password.as_mut()) {
+            *password = map(password)?;
+        }
+        Ok(database)
+    }
+}
+
+impl PostgresConnection {
+    /// libpq connection string, as taken by DuckDB's postgres extension
+    pub fn conninfo(&self) -> String {
+        let mut parts = vec![
+            format!("host={}", libpq_value(&self.host)),
+            format!("dbname={}", libpq_value(&self.dbname)),
+        ];
+        if let Some(port) = self.port {
+            parts.push(format!("port={}", port));
+        }
+        for (key, value) in [("user", &self.user), ("password", &self.password), ("sslmode", &self.sslmode)] {
+            if let Some(value) = value {
+                parts.push(format!("{}={}", key, libpq_value(value)));
+            }
+        }
+        parts.join(" ")
+    }
+}
+
+/// Quote a libpq connection string value
+fn libpq_value(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_postgres_conninfo_and_redaction() {
+        let postgres = PostgresConnection {
+            host: "db.internal".to_string(),
+            port: Some(5432),
+            dbname: "ops".to_string(),
+            user: Some("reader".to_string()),
+            password: Some("it's secret".to_string()),
+            sslmode: None,
+        };
+        assert_eq!(
+            postgres.conninfo(),
+            "host='db.internal' dbname='ops' port=5432 user='reader' password='it\\'s secret'"
+        );
+
+        let database = ExternalDatabase::new("ops".to_string(), DatabaseKind::Postgres, None, Some(postgres));
+        assert_eq!(database.location(), "postgres://reader@db.internal:5432/ops");
+        let redacted = database.redacted();
+        assert_eq!(redacted.postgres.unwrap().password.as_deref(), Some(REDACTED));
+
+        let secrets = Secrets::ephemeral();
+        let sealed = database.sealed(&secrets).unwrap();
+        assert!(!sealed.postgres.as_ref().unwrap().password.as_deref().unwrap().contains("secret"));
+        let opened = sealed.opened(&secrets).unwrap();
+        assert_eq!(opened.postgres.unwrap().password.as_deref(), Some("it's secret"));
+    }
+}
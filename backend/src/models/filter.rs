@@ -0,0 +1,255 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value as JsonValue;
+
+/// Row filter shared by data preview, aggregation, export and dashboards.
+///
+/// Conditions are written as `{"field": "region", "op": "eq", "value": "EU"}`
+/// and combined with `{"and": [...]}`, `{"or": [...]}` and `{"not": {...}}`.
+/// Two legacy forms are still accepted and converted: a plain object such as
+/// `{"region": "EU", "year": 2024}`, where string values match as substrings
+/// and other values by equality, and a list of
+/// `{"column": ..., "operator": "equals", "value": ...}` conditions; both are
+/// combined with AND.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Filter {
+    And { and: Vec<Filter> },
+    Or { or: Vec<Filter> },
+    Not { not: Box<Filter> },
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    pub field: String,
+    pub op: FilterOp,
+    /// Operand; a list for `in`, a `[low, high]` pair for `between` and a
+    /// [`RelativeRange`] for `relative`. Values may use the typed parameter
+    /// form `{"type": "date", "value": "2024-01-31"}`.
+    #[serde(default, skip_serializing_if = "JsonValue::is_null")]
+    pub value: JsonValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterOp {
+    Eq,
+    Neq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    In,
+    Between,
+    Like,
+    IsNull,
+    IsNotNull,
+    Relative,
+}
+
+/// Date range relative to now: the last `last` units, or the current unit
+/// (e.g. this month) when `last` is omitted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelativeRange {
+    pub last: Option<i64>,
+    pub unit: DateUnit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DateUnit {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl DateUnit {
+    /// Name accepted by DuckDB's `date_trunc` and interval syntax
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DateUnit::Hour => "hour",
+            DateUnit::Day => "day",
+            DateUnit::Week => "week",
+            DateUnit::Month => "month",
+            DateUnit::Quarter => "quarter",
+            DateUnit::Year => "year",
+        }
+    }
+}
+
+impl Filter {
+    pub fn condition(field: impl Into<String>, op: FilterOp, value: JsonValue) -> Self {
+        Filter::Condition(Condition {
+            field: field.into(),
+            op,
+            value,
+        })
+    }
+
+    /// Parse the structured form, falling back to the legacy forms
+    pub fn from_json(value: JsonValue) -> Result<Self, String> {
+        let mut map = match value {
+            JsonValue::Object(map) => map,
+            JsonValue::Array(items) => {
+                let and = items.into_iter().map(legacy_condition).collect::<Result<_, _>>()?;
+                return Ok(Filter::And { and });
+            }
+            _ => return Err("Filter must be a JSON object".to_string()),
+        };
+
+        if map.len() == 1 {
+            if let Some(items) = map.remove("and") {
+                return Ok(Filter::And { and: parse_list("and", items)? });
+            }
+            if let Some(items) = map.remove("or") {
+                return Ok(Filter::Or { or: parse_list("or", items)? });
+            }
+            if let Some(inner) = map.remove("not") {
+                return Ok(Filter::Not { not: Box::new(Filter::from_json(inner)?) });
+            }
+        }
+
+        if map.contains_key("field") || map.contains_key("op") {
+            return serde_json::from_value(JsonValue::Object(map))
+                .map(Filter::Condition)
+                .map_err(|e| format!("Invalid filter condition: {}", e));
+        }
+
+        let conditions = map
+            .into_iter()
+            .map(|(field, value)| match value {
+                JsonValue::String(s) => Filter::condition(field, FilterOp::Like, JsonValue::String(format!("%{}%", s))),
+                other => Filter::condition(field, FilterOp::Eq, other),
+            })
+            .collect();
+        Ok(Filter::And { and: conditions })
+    }
+}
+
+/// Condition of the legacy list form
+#[derive(Deserialize)]
+struct LegacyCondition {
+    column: String,
+    operator: String,
+    #[serde(default)]
+    value: JsonValue,
+    values: Option<Vec<JsonValue>>,
+}
+
+fn legacy_condition(item: JsonValue) -> Result<Filter, String> {
+    let LegacyCondition { column, operator, value, values } =
+        serde_json::from_value(item).map_err(|e| format!("Invalid filter condition: {}", e))?;
+    let list = || values.clone().map(JsonValue::Array).unwrap_or_else(|| value.clone());
+    let pattern = |prefix: &str, suffix: &str| {
+        let text = match &value {
+            JsonValue::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        JsonValue::String(format!("{}{}{}", prefix, text, suffix))
+    };
+    let not = |filter: Filter| Filter::Not { not: Box::new(filter) };
+
+    Ok(match operator.as_str() {
+        "equals" => Filter::condition(column, FilterOp::Eq, value),
+        "not_equals" => Filter::condition(column, FilterOp::Neq, value),
+        "greater_than" => Filter::condition(column, FilterOp::Gt, value),
+        "greater_than_or_equal" => Filter::condition(column, FilterOp::Gte, value),
+        "less_than" => Filter::condition(column, FilterOp::Lt, value),
+        "less_than_or_equal" => Filter::condition(column, FilterOp::Lte, value),
+        "contains" => Filter::condition(column, FilterOp::Like, pattern("%", "%")),
+        "not_contains" => not(Filter::condition(column, FilterOp::Like, pattern("%", "%"))),
+        "starts_with" => Filter::condition(column, FilterOp::Like, pattern("", "%")),
+        "ends_with" => Filter::condition(column, FilterOp::Like, pattern("%", "")),
+        "in" => Filter::condition(column, FilterOp::In, list()),
+        "not_in" => not(Filter::condition(column, FilterOp::In, list())),
+        "between" => Filter::condition(column, FilterOp::Between, list()),
+        "is_null" => Filter::condition(column, FilterOp::IsNull, JsonValue::Null),
+        "is_not_null" => Filter::condition(column, FilterOp::IsNotNull, JsonValue::Null),
+        other => return Err(format!("Unsupported filter operator: {}", other)),
+    })
+}
+
+fn parse_list(key: &str, items: JsonValue) -> Result<Vec<Filter>, String> {
+    match items {
+        JsonValue::Array(items) => items.into_iter().map(Filter::from_json).collect(),
+        _ => Err(format!("\"{}\" must be a list of filters", key)),
+    }
+}
+
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Filter::from_json(JsonValue::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_structured_filter() {
+        let filter: Filter = serde_json::from_value(json!({
+            "or": [
+                {"field": "region", "op": "in", "value": ["EU", "US"]},
+                {"not": {"field": "closed_at", "op": "is_null"}}
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            filter,
+            Filter::Or {
+                or: vec![
+                    Filter::condition("region", FilterOp::In, json!(["EU", "US"])),
+                    Filter::Not { not: Box::new(Filter::condition("closed_at", FilterOp::IsNull, JsonValue::Null)) },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_legacy_filter() {
+        let filter: Filter = serde_json::from_value(json!({"region": "EU", "year": 2024})).unwrap();
+        assert_eq!(
+            filter,
+            Filter::And {
+                and: vec![
+                    Filter::condition("region", FilterOp::Like, json!("%EU%")),
+                    Filter::condition("year", FilterOp::Eq, json!(2024)),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_legacy_condition_list() {
+        let filter: Filter = serde_json::from_value(json!([
+            {"column": "region", "operator": "not_in", "value": null, "values": ["EU", "US"]},
+            {"column": "name", "operator": "starts_with", "value": "Ac"}
+        ]))
+        .unwrap();
+        assert_eq!(
+            filter,
+            Filter::And {
+                and: vec![
+                    Filter::Not { not: Box::new(Filter::condition("region", FilterOp::In, json!(["EU", "US"]))) },
+                    Filter::condition("name", FilterOp::Like, json!("Ac%")),
+                ]
+            }
+        );
+
+        let result: Result<Filter, _> = serde_json::from_value(json!([{"column": "x", "operator": "regex", "value": "a+"}]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_invalid_condition() {
+        let result: Result<Filter, _> = serde_json::from_value(json!({"field": "x", "op": "matches"}));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// Lifecycle of an ingestion job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// Background load of an uploaded file into new data sources, or into an
+/// existing one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestJob {
+    pub id: String,
+    pub file_name: String,
+    pub size_bytes: i64,
+    pub data_source_id: Option<String>, // target of an upload into an existing source
+    pub status: JobStatus,
+    pub files_total: usize, // data files after unpacking, known once running
+    pub files_done: usize,
+    #[serde(default)]
+    pub bytes_total: u64, // size of the data files after unpacking
+    #[serde(default)]
+    pub bytes_done: u64,
+    pub progress: f64, // share of the data files' bytes loaded, from 0 to 1
+    pub rows_loaded: i64,
+    pub error: Option<String>,
+    pub result: Option<serde_json::Value>, // the upload response: DataSource, DataSource[] or LoadResult
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Cancelled)
+    }
+}
+
+impl IngestJob {
+    pub fn new(file_name: String, size_bytes: i64, data_source_id: Option<String>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            file_name,
+            size_bytes,
+            data_source_id,
+            status: JobStatus::Queued,
+            files_total: 0,
+            files_done: 0,
+            bytes_total: 0,
+            bytes_done: 0,
+            progress: 0.0,
+            rows_loaded: 0,
+            error: None,
+            result: None,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+        }
+    }
+
+    /// Count a loaded data file of `bytes` towards the job's progress, so a
+    /// large file moves it further than a small one
+    pub fn file_loaded(&mut self, rows: i64, bytes: u64) {
+        self.files_done += 1;
+        self.bytes_done += bytes;
+        self.rows_loaded += rows;
+        if self.bytes_total > 0 {
+            self.progress = (self.bytes_done as f64 / self.bytes_total as f64).min(1.0);
+        } else if self.files_total > 0 {
+            self.progress = (self.files_done as f64 / self.files_total as f64).min(1.0);
+        }
+    }
+
+    pub fn finish(&mut self, status: JobStatus, error: Option<String>) {
+        if status == JobStatus::Done {
+            self.progress = 1.0;
+        }
+        self.status = status;
+        self.error = error;
+        self.finished_at = Some(Utc::now());
+    }
+}
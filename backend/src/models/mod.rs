@@ -1,7 +1,19 @@
 pub mod data_source;
 pub mod dashboard;
+pub mod external_db;
+pub mod filter;
+pub mod job;
 pub mod query;
+pub mod remote;
+pub mod version;
+pub mod watch;
 
 pub use data_source::*;
 pub use dashboard::*;
-pub use query::*;
\ No newline at end of file
+pub use external_db::*;
+pub use filter::*;
+pub use job::*;
+pub use query::*;
+pub use remote::*;
+pub use version::*;
+pub use watch::*;
\ No newline at end of file
@@ -1,19 +1,29 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 
+use super::filter::{DateUnit, Filter};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryRequest {
     pub sql: String,
     pub data_source_id: Option<String>,
     pub params: Option<serde_json::Value>,
     pub cache: Option<bool>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>, // may only shorten the server's query timeout
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryResult {
     pub columns: Vec<String>,
+    #[serde(default)]
+    pub column_types: Vec<String>, // DuckDB type names, e.g. 'TIMESTAMP' | 'DECIMAL(10,2)' | 'VARCHAR[]'
     pub data: Vec<Vec<serde_json::Value>>,
     pub row_count: usize,
+    #[serde(default)]
+    pub truncated: bool, // more rows existed than the requested limit
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,17 +31,54 @@ pub struct AggregationRequest {
     pub data_source_id: String,
     pub operations: Vec<AggregationOperation>,
     pub group_by: Option<Vec<String>>,
-    pub filters: Option<serde_json::Value>,
+    pub time_bucket: Option<TimeBucket>,
+    pub filters: Option<Filter>,
+    pub having: Option<Filter>, // applied to group keys and operation aliases
+    pub order_by: Option<Vec<OrderBy>>,
+    pub totals: Option<GroupTotals>,
     pub limit: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AggregationOperation {
+    pub field: String, // '*' is accepted for 'count'
+    pub operation: String, // 'sum' | 'avg' | 'count' | 'min' | 'max' | 'distinct_count' | 'median' | 'percentile' | 'stddev' | 'approx_count_distinct'
+    pub alias: Option<String>,
+    pub percentile: Option<f64>, // 0.0..=1.0, required for 'percentile'
+}
+
+/// Group key truncating a date or timestamp column to `interval`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeBucket {
     pub field: String,
-    pub operation: String, // 'sum' | 'avg' | 'count' | 'min' | 'max' | 'distinct_count'
+    pub interval: DateUnit,
     pub alias: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBy {
+    pub field: String, // group key or operation alias
+    #[serde(default)]
+    pub direction: SortDirection,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Subtotal rows added to a grouped aggregation. Subtotal rows have NULL in
+/// the rolled-up keys and a non-zero `grouping_id` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupTotals {
+    Rollup,
+    Cube,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AggregationResult {
     pub columns: Vec<String>,
@@ -44,7 +91,7 @@ pub struct AggregationResult {
 pub struct AggregationSummary {
     pub field: String,
     pub operation: String,
-    pub result: serde_json::Value,
+    pub result: serde_json::Value, // grand total over all filtered rows
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,7 +99,7 @@ pub struct ExportRequest {
     pub data_source_id: Option<String>,
     pub query: Option<String>,
     pub format: String, // 'csv' | 'json' | 'parquet'
-    pub filters: Option<serde_json::Value>,
+    pub filters: Option<Filter>,
     pub columns: Option<Vec<String>>,
 }
 
@@ -93,6 +140,74 @@ pub struct MetricValue {
     pub unit: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatisticsRequest {
+    pub data_source_id: String,
+    pub column: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatisticsResult {
+    pub data_source_id: String,
+    pub column: String,
+    pub statistics: HashMap<String, f64>, // count, mean, min, max, std_dev, median, q1, q3
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeSeriesRequest {
+    pub data_source_id: String,
+    pub time_column: String,
+    pub value_column: String,
+    pub interval: String, // 'hour' | 'day' | 'week' | 'month'
+    pub aggregation: String, // 'sum' | 'avg' | 'count' | 'min' | 'max'
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutlierRequest {
+    pub data_source_id: String,
+    pub column: String,
+    pub method: String, // 'iqr' | 'zscore'
+    pub threshold: Option<f64>, // z-score threshold, defaults to 3.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorrelationRequest {
+    pub data_source_id: String,
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorrelationResult {
+    pub data_source_id: String,
+    pub matrix: HashMap<String, HashMap<String, f64>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovingAverageRequest {
+    pub data_source_id: String,
+    pub value_column: String,
+    pub order_column: String,
+    pub window_size: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataQualityReport {
+    pub data_source_id: String,
+    pub columns: Vec<DataQualityMetric>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataQualityMetric {
+    pub column_name: String,
+    pub data_type: String,
+    pub total_rows: i64,
+    pub null_count: i64,
+    pub null_percentage: f64,
+    pub unique_count: Option<i64>,
+    pub min_length: Option<i32>,
+    pub max_length: Option<i32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryCache {
     pub id: String,
@@ -117,16 +232,25 @@ impl QueryResult {
         let row_count = data.len();
         Self {
             columns,
+            column_types: Vec::new(),
             data,
             row_count,
+            truncated: false,
         }
     }
 
+    pub fn with_column_types(mut self, column_types: Vec<String>) -> Self {
+        self.column_types = column_types;
+        self
+    }
+
     pub fn empty() -> Self {
         Self {
             columns: Vec::new(),
+            column_types: Vec::new(),
             data: Vec::new(),
             row_count: 0,
+            truncated: false,
         }
     }
 
@@ -135,12 +259,19 @@ impl QueryResult {
     }
 }
 
+impl QueryRequest {
+    pub fn timeout(&self) -> Option<std::time::Duration> {
+        self.timeout_ms.map(std::time::Duration::from_millis)
+    }
+}
+
 impl AggregationOperation {
     pub fn new(field: String, operation: String) -> Self {
         Self {
             field,
             operation,
             alias: None,
+            percentile: None,
         }
     }
 
@@ -151,7 +282,16 @@ impl AggregationOperation {
 
     pub fn get_alias(&self) -> String {
         self.alias.clone().unwrap_or_else(|| {
-            format!("{}_{}", self.operation, self.field)
+            let field = if self.field == "*" { "all" } else { &self.field };
+            format!("{}_{}", self.operation, field)
+        })
+    }
+}
+
+impl TimeBucket {
+    pub fn get_alias(&self) -> String {
+        self.alias.clone().unwrap_or_else(|| {
+            format!("{}_{}", self.field, self.interval.as_str())
         })
     }
 }
@@ -251,6 +391,7 @@ mod tests {
             data_source_id: Some("source-1".to_string()),
             params: None,
             cache: Some(true),
+            timeout_ms: None,
         };
 
         let json = serde_json::to_string(&request).unwrap();
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+use crate::utils::{error::AppResult, secrets::Secrets};
+
+/// Type of data sources loaded from an HTTP(S) URL
+pub const REMOTE_SOURCE_TYPE: &str = "api";
+
+/// Replaces secrets in responses
+const REDACTED: &str = "********";
+
+/// Where a remote data source is fetched from and when it is refreshed.
+/// Header values and passwords are stored sealed and redacted in responses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteSource {
+    pub data_source_id: String,
+    pub url: String,
+    pub format: Option<String>, // 'csv' | 'json' | 'ndjson' | 'parquet'; detected from the URL or Content-Type when unset
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub basic_auth: Option<BasicAuth>,
+    pub schedule: Option<String>, // cron expression, e.g. '0 */6 * * *'; refreshed only on demand when unset
+    #[serde(default)]
+    pub watermark_param: Option<String>, // query parameter sent with the incremental watermark, e.g. 'since'
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_hash: Option<String>, // SHA-256 of the last payload loaded
+    pub next_refresh_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateRemoteSourceRequest {
+    pub url: String,
+    pub name: Option<String>,
+    pub format: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub basic_auth: Option<BasicAuth>,
+    pub schedule: Option<String>,
+    #[serde(default)]
+    pub watermark_param: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshStatus {
+    /// A new payload was loaded
+    Updated,
+    /// The server answered 304, or sent the payload already loaded
+    Unchanged,
+    Failed,
+}
+
+/// One fetch of a remote data source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceRefresh {
+    pub id: String,
+    pub data_source_id: String,
+    pub status: RefreshStatus,
+    pub http_status: Option<u16>,
+    pub rows_loaded: i64,
+    #[serde(default)]
+    pub rows_removed: i64, // existing rows replaced
+    #[serde(default)]
+    pub rows_skipped: i64, // rows at or below the incremental watermark
+    #[serde(default)]
+    pub rows_without_watermark: i64, // rows dropped for lacking a watermark value
+    pub watermark: Option<String>, // incremental watermark after the refresh
+    pub size_bytes: i64,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl RemoteSource {
+    /// Copy safe to return to clients
+    pub fn redacted(&self) -> Self {
+        let mut remote = self.clone();
+        for value in remote.headers.values_mut() {
+            *value = REDACTED.to_string();
+        }
+        if let Some(password) = remote.basic_auth.as_mut().and_then(|auth| auth.password.as_mut()) {
+            *password = REDACTED.to_string();
+        }
+        remote
+    }
+
+    /// Copy to store, with header values and the password sealed
+    pub fn sealed(&self, secrets: &Secrets) -> AppResult<Self> {
+        self.map_secrets(|value| secrets.seal(value))
+    }
+
+    /// Copy of a stored source, with its secrets opened again
+    pub fn opened(&self, secrets: &Secrets) -> AppResult<Self> {
+        self.map_secrets(|value| secrets.open(value))
+    }
+
+    fn map_secrets(&self, map: impl Fn(&str) -> AppResult<String>) -> AppResult<Self> {
+        let mut remote = self.clone();
+        for value in remote.headers.values_mut() {
+            *value = map(value)?;
+        }
+        if let Some(password) = remote.basic_auth.as_mut().and_then(|auth| auth.password.as_mut()) {
+            *password = map(password)?;
+        }
+        Ok(remote)
+    }
+}
+
+impl SourceRefresh {
+    pub fn new(data_source_id: String) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            data_source_id,
+            status: RefreshStatus::Failed,
+            http_status: None,
+            rows_loaded: 0,
+            rows_removed: 0,
+            rows_skipped: 0,
+            rows_without_watermark: 0,
+            watermark: None,
+            size_bytes: 0,
+            error: None,
+            started_at: Utc::now(),
+            finished_at: None,
+        }
+    }
+}
+
+impl RefreshStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefreshStatus::Updated => "updated",
+            RefreshStatus::Unchanged => "unchanged",
+            RefreshStatus::Failed => "failed",
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+use super::data_source::{source_table, ColumnSchema, DataSource};
+
+/// Snapshot of a data source's rows taken after a load, kept in its own
+/// table. Versions outlive the data source, so deleted sources can still be
+/// queried as they were, and restored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceVersion {
+    pub data_source_id: String,
+    pub version: i64, // 1 for the first load, increasing by one per load
+    pub name: String,
+    #[serde(rename = "type")]
+    pub r#type: String,
+    pub file_path: Option<String>,
+    pub schema: Vec<ColumnSchema>,
+    pub row_count: i64,
+    pub size_bytes: i64,
+    pub table_name: String, // what custom SQL reads the snapshot as
+    pub restored_from: Option<i64>, // version restored to create this one
+    pub created_at: DateTime<Utc>,
+}
+
+/// Restore a data source to a `version`, or to the version current `at` a
+/// point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreVersionRequest {
+    pub version: Option<i64>,
+    pub at: Option<String>, // RFC 3339 timestamp or 'YYYY-MM-DD', in UTC
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreVersionResponse {
+    pub data_source: DataSource,
+    pub version: SourceVersion,
+}
+
+impl SourceVersion {
+    pub fn new(data_source: &DataSource, version: i64, restored_from: Option<i64>) -> Self {
+        Self {
+            data_source_id: data_source.id.clone(),
+            version,
+            name: data_source.name.clone(),
+            r#type: data_source.r#type.clone(),
+            file_path: data_source.file_path.clone(),
+            schema: data_source.schema.clone(),
+            row_count: data_source.row_count,
+            size_bytes: data_source.size_bytes,
+            table_name: version_table(&data_source.id, version),
+            restored_from,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Data source the version was taken of, as registered then
+    pub fn data_source(&self) -> DataSource {
+        let mut data_source = DataSource::new(self.data_source_id.clone(), self.name.clone(), self.r#type.clone())
+            .with_schema(self.schema.clone())
+            .with_stats(self.row_count, self.size_bytes);
+        data_source.file_path = self.file_path.clone();
+        data_source
+    }
+}
+
+/// DuckDB table holding a version of a data source
+pub fn version_table(data_source_id: &str, version: i64) -> String {
+    format!("{}_v{}", source_table(data_source_id), version)
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// Local files polled for new arrivals, each loaded into a data source once
+/// it stops changing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderWatch {
+    pub id: String,
+    pub path: String, // glob of the files to load, e.g. '/data/drops/*.csv'
+    pub data_source_id: Option<String>, // created from the first new file when unset
+    pub name: Option<String>, // name of the data source created from the first new file
+    pub files_ingested: usize,
+    pub last_ingested_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>, // of the most recent file that failed to load
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateWatchRequest {
+    pub path: String,
+    pub data_source_id: Option<String>,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub include_existing: bool, // also load files already present, instead of only new ones
+}
+
+impl FolderWatch {
+    pub fn new(path: String, data_source_id: Option<String>, name: Option<String>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            path,
+            data_source_id,
+            name,
+            files_ingested: 0,
+            last_ingested_at: None,
+            last_error: None,
+            created_at: Utc::now(),
+        }
+    }
+}
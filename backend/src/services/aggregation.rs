@@ -0,0 +1,271 @@
+use serde_json::Value as JsonValue;
+
+use crate::{
+    database::filter::{compile_filter, quote_ident},
+    models::{AggregationOperation, AggregationRequest, GroupTotals, SortDirection},
+    utils::error::{AppError, AppResult},
+};
+
+/// SQL generated for an [`AggregationRequest`]
+#[derive(Debug, Clone)]
+pub struct AggregationQuery {
+    /// Grouped rows, with `having`, `order_by` and `limit` applied
+    pub sql: String,
+    pub params: JsonValue,
+    /// One row holding every operation over all filtered rows
+    pub totals_sql: String,
+    pub totals_params: JsonValue,
+}
+
+/// Source columns referenced by the request, for validation against the
+/// data source schema
+pub fn referenced_columns(request: &AggregationRequest) -> Vec<&str> {
+    let mut columns: Vec<&str> = request
+        .operations
+        .iter()
+        .map(|op| op.field.as_str())
+        .filter(|field| *field != "*")
+        .collect();
+    columns.extend(request.group_by.iter().flatten().map(|g| g.as_str()));
+    columns.extend(request.time_bucket.iter().map(|b| b.field.as_str()));
+    columns
+}
+
+/// Build the grouped query and the grand totals query for `request`.
+///
+/// The grouped query aggregates in a subquery so `having` and `order_by`
+/// can refer to group keys and operation aliases alike.
+pub fn build_aggregation(table_name: &str, request: &AggregationRequest) -> AppResult<AggregationQuery> {
+    if request.operations.is_empty() {
+        return Err(AppError::validation("Aggregation requires at least one operation"));
+    }
+
+    let aggregates = request
+        .operations
+        .iter()
+        .map(|op| Ok(format!("{} AS {}", operation_sql(op)?, quote_ident(&op.get_alias()))))
+        .collect::<AppResult<Vec<_>>>()?;
+
+    // Group keys as (expression, output name)
+    let mut keys = Vec::new();
+    if let Some(bucket) = &request.time_bucket {
+        keys.push((
+            format!("date_trunc('{}', {})", bucket.interval.as_str(), quote_ident(&bucket.field)),
+            bucket.get_alias(),
+        ));
+    }
+    for field in request.group_by.iter().flatten() {
+        keys.push((quote_ident(field), field.clone()));
+    }
+
+    let mut outputs: Vec<String> = keys
+        .iter()
+        .map(|(_, name)| name.clone())
+        .chain(request.operations.iter().map(|op| op.get_alias()))
+        .collect();
+    if request.totals.is_some() {
+        outputs.push("grouping_id".to_string());
+    }
+
+    let filter = compile_filter(request.filters.as_ref())?;
+    let mut select = keys
+        .iter()
+        .map(|(expr, name)| format!("{} AS {}", expr, quote_ident(name)))
+        .collect::<Vec<_>>();
+    select.extend(aggregates.iter().cloned());
+
+    let mut inner = String::new();
+    if !keys.is_empty() {
+        let exprs = keys.iter().map(|(expr, _)| expr.as_str()).collect::<Vec<_>>().join(", ");
+        let group_by = match request.totals {
+            Some(GroupTotals::Rollup) => format!("ROLLUP ({})", exprs),
+            Some(GroupTotals::Cube) => format!("CUBE ({})", exprs),
+            None => exprs.clone(),
+        };
+        if request.totals.is_some() {
+            select.push(format!("GROUPING({}) AS grouping_id", exprs));
+        }
+        inner = format!(" GROUP BY {}", group_by);
+    } else if request.totals.is_some() {
+        return Err(AppError::validation("Totals require group_by or time_bucket"));
+    }
+
+    let mut sql = format!(
+        "SELECT * FROM (SELECT {} FROM {}{}{}) AS aggregated",
+        select.join(", "),
+        table_name,
+        filter.where_clause(),
+        inner
+    );
+
+    let having = compile_filter(request.having.as_ref())?;
+    sql.push_str(&having.where_clause());
+
+    if let Some(order_by) = request.order_by.as_ref().filter(|o| !o.is_empty()) {
+        let terms = order_by
+            .iter()
+            .map(|order| {
+                if !outputs.contains(&order.field) {
+                    return Err(AppError::validation(format!(
+                        "Cannot order by {}: not a group key or operation alias",
+                        order.field
+                    )));
+                }
+                let direction = match order.direction {
+                    SortDirection::Asc => "ASC",
+                    SortDirection::Desc => "DESC",
+                };
+                Ok(format!("{} {}", quote_ident(&order.field), direction))
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+        sql.push_str(&format!(" ORDER BY {}", terms.join(", ")));
+    }
+
+    if let Some(limit) = request.limit {
+        sql.push_str(&format!(" LIMIT {}", limit));
+    }
+
+    let mut params = filter.params.clone();
+    params.extend(having.params);
+
+    Ok(AggregationQuery {
+        sql,
+        params: JsonValue::Array(params),
+        totals_sql: format!("SELECT {} FROM {}{}", aggregates.join(", "), table_name, filter.where_clause()),
+        totals_params: filter.params_json(),
+    })
+}
+
+fn operation_sql(op: &AggregationOperation) -> AppResult<String> {
+    if op.field == "*" && op.operation != "count" {
+        return Err(AppError::validation(format!("'*' is only valid for count, not {}", op.operation)));
+    }
+    let field = if op.field == "*" { "*".to_string() } else { quote_ident(&op.field) };
+
+    Ok(match op.operation.as_str() {
+        "sum" => format!("SUM({})", field),
+        "avg" => format!("AVG({})", field),
+        "count" => format!("COUNT({})", field),
+        "min" => format!("MIN({})", field),
+        "max" => format!("MAX({})", field),
+        "distinct_count" => format!("COUNT(DISTINCT {})", field),
+        "median" => format!("MEDIAN({})", field),
+        "stddev" => format!("STDDEV_SAMP({})", field),
+        "approx_count_distinct" => format!("APPROX_COUNT_DISTINCT({})", field),
+        "percentile" => match op.percentile {
+            Some(p) if (0.0..=1.0).contains(&p) => format!("QUANTILE_CONT({}, {})", field, p),
+            _ => {
+                return Err(AppError::validation(format!(
+                    "percentile on {} requires a percentile between 0 and 1",
+                    op.field
+                )))
+            }
+        },
+        _ => {
+            return Err(AppError::bad_request(format!(
+                "Unsupported aggregation operation: {}",
+                op.operation
+            )))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::params::resolve_params, database::values::collect_rows, models::QueryResult};
+    use duckdb::{params_from_iter, Connection};
+    use serde_json::json;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE sales (region VARCHAR, amount INTEGER, sold_at TIMESTAMP);
+            INSERT INTO sales VALUES
+                ('north', 10, '2024-01-05 10:00'),
+                ('north', 20, '2024-01-20 12:00'),
+                ('south', 30, '2024-02-03 09:00'),
+                ('south', 40, '2024-02-10 15:00'),
+                ('west', 5, '2024-02-11 08:00');
+        ").unwrap();
+        conn
+    }
+
+    fn request(value: JsonValue) -> AggregationRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn run(conn: &Connection, sql: &str, params: &JsonValue) -> QueryResult {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let values = resolve_params(&stmt, Some(params)).unwrap();
+        let rows = stmt.query(params_from_iter(values)).unwrap();
+        collect_rows(rows, None).unwrap()
+    }
+
+    #[test]
+    fn test_grouped_with_having_and_order() {
+        let conn = setup();
+        let query = build_aggregation("sales", &request(json!({
+            "data_source_id": "sales",
+            "operations": [{"field": "amount", "operation": "sum", "alias": "total"}],
+            "group_by": ["region"],
+            "filters": {"field": "amount", "op": "gt", "value": 5},
+            "having": {"field": "total", "op": "gte", "value": 30},
+            "order_by": [{"field": "total", "direction": "desc"}]
+        }))).unwrap();
+
+        let result = run(&conn, &query.sql, &query.params);
+        assert_eq!(result.data, vec![vec![json!("south"), json!(70)], vec![json!("north"), json!(30)]]);
+
+        let totals = run(&conn, &query.totals_sql, &query.totals_params);
+        assert_eq!(totals.data, vec![vec![json!(100)]]);
+    }
+
+    #[test]
+    fn test_time_bucket_rollup() {
+        let conn = setup();
+        let query = build_aggregation("sales", &request(json!({
+            "data_source_id": "sales",
+            "operations": [{"field": "*", "operation": "count"}],
+            "time_bucket": {"field": "sold_at", "interval": "month"},
+            "totals": "rollup",
+            "order_by": [{"field": "grouping_id"}, {"field": "sold_at_month"}]
+        }))).unwrap();
+
+        let result = run(&conn, &query.sql, &query.params);
+        assert_eq!(result.columns, vec!["sold_at_month", "count_all", "grouping_id"]);
+        let counts: Vec<_> = result.data.iter().map(|row| (row[1].clone(), row[2].clone())).collect();
+        assert_eq!(counts, vec![(json!(2), json!(0)), (json!(3), json!(0)), (json!(5), json!(1))]);
+
+        let unordered = request(json!({
+            "data_source_id": "sales",
+            "operations": [{"field": "*", "operation": "count"}],
+            "order_by": [{"field": "amount"}]
+        }));
+        assert!(build_aggregation("sales", &unordered).is_err());
+    }
+
+    #[test]
+    fn test_statistical_operations() {
+        let conn = setup();
+        let query = build_aggregation("sales", &request(json!({
+            "data_source_id": "sales",
+            "operations": [
+                {"field": "amount", "operation": "median"},
+                {"field": "amount", "operation": "percentile", "percentile": 0.5, "alias": "p50"},
+                {"field": "region", "operation": "approx_count_distinct"}
+            ]
+        }))).unwrap();
+
+        let result = run(&conn, &query.sql, &query.params);
+        assert_eq!(result.data[0][0].as_f64(), Some(20.0));
+        assert_eq!(result.data[0][1].as_f64(), Some(20.0));
+        assert_eq!(result.data[0][2], json!(3));
+
+        let invalid = request(json!({
+            "data_source_id": "sales",
+            "operations": [{"field": "amount", "operation": "percentile", "percentile": 95}]
+        }));
+        assert!(build_aggregation("sales", &invalid).is_err());
+    }
+}
@@ -1,9 +1,9 @@
-use std::collections::HashMap;
-use tracing::{debug, info};
+use std::{collections::HashMap, time::Duration};
+use tracing::info;
 
 use crate::{
-    database::DatabasePool,
-    models::{QueryResult, AggregationOperation, MetricValue},
+    database::{filter::quote_ident, DatabasePool},
+    models::{DataQualityMetric, QueryResult},
     services::duckdb::DuckDBService,
     utils::error::{AppError, AppResult},
 };
@@ -20,6 +20,12 @@ impl AnalyticsService {
         }
     }
 
+    /// Interrupt the service's queries after `timeout`
+    pub fn with_query_timeout(mut self, timeout: Duration) -> Self {
+        self.duckdb_service = self.duckdb_service.with_query_timeout(timeout);
+        self
+    }
+
     /// Calculate statistical metrics for a dataset
     pub async fn calculate_statistics(
         &self,
@@ -27,6 +33,7 @@ impl AnalyticsService {
         column_name: &str,
     ) -> AppResult<HashMap<String, f64>> {
         info!("Calculating statistics for {}.{}", table_name, column_name);
+        let column_name = quote_ident(column_name);
 
         let sql = format!(
             "SELECT 
@@ -52,7 +59,7 @@ impl AnalyticsService {
         let row = &result.data[0];
         let mut stats = HashMap::new();
 
-        if let Some(columns) = result.columns.get(0) {
+        if !result.columns.is_empty() {
             for (i, col_name) in result.columns.iter().enumerate() {
                 if let Some(value) = row.get(i) {
                     if let Some(num_value) = value.as_f64() {
@@ -75,6 +82,8 @@ impl AnalyticsService {
         aggregation: &str, // 'sum', 'avg', 'count', 'min', 'max'
     ) -> AppResult<QueryResult> {
         info!("Generating time series aggregation for {}.{} by {}", table_name, value_column, interval);
+        let time_column = quote_ident(time_column);
+        let value_column = quote_ident(value_column);
 
         let time_trunc = match interval {
             "hour" => format!("date_trunc('hour', {})", time_column),
@@ -116,6 +125,7 @@ impl AnalyticsService {
         threshold: Option<f64>,
     ) -> AppResult<QueryResult> {
         info!("Detecting outliers in {}.{} using {} method", table_name, column_name, method);
+        let column_name = quote_ident(column_name);
 
         let sql = match method {
             "iqr" => {
@@ -182,7 +192,7 @@ impl AnalyticsService {
                     "SELECT CORR({}, {}) as correlation
                     FROM {}
                     WHERE {} IS NOT NULL AND {} IS NOT NULL",
-                    col1, col2, table_name, col1, col2
+                    quote_ident(col1), quote_ident(col2), table_name, quote_ident(col1), quote_ident(col2)
                 );
 
                 let result = self.duckdb_service.execute_query_with_params(&sql, None).await?;
@@ -214,6 +224,8 @@ impl AnalyticsService {
         let mut metrics = Vec::new();
 
         for column in table_info.columns {
+            let quoted = quote_ident(&column.name);
+
             // Calculate null percentage
             let null_sql = format!(
                 "SELECT 
@@ -222,7 +234,7 @@ impl AnalyticsService {
                     (COUNT(*) - COUNT({})) as null_rows,
                     ROUND((COUNT(*) - COUNT({})) * 100.0 / COUNT(*), 2) as null_percentage
                 FROM {}",
-                column.name, column.name, column.name, table_name
+                quoted, quoted, quoted, table_name
             );
 
             let null_result = self.duckdb_service.execute_query_with_params(&null_sql, None).await?;
@@ -267,6 +279,8 @@ impl AnalyticsService {
         window_size: i32,
     ) -> AppResult<QueryResult> {
         info!("Calculating {}-period moving average for {}.{}", window_size, table_name, value_column);
+        let value_column = quote_ident(value_column);
+        let order_column = quote_ident(order_column);
 
         let sql = format!(
             "SELECT *,
@@ -283,37 +297,24 @@ impl AnalyticsService {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct DataQualityMetric {
-    pub column_name: String,
-    pub data_type: String,
-    pub total_rows: i64,
-    pub null_count: i64,
-    pub null_percentage: f64,
-    pub unique_count: Option<i64>,
-    pub min_length: Option<i32>,
-    pub max_length: Option<i32>,
-}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use tempfile::TempDir;
 
-    async fn create_test_service() -> AnalyticsService {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap();
-        let db_pool = DatabasePool::new(db_path).unwrap();
-        AnalyticsService::new(db_pool)
+    async fn create_test_service() -> (TempDir, AnalyticsService, DatabasePool) {
+        let dir = TempDir::new().unwrap();
+        let db_pool = DatabasePool::new(dir.path().join("test.db").to_str().unwrap()).unwrap();
+        (dir, AnalyticsService::new(db_pool.clone()), db_pool)
     }
 
     #[tokio::test]
     async fn test_statistics_calculation() {
-        let service = create_test_service().await;
+        let (_dir, service, db_pool) = create_test_service().await;
         
         // Create test data
-        let conn = service.duckdb_service.connection_pool.get_connection();
-        let conn_guard = conn.lock().await;
+        let conn_guard = db_pool.get_writer().await.unwrap();
         conn_guard.execute_batch("
             CREATE TABLE test_stats (
                 id INTEGER,
@@ -0,0 +1,260 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    services::upload_spool::{spool_reader, SpooledUpload},
+    utils::error::{AppError, AppResult},
+};
+
+/// File extensions that can be loaded once unpacked
+const DATA_EXTENSIONS: &[&str] = &["csv", "json", "ndjson", "jsonl", "parquet", "xlsx"];
+
+/// Compression or archive format wrapped around an upload, detected from
+/// its file name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Packing {
+    Gzip,
+    Zstd,
+    Zip,
+    Tar,
+    TarGzip,
+    TarZstd,
+}
+
+/// Packing of `file_name` and the name of the file it wraps
+fn packing(file_name: &str) -> Option<(Packing, &str)> {
+    let lower = file_name.to_lowercase();
+    let strip = |suffix: &str| &file_name[..file_name.len() - suffix.len()];
+
+    [
+        (".tar.gz", Packing::TarGzip),
+        (".tgz", Packing::TarGzip),
+        (".tar.zst", Packing::TarZstd),
+        (".tzst", Packing::TarZstd),
+        (".tar", Packing::Tar),
+        (".zip", Packing::Zip),
+        (".gz", Packing::Gzip),
+        (".zst", Packing::Zstd),
+    ]
+    .into_iter()
+    .find(|(suffix, _)| lower.ends_with(suffix))
+    .map(|(suffix, packing)| (packing, strip(suffix)))
+}
+
+/// Whether the upload is compressed or an archive
+pub fn is_packed(file_name: &str) -> bool {
+    packing(file_name).is_some()
+}
+
+/// Decompress a gzip or zstd upload, or extract the data files of a zip or
+/// tar archive, into the upload's spool directory.
+///
+/// Plain uploads are returned unchanged. Archive members that are not CSV,
+/// JSON, Parquet or Excel files (after decompression) are skipped. At most
+/// `max_size` bytes are unpacked in total.
+pub async fn unpack_upload(upload: SpooledUpload, max_size: u64) -> AppResult<Vec<SpooledUpload>> {
+    if !is_packed(&upload.file_name) {
+        return Ok(vec![upload]);
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let dir = upload.spool_dir();
+        let mut budget = max_size;
+        let files = unpack(upload, &dir, &mut budget)?;
+
+        if files.is_empty() {
+            return Err(AppError::file_upload("Archive contains no CSV, JSON, Parquet or Excel files"));
+        }
+        Ok(files)
+    })
+    .await
+    .map_err(|e| AppError::internal(format!("Unpack task failed: {}", e)))?
+}
+
+/// [`unpack_upload`] each of several uploads, allowing `max_size` bytes for
+/// each
+pub async fn unpack_uploads(uploads: Vec<SpooledUpload>, max_size: u64) -> AppResult<Vec<SpooledUpload>> {
+    let mut files = Vec::with_capacity(uploads.len());
+    for upload in uploads {
+        files.extend(unpack_upload(upload, max_size).await?);
+    }
+    Ok(files)
+}
+
+fn unpack(upload: SpooledUpload, dir: &Path, budget: &mut u64) -> AppResult<Vec<SpooledUpload>> {
+    let Some((packing, inner_name)) = packing(&upload.file_name) else {
+        return Ok(vec![upload]);
+    };
+    let inner_name = inner_name.to_string();
+    debug!("Unpacking {} as {:?}", upload.file_name, packing);
+
+    let file = BufReader::new(File::open(&upload.path)?);
+    match packing {
+        Packing::Gzip => unpack_stream(dir, inner_name, flate2::read::MultiGzDecoder::new(file), budget),
+        Packing::Zstd => unpack_stream(dir, inner_name, zstd::stream::read::Decoder::with_buffer(file)?, budget),
+        Packing::Tar => unpack_tar(dir, file, budget),
+        Packing::TarGzip => unpack_tar(dir, flate2::read::MultiGzDecoder::new(file), budget),
+        Packing::TarZstd => unpack_tar(dir, zstd::stream::read::Decoder::with_buffer(file)?, budget),
+        Packing::Zip => unpack_zip(dir, &upload, budget),
+    }
+}
+
+/// Spool one decompressed stream, unpacking it further if it is itself
+/// packed (e.g. a `.tar` inside a `.gz`)
+fn unpack_stream(dir: &Path, file_name: String, reader: impl Read, budget: &mut u64) -> AppResult<Vec<SpooledUpload>> {
+    let spooled = spool_reader(dir, file_name, reader, *budget)?;
+    *budget -= spooled.size;
+    unpack(spooled, dir, budget)
+}
+
+fn unpack_tar(dir: &Path, reader: impl Read, budget: &mut u64) -> AppResult<Vec<SpooledUpload>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut files = Vec::new();
+
+    for entry in archive.entries().map_err(archive_error)? {
+        let entry = entry.map_err(archive_error)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(archive_error)?.into_owned();
+        if let Some(name) = member_name(&path) {
+            files.extend(unpack_stream(dir, name, entry, budget)?);
+        }
+    }
+
+    Ok(files)
+}
+
+fn unpack_zip(dir: &Path, upload: &SpooledUpload, budget: &mut u64) -> AppResult<Vec<SpooledUpload>> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(&upload.path)?)).map_err(archive_error)?;
+    let mut files = Vec::new();
+
+    for index in 0..archive.len() {
+        let member = archive.by_index(index).map_err(archive_error)?;
+        if !member.is_file() {
+            continue;
+        }
+        let Some(path) = member.enclosed_name() else {
+            warn!("Skipping archive member with an unsafe path: {}", member.name());
+            continue;
+        };
+        if let Some(name) = member_name(&path) {
+            files.extend(unpack_stream(dir, name, member, budget)?);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Base name of a loadable archive member, or `None` for metadata and
+/// unsupported files
+fn member_name(path: &Path) -> Option<String> {
+    if path.components().any(|c| c.as_os_str() == "__MACOSX") {
+        return None;
+    }
+    let name = path.file_name()?.to_str()?.to_string();
+    if name.starts_with('.') {
+        return None;
+    }
+
+    let loadable = match packing(&name) {
+        Some((Packing::Gzip | Packing::Zstd, inner)) => is_data_file(inner),
+        Some(_) => true, // nested archive
+        None => is_data_file(&name),
+    };
+    if !loadable {
+        debug!("Skipping archive member {}", name);
+        return None;
+    }
+    Some(name)
+}
+
+fn is_data_file(file_name: &str) -> bool {
+    Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| DATA_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+fn archive_error(e: impl std::fmt::Display) -> AppError {
+    AppError::file_upload(format!("Failed to read archive: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_packing_from_name() {
+        assert_eq!(packing("Sales.CSV.GZ"), Some((Packing::Gzip, "Sales.CSV")));
+        assert_eq!(packing("drop.tar.gz"), Some((Packing::TarGzip, "drop")));
+        assert_eq!(packing("drop.zst"), Some((Packing::Zstd, "drop")));
+        assert_eq!(packing("sales.csv"), None);
+    }
+
+    #[tokio::test]
+    async fn test_unpack_gzip_and_zstd() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let csv = b"id,amount\n1,10\n";
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "sales.csv.gz".to_string(), &gzip(csv)).await.unwrap();
+        let files = unpack_upload(upload, 1024).await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_name, "sales.csv");
+        assert_eq!(std::fs::read(&files[0].path).unwrap(), csv);
+
+        let compressed = zstd::encode_all(&csv[..], 0).unwrap();
+        let upload = SpooledUpload::from_bytes(dir.path(), "sales.csv.zst".to_string(), &compressed).await.unwrap();
+        let files = unpack_upload(upload, 1024).await.unwrap();
+        assert_eq!(files[0].extension(), "csv");
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "big.csv.gz".to_string(), &gzip(&[b'a'; 4096])).await.unwrap();
+        assert!(unpack_upload(upload, 1024).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unpack_zip_and_tar() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("day1.csv", options).unwrap();
+        zip.write_all(b"id\n1\n").unwrap();
+        zip.start_file("notes/README.txt", options).unwrap();
+        zip.write_all(b"ignored").unwrap();
+        zip.start_file("nested/day2.csv.gz", options).unwrap();
+        zip.write_all(&gzip(b"id\n2\n")).unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "drop.zip".to_string(), &bytes).await.unwrap();
+        let names: Vec<_> = unpack_upload(upload, 1024).await.unwrap().iter().map(|f| f.file_name.clone()).collect();
+        assert_eq!(names, vec!["day1.csv", "day2.csv"]);
+
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        tar.append_data(&mut header, "day3.csv", &b"id\n3\n"[..]).unwrap();
+        let bytes = gzip(&tar.into_inner().unwrap());
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "drop.tgz".to_string(), &bytes).await.unwrap();
+        let files = unpack_upload(upload, 1024).await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_name, "day3.csv");
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "empty.tar".to_string(), &tar::Builder::new(Vec::new()).into_inner().unwrap()).await.unwrap();
+        assert!(unpack_upload(upload, 1024).await.is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use std::{collections::HashMap, path::Path};
+
+use duckdb::Connection;
+use tracing::debug;
+
+use crate::{
+    database::filter::quote_literal,
+    models::{ColumnSchema, CsvDialect, CsvInspection},
+    services::rejects::CsvRejects,
+    utils::error::{AppError, AppResult},
+};
+
+/// Rows DuckDB's sniffer and the confidence check look at
+const SAMPLE_ROWS: usize = 20_480;
+
+/// Sniff the dialect and column types of the CSV file at `path`.
+///
+/// Types come from DuckDB's `sniff_csv`. Each column's confidence is the
+/// share of non-null sampled values that convert to the inferred type.
+/// Malformed rows are left out of the sniff, as loads reject them.
+pub fn inspect(conn: &Connection, path: &Path) -> AppResult<CsvInspection> {
+    let path = quote_literal(&path.to_string_lossy());
+    let sniff = format!("sniff_csv({}, sample_size = {}, ignore_errors = true)", path, SAMPLE_ROWS);
+
+    let dialect = conn.query_row(
+        &format!(
+            "SELECT Delimiter, Quote, Escape, HasHeader, DateFormat, TimestampFormat FROM {}",
+            sniff
+        ),
+        [],
+        |row| {
+            Ok(CsvDialect {
+                delimiter: row.get(0)?,
+                quote: sniffed_char(row.get(1)?),
+                escape: sniffed_char(row.get(2)?),
+                has_header: row.get(3)?,
+                date_format: non_empty(row.get(4)?),
+                timestamp_format: non_empty(row.get(5)?),
+            })
+        },
+    )?;
+    debug!("Sniffed CSV dialect: {:?}", dialect);
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT c.name, c.type FROM (SELECT unnest(Columns) AS c FROM {})",
+        sniff
+    ))?;
+    let mut columns = stmt
+        .query_map([], |row| {
+            Ok(ColumnSchema::new(row.get(0)?, row.get(1)?))
+        })?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+
+    score_columns(conn, &path, &dialect, &mut columns)?;
+
+    Ok(CsvInspection {
+        dialect,
+        columns,
+        sample_size: SAMPLE_ROWS,
+    })
+}
+
+/// Apply user-chosen types over the inferred ones. Overridden columns keep the
+/// confidence of the inferred type and are flagged as overridden.
+pub fn apply_overrides(
+    columns: &mut [ColumnSchema],
+    overrides: &HashMap<String, String>,
+) -> AppResult<()> {
+    for (name, column_type) in overrides {
+        if !is_type_name(column_type) {
+            return Err(AppError::validation(format!("Invalid column type for {}: {}", name, column_type)));
+        }
+        let column = columns
+            .iter_mut()
+            .find(|c| &c.name == name)
+            .ok_or_else(|| AppError::validation(format!("Unknown column in type overrides: {}", name)))?;
+        column.r#type = column_type.to_uppercase();
+        column.overridden = true;
+    }
+    Ok(())
+}
+
+/// `read_csv` call that loads the file with the given dialect and column
+/// types. With `rejects`, rows that fail to parse are skipped and recorded
+/// instead of failing the read.
+pub fn read_csv_sql(
+    path: &Path,
+    dialect: &CsvDialect,
+    columns: &[ColumnSchema],
+    rejects: Option<&CsvRejects>,
+) -> String {
+    let column_types = columns
+        .iter()
+        .map(|c| format!("{}: {}", quote_literal(&c.name), quote_literal(&c.r#type)))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut options = vec![
+        format!("delim = {}", quote_literal(&dialect.delimiter)),
+        format!("quote = {}", quote_literal(&dialect.quote)),
+        format!("escape = {}", quote_literal(&dialect.escape)),
+        format!("header = {}", dialect.has_header),
+        format!("columns = {{{}}}", column_types),
+    ];
+    if let Some(format) = &dialect.date_format {
+        options.push(format!("dateformat = {}", quote_literal(format)));
+    }
+    if let Some(format) = &dialect.timestamp_format {
+        options.push(format!("timestampformat = {}", quote_literal(format)));
+    }
+    if let Some(rejects) = rejects {
+        options.push(rejects.read_options());
+    }
+
+    format!(
+        "read_csv({}, {})",
+        quote_literal(&path.to_string_lossy()),
+        options.join(", ")
+    )
+}
+
+fn score_columns(
+    conn: &Connection,
+    path: &str,
+    dialect: &CsvDialect,
+    columns: &mut [ColumnSchema],
+) -> AppResult<()> {
+    if columns.is_empty() {
+        return Ok(());
+    }
+
+    // Read the sample as text so every value can be checked against its
+    // type, leaving out malformed rows like the sniff does
+    let checks = columns
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let value = format!("column{:05}", i);
+            let converted = match (c.r#type.as_str(), &dialect.date_format, &dialect.timestamp_format) {
+                ("DATE", Some(format), _) | ("TIMESTAMP", _, Some(format)) => {
+                    format!("TRY_STRPTIME({}, {})", value, quote_literal(format))
+                }
+                (column_type, _, _) => format!("TRY_CAST({} AS {})", value, column_type),
+            };
+            format!("COUNT({}), COUNT({})", value, converted)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let sql = format!(
+        "SELECT {} FROM (SELECT * FROM read_csv({}, all_varchar = true, header = {}, delim = {}, quote = {}, escape = {}, \
+         names = [{}], ignore_errors = true) LIMIT {})",
+        checks,
+        path,
+        dialect.has_header,
+        quote_literal(&dialect.delimiter),
+        quote_literal(&dialect.quote),
+        quote_literal(&dialect.escape),
+        (0..columns.len()).map(|i| format!("'column{:05}'", i)).collect::<Vec<_>>().join(", "),
+        SAMPLE_ROWS
+    );
+
+    let counts: Vec<i64> = conn.query_row(&sql, [], |row| {
+        (0..columns.len() * 2).map(|i| row.get(i)).collect()
+    })?;
+
+    for (column, pair) in columns.iter_mut().zip(counts.chunks(2)) {
+        let (present, converted) = (pair[0], pair[1]);
+        column.confidence = Some(if present == 0 { 0.0 } else { converted as f64 / present as f64 });
+    }
+
+    Ok(())
+}
+
+/// DuckDB type names, including parameterized ones like `DECIMAL(10,2)`
+fn is_type_name(column_type: &str) -> bool {
+    !column_type.trim().is_empty()
+        && column_type
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '(' | ')' | ',' | ' ' | '[' | ']'))
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.is_empty())
+}
+
+/// `sniff_csv` reports a quote or escape character it did not find as
+/// `(empty)`, which `read_csv` takes as an empty string
+fn sniffed_char(value: String) -> String {
+    if value == "(empty)" {
+        String::new()
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn write_csv(contents: &str) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), contents).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_inspect_infers_types_and_dialect() {
+        let conn = Connection::open_in_memory().unwrap();
+        let file = write_csv("id;amount;sold_on;note\n1;2.5;2024-01-31;a\n2;3.75;2024-02-01;b\n");
+
+        let inspection = inspect(&conn, file.path()).unwrap();
+        assert_eq!(inspection.dialect.delimiter, ";");
+        assert!(inspection.dialect.has_header);
+
+        let types: Vec<_> = inspection.columns.iter().map(|c| (c.name.as_str(), c.r#type.as_str())).collect();
+        assert_eq!(types, vec![("id", "BIGINT"), ("amount", "DOUBLE"), ("sold_on", "DATE"), ("note", "VARCHAR")]);
+        assert!(inspection.columns.iter().all(|c| c.confidence == Some(1.0)));
+    }
+
+    #[test]
+    fn test_overrides_and_load() {
+        let conn = Connection::open_in_memory().unwrap();
+        let file = write_csv("id,code\n1,007\n2,042\n");
+
+        let inspection = inspect(&conn, file.path()).unwrap();
+        let mut columns = inspection.columns.clone();
+        let overrides = HashMap::from([("code".to_string(), "varchar".to_string())]);
+        apply_overrides(&mut columns, &overrides).unwrap();
+        let code = columns.iter().find(|c| c.name == "code").unwrap();
+        assert_eq!(code.r#type, "VARCHAR");
+        assert!(code.overridden);
+        assert_eq!(code.confidence, inspection.columns[1].confidence);
+
+        conn.execute_batch(&format!(
+            "CREATE TABLE t AS SELECT * FROM {}",
+            read_csv_sql(file.path(), &inspection.dialect, &columns, None)
+        ))
+        .unwrap();
+        let code: String = conn.query_row("SELECT code FROM t WHERE id = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(code, "007");
+
+        let bad = HashMap::from([("code".to_string(), "INT; DROP TABLE t".to_string())]);
+        assert!(apply_overrides(&mut columns, &bad).is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use duckdb::Connection;
+use tracing::{info, warn};
+
+use crate::{
+    database::{
+        queries::{DataSourceQueries, DerivedQueries},
+        sql_guard::referenced_tables,
+        timeout::QueryDeadline,
+        DatabasePool,
+    },
+    models::{source_table, CreateDerivedSourceRequest, DataSource, DerivedConfig, DERIVED_SOURCE_TYPE},
+    services::{
+        file_processor::{count_rows, describe_table, FileProcessor},
+        query_cache::QueryCacheService,
+        schedule::{next_refresh, parse_schedule},
+    },
+    utils::error::{AppError, AppResult},
+};
+
+/// How often the scheduler looks for derived sources due a refresh
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Data sources defined by a SQL query over other data sources.
+///
+/// A derived source is either a view, which always reads the current rows
+/// of the sources it depends on, or materialized into a table. Materialized
+/// sources are refreshed on demand, on a cron schedule, or after a source
+/// they depend on is loaded into, and each refresh is recorded as a version.
+/// Dependencies are stored in `source_dependencies`, so sources read by a
+/// derived source are not deleted from under it.
+#[derive(Clone)]
+pub struct DerivedSourceService {
+    db_pool: DatabasePool,
+    file_processor: FileProcessor,
+    query_cache: QueryCacheService,
+    query_timeout: Option<Duration>,
+}
+
+impl DerivedSourceService {
+    pub fn new(db_pool: DatabasePool, file_processor: FileProcessor, query_cache: QueryCacheService) -> Self {
+        Self {
+            db_pool,
+            file_processor,
+            query_cache,
+            query_timeout: None,
+        }
+    }
+
+    /// Interrupt the SQL of a derived source after `timeout` when it is
+    /// created or refreshed, as it runs on the writer
+    pub fn with_query_timeout(mut self, timeout: Duration) -> Self {
+        self.query_timeout = Some(timeout);
+        self
+    }
+
+    /// Create a data source from `request.sql`, a read-only query over
+    /// registered data sources, which it reads as `data_source_<id>`
+    pub async fn create(&self, request: CreateDerivedSourceRequest) -> AppResult<DataSource> {
+        let name = request.name.trim();
+        if name.is_empty() {
+            return Err(AppError::validation("Derived source name cannot be empty"));
+        }
+        if !request.materialized && (request.schedule.is_some() || request.refresh_on_change) {
+            return Err(AppError::validation("Only materialized derived sources are refreshed"));
+        }
+        if let Some(schedule) = &request.schedule {
+            parse_schedule(schedule)?;
+        }
+
+        let conn_guard = self.db_pool.get_writer().await?;
+        let dependencies = dependencies(&conn_guard, &request.sql)?;
+
+        let config = DerivedConfig {
+            sql: request.sql.trim().trim_end_matches(';').trim_end().to_string(),
+            materialized: request.materialized,
+            next_refresh_at: next_refresh(request.schedule.as_deref()),
+            schedule: request.schedule,
+            refresh_on_change: request.refresh_on_change,
+            refreshed_at: request.materialized.then(Utc::now),
+        };
+        let data_source = DataSource::new(uuid::Uuid::new_v4().to_string(), name.to_string(), DERIVED_SOURCE_TYPE.to_string());
+
+        conn_guard.execute_batch("BEGIN TRANSACTION")?;
+        let created = self.with_deadline(&conn_guard, || self.create_table(&conn_guard, data_source, config, dependencies));
+        match created {
+            Ok(data_source) => {
+                conn_guard.execute_batch("COMMIT")?;
+                info!(
+                    "Created derived source {} over {:?} ({} rows)",
+                    data_source.id, data_source.dependencies, data_source.row_count
+                );
+                Ok(data_source)
+            }
+            Err(e) => {
+                let _ = conn_guard.execute_batch("ROLLBACK");
+                Err(e)
+            }
+        }
+    }
+
+    /// Recompute a materialized derived source from its SQL, recording the
+    /// result as a new version. A failed refresh leaves the previous rows in
+    /// place and is only retried on the next schedule or upstream change.
+    pub async fn refresh(&self, data_source_id: &str) -> AppResult<DataSource> {
+        let conn_guard = self.db_pool.get_writer().await?;
+        let data_source = DataSourceQueries::get_by_id(&conn_guard, data_source_id)?
+            .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", data_source_id)))?;
+        let Some(mut config) = data_source.derived.clone().filter(|config| config.materialized) else {
+            return Err(AppError::validation(format!(
+                "Data source {} is not a materialized derived source",
+                data_source_id
+            )));
+        };
+
+        conn_guard.execute_batch("BEGIN TRANSACTION")?;
+        let refreshed = self.with_deadline(&conn_guard, || self.refresh_table(&conn_guard, data_source, config.clone()));
+        match refreshed {
+            Ok(data_source) => {
+                conn_guard.execute_batch("COMMIT")?;
+                self.query_cache.invalidate_source(&conn_guard, data_source_id)?;
+                info!("Refreshed derived source {} ({} rows)", data_source_id, data_source.row_count);
+                Ok(data_source)
+            }
+            Err(e) => {
+                let _ = conn_guard.execute_batch("ROLLBACK");
+                config.next_refresh_at = next_refresh(config.schedule.as_deref());
+                DerivedQueries::update(&conn_guard, data_source_id, &config)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Run [`Self::refresh_due`] every [`SCHEDULER_INTERVAL`] for the
+    /// lifetime of the process
+    pub fn spawn(&self) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = service.refresh_due().await {
+                    warn!("Derived source refresh failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Refresh every materialized derived source whose schedule is due or
+    /// whose dependencies changed since, returning how many were refreshed
+    pub async fn refresh_due(&self) -> AppResult<usize> {
+        let due = {
+            let conn_guard = self.db_pool.get_connection().await?;
+            let mut due = DerivedQueries::list_due(&conn_guard, Utc::now())?;
+            for id in DerivedQueries::list_stale(&conn_guard)? {
+                if !due.contains(&id) {
+                    due.push(id);
+                }
+            }
+            due
+        };
+
+        let mut refreshed = 0;
+        for data_source_id in due {
+            match self.refresh(&data_source_id).await {
+                Ok(_) => refreshed += 1,
+                Err(e) => warn!("Refresh of derived source {} failed: {}", data_source_id, e),
+            }
+        }
+        Ok(refreshed)
+    }
+
+    /// Run `f`, interrupting it once the query timeout passes
+    fn with_deadline<T>(&self, conn: &Connection, f: impl FnOnce() -> AppResult<T>) -> AppResult<T> {
+        let deadline = self.query_timeout.map(|timeout| QueryDeadline::start(conn, timeout));
+        let result = f();
+        match deadline {
+            Some(deadline) => deadline.finish(result),
+            None => result,
+        }
+    }
+
+    /// Create the view or table of a new derived source and register it, in
+    /// the caller's transaction
+    fn create_table(
+        &self,
+        conn: &Connection,
+        mut data_source: DataSource,
+        config: DerivedConfig,
+        dependencies: Vec<String>,
+    ) -> AppResult<DataSource> {
+        let table_name = source_table(&data_source.id);
+        let kind = if config.materialized { "TABLE" } else { "VIEW" };
+        conn.execute_batch(&format!("CREATE {} {} AS {}", kind, table_name, config.sql))
+            .map_err(|e| AppError::validation(format!("Failed to run derived source SQL: {}", e)))?;
+
+        data_source = data_source
+            .with_schema(describe_table(conn, &table_name)?)
+            .with_stats(count_rows(conn, &table_name)?, 0);
+        DataSourceQueries::create(conn, &data_source)?;
+        DerivedQueries::create(conn, &data_source.id, &config, &dependencies)?;
+        data_source.derived = Some(config);
+        data_source.dependencies = dependencies;
+
+        self.file_processor.record_version(conn, &data_source)?;
+        Ok(data_source)
+    }
+
+    /// Replace a materialized derived source's table, in the caller's
+    /// transaction
+    fn refresh_table(&self, conn: &Connection, data_source: DataSource, mut config: DerivedConfig) -> AppResult<DataSource> {
+        // The SQL is checked again, as the sources it may read have changed
+        // since it was stored
+        let current = dependencies(conn, &config.sql)?;
+        if let Some(missing) = current.iter().find(|id| !data_source.dependencies.contains(id)) {
+            return Err(AppError::validation(format!(
+                "Derived source {} reads data source {}, which it did not depend on",
+                data_source.id, missing
+            )));
+        }
+
+        let table_name = source_table(&data_source.id);
+        conn.execute_batch(&format!("CREATE OR REPLACE TABLE {} AS {}", table_name, config.sql))?;
+
+        let schema = describe_table(conn, &table_name)?;
+        let row_count = count_rows(conn, &table_name)?;
+        DataSourceQueries::update_schema(conn, &data_source.id, &schema)?;
+        DataSourceQueries::update_stats(conn, &data_source.id, row_count, 0)?;
+        config.refreshed_at = Some(Utc::now());
+        config.next_refresh_at = next_refresh(config.schedule.as_deref());
+        DerivedQueries::update(conn, &data_source.id, &config)?;
+
+        let mut data_source = data_source.with_schema(schema).with_stats(row_count, 0);
+        data_source.derived = Some(config);
+        self.file_processor.record_version(conn, &data_source)?;
+        Ok(data_source)
+    }
+}
+
+/// Data sources read by `sql`, which must be a read-only query over
+/// registered data sources only
+fn dependencies(conn: &Connection, sql: &str) -> AppResult<Vec<String>> {
+    let tables: Vec<(String, String)> = DataSourceQueries::list_all(conn)?
+        .into_iter()
+        .map(|data_source| (source_table(&data_source.id), data_source.id))
+        .collect();
+    let allowed: Vec<String> = tables.iter().map(|(table, _)| table.clone()).collect();
+    let referenced = referenced_tables(sql, &allowed)?;
+    Ok(tables
+        .into_iter()
+        .filter(|(table, _)| referenced.contains(table))
+        .map(|(_, id)| id)
+        .collect())
+}
+
+/// Derived sources reading a data source, directly or through other derived
+/// sources, ordered so each comes before any derived source it reads
+pub fn dependents(conn: &Connection, data_source_id: &str) -> AppResult<Vec<String>> {
+    let mut ordered = Vec::new();
+    collect_dependents(conn, data_source_id, &mut ordered)?;
+    Ok(ordered)
+}
+
+fn collect_dependents(conn: &Connection, data_source_id: &str, ordered: &mut Vec<String>) -> AppResult<()> {
+    for dependent in DerivedQueries::dependents(conn, data_source_id)? {
+        if !ordered.contains(&dependent) {
+            collect_dependents(conn, &dependent, ordered)?;
+            ordered.push(dependent);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        database::queries::VersionQueries,
+        models::LoadMode,
+        services::{upload_spool::SpooledUpload, versions::VersionRetention},
+    };
+
+    fn request(sql: &str, materialized: bool) -> CreateDerivedSourceRequest {
+        CreateDerivedSourceRequest {
+            name: "By region".to_string(),
+            sql: sql.to_string(),
+            materialized,
+            schedule: None,
+            refresh_on_change: materialized,
+        }
+    }
+
+    fn total(conn: &Connection, table_name: &str) -> i64 {
+        conn.query_row(&format!("SELECT SUM(amount)::BIGINT FROM {}", table_name), [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_views_and_materializations_follow_dependencies() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = crate::database::test_pool(dir.path()).await;
+        let processor = FileProcessor::new(db_pool.clone()).with_version_retention(VersionRetention::default());
+        let service = DerivedSourceService::new(db_pool.clone(), processor.clone(), QueryCacheService::new(300));
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "sales.csv".to_string(), b"id,region,amount\n1,north,10\n2,south,20\n")
+            .await
+            .unwrap();
+        let sales = processor.process_file(&upload).await.unwrap();
+        DataSourceQueries::create(&db_pool.get_writer().await.unwrap(), &sales).unwrap();
+
+        let sql = format!("SELECT region, SUM(amount)::BIGINT AS amount FROM {} GROUP BY region;", source_table(&sales.id));
+        let view = service.create(request(&sql, false)).await.unwrap();
+        let table = service.create(request(&sql, true)).await.unwrap();
+        let top = service
+            .create(request(&format!("SELECT * FROM {} WHERE amount > 15", source_table(&view.id)), false))
+            .await
+            .unwrap();
+        assert_eq!(view.dependencies, vec![sales.id.clone()]);
+        assert_eq!(top.dependencies, vec![view.id.clone()]);
+        assert_eq!((table.row_count, table.schema.len()), (2, 2));
+        assert!(view.is_view() && !table.is_view());
+        assert!(table.load_refusal().is_some());
+
+        assert!(service.create(request("SELECT * FROM data_sources", false)).await.is_err());
+        let scheduled_view = CreateDerivedSourceRequest { schedule: Some("0 * * * *".to_string()), ..request(&sql, false) };
+        assert!(service.create(scheduled_view).await.is_err());
+
+        // Loads show through the view at once, and through the
+        // materialization once it is refreshed
+        let upload = SpooledUpload::from_bytes(dir.path(), "more.csv".to_string(), b"id,region,amount\n3,north,5\n")
+            .await
+            .unwrap();
+        processor
+            .load_into(&sales, &upload, &HashMap::new(), LoadMode::Append, &[])
+            .await
+            .unwrap();
+        {
+            let conn = db_pool.get_connection().await.unwrap();
+            assert_eq!(total(&conn, &source_table(&view.id)), 35);
+            assert_eq!(total(&conn, &source_table(&table.id)), 30);
+        }
+        assert_eq!(service.refresh_due().await.unwrap(), 1);
+        assert_eq!(service.refresh_due().await.unwrap(), 0);
+
+        let conn = db_pool.get_connection().await.unwrap();
+        assert_eq!(total(&conn, &source_table(&table.id)), 35);
+        assert_eq!(VersionQueries::list(&conn, &table.id).unwrap().len(), 2);
+        let stored = DataSourceQueries::get_by_id(&conn, &table.id).unwrap().unwrap();
+        assert_eq!(stored.dependencies, vec![sales.id.clone()]);
+        assert!(stored.derived.unwrap().refreshed_at.is_some());
+
+        // Derived sources come before those they read
+        let ordered = dependents(&conn, &sales.id).unwrap();
+        assert_eq!(ordered.len(), 3);
+        let position = |id: &str| ordered.iter().position(|dependent| dependent == id).unwrap();
+        assert!(position(top.id.as_str()) < position(view.id.as_str()));
+    }
+}
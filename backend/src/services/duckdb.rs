@@ -1,8 +1,10 @@
-use std::collections::HashMap;
-use duckdb::Connection;
+use std::time::Duration;
+
+use duckdb::params_from_iter;
 use tracing::{debug, info, error};
 
 use crate::{
+    database::{params::prepare_with_params, queries::AnalyticsQueries, timeout::QueryDeadline, values::collect_rows},
     models::QueryResult,
     utils::error::{AppError, AppResult},
 };
@@ -10,64 +12,55 @@ use crate::{
 /// DuckDB service for advanced database operations
 pub struct DuckDBService {
     connection_pool: crate::database::DatabasePool,
+    query_timeout: Option<Duration>,
 }
 
 impl DuckDBService {
     pub fn new(connection_pool: crate::database::DatabasePool) -> Self {
-        Self { connection_pool }
+        Self {
+            connection_pool,
+            query_timeout: None,
+        }
+    }
+
+    /// Interrupt queries run through [`Self::execute_query_with_params`] after `timeout`
+    pub fn with_query_timeout(mut self, timeout: Duration) -> Self {
+        self.query_timeout = Some(timeout);
+        self
     }
 
     /// Execute a raw SQL query with parameters
+    ///
+    /// `params` is either a JSON object keyed by parameter name or a JSON array
+    /// bound positionally; see [`prepare_with_params`].
     pub async fn execute_query_with_params(
         &self,
         sql: &str,
-        params: Option<&HashMap<String, serde_json::Value>>,
+        params: Option<&serde_json::Value>,
     ) -> AppResult<QueryResult> {
         debug!("Executing SQL query: {}", sql);
 
-        let conn = self.connection_pool.get_connection();
-        let conn_guard = conn.lock().await;
+        let conn_guard = self.connection_pool.get_connection().await?;
 
-        // For now, we'll ignore params as DuckDB parameter binding is complex
-        // In a production implementation, you'd properly sanitize and bind parameters
-        let mut stmt = conn_guard.prepare(sql)?;
+        let (mut stmt, values) = prepare_with_params(&conn_guard, sql, params)?;
 
-        let column_count = stmt.column_count();
-        let columns: Vec<String> = (0..column_count)
-            .map(|i| stmt.column_name(i).map_or("unknown".to_string(), |v| v.to_string()))
-            .collect();
+        let deadline = self.query_timeout.map(|timeout| QueryDeadline::start(&conn_guard, timeout));
+        let result = stmt
+            .query(params_from_iter(values))
+            .and_then(|rows| collect_rows(rows, None))
+            .map_err(AppError::from);
 
-        let mut rows = stmt.query([])?;
-
-        let mut data = Vec::new();
-        while let Some(row) = rows.next()? {
-            let mut row_data = Vec::new();
-            for i in 0..column_count {
-                let value = match row.get_ref(i)? {
-                    duckdb::types::ValueRef::Null => serde_json::Value::Null,
-                    duckdb::types::ValueRef::Int(n) => serde_json::Value::Number(n.into()),
-                    duckdb::types::ValueRef::BigInt(n) => serde_json::Value::Number(n.into()),
-                    duckdb::types::ValueRef::Double(f) => serde_json::Value::Number(
-                        serde_json::Number::from_f64(f).unwrap_or_else(|| serde_json::Number::from(0))
-                    ),
-                    duckdb::types::ValueRef::Text(s) => serde_json::Value::String(String::from_utf8_lossy(s).to_string()),
-                    duckdb::types::ValueRef::Blob(_) => serde_json::Value::String("BLOB".to_string()),
-                    _ => serde_json::Value::String("UNKNOWN".to_string()),
-                };
-                row_data.push(value);
-            }
-            data.push(row_data);
+        match deadline {
+            Some(deadline) => deadline.finish(result),
+            None => result,
         }
-
-        Ok(QueryResult::new(columns, data))
     }
 
     /// Get table information
     pub async fn get_table_info(&self, table_name: &str) -> AppResult<TableInfo> {
         debug!("Getting table info for: {}", table_name);
 
-        let conn = self.connection_pool.get_connection();
-        let conn_guard = conn.lock().await;
+        let conn_guard = self.connection_pool.get_connection().await?;
 
         // Get column information
         let describe_sql = format!("DESCRIBE {}", table_name);
@@ -113,8 +106,7 @@ impl DuckDBService {
     ) -> AppResult<i64> {
         info!("Bulk inserting {} rows into {}", data.len(), table_name);
 
-        let conn = self.connection_pool.get_connection();
-        let conn_guard = conn.lock().await;
+        let conn_guard = self.connection_pool.get_writer().await?;
 
         // Create placeholders for the query
         let placeholders = vec!["?"; columns.len()].join(", ");
@@ -131,7 +123,7 @@ impl DuckDBService {
         // Begin transaction for better performance
         conn_guard.execute("BEGIN TRANSACTION", [])?;
 
-        for row in data {
+        for _row in data {
             // Convert JSON values to DuckDB parameters
             // This is a simplified implementation
             if let Err(e) = stmt.execute([]) {
@@ -152,8 +144,7 @@ impl DuckDBService {
     pub async fn optimize_table(&self, table_name: &str) -> AppResult<()> {
         info!("Optimizing table: {}", table_name);
 
-        let conn = self.connection_pool.get_connection();
-        let conn_guard = conn.lock().await;
+        let conn_guard = self.connection_pool.get_writer().await?;
 
         // Analyze table statistics
         let analyze_sql = format!("ANALYZE {}", table_name);
@@ -175,8 +166,7 @@ impl DuckDBService {
         
         info!("Creating index {} on {}.{}", index_name, table_name, column_name);
 
-        let conn = self.connection_pool.get_connection();
-        let conn_guard = conn.lock().await;
+        let conn_guard = self.connection_pool.get_writer().await?;
 
         let create_index_sql = format!(
             "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
@@ -198,21 +188,15 @@ impl DuckDBService {
     ) -> AppResult<i64> {
         info!("Exporting table {} to {} format at {}", table_name, format, file_path);
 
-        let conn = self.connection_pool.get_connection();
-        let conn_guard = conn.lock().await;
-
-        let export_sql = match format.to_lowercase().as_str() {
-            "csv" => format!("COPY {} TO '{}' (FORMAT CSV, HEADER)", table_name, file_path),
-            "parquet" => format!("COPY {} TO '{}' (FORMAT PARQUET)", table_name, file_path),
-            "json" => format!("COPY {} TO '{}' (FORMAT JSON)", table_name, file_path),
-            _ => return Err(AppError::bad_request(format!("Unsupported export format: {}", format))),
-        };
+        let conn_guard = self.connection_pool.get_connection().await?;
 
-        conn_guard.execute(&export_sql, [])?;
-
-        // Get row count for return value
-        let count_sql = format!("SELECT COUNT(*) FROM {}", table_name);
-        let row_count: i64 = conn_guard.query_row(&count_sql, [], |row| row.get(0))?;
+        let row_count = AnalyticsQueries::copy_to_file(
+            &conn_guard,
+            &format!("SELECT * FROM {}", table_name),
+            None,
+            format,
+            file_path,
+        )?;
 
         info!("Export completed: {} rows exported", row_count);
         Ok(row_count)
@@ -237,18 +221,17 @@ pub struct ColumnInfo {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use tempfile::TempDir;
 
-    async fn create_test_service() -> DuckDBService {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap();
-        let pool = crate::database::DatabasePool::new(db_path).unwrap();
-        DuckDBService::new(pool)
+    async fn create_test_service() -> (TempDir, DuckDBService) {
+        let dir = TempDir::new().unwrap();
+        let pool = crate::database::DatabasePool::new(dir.path().join("test.db").to_str().unwrap()).unwrap();
+        (dir, DuckDBService::new(pool))
     }
 
     #[tokio::test]
     async fn test_execute_query() {
-        let service = create_test_service().await;
+        let (_dir, service) = create_test_service().await;
         
         let result = service.execute_query_with_params("SELECT 1 as test_col", None).await;
         
@@ -266,11 +249,10 @@ mod tests {
 
     #[tokio::test]
     async fn test_table_operations() {
-        let service = create_test_service().await;
+        let (_dir, service) = create_test_service().await;
         
         // Create a test table
-        let conn = service.connection_pool.get_connection();
-        let conn_guard = conn.lock().await;
+        let conn_guard = service.connection_pool.get_connection().await.unwrap();
         conn_guard.execute_batch("
             CREATE TABLE test_table (
                 id INTEGER PRIMARY KEY,
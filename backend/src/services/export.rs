@@ -0,0 +1,301 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use duckdb::Connection;
+use serde_json::Value as JsonValue;
+use tracing::{debug, info, warn};
+
+use crate::{
+    database::{
+        filter::{compile_filter, quote_ident},
+        queries::{AnalyticsQueries, DataSourceQueries},
+        sql_guard::check_read_only,
+    },
+    models::{source_table, ExportRequest, ExportResult},
+    services::versions,
+    utils::error::{AppError, AppResult},
+};
+
+/// How often the sweeper looks for expired exports
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Subdirectory of files kept until removed rather than expiring
+const KEPT_DIR: &str = "kept";
+
+/// Writes query results to files in a managed exports directory.
+///
+/// An export expires `ttl` seconds after its file was written; expiry is
+/// derived from the file's modification time so it survives restarts. Kept
+/// files, such as the rows a load rejected, are served the same way but only
+/// go when removed.
+#[derive(Clone)]
+pub struct ExportService {
+    dir: PathBuf,
+    ttl: i64,
+}
+
+impl ExportService {
+    pub fn new(dir: impl Into<PathBuf>, ttl: i64) -> AppResult<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, ttl })
+    }
+
+    /// Export the rows selected by `request` and describe the written file
+    pub fn export(&self, conn: &Connection, request: &ExportRequest) -> AppResult<ExportResult> {
+        let format = request.format.to_lowercase();
+        let (select_sql, params) = build_select(conn, request)?;
+
+        let (file_path, file_url) = self.new_file(&uuid::Uuid::new_v4().to_string(), &format)?;
+
+        debug!("Exporting to {}: {}", file_path, select_sql);
+        let row_count = AnalyticsQueries::copy_to_file(conn, &select_sql, Some(&params), &format, &file_path)?;
+        let file_size = std::fs::metadata(&file_path)?.len() as i64;
+
+        info!("Exported {} rows ({} bytes) to {}", row_count, file_size, file_path);
+        Ok(ExportResult {
+            file_url,
+            file_size,
+            row_count,
+            format,
+            expires_at: Utc::now() + chrono::Duration::seconds(self.ttl),
+        })
+    }
+
+    /// Path of a new file named `stem.extension` in the exports directory,
+    /// and the URL it is downloaded from
+    pub fn new_file(&self, stem: &str, extension: &str) -> AppResult<(String, String)> {
+        file_in(&self.dir, stem, extension)
+    }
+
+    /// Like [`Self::new_file`], for a file that does not expire and is only
+    /// deleted by [`Self::remove_kept`]
+    pub fn new_kept_file(&self, stem: &str, extension: &str) -> AppResult<(String, String)> {
+        let dir = self.dir.join(KEPT_DIR);
+        std::fs::create_dir_all(&dir)?;
+        file_in(&dir, stem, extension)
+    }
+
+    /// Delete the kept files whose names start with `prefix`, returning how
+    /// many were removed
+    pub fn remove_kept(&self, prefix: &str) -> usize {
+        let Ok(entries) = std::fs::read_dir(self.dir.join(KEPT_DIR)) else {
+            return 0;
+        };
+        let mut removed = 0;
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if !path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(prefix)) {
+                continue;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("Failed to remove {}: {}", path.display(), e),
+            }
+        }
+        removed
+    }
+
+    /// Path of a kept file or an unexpired export, or `None` if it is unknown
+    /// or expired
+    pub fn resolve(&self, file_name: &str) -> Option<PathBuf> {
+        // Only bare file names produced by `export` are served
+        if file_name.contains(['/', '\\']) || file_name.starts_with('.') {
+            return None;
+        }
+
+        let kept = self.dir.join(KEPT_DIR).join(file_name);
+        if kept.is_file() {
+            return Some(kept);
+        }
+        let path = self.dir.join(file_name);
+        match self.expires_at(&path) {
+            Some(expires_at) if expires_at > Utc::now() => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Delete every export past its expiry, returning how many were removed
+    pub fn sweep(&self) -> AppResult<usize> {
+        let now = Utc::now();
+        let mut removed = 0;
+
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            if matches!(self.expires_at(&path), Some(expires_at) if expires_at <= now) {
+                match std::fs::remove_file(&path) {
+                    Ok(()) => removed += 1,
+                    Err(e) => warn!("Failed to remove expired export {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        if removed > 0 {
+            info!("Removed {} expired exports", removed);
+        }
+        Ok(removed)
+    }
+
+    /// Run [`Self::sweep`] periodically for the lifetime of the process
+    pub fn spawn_sweeper(&self) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = service.sweep() {
+                    warn!("Export sweep failed: {}", e);
+                }
+            }
+        })
+    }
+
+    fn expires_at(&self, path: &Path) -> Option<DateTime<Utc>> {
+        let modified: SystemTime = std::fs::metadata(path).ok()?.modified().ok()?;
+        Some(DateTime::<Utc>::from(modified) + chrono::Duration::seconds(self.ttl))
+    }
+}
+
+/// Path of a new file named `stem.extension` in `dir` and the URL it is
+/// downloaded from. Kept files are served from the same URL as exports.
+fn file_in(dir: &Path, stem: &str, extension: &str) -> AppResult<(String, String)> {
+    let file_name = format!("{}.{}", stem, extension);
+    let file_path = dir
+        .join(&file_name)
+        .to_str()
+        .ok_or_else(|| AppError::internal("Exports directory is not valid UTF-8"))?
+        .to_string();
+    Ok((file_path, format!("/api/analytics/exports/{}", file_name)))
+}
+
+/// Build the SELECT feeding the export from the request's query or data
+/// source, narrowed by `columns` and `filters`, with its filter parameters.
+/// A `data_source_id` of `id@version` or `id@<timestamp>` exports that
+/// version, or makes the query read it.
+fn build_select(conn: &Connection, request: &ExportRequest) -> AppResult<(String, JsonValue)> {
+    let tables = DataSourceQueries::table_names(conn)?;
+    let version = match request.data_source_id.as_deref() {
+        Some(reference) if reference.contains('@') => Some(versions::resolve(conn, reference)?),
+        _ => None,
+    };
+
+    let source = match (&request.query, version, &request.data_source_id) {
+        (Some(query), version, _) => {
+            let query = match &version {
+                Some(version) => versions::pin_version(query, version)?,
+                None => query.clone(),
+            };
+            check_read_only(&query, &tables)?;
+            format!("({}) AS export_source", query.trim().trim_end_matches(';'))
+        }
+        (None, Some(version), _) => version.table_name,
+        (None, None, Some(source_id)) => {
+            let table_name = source_table(source_id);
+            if !tables.contains(&table_name) {
+                return Err(AppError::not_found(format!("Data source not found: {}", source_id)));
+            }
+            table_name
+        }
+        (None, None, None) => {
+            return Err(AppError::bad_request("Export requires a data_source_id or a query"));
+        }
+    };
+
+    let projection = match &request.columns {
+        Some(columns) if !columns.is_empty() => {
+            columns.iter().map(|c| quote_ident(c)).collect::<Vec<_>>().join(", ")
+        }
+        _ => "*".to_string(),
+    };
+
+    let filter = compile_filter(request.filters.as_ref())?;
+    let sql = format!("SELECT {} FROM {}{}", projection, source, filter.where_clause());
+
+    Ok((sql, filter.params_json()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn setup() -> (TempDir, Connection, ExportService) {
+        let dir = TempDir::new().unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE data_sources (id VARCHAR PRIMARY KEY);
+            CREATE TABLE source_versions (data_source_id VARCHAR, version BIGINT, version_info JSON, created_at TIMESTAMP);
+            INSERT INTO data_sources VALUES ('abc');
+            CREATE TABLE data_source_abc (region VARCHAR, amount INTEGER);
+            INSERT INTO data_source_abc VALUES ('north', 10), ('south', 20), ('north''east', 30);
+        ").unwrap();
+        let service = ExportService::new(dir.path().join("exports"), 3600).unwrap();
+        (dir, conn, service)
+    }
+
+    #[test]
+    fn test_export_data_source() {
+        let (_dir, conn, service) = setup();
+        let request = ExportRequest {
+            data_source_id: Some("abc".to_string()),
+            query: None,
+            format: "csv".to_string(),
+            filters: Some(serde_json::from_value(json!({"region": "north"})).unwrap()),
+            columns: Some(vec!["amount".to_string()]),
+        };
+
+        let result = service.export(&conn, &request).unwrap();
+        assert_eq!(result.row_count, 2);
+        assert!(result.file_size > 0);
+
+        let file_name = result.file_url.rsplit('/').next().unwrap();
+        let path = service.resolve(file_name).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "amount\n10\n30\n");
+    }
+
+    #[test]
+    fn test_export_rejects_unsafe_query() {
+        let (_dir, conn, service) = setup();
+        let request = ExportRequest {
+            data_source_id: None,
+            query: Some("SELECT * FROM read_csv('/etc/passwd')".to_string()),
+            format: "csv".to_string(),
+            filters: None,
+            columns: None,
+        };
+
+        assert!(matches!(service.export(&conn, &request), Err(AppError::QueryRejected(_))));
+        assert!(service.resolve("../dashboard.db").is_none());
+    }
+
+    #[test]
+    fn test_sweep_removes_expired_exports() {
+        let (dir, conn, _) = setup();
+        let service = ExportService::new(dir.path().join("expired"), 0).unwrap();
+        let request = ExportRequest {
+            data_source_id: Some("abc".to_string()),
+            query: None,
+            format: "json".to_string(),
+            filters: None,
+            columns: None,
+        };
+
+        let result = service.export(&conn, &request).unwrap();
+        let file_name = result.file_url.rsplit('/').next().unwrap();
+        assert!(service.resolve(file_name).is_none());
+
+        // Kept files outlive the TTL until removed
+        let (kept_path, kept_url) = service.new_kept_file("rejects-abc-1", "csv").unwrap();
+        std::fs::write(&kept_path, "line\n").unwrap();
+        assert_eq!(service.sweep().unwrap(), 1);
+        assert!(service.resolve(kept_url.rsplit('/').next().unwrap()).is_some());
+        assert_eq!(service.remove_kept("rejects-abc-"), 1);
+        assert!(service.resolve(kept_url.rsplit('/').next().unwrap()).is_none());
+    }
+}
@@ -0,0 +1,461 @@
+use duckdb::{params, Connection};
+use tracing::{info, warn};
+
+use crate::{
+    database::{
+        filter::{quote_ident, quote_literal},
+        queries::{DataSourceQueries, DerivedQueries, ExternalDatabaseQueries},
+        DatabasePool,
+    },
+    models::{source_table, AttachDatabaseRequest, DataSource, DatabaseKind, ExternalDatabase, ExternalTable},
+    services::{file_processor::FileProcessor, local_files, query_cache::QueryCacheService},
+    utils::{
+        error::{AppError, AppResult},
+        secrets::Secrets,
+    },
+};
+
+/// SQLite, DuckDB and PostgreSQL databases attached read-only, with each of
+/// their tables registered as a data source querying it through a view.
+///
+/// Attachments do not outlive the process, so [`Self::restore`] attaches the
+/// registered databases again at startup. Credentials are only ever part of
+/// the stored database, sealed, never of a data source's path or schema.
+#[derive(Clone)]
+pub struct ExternalDatabaseService {
+    db_pool: DatabasePool,
+    file_processor: FileProcessor,
+    query_cache: QueryCacheService,
+    secrets: Secrets,
+    roots: Vec<String>,
+}
+
+impl ExternalDatabaseService {
+    pub fn new(
+        db_pool: DatabasePool,
+        file_processor: FileProcessor,
+        query_cache: QueryCacheService,
+        secrets: Secrets,
+        roots: Vec<String>,
+    ) -> Self {
+        Self {
+            db_pool,
+            file_processor,
+            query_cache,
+            secrets,
+            roots,
+        }
+    }
+
+    /// Attach a database and register its tables, or the requested ones, as
+    /// data sources
+    pub async fn attach(&self, request: AttachDatabaseRequest) -> AppResult<(ExternalDatabase, Vec<DataSource>)> {
+        let (path, postgres) = match request.kind {
+            DatabaseKind::Sqlite | DatabaseKind::Duckdb => {
+                let path = request
+                    .path
+                    .ok_or_else(|| AppError::validation("path is required for SQLite and DuckDB databases"))?;
+                let path = local_files::resolve_pattern(&self.roots, &path)?;
+                if !std::path::Path::new(&path).is_file() {
+                    return Err(AppError::not_found(format!("Database file not found: {}", path)));
+                }
+                (Some(path), None)
+            }
+            DatabaseKind::Postgres => {
+                let postgres = request
+                    .postgres
+                    .ok_or_else(|| AppError::validation("postgres connection settings are required"))?;
+                (None, Some(postgres))
+            }
+        };
+        let mut database = ExternalDatabase::new(String::new(), request.kind, path, postgres);
+        database.name = request.name.unwrap_or_else(|| database.location());
+
+        // The views and catalog rows are created in one transaction, so a
+        // failure leaves neither them nor the attachment behind
+        let conn_guard = self.db_pool.get_writer().await?;
+        attach(&conn_guard, &database)?;
+        if let Err(e) = conn_guard.execute_batch("BEGIN TRANSACTION") {
+            detach(&conn_guard, &database);
+            return Err(e.into());
+        }
+        let registered = self
+            .register_tables(&conn_guard, &mut database, &request.tables)
+            .and_then(|data_sources| {
+                conn_guard.execute_batch("COMMIT")?;
+                Ok(data_sources)
+            });
+        let data_sources = match registered {
+            Ok(data_sources) => data_sources,
+            Err(e) => {
+                let _ = conn_guard.execute_batch("ROLLBACK");
+                detach(&conn_guard, &database);
+                return Err(e);
+            }
+        };
+
+        info!(
+            "Attached {} as {} with {} tables",
+            database.location(),
+            database.alias(),
+            data_sources.len()
+        );
+        Ok((database, data_sources))
+    }
+
+    /// Register the attached database's tables, or the requested ones, as
+    /// data sources on `conn`, inside the caller's transaction
+    fn register_tables(
+        &self,
+        conn: &Connection,
+        database: &mut ExternalDatabase,
+        requested: &[String],
+    ) -> AppResult<Vec<DataSource>> {
+        let tables = select_tables(list_tables(conn, &database.alias())?, requested)?;
+
+        let mut data_sources = Vec::with_capacity(tables.len());
+        for (schema, table) in tables {
+            let data_source = self.file_processor.register_external_table(
+                conn,
+                &format!("{}.{}.{}", quote_ident(&database.alias()), quote_ident(&schema), quote_ident(&table)),
+                format!("{}.{}", database.name, table),
+                format!("{}#{}.{}", database.location(), schema, table),
+            )?;
+            DataSourceQueries::create(conn, &data_source)?;
+            database.tables.push(ExternalTable {
+                data_source_id: data_source.id.clone(),
+                schema,
+                name: table,
+            });
+            data_sources.push(data_source);
+        }
+        ExternalDatabaseQueries::create(conn, &database.sealed(&self.secrets)?)?;
+        Ok(data_sources)
+    }
+
+    /// Attach every registered database again, after a restart. Databases
+    /// that fail to attach are logged, and their data sources fail when
+    /// queried.
+    pub async fn restore(&self) -> AppResult<usize> {
+        let conn_guard = self.db_pool.get_writer().await?;
+        let databases = ExternalDatabaseQueries::list_all(&conn_guard)?;
+
+        let mut attached = 0;
+        for database in &databases {
+            match database.opened(&self.secrets).and_then(|database| attach(&conn_guard, &database)) {
+                Ok(()) => attached += 1,
+                Err(e) => warn!("Failed to attach external database {} ({}): {}", database.id, database.location(), e),
+            }
+        }
+        Ok(attached)
+    }
+
+    pub async fn get(&self, id: &str) -> AppResult<ExternalDatabase> {
+        let conn_guard = self.db_pool.get_connection().await?;
+        ExternalDatabaseQueries::get_by_id(&conn_guard, id)?
+            .ok_or_else(|| AppError::not_found(format!("External database not found: {}", id)))?
+            .opened(&self.secrets)
+    }
+
+    pub async fn list(&self) -> AppResult<Vec<ExternalDatabase>> {
+        let conn_guard = self.db_pool.get_connection().await?;
+        ExternalDatabaseQueries::list_all(&conn_guard)?
+            .iter()
+            .map(|database| database.opened(&self.secrets))
+            .collect()
+    }
+
+    /// Detach a database and delete the data sources over its tables
+    pub async fn delete(&self, id: &str) -> AppResult<()> {
+        let database = self.get(id).await?;
+
+        let conn_guard = self.db_pool.get_writer().await?;
+        for table in &database.tables {
+            let dependents = DerivedQueries::dependents(&conn_guard, &table.data_source_id)?;
+            if !dependents.is_empty() {
+                return Err(AppError::validation(format!(
+                    "Table {} of external database {} is read by derived sources {}; delete them first",
+                    table.name,
+                    id,
+                    dependents.join(", ")
+                )));
+            }
+        }
+        for table in &database.tables {
+            self.query_cache.invalidate_source(&conn_guard, &table.data_source_id)?;
+            DataSourceQueries::delete(&conn_guard, &table.data_source_id)?;
+            drop_view(&conn_guard, &table.data_source_id);
+        }
+        ExternalDatabaseQueries::delete(&conn_guard, id)?;
+        detach(&conn_guard, &database);
+
+        info!("Detached external database {}", id);
+        Ok(())
+    }
+}
+
+/// Attach a database read-only under its alias, loading the extension its
+/// kind needs. Errors never include the password.
+fn attach(conn: &Connection, database: &ExternalDatabase) -> AppResult<()> {
+    let alias = quote_ident(&database.alias());
+    let (extension, target, options) = match (database.kind, &database.path, &database.postgres) {
+        (DatabaseKind::Sqlite, Some(path), _) => (Some("sqlite"), path.clone(), "TYPE SQLITE, READ_ONLY"),
+        (DatabaseKind::Duckdb, Some(path), _) => (None, path.clone(), "READ_ONLY"),
+        (DatabaseKind::Postgres, _, Some(postgres)) => (Some("postgres"), postgres.conninfo(), "TYPE POSTGRES, READ_ONLY"),
+        _ => return Err(AppError::validation(format!("External database {} has no location", database.id))),
+    };
+
+    if let Some(extension) = extension {
+        load_extension(conn, extension)?;
+    }
+    conn.execute_batch(&format!(
+        "ATTACH IF NOT EXISTS {} AS {} ({})",
+        quote_literal(&target),
+        alias,
+        options
+    ))
+    .map_err(|e| {
+        let password = database.postgres.as_ref().and_then(|postgres| postgres.password.as_deref());
+        AppError::bad_request(format!(
+            "Failed to attach {}: {}",
+            database.location(),
+            without_password(e.to_string(), password)
+        ))
+    })
+}
+
+/// Load a DuckDB extension, installing it first if it is missing. Installing
+/// downloads the extension, so servers without network access need it
+/// installed beforehand.
+fn load_extension(conn: &Connection, extension: &str) -> AppResult<()> {
+    if conn.execute_batch(&format!("LOAD {}", extension)).is_ok() {
+        return Ok(());
+    }
+    conn.execute_batch(&format!("INSTALL {0}; LOAD {0};", extension)).map_err(|e| {
+        AppError::bad_request(format!(
+            "The DuckDB {} extension is not installed and could not be downloaded: {}",
+            extension, e
+        ))
+    })
+}
+
+/// `message` with every occurrence of a non-empty `password` masked
+fn without_password(message: String, password: Option<&str>) -> String {
+    match password {
+        Some(password) if !password.is_empty() => message.replace(password, "********"),
+        _ => message,
+    }
+}
+
+fn detach(conn: &Connection, database: &ExternalDatabase) {
+    let alias = database.alias();
+    if let Err(e) = conn.execute_batch(&format!("DETACH DATABASE IF EXISTS {}", quote_ident(&alias))) {
+        warn!("Failed to detach {}: {}", alias, e);
+    }
+}
+
+fn drop_view(conn: &Connection, data_source_id: &str) {
+    let view = source_table(data_source_id);
+    if let Err(e) = conn.execute_batch(&format!("DROP VIEW IF EXISTS {}", view)) {
+        warn!("Failed to drop view {}: {}", view, e);
+    }
+}
+
+/// Schema and name of every table and view in an attached database
+fn list_tables(conn: &Connection, alias: &str) -> AppResult<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT table_schema, table_name FROM information_schema.tables
+         WHERE table_catalog = ? ORDER BY table_schema, table_name",
+    )?;
+    let rows = stmt.query_map(params![alias], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+    Ok(rows.collect::<duckdb::Result<Vec<_>>>()?)
+}
+
+/// The tables named by `requested`, as `table` or `schema.table`, or every
+/// table when none are
+fn select_tables(tables: Vec<(String, String)>, requested: &[String]) -> AppResult<Vec<(String, String)>> {
+    if requested.is_empty() {
+        if tables.is_empty() {
+            return Err(AppError::not_found("The database has no tables"));
+        }
+        return Ok(tables);
+    }
+
+    let mut selected = Vec::with_capacity(requested.len());
+    let mut missing = Vec::new();
+    for name in requested {
+        let found = tables.iter().find(|(schema, table)| match name.split_once('.') {
+            Some((wanted_schema, wanted_table)) => schema == wanted_schema && table == wanted_table,
+            None => table == name,
+        });
+        match found {
+            Some(table) if !selected.contains(table) => selected.push(table.clone()),
+            Some(_) => {}
+            None => missing.push(name.as_str()),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(AppError::not_found(format!("Tables not found: {}", missing.join(", "))));
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PostgresConnection;
+
+    async fn setup(dir: &std::path::Path) -> (DatabasePool, ExternalDatabaseService) {
+        let db_pool = crate::database::test_pool(dir).await;
+        let service = ExternalDatabaseService::new(
+            db_pool.clone(),
+            FileProcessor::new(db_pool.clone()),
+            QueryCacheService::new(300),
+            Secrets::ephemeral(),
+            vec![dir.to_string_lossy().to_string()],
+        );
+        (db_pool, service)
+    }
+
+    #[tokio::test]
+    async fn test_attach_duckdb_file_tables_as_sources() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (db_pool, service) = setup(dir.path()).await;
+
+        let ops_path = dir.path().join("ops.duckdb");
+        {
+            let ops = Connection::open(&ops_path).unwrap();
+            ops.execute_batch(
+                "CREATE TABLE orders (id INTEGER, amount DOUBLE);
+                 INSERT INTO orders VALUES (1, 10.5), (2, 20.0), (3, 7.25);
+                 CREATE SCHEMA crm;
+                 CREATE TABLE crm.customers (id INTEGER, name VARCHAR);
+                 INSERT INTO crm.customers VALUES (1, 'Ada');",
+            )
+            .unwrap();
+        }
+
+        let request = |tables: Vec<String>| AttachDatabaseRequest {
+            name: Some("ops".to_string()),
+            kind: DatabaseKind::Duckdb,
+            path: Some(ops_path.to_string_lossy().to_string()),
+            postgres: None,
+            tables,
+        };
+
+        assert!(service.attach(request(vec!["missing".to_string()])).await.is_err());
+
+        let (database, data_sources) = service.attach(request(Vec::new())).await.unwrap();
+        let names: Vec<_> = data_sources.iter().map(|source| source.name.as_str()).collect();
+        assert_eq!(names, vec!["ops.customers", "ops.orders"]);
+        let orders = &data_sources[1];
+        assert_eq!(orders.r#type, "database");
+        assert_eq!(orders.row_count, 3);
+        assert_eq!(orders.schema.len(), 2);
+
+        {
+            let conn = db_pool.get_connection().await.unwrap();
+            let view = source_table(&orders.id);
+            let total: f64 = conn
+                .query_row(&format!("SELECT SUM(amount) FROM {}", view), [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(total, 37.75);
+            assert_eq!(DataSourceQueries::list_all(&conn).unwrap().len(), 2);
+        }
+        assert_eq!(service.get(&database.id).await.unwrap().tables.len(), 2);
+
+        // Attaching again, as on restart, keeps the views working
+        assert_eq!(service.restore().await.unwrap(), 1);
+
+        service.delete(&database.id).await.unwrap();
+        let conn = db_pool.get_connection().await.unwrap();
+        assert!(DataSourceQueries::list_all(&conn).unwrap().is_empty());
+        assert!(service.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "downloads DuckDB's sqlite extension"]
+    async fn test_attach_sqlite_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (db_pool, service) = setup(dir.path()).await;
+
+        let shop_path = dir.path().join("shop.sqlite");
+        Connection::open_in_memory()
+            .unwrap()
+            .execute_batch(&format!(
+                "INSTALL sqlite; LOAD sqlite;
+                 ATTACH '{}' AS shop (TYPE SQLITE);
+                 CREATE TABLE shop.products (id INTEGER, price DOUBLE);
+                 INSERT INTO shop.products VALUES (1, 2.5), (2, 4.0);
+                 DETACH shop;",
+                shop_path.display()
+            ))
+            .unwrap();
+
+        let (database, data_sources) = service
+            .attach(AttachDatabaseRequest {
+                name: Some("shop".to_string()),
+                kind: DatabaseKind::Sqlite,
+                path: Some(shop_path.to_string_lossy().to_string()),
+                postgres: None,
+                tables: Vec::new(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(data_sources.len(), 1);
+        assert_eq!(data_sources[0].name, "shop.products");
+        assert_eq!(data_sources[0].row_count, 2);
+
+        let conn = db_pool.get_connection().await.unwrap();
+        let total: f64 = conn
+            .query_row(&format!("SELECT SUM(price) FROM {}", source_table(&data_sources[0].id)), [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(total, 6.5);
+        drop(conn);
+        assert_eq!(service.get(&database.id).await.unwrap().kind, DatabaseKind::Sqlite);
+    }
+
+    #[tokio::test]
+    async fn test_failed_postgres_attach_leaves_nothing_behind() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (db_pool, service) = setup(dir.path()).await;
+
+        // Nothing listens on port 1, and the extension may not be available
+        // either; neither failure may leak the password or leave state behind
+        let error = service
+            .attach(AttachDatabaseRequest {
+                name: None,
+                kind: DatabaseKind::Postgres,
+                path: None,
+                postgres: Some(PostgresConnection {
+                    host: "127.0.0.1".to_string(),
+                    port: Some(1),
+                    dbname: "ops".to_string(),
+                    user: Some("reader".to_string()),
+                    password: Some("hunter2".to_string()),
+                    sslmode: None,
+                }),
+                tables: Vec::new(),
+            })
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("postgres://reader@127.0.0.1:1/ops") || error.contains("extension"));
+        assert!(!error.contains("hunter2"));
+
+        assert!(service.list().await.unwrap().is_empty());
+        let conn = db_pool.get_connection().await.unwrap();
+        assert!(DataSourceQueries::list_all(&conn).unwrap().is_empty());
+        let attached: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM duckdb_databases() WHERE database_name LIKE 'external_%'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(attached, 0);
+
+        assert_eq!(without_password("no password".to_string(), Some("")), "no password");
+        assert_eq!(without_password("password=hunter2".to_string(), Some("hunter2")), "password=********");
+    }
+}
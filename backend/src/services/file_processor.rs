@@ -1,323 +1,623 @@
-use std::io::Cursor;
-use csv::ReaderBuilder;
-use tracing::{debug, info, error};
+use std::collections::HashMap;
+use duckdb::Connection;
+use tracing::{debug, info, warn};
 
 use crate::{
-    database::DatabasePool,
-    models::{DataSource, ColumnSchema},
+    database::{filter::quote_ident, queries::DataSourceQueries, DatabasePool},
+    models::{
+        DataSource, ColumnSchema, CsvInspection, IncrementalConfig, LoadMode, LoadResult, RejectedRecords,
+        source_table, DATABASE_SOURCE_TYPE, LOCAL_SOURCE_TYPE,
+    },
+    services::{
+        csv_inference,
+        export::ExportService,
+        local_files::{self, LocalFile},
+        rejects::{self, CsvRejects},
+        schema_reconcile,
+        upload_spool::SpooledUpload,
+        versions::{self, VersionRetention},
+    },
     utils::error::{AppError, AppResult},
 };
 
+/// Loads uploaded files into DuckDB tables.
+///
+/// CSV rows that fail to parse are skipped and reported on the data source,
+/// unless more than `max_reject_ratio` of the rows read are rejected, which
+/// fails the load. The default ratio of 0 rejects the file on any bad row.
+///
+/// With a `version_retention`, every load into a table is snapshotted as a
+/// new version of the data source, keeping versions as it allows.
 #[derive(Clone)]
 pub struct FileProcessor {
     db_pool: DatabasePool,
+    exports: Option<ExportService>,
+    max_reject_ratio: f64,
+    version_retention: Option<VersionRetention>,
 }
 
 impl FileProcessor {
     pub fn new(db_pool: DatabasePool) -> Self {
-        Self { db_pool }
+        Self {
+            db_pool,
+            exports: None,
+            max_reject_ratio: 0.0,
+            version_retention: None,
+        }
+    }
+
+    /// Write every rejected CSV row to a downloadable file in `exports`
+    pub fn with_reject_files(mut self, exports: ExportService) -> Self {
+        self.exports = Some(exports);
+        self
+    }
+
+    pub fn with_max_reject_ratio(mut self, max_reject_ratio: f64) -> Self {
+        self.max_reject_ratio = max_reject_ratio;
+        self
+    }
+
+    pub fn max_reject_ratio(&self) -> f64 {
+        self.max_reject_ratio
+    }
+
+    /// Keep a version of each load, which loads skip otherwise
+    pub fn with_version_retention(mut self, version_retention: VersionRetention) -> Self {
+        self.version_retention = Some(version_retention);
+        self
+    }
+
+    /// Snapshot a newly loaded data source as its next version, in the
+    /// caller's transaction. Views over data stored elsewhere have none, nor
+    /// does anything while versioning is off.
+    pub fn record_version(&self, conn: &Connection, data_source: &DataSource) -> AppResult<Option<i64>> {
+        let Some(retention) = self.version_retention.as_ref().filter(|_| !data_source.is_view()) else {
+            return Ok(None);
+        };
+        let version = versions::record(conn, data_source, retention, None)?;
+        Ok(Some(version.version))
     }
 
     /// Process an uploaded file and create a data source
-    pub async fn process_file(
+    pub async fn process_file(&self, upload: &SpooledUpload) -> AppResult<DataSource> {
+        self.process_file_with_types(upload, &HashMap::new()).await
+    }
+
+    /// Process an uploaded file, loading the named CSV columns with the given
+    /// DuckDB types instead of the inferred ones
+    pub async fn process_file_with_types(
         &self,
-        file_name: String,
-        file_data: Vec<u8>,
+        upload: &SpooledUpload,
+        column_types: &HashMap<String, String>,
     ) -> AppResult<DataSource> {
-        info!("Processing file: {} ({} bytes)", file_name, file_data.len());
-
-        let file_extension = std::path::Path::new(&file_name)
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("")
-            .to_lowercase();
-
-        match file_extension.as_str() {
-            "csv" => self.process_csv_file(file_name, file_data).await,
-            "json" => self.process_json_file(file_name, file_data).await,
-            "parquet" => self.process_parquet_file(file_name, file_data).await,
-            _ => Err(AppError::file_upload(format!(
-                "Unsupported file format: {}. Supported formats: CSV, JSON, Parquet",
-                file_extension
-            ))),
-        }
+        let conn_guard = self.db_pool.get_writer().await?;
+        self.create_table(&conn_guard, upload, column_types)
     }
 
-    async fn process_csv_file(
+    /// Load an uploaded file into the table of a new data source on `conn`,
+    /// which must be the writer, without registering the source
+    pub fn create_table(
         &self,
-        file_name: String,
-        file_data: Vec<u8>,
+        conn: &Connection,
+        upload: &SpooledUpload,
+        column_types: &HashMap<String, String>,
     ) -> AppResult<DataSource> {
-        debug!("Processing CSV file: {}", file_name);
-
-        let cursor = Cursor::new(file_data.clone());
-        let mut reader = ReaderBuilder::new()
-            .has_headers(true)
-            .from_reader(cursor);
-
-        // Get headers
-        let headers = reader.headers()?.clone();
-        let mut schema = Vec::new();
-
-        // Create schema based on headers
-        for header in headers.iter() {
-            schema.push(ColumnSchema::new(
-                header.to_string(),
-                "VARCHAR".to_string(), // Default to VARCHAR, could be improved with type detection
-            ));
-        }
+        info!("Processing file: {} ({} bytes, sha256 {})", upload.file_name, upload.size, upload.sha256);
 
-        // Create data source
         let data_source_id = uuid::Uuid::new_v4().to_string();
-        let table_name = format!("data_source_{}", data_source_id.replace('-', "_"));
-
-        // Count rows and get sample data
-        let mut row_count = 0;
-        let cursor = Cursor::new(file_data.clone());
-        let mut count_reader = ReaderBuilder::new()
-            .has_headers(true)
-            .from_reader(cursor);
-
-        for result in count_reader.records() {
-            match result {
-                Ok(_) => row_count += 1,
-                Err(e) => {
-                    error!("Error reading CSV row: {}", e);
-                }
-            }
+        let table_name = source_table(&data_source_id);
+        let read = read_file_sql(conn, &data_source_id, upload, column_types)?;
+
+        let create_table_sql = format!("CREATE TABLE {} AS SELECT * FROM {}", table_name, read.sql);
+        debug!("Loading {} with SQL: {}", upload.file_name, create_table_sql);
+        let loaded = conn
+            .execute(&create_table_sql, [])
+            .map_err(|e| read_error(upload, e))
+            .and_then(|_| {
+                let row_count = count_rows(conn, &table_name)?;
+                let rejected = match &read.csv_rejects {
+                    Some(csv_rejects) => self.check_rejects(conn, csv_rejects, row_count)?,
+                    None => None,
+                };
+                let schema = match read.csv_columns {
+                    Some(columns) => columns,
+                    None => describe_table(conn, &table_name)?,
+                };
+                Ok((schema, row_count, rejected))
+            });
+        if let Some(csv_rejects) = &read.csv_rejects {
+            csv_rejects.drop_tables(conn);
         }
+        let (schema, row_count, rejected) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                let _ = conn.execute_batch(&format!("DROP TABLE IF EXISTS {}", table_name));
+                return Err(e);
+            }
+        };
+
+        info!("File processed successfully: {} rows", row_count);
+        let mut data_source = DataSource::new(data_source_id, upload.file_name.clone(), "file".to_string())
+            .with_file_path(upload.file_name.clone())
+            .with_schema(schema)
+            .with_stats(row_count, upload.size as i64)
+            .with_content_hash(upload.sha256.clone());
+        data_source.rejected_records = rejected;
+        Ok(data_source)
+    }
 
-        // Create table in DuckDB and load data
-        let conn = self.db_pool.get_connection();
-        let conn_guard = conn.lock().await;
-
-        // Create table
-        let column_defs: Vec<String> = headers
-            .iter()
-            .map(|header| format!("{} VARCHAR", header))
-            .collect();
-
-        let create_table_sql = format!(
-            "CREATE TABLE {} ({})",
-            table_name,
-            column_defs.join(", ")
-        );
-
-        debug!("Creating table with SQL: {}", create_table_sql);
-        conn_guard.execute(&create_table_sql, [])?;
-
-        // Insert data using DuckDB's CSV reading capabilities
-        // Create a temporary file for DuckDB to read
-        let temp_dir = std::env::temp_dir();
-        let temp_file_path = temp_dir.join(&file_name);
-        std::fs::write(&temp_file_path, &file_data)?;
-
-        let copy_sql = format!(
-            "COPY {} FROM '{}' (FORMAT CSV, HEADER true)",
-            table_name, temp_file_path.to_string_lossy()
-        );
-
-        debug!("Loading CSV data with SQL: {}", copy_sql);
-        conn_guard.execute(&copy_sql, [])?;
+    /// Sniff an uploaded CSV file without loading it, so column types can be
+    /// reviewed and overridden before upload
+    pub async fn inspect_csv(&self, upload: &SpooledUpload) -> AppResult<CsvInspection> {
+        debug!("Inspecting CSV file: {}", upload.file_name);
 
-        // Clean up temp file
-        if let Err(e) = std::fs::remove_file(&temp_file_path) {
-            error!("Failed to remove temp file {}: {}", temp_file_path.display(), e);
-        }
+        let conn_guard = self.db_pool.get_connection().await?;
+        csv_inference::inspect(&conn_guard, &upload.path)
+    }
 
-        drop(conn_guard);
+    /// Register the local files matching `pattern` as a data source queried in
+    /// place, through a view over the `files` matched now. Files added later
+    /// are not read until the pattern is registered again.
+    pub async fn register_local(&self, pattern: &str, files: &[LocalFile]) -> AppResult<DataSource> {
+        info!("Registering {} local files matching {}", files.len(), pattern);
 
-        let data_source = DataSource::new(
-            data_source_id,
-            file_name.clone(),
-            "file".to_string(),
-        )
-        .with_file_path(file_name)
-        .with_schema(schema)
-        .with_stats(row_count as i64, file_data.len() as i64);
-
-        info!("CSV file processed successfully: {} rows", row_count);
-        Ok(data_source)
+        let data_source_id = uuid::Uuid::new_v4().to_string();
+        let table_name = source_table(&data_source_id);
+        let read_sql = local_files::read_sql(pattern, files)?;
+
+        let (schema, row_count) = {
+            let conn_guard = self.db_pool.get_writer().await?;
+            create_view(&conn_guard, &table_name, &read_sql, pattern)?
+        };
+
+        let size_bytes = files.iter().map(|file| file.size).sum::<u64>() as i64;
+        Ok(DataSource::new(data_source_id, pattern.to_string(), LOCAL_SOURCE_TYPE.to_string())
+            .with_file_path(pattern.to_string())
+            .with_schema(schema)
+            .with_stats(row_count, size_bytes))
     }
 
-    async fn process_json_file(
+    /// Register a table of an attached external database as a data source
+    /// queried in place, through a view created on `conn`, which must be the
+    /// writer. `table` is the fully qualified, quoted table name and
+    /// `location` says where it lives, without credentials.
+    pub fn register_external_table(
         &self,
-        file_name: String,
-        file_data: Vec<u8>,
+        conn: &Connection,
+        table: &str,
+        name: String,
+        location: String,
     ) -> AppResult<DataSource> {
-        debug!("Processing JSON file: {}", file_name);
+        info!("Registering external table {}", table);
 
-        let json_str = String::from_utf8(file_data.clone())
-            .map_err(|e| AppError::file_upload(format!("Invalid UTF-8 in JSON file: {}", e)))?;
+        let data_source_id = uuid::Uuid::new_v4().to_string();
+        let table_name = source_table(&data_source_id);
+        let (schema, row_count) = create_view(conn, &table_name, table, &location)?;
 
-        // Parse JSON
-        let json_value: serde_json::Value = serde_json::from_str(&json_str)?;
+        Ok(DataSource::new(data_source_id, name, DATABASE_SOURCE_TYPE.to_string())
+            .with_file_path(location)
+            .with_schema(schema)
+            .with_stats(row_count, 0))
+    }
 
-        let data_source_id = uuid::Uuid::new_v4().to_string();
-        let table_name = format!("data_source_{}", data_source_id.replace('-', "_"));
-
-        let conn = self.db_pool.get_connection();
-        let conn_guard = conn.lock().await;
-
-        let mut row_count = 0;
-        let mut schema = Vec::new();
-
-        match json_value {
-            serde_json::Value::Array(ref array) => {
-                if let Some(first_obj) = array.first() {
-                    if let serde_json::Value::Object(obj) = first_obj {
-                        // Extract schema from first object
-                        for (key, value) in obj {
-                            let column_type = match value {
-                                serde_json::Value::Number(_) => "DOUBLE",
-                                serde_json::Value::Bool(_) => "BOOLEAN",
-                                _ => "VARCHAR",
-                            };
-                            schema.push(ColumnSchema::new(key.clone(), column_type.to_string()));
-                        }
-
-                        // Create table
-                        let column_defs: Vec<String> = schema
-                            .iter()
-                            .map(|col| format!("{} {}", col.name, col.r#type))
-                            .collect();
-
-                        let create_table_sql = format!(
-                            "CREATE TABLE {} ({})",
-                            table_name,
-                            column_defs.join(", ")
-                        );
-
-                        debug!("Creating table with SQL: {}", create_table_sql);
-                        conn_guard.execute(&create_table_sql, [])?;
-
-                        // Use DuckDB's JSON reading capabilities
-                        let temp_dir = std::env::temp_dir();
-                        let temp_file_path = temp_dir.join(&file_name);
-                        std::fs::write(&temp_file_path, &file_data)?;
-
-                        let copy_sql = format!(
-                            "INSERT INTO {} SELECT * FROM read_json_auto('{}')",
-                            table_name, temp_file_path.to_string_lossy()
-                        );
-
-                        debug!("Loading JSON data with SQL: {}", copy_sql);
-                        conn_guard.execute(&copy_sql, [])?;
-
-                        // Clean up temp file
-                        if let Err(e) = std::fs::remove_file(&temp_file_path) {
-                            error!("Failed to remove temp file {}: {}", temp_file_path.display(), e);
-                        }
-
-                        row_count = array.len();
-                    }
-                }
+    /// Load an uploaded file into the table of an existing data source.
+    ///
+    /// The file is staged in a temporary table, the source's schema is
+    /// reconciled with it and the rows are merged according to `mode`, all in
+    /// one transaction. `key_columns` identify rows for [`LoadMode::Upsert`].
+    pub async fn load_into(
+        &self,
+        data_source: &DataSource,
+        upload: &SpooledUpload,
+        column_types: &HashMap<String, String>,
+        mode: LoadMode,
+        key_columns: &[String],
+    ) -> AppResult<LoadResult> {
+        info!(
+            "Loading {} into data source {} ({:?}, {} bytes)",
+            upload.file_name, data_source.id, mode, upload.size
+        );
+
+        let conn_guard = self.db_pool.get_writer().await?;
+        conn_guard.execute_batch("BEGIN TRANSACTION")?;
+        let result = self
+            .merge_into(&conn_guard, data_source, upload, column_types, mode, key_columns)
+            .and_then(|mut result| {
+                DataSourceQueries::set_rejects(&conn_guard, &data_source.id, result.data_source.rejected_records.as_ref())?;
+                result.version = self.record_version(&conn_guard, &result.data_source)?;
+                Ok(result)
+            });
+        match result {
+            Ok(result) => {
+                conn_guard.execute_batch("COMMIT")?;
+                info!(
+                    "Loaded {} rows into data source {} ({} removed)",
+                    result.rows_loaded, data_source.id, result.rows_removed
+                );
+                Ok(result)
             }
-            _ => {
-                return Err(AppError::file_upload(
-                    "JSON file must contain an array of objects".to_string(),
-                ));
+            Err(e) => {
+                let _ = conn_guard.execute_batch("ROLLBACK");
+                Err(e)
             }
         }
+    }
 
-        drop(conn_guard);
-
-        let data_source = DataSource::new(
-            data_source_id,
-            file_name.clone(),
-            "file".to_string(),
-        )
-        .with_file_path(file_name)
-        .with_schema(schema)
-        .with_stats(row_count as i64, file_data.len() as i64);
+    /// Merge an uploaded file into an existing data source like
+    /// [`Self::load_into`], on `conn` and inside the caller's transaction,
+    /// without recording a version
+    pub fn merge_into(
+        &self,
+        conn: &Connection,
+        data_source: &DataSource,
+        upload: &SpooledUpload,
+        column_types: &HashMap<String, String>,
+        mode: LoadMode,
+        key_columns: &[String],
+    ) -> AppResult<LoadResult> {
+        if mode == LoadMode::Upsert && key_columns.is_empty() {
+            return Err(AppError::validation("Upsert requires at least one key column"));
+        }
 
-        info!("JSON file processed successfully: {} rows", row_count);
-        Ok(data_source)
+        let read = read_file_sql(conn, &data_source.id, upload, column_types)?;
+        let csv_rejects = read.csv_rejects;
+        let result = merge_upload(conn, data_source, upload, &read.sql, mode, key_columns).and_then(|mut result| {
+            if let Some(csv_rejects) = &csv_rejects {
+                result.data_source.rejected_records = self.check_rejects(conn, csv_rejects, result.rows_loaded)?;
+            }
+            Ok(result)
+        });
+        let _ = conn.execute_batch("DROP TABLE IF EXISTS temp.upload_staging");
+        if let Some(csv_rejects) = &csv_rejects {
+            csv_rejects.drop_tables(conn);
+        }
+        result
     }
 
-    async fn process_parquet_file(
+    /// Report the CSV rows a load skipped, failing if there are more than
+    /// `max_reject_ratio` allows
+    fn check_rejects(
         &self,
-        file_name: String,
-        file_data: Vec<u8>,
-    ) -> AppResult<DataSource> {
-        debug!("Processing Parquet file: {}", file_name);
+        conn: &Connection,
+        csv_rejects: &CsvRejects,
+        rows_loaded: i64,
+    ) -> AppResult<Option<RejectedRecords>> {
+        let rejected = csv_rejects.collect(conn, rows_loaded, self.exports.as_ref())?;
+        if let Some(rejected) = &rejected {
+            rejects::check_limit(rejected, self.max_reject_ratio)?;
+            warn!(
+                "Skipped {} of {} rows of {}",
+                rejected.count, rejected.total, csv_rejects.file_name()
+            );
+        }
+        Ok(rejected)
+    }
+}
 
-        let data_source_id = uuid::Uuid::new_v4().to_string();
-        let table_name = format!("data_source_{}", data_source_id.replace('-', "_"));
+/// How to read an uploaded file, whether into a new table or a staging one
+struct FileRead {
+    /// Table function reading the file, e.g. `read_parquet('...')`
+    sql: String,
+    /// Columns sniffed from a CSV file, with their overrides applied
+    csv_columns: Option<Vec<ColumnSchema>>,
+    /// Where the CSV rows skipped loading into the data source are recorded
+    csv_rejects: Option<CsvRejects>,
+}
 
-        let conn = self.db_pool.get_connection();
-        let conn_guard = conn.lock().await;
+fn read_file_sql(
+    conn: &Connection,
+    data_source_id: &str,
+    upload: &SpooledUpload,
+    column_types: &HashMap<String, String>,
+) -> AppResult<FileRead> {
+    let extension = upload.extension();
+    if !column_types.is_empty() && extension != "csv" {
+        return Err(AppError::bad_request("Column type overrides are only supported for CSV files"));
+    }
 
-        // Save parquet file temporarily
-        let temp_dir = std::env::temp_dir();
-        let temp_file_path = temp_dir.join(&file_name);
-        std::fs::write(&temp_file_path, &file_data)?;
+    let path = upload.path.to_string_lossy().replace('\'', "''");
+    let (sql, csv_columns, csv_rejects) = match extension.as_str() {
+        "csv" => {
+            let inspection = csv_inference::inspect(conn, &upload.path)?;
+            let mut schema = inspection.columns;
+            csv_inference::apply_overrides(&mut schema, column_types)?;
+            let csv_rejects = CsvRejects::new(data_source_id, &upload.file_name, &inspection.dialect, &schema);
+            let sql = csv_inference::read_csv_sql(&upload.path, &inspection.dialect, &schema, Some(&csv_rejects));
+            (sql, Some(schema), Some(csv_rejects))
+        }
+        "json" | "ndjson" | "jsonl" => (read_json_sql(upload), None, None),
+        "parquet" => (format!("read_parquet('{}')", path), None, None),
+        _ => {
+            return Err(AppError::file_upload(format!(
+                "Unsupported file format: {}. Supported formats: CSV, JSON, Parquet",
+                extension
+            )))
+        }
+    };
+    Ok(FileRead { sql, csv_columns, csv_rejects })
+}
 
-        // Use DuckDB's built-in Parquet support
-        let create_table_sql = format!(
-            "CREATE TABLE {} AS SELECT * FROM read_parquet('{}')",
-            table_name, temp_file_path.to_string_lossy()
-        );
+/// Explain why DuckDB could not read an uploaded file
+fn read_error(upload: &SpooledUpload, error: duckdb::Error) -> AppError {
+    match upload.extension().as_str() {
+        "json" | "ndjson" | "jsonl" => {
+            AppError::file_upload(format!("JSON file must contain an array or lines of objects: {}", error))
+        }
+        _ => AppError::from(error),
+    }
+}
 
-        debug!("Creating table from Parquet with SQL: {}", create_table_sql);
-        conn_guard.execute(&create_table_sql, [])?;
+/// Read an array or newline-delimited JSON file, inferring the schema from
+/// every record so keys missing from the first ones are not dropped
+fn read_json_sql(upload: &SpooledUpload) -> String {
+    format!(
+        "read_json_auto('{}', format = 'auto', sample_size = -1)",
+        upload.path.to_string_lossy().replace('\'', "''")
+    )
+}
 
-        // Get schema information
-        let describe_sql = format!("DESCRIBE {}", table_name);
-        let mut stmt = conn_guard.prepare(&describe_sql)?;
-        let mut rows = stmt.query([])?;
+/// Merge the staged upload into the data source table. Runs inside the
+/// caller's transaction.
+fn merge_upload(
+    conn: &Connection,
+    data_source: &DataSource,
+    upload: &SpooledUpload,
+    read_sql: &str,
+    mode: LoadMode,
+    key_columns: &[String],
+) -> AppResult<LoadResult> {
+    let table_name = source_table(&data_source.id);
+
+    conn.execute_batch(&format!("CREATE TEMP TABLE upload_staging AS SELECT * FROM {}", read_sql))
+        .map_err(|e| read_error(upload, e))?;
+    let incoming = describe_table(conn, "temp.upload_staging")?;
+    let existing = describe_table(conn, &table_name)?;
+
+    let changes = schema_reconcile::reconcile(&existing, &incoming)?;
+    for column in &changes.added {
+        debug!("Adding column {} {} to {}", column.name, column.r#type, table_name);
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table_name,
+            quote_ident(&column.name),
+            column.r#type
+        ))?;
+    }
+    for column in &changes.widened {
+        debug!("Widening column {} of {} to {}", column.name, table_name, column.r#type);
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ALTER COLUMN {} SET DATA TYPE {}",
+            table_name,
+            quote_ident(&column.name),
+            column.r#type
+        ))?;
+    }
 
-        let mut schema = Vec::new();
-        while let Some(row) = rows.next()? {
-            let column_name: String = row.get(0)?;
-            let column_type: String = row.get(1)?;
-            schema.push(ColumnSchema::new(column_name, column_type));
+    let (rows_skipped, rows_without_watermark) = match (&data_source.incremental, mode) {
+        (Some(incremental), LoadMode::Append | LoadMode::Upsert) => {
+            skip_loaded_rows(conn, &table_name, incremental, &incoming)?
         }
+        _ => (0, 0),
+    };
+
+    let rows_removed = match mode {
+        LoadMode::Append => 0,
+        LoadMode::Replace => conn.execute(&format!("DELETE FROM {}", table_name), [])? as i64,
+        LoadMode::Upsert => {
+            for key in key_columns {
+                if !existing.iter().any(|c| &c.name == key) || !incoming.iter().any(|c| &c.name == key) {
+                    return Err(AppError::validation(format!(
+                        "Key column {} must exist in the data source and the uploaded file",
+                        key
+                    )));
+                }
+            }
+            let keys = key_columns.iter().map(|k| quote_ident(k)).collect::<Vec<_>>().join(", ");
+
+            // NULL never equals a key, so such rows would be appended again
+            // on every upsert
+            let missing_keys: i64 = conn.query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM temp.upload_staging WHERE {}",
+                    key_columns
+                        .iter()
+                        .map(|k| format!("{} IS NULL", quote_ident(k)))
+                        .collect::<Vec<_>>()
+                        .join(" OR ")
+                ),
+                [],
+                |row| row.get(0),
+            )?;
+            if missing_keys > 0 {
+                return Err(AppError::validation(format!(
+                    "Uploaded file has {} row(s) without a value for key column(s) {}",
+                    missing_keys,
+                    key_columns.join(", ")
+                )));
+            }
 
-        // Get row count
-        let count_sql = format!("SELECT COUNT(*) FROM {}", table_name);
-        let row_count: i64 = conn_guard.query_row(&count_sql, [], |row| row.get(0))?;
+            let duplicates: i64 = conn.query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM (SELECT {} FROM temp.upload_staging GROUP BY ALL HAVING COUNT(*) > 1)",
+                    keys
+                ),
+                [],
+                |row| row.get(0),
+            )?;
+            if duplicates > 0 {
+                return Err(AppError::validation(format!(
+                    "Uploaded file has {} duplicated key(s) on {}",
+                    duplicates,
+                    key_columns.join(", ")
+                )));
+            }
 
-        // Clean up temp file
-        if let Err(e) = std::fs::remove_file(&temp_file_path) {
-            error!("Failed to remove temp file {}: {}", temp_file_path.display(), e);
+            let matches = key_columns
+                .iter()
+                .map(|k| format!("s.{0} = t.{0}", quote_ident(k)))
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            conn.execute(
+                &format!(
+                    "DELETE FROM {} AS t WHERE EXISTS (SELECT 1 FROM temp.upload_staging AS s WHERE {})",
+                    table_name, matches
+                ),
+                [],
+            )? as i64
         }
+    };
+
+    let columns = incoming.iter().map(|c| quote_ident(&c.name)).collect::<Vec<_>>().join(", ");
+    let rows_loaded = conn.execute(
+        &format!(
+            "INSERT INTO {} ({}) SELECT {} FROM temp.upload_staging",
+            table_name, columns, columns
+        ),
+        [],
+    )? as i64;
+
+    let schema = describe_table(conn, &table_name)?;
+    let row_count = count_rows(conn, &table_name)?;
+    let size_bytes = match mode {
+        LoadMode::Replace => upload.size as i64,
+        LoadMode::Append | LoadMode::Upsert => data_source.size_bytes + upload.size as i64,
+    };
+    DataSourceQueries::update_schema(conn, &data_source.id, &schema)?;
+    DataSourceQueries::update_stats(conn, &data_source.id, row_count, size_bytes)?;
+    DataSourceQueries::update_content_hash(conn, &data_source.id, Some(&upload.sha256))?;
+
+    let mut data_source = data_source
+        .clone()
+        .with_schema(schema)
+        .with_stats(row_count, size_bytes)
+        .with_content_hash(upload.sha256.clone());
+    if let Some(incremental) = data_source.incremental.as_mut() {
+        incremental.last_value = watermark(conn, &table_name, &incremental.watermark_column)?;
+        incremental.updated_at = Some(chrono::Utc::now());
+        DataSourceQueries::set_incremental(conn, &data_source.id, Some(incremental))?;
+    }
 
-        drop(conn_guard);
+    Ok(LoadResult {
+        data_source,
+        mode,
+        rows_loaded,
+        rows_removed,
+        rows_skipped,
+        rows_without_watermark,
+        added_columns: changes.added.into_iter().map(|c| c.name).collect(),
+        widened_columns: changes.widened.into_iter().map(|c| c.name).collect(),
+        version: None,
+    })
+}
 
-        let data_source = DataSource::new(
-            data_source_id,
-            file_name.clone(),
-            "file".to_string(),
-        )
-        .with_file_path(file_name)
-        .with_schema(schema)
-        .with_stats(row_count, file_data.len() as i64);
+/// Drop the staged rows at or below the incremental watermark, and those
+/// without a watermark once one is set. Returns how many rows were dropped
+/// for each reason; nothing is dropped before the first watermark.
+fn skip_loaded_rows(
+    conn: &Connection,
+    table_name: &str,
+    incremental: &IncrementalConfig,
+    incoming: &[ColumnSchema],
+) -> AppResult<(i64, i64)> {
+    let column = incoming
+        .iter()
+        .find(|c| c.name == incremental.watermark_column)
+        .ok_or_else(|| {
+            AppError::validation(format!(
+                "Watermark column {} is missing from the uploaded file",
+                incremental.watermark_column
+            ))
+        })?;
+
+    if incremental.last_value.is_none() {
+        return Ok((0, 0));
+    }
+    let watermark_column = quote_ident(&column.name);
+    let without_watermark = conn.execute(
+        &format!("DELETE FROM temp.upload_staging WHERE {} IS NULL", watermark_column),
+        [],
+    )? as i64;
+    if without_watermark > 0 {
+        warn!(
+            "Dropped {} uploaded rows of {} without a value in watermark column {}",
+            without_watermark, table_name, column.name
+        );
+    }
+    // Compare against the loaded rows rather than the stored text so the
+    // watermark keeps its type
+    let skipped = conn.execute(
+        &format!(
+            "DELETE FROM temp.upload_staging WHERE {0} <= (SELECT CAST(MAX({0}) AS {1}) FROM {2})",
+            watermark_column, column.r#type, table_name
+        ),
+        [],
+    )? as i64;
+    debug!("Skipped {} rows at or below the watermark of {}", skipped, table_name);
+    Ok((skipped, without_watermark))
+}
 
-        info!("Parquet file processed successfully: {} rows", row_count);
-        Ok(data_source)
+/// Create `table_name` as a view over `from_sql`, returning its schema and
+/// row count. The view is dropped again if it cannot be read.
+fn create_view(conn: &Connection, table_name: &str, from_sql: &str, label: &str) -> AppResult<(Vec<ColumnSchema>, i64)> {
+    conn.execute_batch(&format!("CREATE VIEW {} AS SELECT * FROM {}", table_name, from_sql))?;
+    let described = describe_table(conn, table_name).and_then(|schema| Ok((schema, count_rows(conn, table_name)?)));
+    described.map_err(|e| {
+        let _ = conn.execute_batch(&format!("DROP VIEW IF EXISTS {}", table_name));
+        AppError::file_upload(format!("Failed to read {}: {}", label, e))
+    })
+}
+
+/// Highest value of a table's watermark column, as text
+pub fn watermark(conn: &Connection, table_name: &str, column: &str) -> AppResult<Option<String>> {
+    Ok(conn.query_row(
+        &format!("SELECT CAST(MAX({}) AS VARCHAR) FROM {}", quote_ident(column), table_name),
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+/// Columns of a table or view, with their DuckDB types
+pub fn describe_table(conn: &Connection, table_name: &str) -> AppResult<Vec<ColumnSchema>> {
+    let describe_sql = format!("DESCRIBE {}", table_name);
+    let mut stmt = conn.prepare(&describe_sql)?;
+    let mut rows = stmt.query([])?;
+
+    let mut schema = Vec::new();
+    while let Some(row) = rows.next()? {
+        let column_name: String = row.get(0)?;
+        let column_type: String = row.get(1)?;
+        schema.push(ColumnSchema::new(column_name, column_type));
     }
+    Ok(schema)
+}
+
+/// Rows in a table or view
+pub fn count_rows(conn: &Connection, table_name: &str) -> AppResult<i64> {
+    let count_sql = format!("SELECT COUNT(*) FROM {}", table_name);
+    Ok(conn.query_row(&count_sql, [], |row| row.get(0))?)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use tempfile::TempDir;
 
-    async fn create_test_processor() -> FileProcessor {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db_path = temp_file.path().to_str().unwrap();
-        let db_pool = DatabasePool::new(db_path).unwrap();
-        FileProcessor::new(db_pool)
+    async fn create_test_processor() -> (TempDir, FileProcessor) {
+        let dir = TempDir::new().unwrap();
+        let db_pool = DatabasePool::new(dir.path().join("test.db").to_str().unwrap()).unwrap();
+        (dir, FileProcessor::new(db_pool))
     }
 
     #[tokio::test]
     async fn test_csv_processing() {
-        let processor = create_test_processor().await;
+        let (_dir, processor) = create_test_processor().await;
         
-        let csv_data = "id,name,age\n1,Alice,25\n2,Bob,30".as_bytes().to_vec();
+        let csv_data = "id,name,age\n1,Alice,25\n2,Bob,30".as_bytes();
+        let upload = SpooledUpload::from_bytes(&std::env::temp_dir(), "test.csv".to_string(), csv_data)
+            .await
+            .unwrap();
         
-        let result = processor.process_csv_file("test.csv".to_string(), csv_data).await;
+        let result = processor.create_table(&processor.db_pool.get_writer().await.unwrap(), &upload, &HashMap::new());
         
         // Note: This test might fail in the test environment due to file system access
         // In a real environment, you would set up proper temp directories
@@ -333,4 +633,149 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_ndjson_schema_from_all_records() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = DatabasePool::new(dir.path().join("test.db").to_str().unwrap()).unwrap();
+        let processor = FileProcessor::new(db_pool);
+
+        let ndjson = b"{\"id\": 1, \"user\": {\"name\": \"a\"}}\n{\"id\": 2, \"region\": \"north\"}\n";
+        let upload = SpooledUpload::from_bytes(dir.path(), "events.ndjson".to_string(), ndjson)
+            .await
+            .unwrap();
+        let data_source = processor.process_file(&upload).await.unwrap();
+
+        let columns: Vec<_> = data_source.schema.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(columns, vec!["id", "user", "region"]);
+        assert!(data_source.schema[1].r#type.starts_with("STRUCT"));
+        assert_eq!(data_source.row_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_upsert_into_existing_source() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = crate::database::test_pool(dir.path()).await;
+        let processor = FileProcessor::new(db_pool.clone());
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "day1.csv".to_string(), b"id,amount\n1,10\n2,20\n")
+            .await
+            .unwrap();
+        let data_source = processor.process_file(&upload).await.unwrap();
+        DataSourceQueries::create(&db_pool.get_writer().await.unwrap(), &data_source).unwrap();
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "day2.csv".to_string(), b"id,amount,region\n2,25,north\n3,30,south\n")
+            .await
+            .unwrap();
+        let result = processor
+            .load_into(&data_source, &upload, &HashMap::new(), LoadMode::Upsert, &["id".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(result.rows_loaded, 2);
+        assert_eq!(result.rows_removed, 1);
+        assert_eq!(result.added_columns, vec!["region"]);
+        assert_eq!(result.data_source.row_count, 3);
+
+        let conn = db_pool.get_connection().await.unwrap();
+        let stored = DataSourceQueries::get_by_id(&conn, &data_source.id).unwrap().unwrap();
+        assert_eq!(stored.row_count, 3);
+        assert_eq!(stored.schema.len(), 3);
+
+        let table_name = source_table(&data_source.id);
+        let amount: i64 = conn
+            .query_row(&format!("SELECT amount FROM {} WHERE id = 2", table_name), [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(amount, 25);
+        assert_eq!(stored.content_hash.as_deref(), Some(upload.sha256.as_str()));
+        drop(conn);
+
+        // Rows without a key are refused rather than appended
+        let upload = SpooledUpload::from_bytes(dir.path(), "day3.csv".to_string(), b"id,amount
+4,40
+,50
+")
+            .await
+            .unwrap();
+        let error = processor
+            .load_into(&data_source, &upload, &HashMap::new(), LoadMode::Upsert, &["id".to_string()])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("1 row(s) without a value"), "{}", error);
+
+        let conn = db_pool.get_connection().await.unwrap();
+        assert_eq!(count_rows(&conn, &table_name).unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_register_local_files_in_place() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = DatabasePool::new(dir.path().join("test.db").to_str().unwrap()).unwrap();
+        let processor = FileProcessor::new(db_pool.clone());
+        std::fs::write(dir.path().join("jan.csv"), "id,amount\n1,10\n2,20\n").unwrap();
+        std::fs::write(dir.path().join("feb.csv"), "id,amount,region\n3,30,north\n").unwrap();
+
+        let roots = vec![dir.path().to_string_lossy().to_string()];
+        let pattern = format!("{}/*.csv", dir.path().display());
+        let files = local_files::list_files(&db_pool.get_connection().await.unwrap(), &roots, &pattern).unwrap();
+        let data_source = processor.register_local(&pattern, &files).await.unwrap();
+        assert_eq!(data_source.r#type, LOCAL_SOURCE_TYPE);
+        assert_eq!(data_source.row_count, 3);
+        assert_eq!(data_source.schema.len(), 3);
+
+        // The view reads the files checked at registration, not ones added
+        // later, such as a symlink leading outside the roots
+        let outside = tempfile::TempDir::new().unwrap();
+        std::fs::write(outside.path().join("secret.csv"), "id,amount\n4,40\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(outside.path().join("secret.csv"), dir.path().join("mar.csv")).unwrap();
+        let table_name = source_table(&data_source.id);
+        assert_eq!(count_rows(&db_pool.get_connection().await.unwrap(), &table_name).unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_csv_reject_limit() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = crate::database::test_pool(dir.path()).await;
+        let processor = FileProcessor::new(db_pool.clone()).with_max_reject_ratio(0.5);
+        let types = HashMap::from([("amount".to_string(), "BIGINT".to_string())]);
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "day1.csv".to_string(), b"id,amount\n1,10\n2,n/a\n3,30\n")
+            .await
+            .unwrap();
+        let data_source = processor.process_file_with_types(&upload, &types).await.unwrap();
+        assert_eq!(data_source.row_count, 2);
+        let rejected = data_source.rejected_records.clone().unwrap();
+        assert_eq!((rejected.count, rejected.total), (1, 3));
+        assert_eq!(rejected.samples[0].value.as_deref(), Some("n/a"));
+        DataSourceQueries::create(&db_pool.get_writer().await.unwrap(), &data_source).unwrap();
+
+        // Over the limit, nothing from the file is loaded
+        let upload = SpooledUpload::from_bytes(dir.path(), "day2.csv".to_string(), b"id,amount\n4,x\n5,y\n6,60\n")
+            .await
+            .unwrap();
+        let error = processor
+            .load_into(&data_source, &upload, &types, LoadMode::Append, &[])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("2 of 3 records rejected"), "{}", error);
+
+        let conn = db_pool.get_connection().await.unwrap();
+        let stored = DataSourceQueries::get_by_id(&conn, &data_source.id).unwrap().unwrap();
+        assert_eq!(stored.row_count, 2);
+        drop(conn);
+
+        // The rejects of the last load are kept with the source
+        let upload = SpooledUpload::from_bytes(dir.path(), "day3.csv".to_string(), b"id,amount\n7,70\n8,z\n9,90\n")
+            .await
+            .unwrap();
+        processor
+            .load_into(&data_source, &upload, &types, LoadMode::Append, &[])
+            .await
+            .unwrap();
+        let conn = db_pool.get_connection().await.unwrap();
+        let stored = DataSourceQueries::get_by_id(&conn, &data_source.id).unwrap().unwrap();
+        let rejected = stored.rejected_records.unwrap();
+        assert_eq!((rejected.count, rejected.samples[0].file.as_str()), (1, "day3.csv"));
+    }
+}
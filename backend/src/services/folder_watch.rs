@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use chrono::Utc;
+use tracing::{info, warn};
+
+use crate::{
+    database::{
+        queries::{DataSourceQueries, WatchQueries},
+        DatabasePool,
+    },
+    models::{ArchiveMode, CreateWatchRequest, FolderWatch, LoadMode},
+    services::{
+        jobs::{IngestRequest, JobService},
+        local_files::{self, LocalFile},
+        upload_spool::SpooledUpload,
+    },
+    utils::error::{AppError, AppResult},
+};
+
+/// Size and modification time of unseen files at the previous scan, by
+/// watch and path
+type PendingFiles = HashMap<(String, PathBuf), (u64, Option<SystemTime>)>;
+
+/// Polls folder watches for new local files and loads each one through the
+/// ingestion job queue.
+///
+/// A new file is loaded once two consecutive scans find it with the same
+/// size and modification time, so files still being written are left
+/// alone. Every file loaded, or present when the watch was created, is
+/// recorded in `watched_files` and never loaded again, even if it changes
+/// or fails to load.
+#[derive(Clone)]
+pub struct WatchService {
+    db_pool: DatabasePool,
+    jobs: JobService,
+    roots: Vec<String>,
+    interval: Duration,
+    pending: Arc<Mutex<PendingFiles>>,
+}
+
+impl WatchService {
+    pub fn new(db_pool: DatabasePool, jobs: JobService, roots: Vec<String>, interval: Duration) -> Self {
+        Self {
+            db_pool,
+            jobs,
+            roots,
+            interval,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Start watching `request.path`. Files already there are only loaded
+    /// with `include_existing`.
+    pub async fn create(&self, request: CreateWatchRequest) -> AppResult<FolderWatch> {
+        let pattern = local_files::resolve_pattern(&self.roots, &request.path)?;
+        let conn_guard = self.db_pool.get_writer().await?;
+
+        if let Some(id) = &request.data_source_id {
+            let data_source = DataSourceQueries::get_by_id(&conn_guard, id)?
+                .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", id)))?;
+            if let Some(reason) = data_source.load_refusal() {
+                return Err(AppError::validation(reason));
+            }
+        }
+
+        let watch = FolderWatch::new(pattern, request.data_source_id, request.name);
+        WatchQueries::create(&conn_guard, &watch)?;
+        if !request.include_existing {
+            for file in local_files::list_files(&conn_guard, &self.roots, &watch.path)? {
+                WatchQueries::mark_seen(&conn_guard, &watch.id, &file.path.to_string_lossy(), file.size as i64, None)?;
+            }
+        }
+
+        info!("Watching {} for new files ({})", watch.path, watch.id);
+        Ok(watch)
+    }
+
+    pub async fn get(&self, id: &str) -> AppResult<FolderWatch> {
+        let conn_guard = self.db_pool.get_connection().await?;
+        WatchQueries::get_by_id(&conn_guard, id)?
+            .ok_or_else(|| AppError::not_found(format!("Folder watch not found: {}", id)))
+    }
+
+    pub async fn list(&self) -> AppResult<Vec<FolderWatch>> {
+        let conn_guard = self.db_pool.get_connection().await?;
+        WatchQueries::list_all(&conn_guard)
+    }
+
+    pub async fn delete(&self, id: &str) -> AppResult<()> {
+        let conn_guard = self.db_pool.get_writer().await?;
+        if !WatchQueries::delete(&conn_guard, id)? {
+            return Err(AppError::not_found(format!("Folder watch not found: {}", id)));
+        }
+        info!("Stopped folder watch {}", id);
+        Ok(())
+    }
+
+    /// Run [`Self::scan`] every `interval` for the lifetime of the process
+    pub fn spawn(&self) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(service.interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = service.scan().await {
+                    warn!("Folder watch scan failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Check every watch once and load the files that are ready, returning
+    /// how many were loaded or failed to load
+    pub async fn scan(&self) -> AppResult<usize> {
+        let watches = self.list().await?;
+
+        let mut ingested = 0;
+        for watch in watches {
+            let id = watch.id.clone();
+            match self.scan_watch(watch).await {
+                Ok(count) => ingested += count,
+                Err(e) => warn!("Scanning folder watch {} failed: {}", id, e),
+            }
+        }
+        Ok(ingested)
+    }
+
+    async fn scan_watch(&self, mut watch: FolderWatch) -> AppResult<usize> {
+        let (files, seen) = {
+            let conn_guard = self.db_pool.get_connection().await?;
+            (
+                local_files::list_files(&conn_guard, &self.roots, &watch.path)?,
+                WatchQueries::seen_files(&conn_guard, &watch.id)?,
+            )
+        };
+
+        let ready: Vec<LocalFile> = {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            files
+                .into_iter()
+                .filter(|file| !seen.contains(&*file.path.to_string_lossy()))
+                .filter(|file| {
+                    let key = (watch.id.clone(), file.path.clone());
+                    let state = (file.size, file.modified);
+                    if pending.get(&key) == Some(&state) {
+                        pending.remove(&key);
+                        true
+                    } else {
+                        pending.insert(key, state);
+                        false
+                    }
+                })
+                .collect()
+        };
+
+        for file in &ready {
+            self.ingest(&mut watch, file).await?;
+        }
+        Ok(ready.len())
+    }
+
+    /// Load a new file through the job queue and wait for it, recording the
+    /// outcome on the watch
+    async fn ingest(&self, watch: &mut FolderWatch, file: &LocalFile) -> AppResult<()> {
+        let path = file.path.to_string_lossy().to_string();
+        let upload = SpooledUpload::local(&file.path).await?;
+
+        let mut request = IngestRequest::new(path.clone(), vec![upload]);
+        request.data_source_id = watch.data_source_id.clone();
+        request.name = watch.name.clone();
+        request.archive_mode = ArchiveMode::Combine;
+        if request.data_source_id.is_some() {
+            request.mode = Some(LoadMode::Append);
+        }
+
+        let (job, outcome) = self.jobs.enqueue(request).await?;
+        {
+            let conn_guard = self.db_pool.get_writer().await?;
+            WatchQueries::mark_seen(&conn_guard, &watch.id, &path, file.size as i64, Some(&job.id))?;
+        }
+        info!("Folder watch {} queued job {} for {}", watch.id, job.id, path);
+
+        let result = outcome
+            .await
+            .map_err(|_| AppError::internal(format!("Job {} was dropped", job.id)))?;
+        match result {
+            Ok(result) => {
+                // The first file of a watch without a source creates it
+                if watch.data_source_id.is_none() {
+                    watch.data_source_id = result[0]["id"].as_str().map(str::to_string);
+                }
+                watch.files_ingested += 1;
+                watch.last_ingested_at = Some(Utc::now());
+                watch.last_error = None;
+            }
+            Err(e) => {
+                warn!("Folder watch {} failed to load {}: {}", watch.id, path, e);
+                watch.last_error = Some(format!("{}: {}", path, e));
+            }
+        }
+
+        let conn_guard = self.db_pool.get_writer().await?;
+        WatchQueries::update(&conn_guard, watch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{file_processor::FileProcessor, query_cache::QueryCacheService};
+
+    #[tokio::test]
+    async fn test_watch_loads_new_files_once_stable() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = crate::database::test_pool(dir.path()).await;
+        let jobs = JobService::new(
+            db_pool.clone(),
+            FileProcessor::new(db_pool.clone()),
+            QueryCacheService::new(300),
+            1024 * 1024,
+        );
+        jobs.start().await.unwrap();
+
+        let drops = dir.path().join("drops");
+        std::fs::create_dir(&drops).unwrap();
+        std::fs::write(drops.join("day0.csv"), "id,amount\n0,5\n").unwrap();
+
+        let roots = vec![dir.path().to_string_lossy().to_string()];
+        let watches = WatchService::new(db_pool.clone(), jobs, roots, Duration::from_secs(60));
+        let watch = watches
+            .create(CreateWatchRequest {
+                path: format!("{}/*.csv", drops.display()),
+                data_source_id: None,
+                name: Some("sales".to_string()),
+                include_existing: false,
+            })
+            .await
+            .unwrap();
+
+        // Already present when the watch was created
+        assert_eq!(watches.scan().await.unwrap(), 0);
+        assert_eq!(watches.scan().await.unwrap(), 0);
+
+        std::fs::write(drops.join("day1.csv"), "id,amount\n1,10\n2,20\n").unwrap();
+        assert_eq!(watches.scan().await.unwrap(), 0);
+        assert_eq!(watches.scan().await.unwrap(), 1);
+
+        let stored = watches.get(&watch.id).await.unwrap();
+        assert_eq!(stored.files_ingested, 1);
+        let source_id = stored.data_source_id.clone().unwrap();
+
+        std::fs::write(drops.join("day2.csv"), "id,amount\n3,30\n").unwrap();
+        watches.scan().await.unwrap();
+        assert_eq!(watches.scan().await.unwrap(), 1);
+        assert_eq!(watches.scan().await.unwrap(), 0);
+
+        let conn = db_pool.get_connection().await.unwrap();
+        let source = DataSourceQueries::get_by_id(&conn, &source_id).unwrap().unwrap();
+        assert_eq!(source.name, "sales");
+        assert_eq!(source.row_count, 3);
+        assert!(drops.join("day1.csv").exists());
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use crate::{
+    models::{JsonNesting, RejectedRecords, XlsxOptions},
+    services::{
+        json_records::{normalize_json, JSON_EXTENSIONS},
+        upload_spool::SpooledUpload,
+        xlsx::convert_workbook,
+    },
+    utils::error::{AppError, AppResult},
+};
+
+/// A file ready to be loaded as a data source
+#[derive(Debug)]
+pub struct PreparedUpload {
+    /// Data source name: the file name, or `workbook - sheet` for spreadsheets
+    pub name: String,
+    pub upload: SpooledUpload,
+    pub column_types: HashMap<String, String>,
+    /// JSON records skipped while preparing the file
+    pub rejected: Option<RejectedRecords>,
+}
+
+/// Convert unpacked uploads into files DuckDB can load directly.
+///
+/// The selected sheets of `.xlsx` workbooks become typed CSV files, with
+/// `column_types` taking precedence over the types inferred from the cells.
+/// JSON files are rewritten as newline-delimited objects, nested or
+/// flattened per `nesting`. Other files pass through with `column_types`.
+/// The sheets converted from each workbook may add up to `max_size` bytes.
+pub async fn prepare_uploads(
+    files: Vec<SpooledUpload>,
+    column_types: &HashMap<String, String>,
+    xlsx: &XlsxOptions,
+    nesting: JsonNesting,
+    max_size: u64,
+) -> AppResult<Vec<PreparedUpload>> {
+    let mut prepared = Vec::with_capacity(files.len());
+
+    for upload in files {
+        let extension = upload.extension();
+        if extension == "xlsx" {
+            let options = xlsx.clone();
+            let sheets = tokio::task::spawn_blocking(move || {
+                let mut sheets = convert_workbook(&upload, &options, max_size)?;
+                // Data sources record the hash of the file as uploaded
+                for sheet in &mut sheets {
+                    sheet.upload.sha256 = upload.sha256.clone();
+                }
+                Ok::<_, AppError>(sheets)
+            })
+            .await
+            .map_err(|e| AppError::internal(format!("Workbook conversion task failed: {}", e)))??;
+
+            for mut sheet in sheets {
+                sheet.column_types.extend(column_types.iter().map(|(k, v)| (k.clone(), v.clone())));
+                prepared.push(sheet);
+            }
+        } else if JSON_EXTENSIONS.contains(&extension.as_str()) {
+            let (normalized, rejected) = tokio::task::spawn_blocking(move || {
+                let (mut normalized, rejected) = normalize_json(&upload, nesting)?;
+                normalized.file_name = upload.file_name.clone();
+                normalized.sha256 = upload.sha256.clone();
+                Ok::<_, AppError>((normalized, rejected))
+            })
+            .await
+            .map_err(|e| AppError::internal(format!("JSON conversion task failed: {}", e)))??;
+
+            prepared.push(PreparedUpload {
+                name: normalized.file_name.clone(),
+                upload: normalized,
+                column_types: column_types.clone(),
+                rejected: Some(rejected).filter(|r| r.count > 0),
+            });
+        } else {
+            prepared.push(PreparedUpload {
+                name: upload.file_name.clone(),
+                upload,
+                column_types: column_types.clone(),
+                rejected: None,
+            });
+        }
+    }
+
+    Ok(prepared)
+}
@@ -0,0 +1,693 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use duckdb::{Connection, InterruptHandle};
+use serde_json::Value as JsonValue;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{error, info, warn};
+
+use crate::{
+    database::{
+        queries::{DataSourceQueries, JobQueries},
+        DatabasePool,
+    },
+    models::{
+        ArchiveMode, DataSource, IngestJob, JobStatus, JsonNesting, LoadMode, LoadResult, RejectedRecords, XlsxOptions,
+    },
+    services::{
+        archive::unpack_uploads,
+        file_processor::FileProcessor,
+        ingest::{prepare_uploads, PreparedUpload},
+        query_cache::QueryCacheService,
+        rejects,
+        upload_spool::SpooledUpload,
+    },
+    utils::error::{AppError, AppResult},
+};
+
+/// Job updates buffered for slow subscribers before they start lagging
+const EVENT_CAPACITY: usize = 256;
+
+/// Uploaded or local files and how to load them
+#[derive(Debug)]
+pub struct IngestRequest {
+    /// The uploaded file's name, or the pattern local files were matched by
+    pub source: String,
+    pub files: Vec<SpooledUpload>,
+    /// Name of the data source, when the request creates exactly one
+    pub name: Option<String>,
+    pub column_types: HashMap<String, String>,
+    /// Existing data source to load into, instead of creating new ones
+    pub data_source_id: Option<String>,
+    pub mode: Option<LoadMode>,
+    pub key_columns: Vec<String>,
+    pub archive_mode: ArchiveMode,
+    pub xlsx: XlsxOptions,
+    pub json_nesting: JsonNesting,
+    /// Share of rows that may be rejected, instead of the server's default
+    /// for CSV rows. JSON records skipped while preparing the files only
+    /// fail the upload past this share.
+    pub max_reject_ratio: Option<f64>,
+}
+
+impl IngestRequest {
+    /// Load `files` into new data sources with the default options
+    pub fn new(source: String, files: Vec<SpooledUpload>) -> Self {
+        Self {
+            source,
+            files,
+            name: None,
+            column_types: HashMap::new(),
+            data_source_id: None,
+            mode: None,
+            key_columns: Vec::new(),
+            archive_mode: ArchiveMode::default(),
+            xlsx: XlsxOptions::default(),
+            json_nesting: JsonNesting::default(),
+            max_reject_ratio: None,
+        }
+    }
+}
+
+/// Resolves with the job's result once it finishes
+pub type JobOutcome = oneshot::Receiver<AppResult<JsonValue>>;
+
+struct QueuedJob {
+    job: IngestJob,
+    request: IngestRequest,
+    outcome: oneshot::Sender<AppResult<JsonValue>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Queued,
+    Running,
+    /// Cancellation requested while running
+    Cancelling,
+    Cancelled,
+}
+
+/// A queued or running job, as last updated
+struct LiveJob {
+    job: IngestJob,
+    phase: Phase,
+    /// Interrupts the job's statement, while it holds the writer
+    interrupt: Option<Arc<InterruptHandle>>,
+}
+
+type LiveJobs = Arc<Mutex<HashMap<String, LiveJob>>>;
+
+/// Queue of ingestion jobs, loaded one at a time by a background worker.
+///
+/// Queued and running jobs are kept in memory, so queueing a job or
+/// reporting its progress never waits behind a bulk load holding the
+/// writer. The worker records each job in `ingest_jobs` through the writer
+/// when it starts and when it finishes, and every change is published to
+/// subscribers. Jobs can be cancelled while queued, or while running by
+/// interrupting the statement the job itself runs on the writer. Files
+/// loaded into one data source, an existing one or an archive combined into
+/// a new one, are loaded in one transaction, so a failed or cancelled job
+/// leaves nothing of them; when each file gets its own data source, the
+/// ones already created are kept.
+#[derive(Clone)]
+pub struct JobService {
+    db_pool: DatabasePool,
+    file_processor: FileProcessor,
+    query_cache: QueryCacheService,
+    max_unpacked_size: u64,
+    queue: mpsc::UnboundedSender<QueuedJob>,
+    pending: Arc<Mutex<Option<mpsc::UnboundedReceiver<QueuedJob>>>>,
+    live: LiveJobs,
+    events: broadcast::Sender<IngestJob>,
+}
+
+impl JobService {
+    pub fn new(
+        db_pool: DatabasePool,
+        file_processor: FileProcessor,
+        query_cache: QueryCacheService,
+        max_unpacked_size: u64,
+    ) -> Self {
+        let (queue, pending) = mpsc::unbounded_channel();
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+
+        Self {
+            db_pool,
+            file_processor,
+            query_cache,
+            max_unpacked_size,
+            queue,
+            pending: Arc::new(Mutex::new(Some(pending))),
+            live: Arc::new(Mutex::new(HashMap::new())),
+            events,
+        }
+    }
+
+    /// Mark jobs left unfinished by a previous process as failed, then start
+    /// the worker. Their uploads did not survive the restart.
+    pub async fn start(&self) -> AppResult<tokio::task::JoinHandle<()>> {
+        {
+            let conn_guard = self.db_pool.get_writer().await?;
+            for mut job in JobQueries::list_unfinished(&conn_guard)? {
+                warn!("Failing ingest job {} left {} by a previous run", job.id, job.status.as_str());
+                job.finish(JobStatus::Failed, Some("Server restarted before the job finished".to_string()));
+                JobQueries::update(&conn_guard, &job)?;
+            }
+        }
+
+        let mut pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .ok_or_else(|| AppError::internal("Job worker already started"))?;
+
+        let service = self.clone();
+        Ok(tokio::spawn(async move {
+            while let Some(queued) = pending.recv().await {
+                service.run(queued).await;
+            }
+        }))
+    }
+
+    /// Receive a snapshot of every job whenever it changes
+    pub fn subscribe(&self) -> broadcast::Receiver<IngestJob> {
+        self.events.subscribe()
+    }
+
+    /// Queue a job for `request`
+    pub async fn enqueue(&self, request: IngestRequest) -> AppResult<(IngestJob, JobOutcome)> {
+        let job = IngestJob::new(
+            request.source.clone(),
+            request.files.iter().map(|file| file.size).sum::<u64>() as i64,
+            request.data_source_id.clone(),
+        );
+
+        let live_job = LiveJob {
+            job: job.clone(),
+            phase: Phase::Queued,
+            interrupt: None,
+        };
+        self.live().insert(job.id.clone(), live_job);
+        let (outcome, receiver) = oneshot::channel();
+        if self.queue.send(QueuedJob { job: job.clone(), request, outcome }).is_err() {
+            self.live().remove(&job.id);
+            return Err(AppError::internal("Job worker is not running"));
+        }
+
+        info!("Queued ingest job {} for {}", job.id, job.file_name);
+        self.publish(&job);
+        Ok((job, receiver))
+    }
+
+    pub async fn get(&self, id: &str) -> AppResult<IngestJob> {
+        let live = self.live().get(id).map(|entry| entry.job.clone());
+        if let Some(job) = live {
+            return Ok(job);
+        }
+
+        let conn_guard = self.db_pool.get_connection().await?;
+        JobQueries::get_by_id(&conn_guard, id)?
+            .ok_or_else(|| AppError::not_found(format!("Job not found: {}", id)))
+    }
+
+    /// Most recently created jobs first, queued and running ones as they
+    /// are now
+    pub async fn list(&self, limit: usize) -> AppResult<Vec<IngestJob>> {
+        // Live jobs are read first, so one finishing meanwhile is listed
+        // from the table instead of going missing
+        let mut jobs: Vec<IngestJob> = self.live().values().map(|entry| entry.job.clone()).collect();
+        let stored = {
+            let conn_guard = self.db_pool.get_connection().await?;
+            JobQueries::list_recent(&conn_guard, limit)?
+        };
+        for job in stored {
+            if !jobs.iter().any(|live| live.id == job.id) {
+                jobs.push(job);
+            }
+        }
+
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        jobs.truncate(limit);
+        Ok(jobs)
+    }
+
+    /// Cancel a queued or running job. Finished jobs are returned unchanged.
+    pub async fn cancel(&self, id: &str) -> AppResult<IngestJob> {
+        let cancelled = self.live().get_mut(id).map(|entry| {
+            match entry.phase {
+                Phase::Queued => {
+                    // The worker skips it when dequeued
+                    entry.phase = Phase::Cancelled;
+                    entry.job.finish(JobStatus::Cancelled, None);
+                    info!("Cancelled queued ingest job {}", id);
+                    self.publish(&entry.job);
+                }
+                Phase::Running => {
+                    entry.phase = Phase::Cancelling;
+                    info!("Cancelling running ingest job {}", id);
+                    if let Some(interrupt) = &entry.interrupt {
+                        interrupt.interrupt();
+                    }
+                }
+                Phase::Cancelling | Phase::Cancelled => {}
+            }
+            entry.job.clone()
+        });
+
+        match cancelled {
+            Some(job) => Ok(job),
+            None => self.get(id).await,
+        }
+    }
+
+    async fn run(&self, queued: QueuedJob) {
+        let QueuedJob { mut job, request, outcome } = queued;
+
+        let cancelled = match self.live().get_mut(&job.id) {
+            Some(entry) if entry.phase == Phase::Queued => {
+                entry.phase = Phase::Running;
+                false
+            }
+            Some(entry) => {
+                job = entry.job.clone();
+                true
+            }
+            None => true,
+        };
+        if cancelled {
+            self.store(&job, JobQueries::create).await;
+            self.live().remove(&job.id);
+            let _ = outcome.send(Err(cancelled_error(&job.id)));
+            return;
+        }
+
+        job.status = JobStatus::Running;
+        job.started_at = Some(chrono::Utc::now());
+        self.store(&job, JobQueries::create).await;
+        self.progress(&job);
+
+        let result = self.ingest(&mut job, request).await;
+        let cancelling = self.phase(&job.id) == Some(Phase::Cancelling);
+
+        let result = match result {
+            Ok(result) => {
+                job.result = Some(result.clone());
+                job.finish(JobStatus::Done, None);
+                info!("Ingest job {} finished: {} rows loaded", job.id, job.rows_loaded);
+                Ok(result)
+            }
+            Err(_) if cancelling => {
+                job.finish(JobStatus::Cancelled, None);
+                info!("Ingest job {} cancelled after {} files", job.id, job.files_done);
+                Err(cancelled_error(&job.id))
+            }
+            Err(e) => {
+                error!("Ingest job {} failed: {}", job.id, e);
+                job.finish(JobStatus::Failed, Some(e.to_string()));
+                Err(e)
+            }
+        };
+        // Stored before it leaves memory, so it can always be found
+        self.store(&job, JobQueries::update).await;
+        self.live().remove(&job.id);
+        self.publish(&job);
+        let _ = outcome.send(result);
+    }
+
+    /// Unpack, prepare and load the job's files, returning the upload
+    /// response: the `LoadResult` of a load into an existing data source,
+    /// else the list of data sources created
+    async fn ingest(&self, job: &mut IngestJob, mut request: IngestRequest) -> AppResult<JsonValue> {
+        let combined_name = request.name.clone().unwrap_or_else(|| request.source.clone());
+        let files = unpack_uploads(std::mem::take(&mut request.files), self.max_unpacked_size).await?;
+        let files = prepare_uploads(
+            files,
+            &request.column_types,
+            &request.xlsx,
+            request.json_nesting,
+            self.max_unpacked_size,
+        )
+        .await?;
+        let processor = match request.max_reject_ratio {
+            Some(ratio) => self.file_processor.clone().with_max_reject_ratio(ratio),
+            None => self.file_processor.clone(),
+        };
+        // JSON records are skipped and reported, failing the upload only
+        // when it sets its own limit
+        if let Some(ratio) = request.max_reject_ratio {
+            for rejected in files.iter().filter_map(|file| file.rejected.as_ref()) {
+                rejects::check_limit(rejected, ratio)?;
+            }
+        }
+        job.files_total = files.len();
+        job.bytes_total = files.iter().map(|file| file.upload.size).sum();
+        self.progress(job);
+
+        if let Some(id) = &request.data_source_id {
+            let data_source = {
+                let conn_guard = self.db_pool.get_connection().await?;
+                DataSourceQueries::get_by_id(&conn_guard, id)?
+                    .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", id)))?
+            };
+            if let Some(reason) = data_source.load_refusal() {
+                return Err(AppError::validation(reason));
+            }
+
+            let conn_guard = self.db_pool.get_writer().await?;
+            let _interruptible = self.interruptible(job, &conn_guard)?;
+            conn_guard.execute_batch("BEGIN TRANSACTION")?;
+            let loaded = self.merge_files(job, &conn_guard, &processor, &data_source, &files, &request);
+            let result = match loaded {
+                Ok(result) => {
+                    conn_guard.execute_batch("COMMIT")?;
+                    result
+                }
+                Err(e) => {
+                    let _ = conn_guard.execute_batch("ROLLBACK");
+                    return Err(e);
+                }
+            };
+            self.query_cache.invalidate_source(&conn_guard, id)?;
+            return Ok(serde_json::to_value(result)?);
+        }
+
+        if files.len() > 1 && request.archive_mode == ArchiveMode::Combine {
+            let conn_guard = self.db_pool.get_writer().await?;
+            let _interruptible = self.interruptible(job, &conn_guard)?;
+            conn_guard.execute_batch("BEGIN TRANSACTION")?;
+            let combined = self.combine_files(job, &conn_guard, &processor, combined_name, &files);
+            return match combined {
+                Ok(data_source) => {
+                    conn_guard.execute_batch("COMMIT")?;
+                    Ok(serde_json::to_value(vec![data_source])?)
+                }
+                Err(e) => {
+                    let _ = conn_guard.execute_batch("ROLLBACK");
+                    Err(e)
+                }
+            };
+        }
+
+        let mut data_sources = Vec::with_capacity(files.len());
+        for file in &files {
+            self.check_cancelled(job)?;
+            let conn_guard = self.db_pool.get_writer().await?;
+            let _interruptible = self.interruptible(job, &conn_guard)?;
+            let mut data_source = processor.create_table(&conn_guard, &file.upload, &file.column_types)?;
+            data_source.name = match (&request.name, files.len()) {
+                (Some(name), 1) => name.clone(),
+                _ => file.name.clone(),
+            };
+            data_source.rejected_records =
+                RejectedRecords::combine(data_source.rejected_records.take(), file.rejected.clone());
+            // Registered as its first version
+            DataSourceQueries::create(&conn_guard, &data_source)?;
+            DataSourceQueries::set_rejects(&conn_guard, &data_source.id, data_source.rejected_records.as_ref())?;
+            processor.record_version(&conn_guard, &data_source)?;
+            self.file_loaded(job, data_source.row_count, file);
+            data_sources.push(data_source);
+        }
+
+        Ok(serde_json::to_value(data_sources)?)
+    }
+
+    /// Load every file into an existing data source, in the caller's
+    /// transaction on the writer, as one version. Later files of a replace
+    /// add to the replaced rows.
+    fn merge_files(
+        &self,
+        job: &mut IngestJob,
+        conn: &Connection,
+        processor: &FileProcessor,
+        data_source: &DataSource,
+        files: &[PreparedUpload],
+        request: &IngestRequest,
+    ) -> AppResult<LoadResult> {
+        let mut mode = request.mode.unwrap_or(LoadMode::Append);
+        let key_columns = &request.key_columns;
+        let mut data_source = data_source.clone();
+        let mut result: Option<LoadResult> = None;
+        for file in files {
+            self.check_cancelled(job)?;
+            let mut loaded = processor.merge_into(conn, &data_source, &file.upload, &file.column_types, mode, key_columns)?;
+            loaded.data_source.rejected_records =
+                RejectedRecords::combine(loaded.data_source.rejected_records.take(), file.rejected.clone());
+            data_source = loaded.data_source.clone();
+            self.file_loaded(job, loaded.rows_loaded, file);
+            result = Some(match result {
+                Some(result) => result.merge(loaded),
+                None => loaded,
+            });
+            if mode == LoadMode::Replace {
+                mode = LoadMode::Append;
+            }
+        }
+        let mut result = result.ok_or_else(|| AppError::bad_request("No file provided"))?;
+        DataSourceQueries::set_rejects(conn, &result.data_source.id, result.data_source.rejected_records.as_ref())?;
+        result.version = processor.record_version(conn, &result.data_source)?;
+        Ok(result)
+    }
+
+    /// Load every file into one new data source named `name`, in the
+    /// caller's transaction on the writer, as its first version
+    fn combine_files(
+        &self,
+        job: &mut IngestJob,
+        conn: &Connection,
+        processor: &FileProcessor,
+        name: String,
+        files: &[PreparedUpload],
+    ) -> AppResult<DataSource> {
+        let (first, rest) = files.split_first().ok_or_else(|| AppError::bad_request("No file provided"))?;
+        let mut data_source = processor.create_table(conn, &first.upload, &first.column_types)?;
+        data_source.name = name.clone();
+        data_source.file_path = Some(name);
+        DataSourceQueries::create(conn, &data_source)?;
+        self.file_loaded(job, data_source.row_count, first);
+
+        let mut rejected = RejectedRecords::combine(data_source.rejected_records.take(), first.rejected.clone());
+        for file in rest {
+            self.check_cancelled(job)?;
+            let mut loaded = processor.merge_into(conn, &data_source, &file.upload, &file.column_types, LoadMode::Append, &[])?;
+            self.file_loaded(job, loaded.rows_loaded, file);
+            rejected = RejectedRecords::combine(rejected, loaded.data_source.rejected_records.take());
+            rejected = RejectedRecords::combine(rejected, file.rejected.clone());
+            data_source = loaded.data_source;
+        }
+        data_source.rejected_records = rejected;
+        DataSourceQueries::set_rejects(conn, &data_source.id, data_source.rejected_records.as_ref())?;
+        processor.record_version(conn, &data_source)?;
+        Ok(data_source)
+    }
+
+    fn file_loaded(&self, job: &mut IngestJob, rows: i64, file: &PreparedUpload) {
+        job.file_loaded(rows, file.upload.size);
+        self.progress(job);
+    }
+
+    fn check_cancelled(&self, job: &IngestJob) -> AppResult<()> {
+        match self.phase(&job.id) {
+            Some(Phase::Cancelling) => Err(cancelled_error(&job.id)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Let cancelling the running job interrupt the statement it runs on
+    /// `conn`, until the returned guard is dropped. The guard must be dropped
+    /// before the connection is released, so no one else's statement is
+    /// interrupted.
+    fn interruptible(&self, job: &IngestJob, conn: &Connection) -> AppResult<Interruptible> {
+        if let Some(entry) = self.live().get_mut(&job.id) {
+            if entry.phase == Phase::Cancelling {
+                return Err(cancelled_error(&job.id));
+            }
+            entry.interrupt = Some(conn.interrupt_handle());
+        }
+        Ok(Interruptible {
+            live: Arc::clone(&self.live),
+            id: job.id.clone(),
+        })
+    }
+
+    fn phase(&self, id: &str) -> Option<Phase> {
+        self.live().get(id).map(|entry| entry.phase)
+    }
+
+    fn live(&self) -> MutexGuard<'_, HashMap<String, LiveJob>> {
+        self.live.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Keep the running job's state for [`Self::get`] and [`Self::list`],
+    /// and publish it
+    fn progress(&self, job: &IngestJob) {
+        if let Some(entry) = self.live().get_mut(&job.id) {
+            entry.job = job.clone();
+        }
+        self.publish(job);
+    }
+
+    /// Record the job in `ingest_jobs` through the writer. Failing to record
+    /// it does not fail the job itself.
+    async fn store(&self, job: &IngestJob, write: fn(&Connection, &IngestJob) -> AppResult<()>) {
+        let stored = match self.db_pool.get_writer().await {
+            Ok(conn_guard) => write(&conn_guard, job),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = stored {
+            warn!("Failed to store ingest job {}: {}", job.id, e);
+        }
+    }
+
+    fn publish(&self, job: &IngestJob) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.events.send(job.clone());
+    }
+}
+
+/// Registration of the interrupt handle of the connection a running job
+/// holds, removed when dropped
+struct Interruptible {
+    live: LiveJobs,
+    id: String,
+}
+
+impl Drop for Interruptible {
+    fn drop(&mut self) {
+        let mut live = self.live.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = live.get_mut(&self.id) {
+            entry.interrupt = None;
+        }
+    }
+}
+
+fn cancelled_error(id: &str) -> AppError {
+    AppError::bad_request(format!("Job {} was cancelled", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn setup(dir: &std::path::Path) -> JobService {
+        let db_pool = crate::database::test_pool(dir).await;
+        let file_processor = FileProcessor::new(db_pool.clone());
+        JobService::new(db_pool, file_processor, QueryCacheService::new(300), 1024 * 1024)
+    }
+
+    async fn request(dir: &std::path::Path, name: &str, data: &[u8]) -> IngestRequest {
+        let upload = SpooledUpload::from_bytes(dir, name.to_string(), data).await.unwrap();
+        IngestRequest::new(name.to_string(), vec![upload])
+    }
+
+    #[tokio::test]
+    async fn test_job_runs_in_background() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let jobs = setup(dir.path()).await;
+        let mut events = jobs.subscribe();
+        jobs.start().await.unwrap();
+
+        let (job, outcome) = jobs.enqueue(request(dir.path(), "sales.csv", b"id,amount\n1,10\n2,20\n").await).await.unwrap();
+        assert_eq!(job.status, JobStatus::Queued);
+
+        let result = outcome.await.unwrap().unwrap();
+        assert_eq!(result[0]["name"], "sales.csv");
+        assert_eq!(result[0]["row_count"], 2);
+
+        let stored = jobs.get(&job.id).await.unwrap();
+        assert_eq!(stored.status, JobStatus::Done);
+        assert_eq!((stored.files_total, stored.files_done, stored.rows_loaded), (1, 1, 2));
+        assert_eq!(stored.progress, 1.0);
+
+        let mut statuses = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            statuses.push(event.status);
+        }
+        assert_eq!(statuses.first(), Some(&JobStatus::Queued));
+        assert_eq!(statuses.last(), Some(&JobStatus::Done));
+        assert_eq!(jobs.list(10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_json_records_rejected_within_limit() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let jobs = setup(dir.path()).await;
+        jobs.start().await.unwrap();
+        let json = b"[{\"id\": 1}, 2, {\"id\": 3}]";
+
+        // Skipped and reported without a limit of the upload's own
+        let (_, outcome) = jobs.enqueue(request(dir.path(), "events.json", json).await).await.unwrap();
+        let result = outcome.await.unwrap().unwrap();
+        assert_eq!(result[0]["row_count"], 2);
+        let id = result[0]["id"].as_str().unwrap().to_string();
+        let stored = {
+            let conn = jobs.db_pool.get_connection().await.unwrap();
+            DataSourceQueries::get_by_id(&conn, &id).unwrap().unwrap()
+        };
+        assert_eq!(stored.rejected_records.unwrap().count, 1);
+
+        let mut strict = request(dir.path(), "events.json", json).await;
+        strict.max_reject_ratio = Some(0.0);
+        let (_, outcome) = jobs.enqueue(strict).await.unwrap();
+        assert!(outcome.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_queued_job() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let jobs = setup(dir.path()).await;
+
+        // Not started yet, so the job stays queued
+        let (job, outcome) = jobs.enqueue(request(dir.path(), "sales.csv", b"id\n1\n").await).await.unwrap();
+        let cancelled = jobs.cancel(&job.id).await.unwrap();
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+        assert_eq!(jobs.list(10).await.unwrap()[0].status, JobStatus::Cancelled);
+
+        jobs.start().await.unwrap();
+        assert!(outcome.await.unwrap().is_err());
+        assert_eq!(jobs.get(&job.id).await.unwrap().status, JobStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_progress_weighs_files_by_size() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let jobs = setup(dir.path()).await;
+        let mut events = jobs.subscribe();
+        jobs.start().await.unwrap();
+
+        let small = b"id\n1\n".to_vec();
+        let large = format!("id\n{}", (0..100).map(|i| format!("{}\n", i)).collect::<String>()).into_bytes();
+        let mut request = request(dir.path(), "small.csv", &small).await;
+        request.files.push(SpooledUpload::from_bytes(dir.path(), "large.csv".to_string(), &large).await.unwrap());
+        let (_, outcome) = jobs.enqueue(request).await.unwrap();
+        outcome.await.unwrap().unwrap();
+
+        let mut after_first = None;
+        while let Ok(event) = events.try_recv() {
+            if event.status == JobStatus::Running && event.files_done == 1 {
+                after_first = Some(event.progress);
+            }
+        }
+        let expected = small.len() as f64 / (small.len() + large.len()) as f64;
+        assert!((after_first.unwrap() - expected).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_combined_files_load_in_one_transaction() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let jobs = setup(dir.path()).await;
+        jobs.start().await.unwrap();
+
+        let mut request = request(dir.path(), "jan.csv", b"id,amount\n1,10\n").await;
+        request.files.push(SpooledUpload::from_bytes(dir.path(), "feb.parquet".to_string(), b"not parquet").await.unwrap());
+        request.archive_mode = ArchiveMode::Combine;
+        let (_, outcome) = jobs.enqueue(request).await.unwrap();
+        assert!(outcome.await.unwrap().is_err());
+
+        // The first file was rolled back with the second
+        let conn = jobs.db_pool.get_connection().await.unwrap();
+        assert!(DataSourceQueries::list_all(&conn).unwrap().is_empty());
+    }
+}
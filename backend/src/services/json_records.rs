@@ -0,0 +1,257 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde_json::{Map, Value as JsonValue};
+use tracing::debug;
+
+use crate::{
+    models::{JsonNesting, RejectedRecord, RejectedRecords},
+    services::upload_spool::{SpoolWriter, SpooledUpload},
+    utils::error::{AppError, AppResult},
+};
+
+/// File extensions read as JSON
+pub const JSON_EXTENSIONS: &[&str] = &["json", "ndjson", "jsonl"];
+
+/// Rewrite a JSON upload as newline-delimited JSON with one object per line,
+/// one record at a time.
+///
+/// Both a top-level array and newline-delimited records are accepted. Array
+/// elements and lines that are not JSON objects are skipped and reported.
+/// With [`JsonNesting::Flatten`], nested objects are expanded into
+/// `parent_child` keys, and records where that names two values alike are
+/// rejected too.
+pub fn normalize_json(upload: &SpooledUpload, nesting: JsonNesting) -> AppResult<(SpooledUpload, RejectedRecords)> {
+    let dir = upload.spool_dir();
+    let stem = Path::new(&upload.file_name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("records");
+
+    let mut output = RecordWriter {
+        file_name: upload.file_name.clone(),
+        writer: SpoolWriter::create(&dir, format!("{}.ndjson", stem))?,
+        nesting,
+        rejected: RejectedRecords::default(),
+        records: 0,
+    };
+
+    let mut reader = BufReader::new(File::open(&upload.path)?);
+    if starts_with_array(&mut reader)? {
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        deserializer
+            .deserialize_seq(ArrayVisitor(&mut output))
+            .and_then(|_| deserializer.end())
+            .map_err(|e| AppError::file_upload(format!("Invalid JSON in {}: {}", upload.file_name, e)))?;
+    } else {
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<JsonValue>(&line) {
+                Ok(value) => output.write(index + 1, value)?,
+                Err(e) => output.reject(index + 1, e.to_string()),
+            }
+        }
+    }
+
+    if output.records == 0 {
+        return Err(AppError::file_upload(format!("{} contains no JSON objects", upload.file_name)));
+    }
+    output.rejected.total = output.records + output.rejected.count;
+    debug!(
+        "Normalized {}: {} records, {} rejected",
+        upload.file_name, output.records, output.rejected.count
+    );
+    Ok((output.writer.finish()?, output.rejected))
+}
+
+/// Whether the first non-whitespace byte opens an array
+fn starts_with_array(reader: &mut BufReader<File>) -> AppResult<bool> {
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(false);
+        }
+        match buffer.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(position) => return Ok(buffer[position] == b'['),
+            None => {
+                let len = buffer.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+struct RecordWriter {
+    file_name: String,
+    writer: SpoolWriter,
+    nesting: JsonNesting,
+    rejected: RejectedRecords,
+    records: usize,
+}
+
+impl RecordWriter {
+    fn write(&mut self, record: usize, value: JsonValue) -> AppResult<()> {
+        let JsonValue::Object(object) = value else {
+            self.reject(record, format!("expected an object, found {}", json_kind(&value)));
+            return Ok(());
+        };
+
+        let object = match self.nesting {
+            JsonNesting::Nested => object,
+            JsonNesting::Flatten => {
+                let mut flat = Map::new();
+                if let Err(key) = flatten_into(&mut flat, None, object) {
+                    self.rejected.push(RejectedRecord {
+                        file: self.file_name.clone(),
+                        record,
+                        error: format!("flattened key {} names two values", key),
+                        column: Some(key),
+                        value: None,
+                        raw: None,
+                    });
+                    return Ok(());
+                }
+                flat
+            }
+        };
+        serde_json::to_writer(&mut self.writer, &object)
+            .map_err(|e| AppError::internal(format!("Failed to write JSON record: {}", e)))?;
+        self.writer.write_all(b"\n")?;
+        self.records += 1;
+        Ok(())
+    }
+
+    fn reject(&mut self, record: usize, error: String) {
+        self.rejected.push(RejectedRecord {
+            file: self.file_name.clone(),
+            record,
+            error,
+            column: None,
+            value: None,
+            raw: None,
+        });
+    }
+}
+
+/// Move the keys of `object` into `flat`, prefixing those of nested objects
+/// with their parent's key. Fails with the key when two values flatten to
+/// it, e.g. `{"a_b": 1, "a": {"b": 2}}`.
+fn flatten_into(
+    flat: &mut Map<String, JsonValue>,
+    prefix: Option<&str>,
+    object: Map<String, JsonValue>,
+) -> Result<(), String> {
+    for (key, value) in object {
+        let key = match prefix {
+            Some(prefix) => format!("{}_{}", prefix, key),
+            None => key,
+        };
+        match value {
+            JsonValue::Object(nested) => flatten_into(flat, Some(&key), nested)?,
+            value => {
+                if flat.contains_key(&key) {
+                    return Err(key);
+                }
+                flat.insert(key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn json_kind(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "a boolean",
+        JsonValue::Number(_) => "a number",
+        JsonValue::String(_) => "a string",
+        JsonValue::Array(_) => "an array",
+        JsonValue::Object(_) => "an object",
+    }
+}
+
+/// Streams the elements of a top-level array into a [`RecordWriter`]
+struct ArrayVisitor<'a>(&'a mut RecordWriter);
+
+impl<'de> Visitor<'de> for ArrayVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of objects")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut index = 0;
+        while let Some(value) = seq.next_element::<JsonValue>()? {
+            index += 1;
+            self.0.write(index, value).map_err(de::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn read_records(upload: &SpooledUpload) -> Vec<JsonValue> {
+        let text = std::fs::read_to_string(&upload.path).unwrap();
+        text.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    async fn spool(dir: &Path, name: &str, data: &str) -> SpooledUpload {
+        SpooledUpload::from_bytes(dir, name.to_string(), data.as_bytes()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_array_with_rejected_elements() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let upload = spool(dir.path(), "orders.json", r#"  [{"id": 1, "customer": {"name": "a"}}, 5, {"id": 2, "tags": ["x"]}]"#).await;
+
+        let (normalized, rejected) = normalize_json(&upload, JsonNesting::Nested).unwrap();
+        assert_eq!(normalized.extension(), "ndjson");
+        assert_eq!(
+            read_records(&normalized),
+            vec![json!({"id": 1, "customer": {"name": "a"}}), json!({"id": 2, "tags": ["x"]})]
+        );
+        assert_eq!(rejected.count, 1);
+        assert_eq!(rejected.total, 3);
+        assert_eq!(rejected.samples[0].record, 2);
+        assert_eq!(rejected.samples[0].error, "expected an object, found a number");
+
+        let broken = spool(dir.path(), "broken.json", r#"[{"id": 1}, {"id": "#).await;
+        assert!(normalize_json(&broken, JsonNesting::Nested).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_flattened() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let upload = spool(
+            dir.path(),
+            "events.ndjson",
+            "{\"id\": 1, \"user\": {\"name\": \"a\", \"geo\": {\"country\": \"NZ\"}}}\n\n{\"id\": 2, oops}\n{\"id\": 3, \"tags\": [1, 2]}\n{\"id\": 4, \"user_name\": \"b\", \"user\": {\"name\": \"c\"}}\n",
+        )
+        .await;
+
+        let (normalized, rejected) = normalize_json(&upload, JsonNesting::Flatten).unwrap();
+        assert_eq!(
+            read_records(&normalized),
+            vec![json!({"id": 1, "user_name": "a", "user_geo_country": "NZ"}), json!({"id": 3, "tags": [1, 2]})]
+        );
+        assert_eq!(rejected.count, 2);
+        assert_eq!(rejected.samples[0].file, "events.ndjson");
+        assert_eq!(rejected.samples[0].record, 3);
+        // Flattening would have dropped one of the two names
+        assert_eq!(rejected.samples[1].record, 5);
+        assert_eq!(rejected.samples[1].column.as_deref(), Some("user_name"));
+    }
+}
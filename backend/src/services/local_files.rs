@@ -0,0 +1,214 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use duckdb::Connection;
+
+use crate::{
+    database::filter::quote_literal,
+    utils::error::{AppError, AppResult},
+};
+
+/// A file on the server matched by a local path pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub resolved: PathBuf, // the path with symlinks resolved, as checked against the roots
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Check that `pattern`, an absolute file path or glob such as
+/// `/data/drops/*.parquet`, lies under one of `roots`.
+///
+/// The directories before the first wildcard are resolved, following
+/// symlinks, and the pattern is returned rebuilt on the resolved directory.
+/// `..` is refused anywhere in the pattern.
+pub fn resolve_pattern(roots: &[String], pattern: &str) -> AppResult<String> {
+    if roots.is_empty() {
+        return Err(AppError::bad_request(
+            "Local ingestion is disabled; start the server with --local-roots",
+        ));
+    }
+
+    let path = Path::new(pattern.trim());
+    if !path.is_absolute() {
+        return Err(AppError::validation(format!("Local path must be absolute: {}", pattern)));
+    }
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(AppError::validation(format!("Local path must not contain '..': {}", pattern)));
+    }
+
+    let components: Vec<_> = path.components().collect();
+    let wildcard = components
+        .iter()
+        .position(|c| c.as_os_str().to_string_lossy().contains(['*', '?', '[', '{']))
+        .unwrap_or(components.len() - 1);
+    let base: PathBuf = components[..wildcard].iter().collect();
+    let rest: PathBuf = components[wildcard..].iter().collect();
+
+    let base = std::fs::canonicalize(&base)
+        .map_err(|_| AppError::not_found(format!("Directory not found: {}", base.display())))?;
+    if !within_roots(roots, &base) {
+        return Err(AppError::validation(format!(
+            "Local path is outside the allowed directories: {}",
+            pattern
+        )));
+    }
+
+    base.join(rest)
+        .to_str()
+        .map(str::to_string)
+        .ok_or_else(|| AppError::validation(format!("Local path is not valid UTF-8: {}", pattern)))
+}
+
+/// Files matching a resolved pattern, in path order. Matching uses DuckDB's
+/// `glob`, so it agrees with what DuckDB would read for the pattern.
+///
+/// The part of the pattern after the first wildcard can match symlinks, so
+/// every match is resolved again and the pattern is refused if one leads
+/// outside `roots`. Files are read through their resolved path, so a
+/// symlink changed after the check is not followed.
+pub fn list_files(conn: &Connection, roots: &[String], pattern: &str) -> AppResult<Vec<LocalFile>> {
+    let mut stmt = conn.prepare(&format!("SELECT file FROM glob({}) ORDER BY file", quote_literal(pattern)))?;
+    let paths = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let Ok(resolved) = std::fs::canonicalize(&path) else {
+            continue;
+        };
+        if !within_roots(roots, &resolved) {
+            return Err(AppError::validation(format!(
+                "{} leads outside the allowed directories",
+                path
+            )));
+        }
+        let Some(metadata) = std::fs::metadata(&resolved).ok().filter(|m| m.is_file()) else {
+            continue;
+        };
+        files.push(LocalFile {
+            path: PathBuf::from(path),
+            resolved,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    Ok(files)
+}
+
+/// Whether a resolved path lies under one of `roots`
+fn within_roots(roots: &[String], path: &Path) -> bool {
+    roots
+        .iter()
+        .filter_map(|root| std::fs::canonicalize(root).ok())
+        .any(|root| path.starts_with(root))
+}
+
+/// Table function reading `files`, matched by `pattern`, in place with
+/// columns matched by name across files. Only the files listed are read,
+/// through their resolved paths, rather than the pattern itself: files
+/// appearing later have not been checked against the roots.
+pub fn read_sql(pattern: &str, files: &[LocalFile]) -> AppResult<String> {
+    let lower = pattern.to_lowercase();
+    let lower = lower
+        .strip_suffix(".gz")
+        .or_else(|| lower.strip_suffix(".zst"))
+        .unwrap_or(&lower);
+    let paths = files
+        .iter()
+        .map(|file| {
+            file.resolved
+                .to_str()
+                .map(quote_literal)
+                .ok_or_else(|| AppError::validation(format!("Local path is not valid UTF-8: {}", file.resolved.display())))
+        })
+        .collect::<AppResult<Vec<_>>>()?;
+    let path = format!("[{}]", paths.join(", "));
+
+    if lower.ends_with(".parquet") {
+        Ok(format!("read_parquet({}, union_by_name = true)", path))
+    } else if lower.ends_with(".csv") {
+        Ok(format!("read_csv({}, union_by_name = true)", path))
+    } else if [".json", ".ndjson", ".jsonl"].iter().any(|ext| lower.ends_with(ext)) {
+        Ok(format!("read_json_auto({}, format = 'auto', union_by_name = true)", path))
+    } else {
+        Err(AppError::bad_request(format!(
+            "Only CSV, JSON and Parquet files can be queried in place: {}",
+            pattern
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_pattern_within_roots() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let drops = dir.path().join("drops");
+        std::fs::create_dir(&drops).unwrap();
+        std::fs::write(drops.join("a.csv"), "id\n1\n").unwrap();
+        std::fs::write(drops.join("b.csv"), "id\n2\n").unwrap();
+        std::fs::write(drops.join("notes.txt"), "").unwrap();
+        let roots = vec![dir.path().to_string_lossy().to_string()];
+
+        let pattern = resolve_pattern(&roots, &format!("{}/*.csv", drops.display())).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        let files = list_files(&conn, &roots, &pattern).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.path.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["a.csv", "b.csv"]);
+        assert_eq!(files[0].size, 5);
+
+        assert!(resolve_pattern(&roots, &format!("{}/../*.csv", drops.display())).is_err());
+        assert!(resolve_pattern(&roots, "drops/*.csv").is_err());
+        assert!(resolve_pattern(&[drops.to_string_lossy().to_string()], &format!("{}/*", dir.path().display())).is_err());
+        assert!(resolve_pattern(&[], &pattern).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_files_refuses_symlinks_out_of_roots() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        std::fs::write(outside.path().join("secret.csv"), "id\n1\n").unwrap();
+        let drops = dir.path().join("drops");
+        std::fs::create_dir(&drops).unwrap();
+        std::fs::write(drops.join("a.csv"), "id\n1\n").unwrap();
+        let roots = vec![dir.path().to_string_lossy().to_string()];
+        let conn = Connection::open_in_memory().unwrap();
+
+        let pattern = resolve_pattern(&roots, &format!("{}/*.csv", drops.display())).unwrap();
+        assert_eq!(list_files(&conn, &roots, &pattern).unwrap().len(), 1);
+
+        std::os::unix::fs::symlink(outside.path().join("secret.csv"), drops.join("b.csv")).unwrap();
+        assert!(list_files(&conn, &roots, &pattern).is_err());
+    }
+
+    #[test]
+    fn test_read_sql_by_extension() {
+        let files = |names: &[&str]| -> Vec<LocalFile> {
+            names
+                .iter()
+                .map(|name| LocalFile {
+                    path: PathBuf::from(name),
+                    resolved: PathBuf::from(name),
+                    size: 0,
+                    modified: None,
+                })
+                .collect()
+        };
+        assert_eq!(
+            read_sql("/data/*.parquet", &files(&["/data/a.parquet", "/data/b.parquet"])).unwrap(),
+            "read_parquet(['/data/a.parquet', '/data/b.parquet'], union_by_name = true)"
+        );
+        assert!(read_sql("/data/events-*.ndjson.gz", &files(&["/data/events-1.ndjson.gz"]))
+            .unwrap()
+            .starts_with("read_json_auto("));
+        assert!(read_sql("/data/book.xlsx", &files(&["/data/book.xlsx"])).is_err());
+    }
+}
@@ -1,3 +1,22 @@
+pub mod aggregation;
 pub mod analytics;
+pub mod archive;
+pub mod csv_inference;
+pub mod derived;
 pub mod duckdb;
-pub mod file_processor;
\ No newline at end of file
+pub mod export;
+pub mod external_db;
+pub mod file_processor;
+pub mod folder_watch;
+pub mod ingest;
+pub mod jobs;
+pub mod json_records;
+pub mod local_files;
+pub mod query_cache;
+pub mod rejects;
+pub mod remote;
+pub mod schedule;
+pub mod schema_reconcile;
+pub mod upload_spool;
+pub mod versions;
+pub mod xlsx;
\ No newline at end of file
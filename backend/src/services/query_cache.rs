@@ -0,0 +1,352 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use duckdb::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::{
+    database::{
+        queries::{DerivedQueries, QueryCacheQueries},
+        DatabasePool,
+    },
+    models::{QueryCache, QueryResult},
+    utils::error::AppResult,
+};
+
+/// Query result cache backed by the `query_cache` table.
+///
+/// Entries are keyed on the normalized SQL, the bound parameters and the
+/// target data source, and are dropped when a data source they read from is
+/// replaced or deleted.
+#[derive(Clone)]
+pub struct QueryCacheService {
+    default_ttl: i64,
+    counters: Arc<CacheCounters>,
+}
+
+#[derive(Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stores: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub stores: u64,
+    pub invalidations: u64,
+    pub hit_ratio: f64,
+    pub entries: i64,
+}
+
+/// Identity of a cacheable query
+#[derive(Debug, Clone)]
+pub struct CacheKey {
+    pub hash: String,
+    pub normalized_sql: String,
+    pub data_source_id: Option<String>,
+    pub source_ids: Vec<String>,
+}
+
+impl CacheKey {
+    pub fn new(sql: &str, params: Option<&serde_json::Value>, data_source_id: Option<&str>) -> Self {
+        Self::build(sql, params, data_source_id, data_source_id)
+    }
+
+    /// Key of a query over `reference`, a version of `data_source_id` as
+    /// `id@version`. The result is filed under the data source itself, which
+    /// cached rows must reference.
+    pub fn for_version(sql: &str, params: Option<&serde_json::Value>, data_source_id: &str, reference: &str) -> Self {
+        Self::build(sql, params, Some(reference), Some(data_source_id))
+    }
+
+    fn build(sql: &str, params: Option<&serde_json::Value>, target: Option<&str>, data_source_id: Option<&str>) -> Self {
+        let normalized_sql = normalize_sql(sql);
+
+        let mut hasher = Sha256::new();
+        hasher.update(normalized_sql.as_bytes());
+        hasher.update([0]);
+        hasher.update(params.map(canonical_json).unwrap_or_default().as_bytes());
+        hasher.update([0]);
+        hasher.update(target.unwrap_or_default().as_bytes());
+
+        let mut source_ids = referenced_sources(&normalized_sql);
+        if let Some(id) = data_source_id {
+            if !source_ids.iter().any(|s| s == id) {
+                source_ids.push(id.to_string());
+            }
+        }
+
+        Self {
+            hash: format!("{:x}", hasher.finalize()),
+            normalized_sql,
+            data_source_id: data_source_id.map(str::to_string),
+            source_ids,
+        }
+    }
+}
+
+impl QueryCacheService {
+    pub fn new(default_ttl: i64) -> Self {
+        Self {
+            default_ttl,
+            counters: Arc::new(CacheCounters::default()),
+        }
+    }
+
+    /// Look up a cached result, recording a hit or miss
+    pub fn get(&self, conn: &Connection, key: &CacheKey) -> Option<QueryResult> {
+        match QueryCacheQueries::get_valid(conn, &key.hash) {
+            Ok(Some(result)) => {
+                debug!("Query cache hit: {}", key.hash);
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                Some(result)
+            }
+            Ok(None) => {
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Err(e) => {
+                warn!("Query cache lookup failed: {}", e);
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Store a result for `ttl` seconds, or the configured default TTL.
+    /// Failing to cache never fails the query.
+    pub fn put(&self, conn: &Connection, key: &CacheKey, result: &QueryResult, ttl: Option<i64>) {
+        let ttl = ttl.unwrap_or(self.default_ttl);
+        if ttl <= 0 {
+            return;
+        }
+
+        let entry = QueryCache::new(
+            key.hash.clone(),
+            key.normalized_sql.clone(),
+            result.clone(),
+            key.data_source_id.clone(),
+            ttl,
+        );
+
+        if let Err(e) = QueryCacheQueries::purge_expired(conn)
+            .and_then(|_| QueryCacheQueries::upsert(conn, &entry, &key.source_ids))
+        {
+            warn!("Failed to cache query result: {}", e);
+            return;
+        }
+        self.counters.stores.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Store a result through the writer connection, skipping it when the
+    /// writer is busy so that queries never wait behind a bulk load
+    pub fn store(&self, db_pool: &DatabasePool, key: &CacheKey, result: &QueryResult, ttl: Option<i64>) {
+        match db_pool.try_get_writer() {
+            Some(conn_guard) => self.put(&conn_guard, key, result, ttl),
+            None => debug!("Writer busy, not caching query result: {}", key.hash),
+        }
+    }
+
+    /// Drop cached results computed from a data source, or from a derived
+    /// source reading it
+    pub fn invalidate_source(&self, conn: &Connection, data_source_id: &str) -> AppResult<()> {
+        let mut pending = vec![data_source_id.to_string()];
+        let mut seen = HashSet::new();
+        while let Some(id) = pending.pop() {
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            let removed = QueryCacheQueries::delete_for_source(conn, &id)?;
+            if removed > 0 {
+                debug!("Invalidated {} cached results for {}", removed, id);
+                self.counters.invalidations.fetch_add(removed as u64, Ordering::Relaxed);
+            }
+            pending.extend(DerivedQueries::dependents(conn, &id)?);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self, conn: &Connection) -> AppResult<()> {
+        let removed = QueryCacheQueries::clear(conn)?;
+        self.counters.invalidations.fetch_add(removed as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn stats(&self, conn: &Connection) -> CacheStats {
+        let hits = self.counters.hits.load(Ordering::Relaxed);
+        let misses = self.counters.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+
+        CacheStats {
+            hits,
+            misses,
+            stores: self.counters.stores.load(Ordering::Relaxed),
+            invalidations: self.counters.invalidations.load(Ordering::Relaxed),
+            hit_ratio: if lookups > 0 { hits as f64 / lookups as f64 } else { 0.0 },
+            entries: QueryCacheQueries::count(conn).unwrap_or(0),
+        }
+    }
+}
+
+/// Serialize `value` with the keys of every object sorted, so equal parameter
+/// sets hash alike whatever order their keys arrived in
+fn canonical_json(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries: Vec<_> = object.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let fields: Vec<String> = entries
+                .into_iter()
+                .map(|(key, value)| format!("{}:{}", serde_json::Value::from(key.as_str()), canonical_json(value)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        serde_json::Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        value => value.to_string(),
+    }
+}
+
+/// Collapse whitespace outside string literals and drop trailing semicolons so
+/// formatting differences do not defeat the cache
+pub fn normalize_sql(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut quote: Option<char> = None;
+    let mut pending_space = false;
+
+    for c in sql.trim().chars() {
+        match quote {
+            Some(q) => {
+                out.push(c);
+                if c == q {
+                    quote = None;
+                }
+            }
+            None if c.is_whitespace() => pending_space = true,
+            None => {
+                if pending_space && !out.is_empty() {
+                    out.push(' ');
+                }
+                pending_space = false;
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+                out.push(c);
+            }
+        }
+    }
+
+    out.trim_end_matches(|c: char| c == ';' || c.is_whitespace()).to_string()
+}
+
+/// Data source ids referenced through their `data_source_<uuid>` table names
+fn referenced_sources(sql: &str) -> Vec<String> {
+    const PREFIX: &str = "data_source_";
+    const ID_LEN: usize = 36;
+
+    let lower = sql.to_lowercase();
+    let mut ids: Vec<String> = Vec::new();
+    let mut rest = lower.as_str();
+
+    while let Some(pos) = rest.find(PREFIX) {
+        let candidate = &rest[pos + PREFIX.len()..];
+        if candidate.len() >= ID_LEN
+            && candidate[..ID_LEN].chars().all(|c| c.is_ascii_hexdigit() || c == '_')
+        {
+            let id = candidate[..ID_LEN].replace('_', "-");
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        rest = &rest[pos + PREFIX.len()..];
+    }
+
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::queries::DataSourceQueries, models::DataSource};
+    use serde_json::json;
+
+    #[test]
+    fn test_normalize_sql() {
+        assert_eq!(
+            normalize_sql("  SELECT *\n\tFROM   sales  WHERE region = 'A  B' ;"),
+            "SELECT * FROM sales WHERE region = 'A  B'"
+        );
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let a = CacheKey::new("SELECT $x", Some(&json!({"x": 1})), None);
+        let b = CacheKey::new("SELECT   $x;", Some(&json!({"x": 1})), None);
+        let c = CacheKey::new("SELECT $x", Some(&json!({"x": 2})), None);
+
+        assert_eq!(a.hash, b.hash);
+        assert_ne!(a.hash, c.hash);
+
+        // Key order does not matter, at any depth
+        let d = CacheKey::new("SELECT $x", Some(&json!({"x": 1, "f": {"a": [1], "b": null}})), None);
+        let e = CacheKey::new("SELECT $x", Some(&json!({"f": {"b": null, "a": [1]}, "x": 1})), None);
+        assert_eq!(d.hash, e.hash);
+    }
+
+    #[test]
+    fn test_referenced_sources() {
+        let sql = "SELECT * FROM data_source_0b7c1a9e_4f2d_4c3b_9a8e_1d2c3b4a5f6e a \
+                   JOIN DATA_SOURCE_0B7C1A9E_4F2D_4C3B_9A8E_1D2C3B4A5F6E b USING (id)";
+        assert_eq!(
+            referenced_sources(sql),
+            vec!["0b7c1a9e-4f2d-4c3b-9a8e-1d2c3b4a5f6e".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_roundtrip_and_invalidation() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = crate::database::test_pool(dir.path()).await;
+        let conn = db_pool.get_writer().await.unwrap();
+        for id in ["source-1", "derived-1"] {
+            let data_source = DataSource::new(id.to_string(), format!("{}.csv", id), "file".to_string());
+            DataSourceQueries::create(&conn, &data_source).unwrap();
+        }
+
+        let cache = QueryCacheService::new(300);
+        let key = CacheKey::new("SELECT 1", None, Some("source-1"));
+        let result = QueryResult::new(vec!["1".to_string()], vec![vec![json!(1)]]);
+
+        assert!(cache.get(&conn, &key).is_none());
+        cache.put(&conn, &key, &result, None);
+        assert_eq!(cache.get(&conn, &key).unwrap().row_count, 1);
+
+        cache.invalidate_source(&conn, "source-1").unwrap();
+        assert!(cache.get(&conn, &key).is_none());
+
+        // Results of a derived source go with those of the sources it reads
+        conn.execute_batch("INSERT INTO source_dependencies VALUES ('derived-1', 'source-1')").unwrap();
+        let derived_key = CacheKey::new("SELECT 1", None, Some("derived-1"));
+        cache.put(&conn, &derived_key, &result, None);
+        cache.invalidate_source(&conn, "source-1").unwrap();
+        assert!(cache.get(&conn, &derived_key).is_none());
+
+        let stats = cache.stats(&conn);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.invalidations, 2);
+    }
+}
@@ -0,0 +1,240 @@
+use duckdb::Connection;
+use tracing::{debug, warn};
+
+use crate::{
+    database::{filter::quote_literal, queries::AnalyticsQueries},
+    models::{ColumnSchema, CsvDialect, RejectedRecord, RejectedRecords},
+    services::export::ExportService,
+    utils::error::{AppError, AppResult},
+};
+
+/// Rows of a CSV file that `read_csv` skipped rather than failing the load.
+///
+/// DuckDB records every skipped row in a pair of temporary tables named
+/// after this load, dropped again by [`CsvRejects::drop_tables`].
+pub struct CsvRejects {
+    errors_table: String,
+    scans_table: String,
+    data_source_id: String,
+    file_name: String,
+    dialect: CsvDialect,
+    columns: Vec<String>,
+}
+
+impl CsvRejects {
+    /// Record the rows skipped loading `file_name` into the data source
+    /// `data_source_id`
+    pub fn new(data_source_id: &str, file_name: &str, dialect: &CsvDialect, columns: &[ColumnSchema]) -> Self {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        Self {
+            errors_table: format!("reject_errors_{}", id),
+            scans_table: format!("reject_scans_{}", id),
+            data_source_id: data_source_id.to_string(),
+            file_name: file_name.to_string(),
+            dialect: dialect.clone(),
+            columns: columns.iter().map(|c| c.name.clone()).collect(),
+        }
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// `read_csv` options storing rows that fail to parse instead of failing
+    pub fn read_options(&self) -> String {
+        format!(
+            "store_rejects = true, rejects_table = '{}', rejects_scan = '{}'",
+            self.errors_table, self.scans_table
+        )
+    }
+
+    /// Report the rows skipped by the load, or `None` if every row loaded.
+    ///
+    /// `rows_loaded` is the number of rows that did load. With `exports`,
+    /// every rejected row is also written to a downloadable CSV file, kept
+    /// until the data source is deleted.
+    pub fn collect(
+        &self,
+        conn: &Connection,
+        rows_loaded: i64,
+        exports: Option<&ExportService>,
+    ) -> AppResult<Option<RejectedRecords>> {
+        // A row rejected for several columns has one error per column
+        let count: i64 = conn.query_row(
+            &format!("SELECT COUNT(DISTINCT line) FROM {}", self.errors_table),
+            [],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            return Ok(None);
+        }
+
+        let mut rejected = RejectedRecords::default();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT CAST(line AS BIGINT), column_name, CAST(error_type AS VARCHAR), error_message, csv_line \
+             FROM {} ORDER BY line, column_idx LIMIT {}",
+            self.errors_table,
+            RejectedRecords::MAX_SAMPLES
+        ))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let line: i64 = row.get(0)?;
+            let column: Option<String> = row.get(1)?;
+            let error_type: String = row.get(2)?;
+            let message: Option<String> = row.get(3)?;
+            let raw: Option<String> = row.get(4)?;
+
+            let value = match (&column, &raw) {
+                (Some(column), Some(raw)) if error_type == "CAST" => self.field(raw, column),
+                _ => None,
+            };
+            rejected.samples.push(RejectedRecord {
+                file: self.file_name.clone(),
+                record: line as usize,
+                error: message.unwrap_or(error_type),
+                column,
+                value,
+                raw,
+            });
+        }
+        rejected.count = count as usize;
+        rejected.total = rows_loaded as usize + rejected.count;
+
+        if let Some(exports) = exports {
+            let stem = format!("{}{}", file_prefix(&self.data_source_id), uuid::Uuid::new_v4().simple());
+            let (file_path, file_url) = exports.new_kept_file(&stem, "csv")?;
+            let select_sql = format!(
+                "SELECT {} AS file, line, column_name AS column, error_type AS error, error_message AS reason, \
+                 csv_line AS raw FROM {} ORDER BY line, column_idx",
+                quote_literal(&self.file_name),
+                self.errors_table
+            );
+            AnalyticsQueries::copy_to_file(conn, &select_sql, None, "csv", &file_path)?;
+            rejected.files.push(file_url);
+        }
+
+        debug!("{} of {} rows of {} rejected", rejected.count, rejected.total, self.file_name);
+        Ok(Some(rejected))
+    }
+
+    /// Drop the tables DuckDB stored the rejected rows in
+    pub fn drop_tables(&self, conn: &Connection) {
+        for table in [&self.errors_table, &self.scans_table] {
+            if let Err(e) = conn.execute_batch(&format!("DROP TABLE IF EXISTS {}", table)) {
+                warn!("Failed to drop reject table {}: {}", table, e);
+            }
+        }
+    }
+
+    /// The value of `column` in a raw CSV line
+    fn field(&self, raw: &str, column: &str) -> Option<String> {
+        let index = self.columns.iter().position(|c| c == column)?;
+        let delimiter = single_byte(&self.dialect.delimiter)?;
+        let quote = single_byte(&self.dialect.quote).unwrap_or(b'"');
+
+        let mut builder = csv::ReaderBuilder::new();
+        builder.has_headers(false).flexible(true).delimiter(delimiter).quote(quote);
+        if let Some(escape) = single_byte(&self.dialect.escape).filter(|e| *e != quote) {
+            builder.escape(Some(escape));
+        }
+        let record = builder.from_reader(raw.as_bytes()).records().next()?.ok()?;
+        record.get(index).map(str::to_string)
+    }
+}
+
+/// Fail when more than `max_ratio` of the records read were rejected
+pub fn check_limit(rejected: &RejectedRecords, max_ratio: f64) -> AppResult<()> {
+    if rejected.ratio() <= max_ratio {
+        return Ok(());
+    }
+
+    let mut message = format!(
+        "{} of {} records rejected ({:.2}%), more than the allowed {:.2}%",
+        rejected.count,
+        rejected.total,
+        rejected.ratio() * 100.0,
+        max_ratio * 100.0
+    );
+    if let Some(first) = rejected.samples.first() {
+        message.push_str(&format!(". {} line {}", first.file, first.record));
+        if let Some(column) = &first.column {
+            message.push_str(&format!(", column {}", column));
+        }
+        message.push_str(&format!(": {}", first.error));
+    }
+    if !rejected.files.is_empty() {
+        message.push_str(&format!(". Rejected rows: {}", rejected.files.join(", ")));
+    }
+    Err(AppError::validation(message))
+}
+
+/// Delete the files of rejected rows kept for a data source
+pub fn remove_files(exports: &ExportService, data_source_id: &str) -> usize {
+    exports.remove_kept(&file_prefix(data_source_id))
+}
+
+/// Start of the names of the files of rows rejected loading a data source
+fn file_prefix(data_source_id: &str) -> String {
+    format!("rejects-{}-", data_source_id)
+}
+
+fn single_byte(value: &str) -> Option<u8> {
+    match value.as_bytes() {
+        [byte] => Some(*byte),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::csv_inference;
+
+    #[test]
+    fn test_collect_rejected_rows() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        let path = dir.path().join("orders.csv");
+        std::fs::write(&path, "id,amount,note\n1,10,a\n2,ten,b\n3,30,c\n4,40\n").unwrap();
+
+        let inspection = csv_inference::inspect(&conn, &path).unwrap();
+        let mut columns = inspection.columns.clone();
+        for column in &mut columns[..2] {
+            column.r#type = "BIGINT".to_string();
+        }
+        columns[2].r#type = "VARCHAR".to_string();
+
+        let rejects = CsvRejects::new("orders", "orders.csv", &inspection.dialect, &columns);
+        conn.execute_batch(&format!(
+            "CREATE TABLE t AS SELECT * FROM {}",
+            csv_inference::read_csv_sql(&path, &inspection.dialect, &columns, Some(&rejects))
+        ))
+        .unwrap();
+        let loaded: i64 = conn.query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0)).unwrap();
+        assert_eq!(loaded, 2);
+
+        let exports = ExportService::new(dir.path().join("exports"), 3600).unwrap();
+        let rejected = rejects.collect(&conn, loaded, Some(&exports)).unwrap().unwrap();
+        rejects.drop_tables(&conn);
+
+        assert_eq!(rejected.count, 2);
+        assert_eq!(rejected.total, 4);
+        let cast = &rejected.samples[0];
+        assert_eq!(cast.record, 3);
+        assert_eq!(cast.column.as_deref(), Some("amount"));
+        assert_eq!(cast.value.as_deref(), Some("ten"));
+        assert_eq!(cast.raw.as_deref(), Some("2,ten,b"));
+        assert_eq!(rejected.samples[1].record, 5);
+
+        let file_name = rejected.files[0].rsplit('/').next().unwrap();
+        let listing = std::fs::read_to_string(exports.resolve(file_name).unwrap()).unwrap();
+        assert!(listing.starts_with("file,line,column,error,reason,raw"));
+        assert_eq!(listing.lines().count(), 3);
+        assert_eq!(remove_files(&exports, "orders"), 1);
+
+        assert!(check_limit(&rejected, 0.5).is_ok());
+        let error = check_limit(&rejected, 0.01).unwrap_err().to_string();
+        assert!(error.contains("2 of 4 records rejected (50.00%)"), "{}", error);
+        assert!(error.contains("orders.csv line 3, column amount"), "{}", error);
+    }
+}
@@ -0,0 +1,758 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Utc;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header, redirect, StatusCode, Url,
+};
+use tracing::{info, warn};
+
+use crate::{
+    database::{
+        queries::{DataSourceQueries, RemoteQueries},
+        DatabasePool,
+    },
+    models::{
+        ArchiveMode, CreateRemoteSourceRequest, DataSource, LoadMode, LoadResult, RefreshStatus, RemoteSource,
+        SourceRefresh, REMOTE_SOURCE_TYPE,
+    },
+    services::{
+        jobs::{IngestRequest, JobService},
+        schedule::{next_refresh, parse_schedule},
+        upload_spool::{spool_stream, SpooledUpload},
+    },
+    utils::{
+        error::{AppError, AppResult},
+        secrets::Secrets,
+    },
+};
+
+/// How often the scheduler looks for remote sources due a refresh
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest a fetch waits for the server to send more of its response
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest a whole fetch may take, download included, so one slow server
+/// cannot hold up the scheduler for good
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Redirects followed before a fetch fails
+const MAX_REDIRECTS: usize = 10;
+
+/// Cloud instance metadata endpoints outside the link-local ranges
+const METADATA_ADDRESSES: &[IpAddr] = &[
+    IpAddr::V4(Ipv4Addr::new(100, 100, 100, 200)),              // Alibaba Cloud
+    IpAddr::V6(Ipv6Addr::new(0xfd00, 0x0ec2, 0, 0, 0, 0, 0, 0x254)), // AWS over IPv6
+];
+
+/// Payload formats a remote source can be loaded from
+const REMOTE_FORMATS: &[&str] = &["csv", "json", "ndjson", "jsonl", "parquet"];
+
+/// Data sources loaded from HTTP(S) URLs and refreshed on a schedule.
+///
+/// Payloads are downloaded to the temp directory and loaded through the
+/// ingestion job queue: the first fetch creates the data source and each
+/// refresh replaces its rows, or merges only the newer ones into a source
+/// loaded incrementally. Refreshes send the last `ETag` and
+/// `Last-Modified` back, and a 304 or a payload identical to the last one
+/// loaded leaves the source untouched. Every fetch is recorded in
+/// `source_refreshes`, and header values and passwords are stored sealed.
+///
+/// URLs, and the redirects they lead to, may not reach loopback, link-local
+/// or cloud metadata addresses, so a source cannot read the server itself or
+/// the credentials of the instance it runs on.
+#[derive(Clone)]
+pub struct RemoteService {
+    db_pool: DatabasePool,
+    jobs: JobService,
+    secrets: Secrets,
+    client: reqwest::Client,
+    max_size: u64,
+    allow_local: bool,
+    /// Data sources with a refresh in flight
+    refreshing: Arc<Mutex<HashSet<String>>>,
+}
+
+/// A data source's entry in [`RemoteService::refreshing`], removed when the
+/// refresh ends or is dropped
+struct Refreshing {
+    refreshing: Arc<Mutex<HashSet<String>>>,
+    data_source_id: String,
+}
+
+impl Drop for Refreshing {
+    fn drop(&mut self) {
+        self.refreshing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.data_source_id);
+    }
+}
+
+/// Resolves host names only to addresses a remote source may reach
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<_> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| !is_local(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} resolves only to local addresses", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Response to a fetch
+enum Fetched {
+    NotModified,
+    Payload {
+        upload: SpooledUpload,
+        http_status: u16,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+}
+
+impl RemoteService {
+    pub fn new(db_pool: DatabasePool, jobs: JobService, secrets: Secrets, max_size: u64) -> AppResult<Self> {
+        Ok(Self {
+            db_pool,
+            jobs,
+            secrets,
+            client: http_client(false)?,
+            max_size,
+            allow_local: false,
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    /// Let URLs reach local addresses too, for servers that load from
+    /// services on the same host
+    pub fn with_local_addresses(mut self) -> AppResult<Self> {
+        self.client = http_client(true)?;
+        self.allow_local = true;
+        Ok(self)
+    }
+
+    /// Fetch `request.url` and load it into a new data source
+    pub async fn register(&self, request: CreateRemoteSourceRequest) -> AppResult<DataSource> {
+        let url = Url::parse(request.url.trim())
+            .map_err(|e| AppError::validation(format!("Invalid URL {}: {}", request.url, e)))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(AppError::validation(format!("Only HTTP(S) URLs can be loaded: {}", url)));
+        }
+        if !self.allow_local && names_local_address(&url) {
+            return Err(AppError::validation(format!("URLs may not point at local addresses: {}", url)));
+        }
+        let format = request.format.map(|format| format.to_lowercase());
+        if let Some(format) = format.as_deref().filter(|format| !REMOTE_FORMATS.contains(format)) {
+            return Err(AppError::validation(format!(
+                "Unsupported format '{}'; expected one of {}",
+                format,
+                REMOTE_FORMATS.join(", ")
+            )));
+        }
+        if let Some(schedule) = &request.schedule {
+            parse_schedule(schedule)?;
+        }
+
+        let mut remote = RemoteSource {
+            data_source_id: String::new(),
+            url: url.to_string(),
+            format,
+            headers: request.headers,
+            basic_auth: request.basic_auth,
+            schedule: request.schedule,
+            watermark_param: request.watermark_param,
+            etag: None,
+            last_modified: None,
+            content_hash: None,
+            next_refresh_at: None,
+        };
+        let mut refresh = SourceRefresh::new(String::new());
+
+        let Fetched::Payload { upload, http_status, etag, last_modified } = self.fetch(&remote, false, None).await? else {
+            return Err(AppError::file_upload(format!("{} answered 304 without a conditional request", remote.url)));
+        };
+        refresh.http_status = Some(http_status);
+        refresh.size_bytes = upload.size as i64;
+        remote.content_hash = Some(upload.sha256.clone());
+        remote.etag = etag;
+        remote.last_modified = last_modified;
+
+        let mut ingest = IngestRequest::new(remote.url.clone(), vec![upload]);
+        ingest.name = Some(request.name.unwrap_or_else(|| remote.url.clone()));
+        ingest.archive_mode = ArchiveMode::Combine;
+        let result = self.run(ingest).await?;
+        let mut data_source = serde_json::from_value::<Vec<DataSource>>(result)?
+            .pop()
+            .ok_or_else(|| AppError::internal(format!("Loading {} created no data source", remote.url)))?;
+        data_source.r#type = REMOTE_SOURCE_TYPE.to_string();
+        data_source.file_path = Some(remote.url.clone());
+
+        remote.data_source_id = data_source.id.clone();
+        remote.next_refresh_at = next_refresh(remote.schedule.as_deref());
+        refresh.data_source_id = data_source.id.clone();
+        refresh.status = RefreshStatus::Updated;
+        refresh.rows_loaded = data_source.row_count;
+        refresh.finished_at = Some(Utc::now());
+        {
+            let conn_guard = self.db_pool.get_writer().await?;
+            DataSourceQueries::update_origin(&conn_guard, &data_source.id, REMOTE_SOURCE_TYPE, &remote.url)?;
+            RemoteQueries::create(&conn_guard, &remote.sealed(&self.secrets)?)?;
+            RemoteQueries::add_refresh(&conn_guard, &refresh)?;
+        }
+
+        info!("Registered remote source {} from {}", data_source.id, remote.url);
+        Ok(data_source)
+    }
+
+    pub async fn get(&self, data_source_id: &str) -> AppResult<RemoteSource> {
+        let conn_guard = self.db_pool.get_connection().await?;
+        RemoteQueries::get(&conn_guard, data_source_id)?
+            .ok_or_else(|| AppError::not_found(format!("Data source {} is not a remote source", data_source_id)))?
+            .opened(&self.secrets)
+    }
+
+    pub async fn history(&self, data_source_id: &str, limit: usize) -> AppResult<Vec<SourceRefresh>> {
+        let conn_guard = self.db_pool.get_connection().await?;
+        RemoteQueries::list_refreshes(&conn_guard, data_source_id, limit)
+    }
+
+    /// Fetch the source again, replacing its rows if the payload changed.
+    /// A failed fetch or load is recorded and returned rather than raised.
+    pub async fn refresh(&self, data_source_id: &str) -> AppResult<SourceRefresh> {
+        let remote = self.get(data_source_id).await?;
+        if !self.refreshing.lock().unwrap_or_else(|e| e.into_inner()).insert(data_source_id.to_string()) {
+            return Err(AppError::bad_request(format!("Data source {} is already refreshing", data_source_id)));
+        }
+        // Also released if the caller goes away mid-refresh
+        let _refreshing = Refreshing {
+            refreshing: Arc::clone(&self.refreshing),
+            data_source_id: data_source_id.to_string(),
+        };
+
+        self.refresh_remote(remote).await
+    }
+
+    /// Run [`Self::refresh_due`] every [`SCHEDULER_INTERVAL`] for the
+    /// lifetime of the process
+    pub fn spawn(&self) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = service.refresh_due().await {
+                    warn!("Scheduled refresh failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Refresh every remote source whose schedule is due, returning how many
+    /// were refreshed
+    pub async fn refresh_due(&self) -> AppResult<usize> {
+        let due = {
+            let conn_guard = self.db_pool.get_connection().await?;
+            RemoteQueries::list_due(&conn_guard, Utc::now())?
+        };
+
+        let mut refreshed = 0;
+        for remote in due {
+            match self.refresh(&remote.data_source_id).await {
+                Ok(refresh) => {
+                    refreshed += 1;
+                    info!("Scheduled refresh of {}: {}", remote.data_source_id, refresh.status.as_str());
+                }
+                Err(e) => warn!("Scheduled refresh of {} failed: {}", remote.data_source_id, e),
+            }
+        }
+        Ok(refreshed)
+    }
+
+    async fn refresh_remote(&self, mut remote: RemoteSource) -> AppResult<SourceRefresh> {
+        let mut refresh = SourceRefresh::new(remote.data_source_id.clone());
+        let data_source = {
+            let conn_guard = self.db_pool.get_connection().await?;
+            DataSourceQueries::get_by_id(&conn_guard, &remote.data_source_id)?
+                .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", remote.data_source_id)))?
+        };
+        let (mode, key_columns) = match &data_source.incremental {
+            Some(incremental) if !incremental.key_columns.is_empty() => {
+                (LoadMode::Upsert, incremental.key_columns.clone())
+            }
+            Some(_) => (LoadMode::Append, Vec::new()),
+            None => (LoadMode::Replace, Vec::new()),
+        };
+        refresh.watermark = data_source.incremental.and_then(|incremental| incremental.last_value);
+
+        let outcome: AppResult<()> = async {
+            let since = refresh.watermark.clone();
+            let Fetched::Payload { upload, http_status, etag, last_modified } =
+                self.fetch(&remote, true, since.as_deref()).await?
+            else {
+                refresh.http_status = Some(StatusCode::NOT_MODIFIED.as_u16());
+                refresh.status = RefreshStatus::Unchanged;
+                return Ok(());
+            };
+            refresh.http_status = Some(http_status);
+            refresh.size_bytes = upload.size as i64;
+
+            if remote.content_hash.as_deref() == Some(upload.sha256.as_str()) {
+                refresh.status = RefreshStatus::Unchanged;
+            } else {
+                let content_hash = upload.sha256.clone();
+                let mut ingest = IngestRequest::new(remote.url.clone(), vec![upload]);
+                ingest.data_source_id = Some(remote.data_source_id.clone());
+                ingest.mode = Some(mode);
+                ingest.key_columns = key_columns;
+                let result: LoadResult = serde_json::from_value(self.run(ingest).await?)?;
+                refresh.rows_loaded = result.rows_loaded;
+                refresh.rows_removed = result.rows_removed;
+                refresh.rows_skipped = result.rows_skipped;
+                refresh.rows_without_watermark = result.rows_without_watermark;
+                refresh.watermark = result.data_source.incremental.and_then(|incremental| incremental.last_value);
+                refresh.status = RefreshStatus::Updated;
+                remote.content_hash = Some(content_hash);
+            }
+            // Only remembered once the payload they describe is loaded
+            remote.etag = etag;
+            remote.last_modified = last_modified;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = outcome {
+            warn!("Refreshing {} from {} failed: {}", remote.data_source_id, remote.url, e);
+            refresh.status = RefreshStatus::Failed;
+            refresh.error = Some(e.to_string());
+        }
+        refresh.finished_at = Some(Utc::now());
+        remote.next_refresh_at = next_refresh(remote.schedule.as_deref());
+
+        let conn_guard = self.db_pool.get_writer().await?;
+        RemoteQueries::update(&conn_guard, &remote.sealed(&self.secrets)?)?;
+        RemoteQueries::add_refresh(&conn_guard, &refresh)?;
+        Ok(refresh)
+    }
+
+    /// Download the source's URL, conditionally on the last payload loaded
+    /// when `conditional` is set. The incremental watermark, when given, is
+    /// sent as the source's watermark parameter so the server can leave out
+    /// rows already loaded.
+    async fn fetch(&self, remote: &RemoteSource, conditional: bool, watermark: Option<&str>) -> AppResult<Fetched> {
+        let mut url = Url::parse(&remote.url)
+            .map_err(|e| AppError::validation(format!("Invalid URL {}: {}", remote.url, e)))?;
+        if !self.allow_local && names_local_address(&url) {
+            return Err(AppError::validation(format!("URLs may not point at local addresses: {}", remote.url)));
+        }
+        if let (Some(param), Some(watermark)) = (&remote.watermark_param, watermark) {
+            url.query_pairs_mut().append_pair(param, watermark);
+        }
+        let mut request = self.client.get(url);
+        for (name, value) in &remote.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(auth) = &remote.basic_auth {
+            request = request.basic_auth(&auth.username, auth.password.as_ref());
+        }
+        if conditional {
+            if let Some(etag) = &remote.etag {
+                request = request.header(header::IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &remote.last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| AppError::file_upload(format!("Failed to fetch {}: {}", remote.url, e)))?;
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        if !status.is_success() {
+            return Err(AppError::file_upload(format!("{} answered {}", remote.url, status)));
+        }
+
+        let header_value = |name: header::HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let etag = header_value(header::ETAG);
+        let last_modified = header_value(header::LAST_MODIFIED);
+        let file_name = payload_name(remote, header_value(header::CONTENT_TYPE).as_deref())?;
+
+        let upload = spool_stream(&std::env::temp_dir(), file_name, response.bytes_stream(), self.max_size).await?;
+        Ok(Fetched::Payload {
+            upload,
+            http_status: status.as_u16(),
+            etag,
+            last_modified,
+        })
+    }
+
+    /// Run an ingestion job and wait for its result
+    async fn run(&self, request: IngestRequest) -> AppResult<serde_json::Value> {
+        let (job, outcome) = self.jobs.enqueue(request).await?;
+        outcome
+            .await
+            .map_err(|_| AppError::internal(format!("Job {} was dropped", job.id)))?
+    }
+}
+
+/// HTTP client for fetching payloads, which refuses local addresses unless
+/// `allow_local` is set
+fn http_client(allow_local: bool) -> AppResult<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(concat!("duckdb-dashboard/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(Duration::from_secs(30))
+        .read_timeout(READ_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT);
+    if !allow_local {
+        // Host names are checked as they resolve, and literal addresses,
+        // which are not resolved, as each redirect is followed
+        builder = builder
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(redirect::Policy::custom(|attempt| {
+                if names_local_address(attempt.url()) {
+                    let error = format!("Redirect to a local address refused: {}", attempt.url());
+                    attempt.error(error)
+                } else if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("Too many redirects")
+                } else {
+                    attempt.follow()
+                }
+            }));
+    }
+    builder
+        .build()
+        .map_err(|e| AppError::internal(format!("Failed to create HTTP client: {}", e)))
+}
+
+/// Whether `url`'s host is a literal local address. Host names are checked
+/// by [`PublicResolver`] instead.
+fn names_local_address(url: &Url) -> bool {
+    url.host_str()
+        .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+        .and_then(|host| host.parse::<IpAddr>().ok())
+        .is_some_and(is_local)
+}
+
+/// Loopback, link-local, unspecified and cloud metadata addresses, which
+/// reach the server itself or its host's credentials rather than a remote
+/// service
+fn is_local(ip: IpAddr) -> bool {
+    if METADATA_ADDRESSES.contains(&ip) {
+        return true;
+    }
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_link_local() || ip.is_unspecified(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_local(IpAddr::V4(ip)),
+            None => ip.is_loopback() || ip.is_unspecified() || (ip.segments()[0] & 0xffc0) == 0xfe80,
+        },
+    }
+}
+
+/// File name for a downloaded payload, whose extension picks how it is
+/// loaded: the configured format, else the URL's extension, else the
+/// response's Content-Type
+fn payload_name(remote: &RemoteSource, content_type: Option<&str>) -> AppResult<String> {
+    let last_segment = Url::parse(&remote.url)
+        .ok()
+        .and_then(|url| url.path_segments().and_then(|mut segments| segments.next_back().map(str::to_string)))
+        .filter(|segment| !segment.is_empty())
+        .unwrap_or_else(|| "download".to_string());
+
+    if let Some(format) = &remote.format {
+        let stem = last_segment.split('.').next().unwrap_or("download");
+        return Ok(format!("{}.{}", stem, format));
+    }
+
+    let lower = last_segment.to_lowercase();
+    let unwrapped = lower
+        .strip_suffix(".gz")
+        .or_else(|| lower.strip_suffix(".zst"))
+        .unwrap_or(&lower);
+    if REMOTE_FORMATS.iter().any(|format| unwrapped.ends_with(&format!(".{}", format))) {
+        return Ok(last_segment);
+    }
+
+    let mime = content_type
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .map(|mime| mime.essence_str().to_string());
+    let extension = match mime.as_deref() {
+        Some("text/csv") => "csv",
+        Some("application/json") => "json",
+        Some("application/x-ndjson") | Some("application/jsonl") => "ndjson",
+        Some("application/vnd.apache.parquet") | Some("application/x-parquet") => "parquet",
+        _ => {
+            return Err(AppError::bad_request(format!(
+                "Cannot tell the format of {} (Content-Type {}); set format to one of {}",
+                remote.url,
+                content_type.unwrap_or("missing"),
+                REMOTE_FORMATS.join(", ")
+            )))
+        }
+    };
+    Ok(format!("{}.{}", last_segment.split('.').next().unwrap_or("download"), extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{source_table, BasicAuth, IncrementalConfig},
+        services::{file_processor::FileProcessor, query_cache::QueryCacheService},
+    };
+    use axum::{
+        extract::{RawQuery, State},
+        http::HeaderMap,
+        response::IntoResponse,
+        routing::get,
+        Router,
+    };
+
+    /// Body and ETag served as `/sales.csv`, and the query string of the
+    /// last request
+    type Served = Arc<Mutex<(String, String, Option<String>)>>;
+
+    async fn sales(
+        State(served): State<Served>,
+        RawQuery(query): RawQuery,
+        headers: HeaderMap,
+    ) -> axum::response::Response {
+        // user:secret
+        if headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) != Some("Basic dXNlcjpzZWNyZXQ=") {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        let (body, etag) = {
+            let mut served = served.lock().unwrap();
+            served.2 = query;
+            (served.0.clone(), served.1.clone())
+        };
+        if headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) == Some(etag.as_str()) {
+            return StatusCode::NOT_MODIFIED.into_response();
+        }
+        ([(header::ETAG, etag)], body).into_response()
+    }
+
+    async fn serve(served: Served) -> String {
+        let app = Router::new().route("/sales.csv", get(sales)).with_state(served);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    async fn setup(dir: &std::path::Path) -> (DatabasePool, RemoteService) {
+        let db_pool = crate::database::test_pool(dir).await;
+        let jobs = JobService::new(
+            db_pool.clone(),
+            FileProcessor::new(db_pool.clone()),
+            QueryCacheService::new(300),
+            1024 * 1024,
+        );
+        jobs.start().await.unwrap();
+        let remote = RemoteService::new(db_pool.clone(), jobs, Secrets::ephemeral(), 1024 * 1024)
+            .and_then(RemoteService::with_local_addresses)
+            .unwrap();
+        (db_pool, remote)
+    }
+
+    fn request(url: String, password: &str) -> CreateRemoteSourceRequest {
+        CreateRemoteSourceRequest {
+            url,
+            name: Some("sales".to_string()),
+            format: None,
+            headers: Default::default(),
+            basic_auth: Some(BasicAuth {
+                username: "user".to_string(),
+                password: Some(password.to_string()),
+            }),
+            schedule: Some("*/5 * * * *".to_string()),
+            watermark_param: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_and_refresh_remote_source() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (db_pool, service) = setup(dir.path()).await;
+        let served: Served = Arc::new(Mutex::new(("id,amount\n1,10\n2,20\n".to_string(), "\"v1\"".to_string(), None)));
+        let base = serve(served.clone()).await;
+        let url = format!("{}/sales.csv", base);
+
+        assert!(service.register(request(url.clone(), "wrong")).await.is_err());
+
+        let data_source = service.register(request(url.clone(), "secret")).await.unwrap();
+        assert_eq!(data_source.name, "sales");
+        assert_eq!(data_source.r#type, REMOTE_SOURCE_TYPE);
+        assert_eq!(data_source.row_count, 2);
+
+        let remote = service.get(&data_source.id).await.unwrap();
+        assert_eq!(remote.etag.as_deref(), Some("\"v1\""));
+        assert!(remote.next_refresh_at.unwrap() > Utc::now());
+        assert_eq!(remote.redacted().basic_auth.unwrap().password.as_deref(), Some("********"));
+        {
+            let conn = db_pool.get_connection().await.unwrap();
+            let stored = RemoteQueries::get(&conn, &data_source.id).unwrap().unwrap();
+            assert!(!stored.basic_auth.unwrap().password.unwrap().contains("secret"));
+        }
+
+        let unchanged = service.refresh(&data_source.id).await.unwrap();
+        assert_eq!(unchanged.status, RefreshStatus::Unchanged);
+        assert_eq!(unchanged.http_status, Some(304));
+
+        *served.lock().unwrap() = ("id,amount\n1,10\n2,20\n3,30\n".to_string(), "\"v2\"".to_string(), None);
+        let updated = service.refresh(&data_source.id).await.unwrap();
+        assert_eq!(updated.status, RefreshStatus::Updated);
+        assert_eq!(updated.rows_loaded, 3);
+
+        {
+            let conn = db_pool.get_connection().await.unwrap();
+            let stored = DataSourceQueries::get_by_id(&conn, &data_source.id).unwrap().unwrap();
+            assert_eq!(stored.r#type, REMOTE_SOURCE_TYPE);
+            assert_eq!(stored.file_path.as_deref(), Some(url.as_str()));
+            assert_eq!(stored.row_count, 3);
+        }
+
+        // Not due for another few minutes
+        assert_eq!(service.refresh_due().await.unwrap(), 0);
+
+        let statuses: Vec<_> = service
+            .history(&data_source.id, 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|refresh| refresh.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![RefreshStatus::Updated, RefreshStatus::Unchanged, RefreshStatus::Updated]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_incremental_refresh_merges_newer_rows() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (db_pool, service) = setup(dir.path()).await;
+        let served: Served = Arc::new(Mutex::new((
+            "id,updated_at,amount\n1,2024-01-01 00:00:00,10\n2,2024-01-02 00:00:00,20\n".to_string(),
+            "\"v1\"".to_string(),
+            None,
+        )));
+        let base = serve(served.clone()).await;
+        let mut create = request(format!("{}/sales.csv", base), "secret");
+        create.watermark_param = Some("since".to_string());
+        let data_source = service.register(create).await.unwrap();
+        // Nothing to send before the source loads incrementally
+        assert_eq!(served.lock().unwrap().2, None);
+
+        {
+            let conn = db_pool.get_writer().await.unwrap();
+            let table_name = source_table(&data_source.id);
+            let config = IncrementalConfig {
+                watermark_column: "updated_at".to_string(),
+                key_columns: vec!["id".to_string()],
+                last_value: crate::services::file_processor::watermark(&conn, &table_name, "updated_at").unwrap(),
+                updated_at: None,
+            };
+            assert_eq!(config.last_value.as_deref(), Some("2024-01-02 00:00:00"));
+            DataSourceQueries::set_incremental(&conn, &data_source.id, Some(&config)).unwrap();
+        }
+
+        // Row 1 is unchanged, row 2 was updated, row 3 is new and row 4 has
+        // no watermark
+        *served.lock().unwrap() = (
+            "id,updated_at,amount\n1,2024-01-01 00:00:00,10\n2,2024-01-03 00:00:00,25\n3,2024-01-04 00:00:00,30\n4,,40\n"
+                .to_string(),
+            "\"v2\"".to_string(),
+            None,
+        );
+        let refresh = service.refresh(&data_source.id).await.unwrap();
+        assert_eq!(served.lock().unwrap().2.as_deref(), Some("since=2024-01-02+00%3A00%3A00"));
+        assert_eq!(refresh.status, RefreshStatus::Updated);
+        assert_eq!((refresh.rows_loaded, refresh.rows_removed, refresh.rows_skipped), (2, 1, 1));
+        assert_eq!(refresh.rows_without_watermark, 1);
+        assert_eq!(refresh.watermark.as_deref(), Some("2024-01-04 00:00:00"));
+
+        let conn = db_pool.get_connection().await.unwrap();
+        let stored = DataSourceQueries::get_by_id(&conn, &data_source.id).unwrap().unwrap();
+        assert_eq!(stored.row_count, 3);
+        assert_eq!(stored.incremental.unwrap().last_value.as_deref(), Some("2024-01-04 00:00:00"));
+        let table_name = source_table(&data_source.id);
+        let total: f64 = conn
+            .query_row(&format!("SELECT SUM(amount)::DOUBLE FROM {}", table_name), [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(total, 65.0);
+    }
+
+    #[tokio::test]
+    async fn test_local_addresses_are_refused() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (db_pool, _) = setup(dir.path()).await;
+        let jobs = JobService::new(
+            db_pool.clone(),
+            FileProcessor::new(db_pool.clone()),
+            QueryCacheService::new(300),
+            1024 * 1024,
+        );
+        let service = RemoteService::new(db_pool, jobs, Secrets::ephemeral(), 1024 * 1024).unwrap();
+        let base = serve(Arc::new(Mutex::new(Default::default()))).await;
+        let port = base.rsplit(':').next().unwrap();
+
+        for url in [
+            format!("{}/sales.csv", base),
+            format!("http://localhost:{}/sales.csv", port),
+            format!("http://[::ffff:127.0.0.1]:{}/sales.csv", port),
+            "http://169.254.169.254/latest/meta-data/".to_string(),
+        ] {
+            assert!(service.register(request(url, "secret")).await.is_err());
+        }
+        assert!(is_local("fe80::1".parse().unwrap()));
+        assert!(!is_local("93.184.216.34".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_payload_name() {
+        let mut remote = RemoteSource {
+            data_source_id: "id".to_string(),
+            url: "https://example.com/export?day=1".to_string(),
+            format: None,
+            headers: Default::default(),
+            basic_auth: None,
+            schedule: None,
+            watermark_param: None,
+            etag: None,
+            last_modified: None,
+            content_hash: None,
+            next_refresh_at: None,
+        };
+        assert_eq!(payload_name(&remote, Some("text/csv; charset=utf-8")).unwrap(), "export.csv");
+        assert!(payload_name(&remote, Some("text/html")).is_err());
+        remote.format = Some("parquet".to_string());
+        assert_eq!(payload_name(&remote, None).unwrap(), "export.parquet");
+        remote.format = None;
+        remote.url = "https://example.com/data/events.ndjson.gz".to_string();
+        assert_eq!(payload_name(&remote, None).unwrap(), "events.ndjson.gz");
+    }
+}
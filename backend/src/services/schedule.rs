@@ -0,0 +1,36 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+
+use crate::utils::error::{AppError, AppResult};
+
+/// Parse a cron expression, either standard five-field cron or with a
+/// leading seconds field
+pub fn parse_schedule(expression: &str) -> AppResult<cron::Schedule> {
+    let expression = expression.trim();
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression.to_string(),
+    };
+    cron::Schedule::from_str(&expression)
+        .map_err(|e| AppError::validation(format!("Invalid schedule '{}': {}", expression, e)))
+}
+
+/// When a schedule next fires, or `None` without a valid schedule
+pub fn next_refresh(schedule: Option<&str>) -> Option<DateTime<Utc>> {
+    parse_schedule(schedule?).ok()?.upcoming(Utc).next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_schedule() {
+        assert!(parse_schedule("0 */6 * * *").is_ok());
+        assert!(parse_schedule("*/30 0 */6 * * *").is_ok());
+        assert!(parse_schedule("every hour").is_err());
+        assert!(next_refresh(Some("0 0 * * *")).unwrap() > Utc::now());
+        assert!(next_refresh(None).is_none());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    models::ColumnSchema,
+    utils::error::{AppError, AppResult},
+};
+
+/// Changes needed before rows with the incoming schema can be loaded into an
+/// existing table
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SchemaChanges {
+    /// Columns only in the incoming file; added as nullable
+    pub added: Vec<ColumnSchema>,
+    /// Existing columns whose type must be widened to the incoming type
+    pub widened: Vec<ColumnSchema>,
+}
+
+/// Compare the incoming schema with the existing one.
+///
+/// New columns are added, existing columns missing from the file are loaded
+/// as NULL, and a column whose incoming type is wider than the existing one
+/// (e.g. `INTEGER` to `BIGINT`, `DATE` to `TIMESTAMP`) is widened. Any other
+/// type change is rejected; `column_types` overrides can load the file with
+/// the existing types instead.
+pub fn reconcile(existing: &[ColumnSchema], incoming: &[ColumnSchema]) -> AppResult<SchemaChanges> {
+    let mut changes = SchemaChanges::default();
+    let mut conflicts = Vec::new();
+
+    for column in incoming {
+        match existing.iter().find(|c| c.name == column.name) {
+            None => changes.added.push(ColumnSchema::new(column.name.clone(), column.r#type.clone())),
+            Some(current) => match compare_types(&current.r#type, &column.r#type) {
+                TypeChange::Compatible => {}
+                TypeChange::Widen => changes.widened.push(ColumnSchema::new(column.name.clone(), column.r#type.clone())),
+                TypeChange::Incompatible => {
+                    conflicts.push(format!("{} ({} -> {})", column.name, current.r#type, column.r#type));
+                }
+            },
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(AppError::validation(format!(
+            "Incompatible column types: {}. Use column_types to load them with the existing types",
+            conflicts.join(", ")
+        )));
+    }
+    Ok(changes)
+}
+
+#[derive(Debug, PartialEq)]
+enum TypeChange {
+    /// Incoming values cast into the existing type without loss
+    Compatible,
+    /// The existing column must take the incoming, wider type
+    Widen,
+    Incompatible,
+}
+
+fn compare_types(existing: &str, incoming: &str) -> TypeChange {
+    let (existing, incoming) = (existing.trim().to_uppercase(), incoming.trim().to_uppercase());
+    if existing == incoming || existing == "VARCHAR" {
+        return TypeChange::Compatible;
+    }
+
+    match (type_rank(&existing), type_rank(&incoming)) {
+        (Some((family, current)), Some((incoming_family, next))) if family == incoming_family => {
+            if next <= current {
+                TypeChange::Compatible
+            } else {
+                TypeChange::Widen
+            }
+        }
+        _ => TypeChange::Incompatible,
+    }
+}
+
+/// Family and position of a type in its widening order
+fn type_rank(column_type: &str) -> Option<(&'static str, u8)> {
+    let base = column_type.split('(').next().unwrap_or(column_type).trim();
+    let rank = match base {
+        "TINYINT" => ("numeric", 1),
+        "SMALLINT" => ("numeric", 2),
+        "INTEGER" => ("numeric", 3),
+        "BIGINT" => ("numeric", 4),
+        "HUGEINT" => ("numeric", 5),
+        "DECIMAL" => ("numeric", 6),
+        "FLOAT" => ("numeric", 7),
+        "DOUBLE" => ("numeric", 8),
+        "DATE" => ("temporal", 1),
+        "TIMESTAMP" => ("temporal", 2),
+        _ => return None,
+    };
+    Some(rank)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(columns: &[(&str, &str)]) -> Vec<ColumnSchema> {
+        columns
+            .iter()
+            .map(|(name, column_type)| ColumnSchema::new(name.to_string(), column_type.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_compatible_changes() {
+        let existing = schema(&[("id", "INTEGER"), ("sold_on", "DATE"), ("note", "VARCHAR"), ("amount", "DOUBLE")]);
+        let incoming = schema(&[("id", "BIGINT"), ("sold_on", "TIMESTAMP"), ("note", "BIGINT"), ("amount", "INTEGER"), ("region", "VARCHAR")]);
+
+        let changes = reconcile(&existing, &incoming).unwrap();
+        assert_eq!(changes.added, schema(&[("region", "VARCHAR")]));
+        assert_eq!(changes.widened, schema(&[("id", "BIGINT"), ("sold_on", "TIMESTAMP")]));
+    }
+
+    #[test]
+    fn test_incompatible_changes() {
+        let existing = schema(&[("id", "INTEGER"), ("sold_on", "DATE")]);
+        let incoming = schema(&[("id", "VARCHAR"), ("sold_on", "BOOLEAN")]);
+
+        let err = reconcile(&existing, &incoming).unwrap_err().to_string();
+        assert!(err.contains("id (INTEGER -> VARCHAR)"));
+        assert!(err.contains("sold_on (DATE -> BOOLEAN)"));
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use axum::extract::multipart::Field;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, error};
+
+use crate::utils::error::{AppError, AppResult};
+
+/// Uploaded file written to a uniquely named spool file.
+///
+/// The spool file is removed when the value is dropped, unless it is a
+/// local file read in place (see [`SpooledUpload::local`]).
+#[derive(Debug)]
+pub struct SpooledUpload {
+    pub file_name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+    local: bool,
+}
+
+impl SpooledUpload {
+    /// Lower-cased extension of the original file name
+    pub fn extension(&self) -> String {
+        Path::new(&self.file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_lowercase()
+    }
+
+    /// Spool in-memory data, for callers that do not read from multipart
+    pub async fn from_bytes(dir: &Path, file_name: String, data: &[u8]) -> AppResult<Self> {
+        let path = spool_path(dir, &file_name);
+        tokio::fs::write(&path, data).await?;
+
+        Ok(Self {
+            file_name,
+            path,
+            size: data.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(data)),
+            local: false,
+        })
+    }
+
+    /// Use a file on the server's disk where it is, without copying it. The
+    /// file is never removed, and files unpacked or converted from it are
+    /// spooled to the temp directory instead of next to it.
+    pub async fn local(path: &Path) -> AppResult<Self> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| AppError::bad_request(format!("Not a file: {}", path.display())))?
+                .to_string();
+
+            let mut file = std::fs::File::open(&path)?;
+            let mut hasher = Sha256::new();
+            let size = std::io::copy(&mut file, &mut hasher)?;
+
+            Ok(Self {
+                file_name,
+                path,
+                size,
+                sha256: format!("{:x}", hasher.finalize()),
+                local: true,
+            })
+        })
+        .await
+        .map_err(|e| AppError::internal(format!("Local file task failed: {}", e)))?
+    }
+
+    /// Directory for files derived from this one
+    pub fn spool_dir(&self) -> PathBuf {
+        match self.path.parent() {
+            Some(dir) if !self.local => dir.to_path_buf(),
+            _ => std::env::temp_dir(),
+        }
+    }
+}
+
+impl Drop for SpooledUpload {
+    fn drop(&mut self) {
+        if self.local {
+            return;
+        }
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                error!("Failed to remove spool file {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Stream a multipart file field into `dir`, hashing it as it is written
+pub async fn spool_field(mut field: Field<'_>, dir: &Path) -> AppResult<SpooledUpload> {
+    let file_name = field
+        .file_name()
+        .map(|s| s.to_string())
+        .ok_or_else(|| AppError::bad_request("No file provided"))?;
+
+    let path = spool_path(dir, &file_name);
+    let mut file = tokio::fs::File::create(&path).await?;
+
+    // Owns the path from here so a failed upload leaves nothing behind
+    let mut upload = SpooledUpload {
+        file_name,
+        path,
+        size: 0,
+        sha256: String::new(),
+        local: false,
+    };
+
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = field.chunk().await.map_err(|e| {
+        AppError::file_upload(format!("Failed to read file data: {}", e))
+    })? {
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        upload.size += chunk.len() as u64;
+    }
+    file.flush().await?;
+
+    upload.sha256 = format!("{:x}", hasher.finalize());
+    debug!("Spooled {} ({} bytes) to {}", upload.file_name, upload.size, upload.path.display());
+    Ok(upload)
+}
+
+/// Stream downloaded chunks into `dir`, hashing them as they are written and
+/// failing once more than `limit` bytes have arrived
+pub async fn spool_stream<E: std::fmt::Display>(
+    dir: &Path,
+    file_name: String,
+    stream: impl Stream<Item = Result<Bytes, E>>,
+    limit: u64,
+) -> AppResult<SpooledUpload> {
+    let path = spool_path(dir, &file_name);
+    let mut file = tokio::fs::File::create(&path).await?;
+
+    let mut upload = SpooledUpload {
+        file_name,
+        path,
+        size: 0,
+        sha256: String::new(),
+        local: false,
+    };
+
+    let mut hasher = Sha256::new();
+    let mut stream = std::pin::pin!(stream);
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| AppError::file_upload(format!("Failed to download {}: {}", upload.file_name, e)))?;
+        upload.size += chunk.len() as u64;
+        if upload.size > limit {
+            return Err(AppError::file_upload(format!(
+                "{} is larger than the limit of {} bytes",
+                upload.file_name, limit
+            )));
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    upload.sha256 = format!("{:x}", hasher.finalize());
+    debug!("Spooled {} ({} bytes) to {}", upload.file_name, upload.size, upload.path.display());
+    Ok(upload)
+}
+
+/// Copy a blocking reader into `dir`, failing once more than `limit` bytes
+/// have been read. Used for files unpacked from an upload.
+pub fn spool_reader(dir: &Path, file_name: String, mut reader: impl Read, limit: u64) -> AppResult<SpooledUpload> {
+    let path = spool_path(dir, &file_name);
+    let mut file = std::fs::File::create(&path)?;
+
+    let mut upload = SpooledUpload {
+        file_name,
+        path,
+        size: 0,
+        sha256: String::new(),
+        local: false,
+    };
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer).map_err(|e| {
+            AppError::file_upload(format!("Failed to unpack {}: {}", upload.file_name, e))
+        })?;
+        if read == 0 {
+            break;
+        }
+        upload.size += read as u64;
+        if upload.size > limit {
+            return Err(AppError::file_upload(format!(
+                "Unpacked upload exceeds the limit of {} bytes",
+                limit
+            )));
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+    }
+    file.flush()?;
+
+    upload.sha256 = format!("{:x}", hasher.finalize());
+    debug!("Unpacked {} ({} bytes) to {}", upload.file_name, upload.size, upload.path.display());
+    Ok(upload)
+}
+
+/// Spool file written incrementally, e.g. by a format conversion. The file
+/// is removed if the writer is dropped before [`SpoolWriter::finish`].
+pub struct SpoolWriter {
+    upload: SpooledUpload,
+    file: std::io::BufWriter<std::fs::File>,
+    hasher: Sha256,
+}
+
+impl SpoolWriter {
+    pub fn create(dir: &Path, file_name: String) -> AppResult<Self> {
+        let path = spool_path(dir, &file_name);
+        let file = std::fs::File::create(&path)?;
+
+        Ok(Self {
+            upload: SpooledUpload {
+                file_name,
+                path,
+                size: 0,
+                sha256: String::new(),
+                local: false,
+            },
+            file: std::io::BufWriter::new(file),
+            hasher: Sha256::new(),
+        })
+    }
+
+    pub fn finish(mut self) -> AppResult<SpooledUpload> {
+        self.file.flush()?;
+        self.upload.sha256 = format!("{:x}", self.hasher.finalize());
+        debug!("Wrote {} ({} bytes) to {}", self.upload.file_name, self.upload.size, self.upload.path.display());
+        Ok(self.upload)
+    }
+}
+
+impl Write for SpoolWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.upload.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn spool_path(dir: &Path, file_name: &str) -> PathBuf {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| format!(".{}", ext))
+        .unwrap_or_default();
+    dir.join(format!("upload_{}{}", uuid::Uuid::new_v4(), extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spool_from_bytes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let upload = SpooledUpload::from_bytes(dir.path(), "Sales.CSV".to_string(), b"a,b\n1,2\n")
+            .await
+            .unwrap();
+
+        assert_eq!(upload.extension(), "csv");
+        assert_eq!(upload.size, 8);
+        assert_eq!(upload.sha256.len(), 64);
+        assert!(upload.path.exists());
+
+        let path = upload.path.clone();
+        drop(upload);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,523 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use duckdb::Connection;
+use tracing::{debug, info, warn};
+
+use crate::{
+    database::{
+        params::parse_timestamp,
+        queries::{DataSourceQueries, VersionQueries},
+        sql_guard::reads_qualified,
+        DatabasePool,
+    },
+    models::{source_table, ColumnSchema, DataSource, RestoreVersionRequest, RestoreVersionResponse, SourceVersion},
+    services::{
+        file_processor::watermark,
+        query_cache::{CacheKey, QueryCacheService},
+    },
+    utils::error::{AppError, AppResult},
+};
+
+/// How often the sweeper drops versions past their maximum age
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How many versions of each data source are kept, and for how long. The
+/// newest version of a data source is kept for as long as the source exists.
+#[derive(Debug, Clone, Copy)]
+pub struct VersionRetention {
+    pub max_versions: usize, // 0 keeps every version
+    pub max_age_days: i64,   // 0 keeps versions forever
+}
+
+impl Default for VersionRetention {
+    fn default() -> Self {
+        Self {
+            max_versions: 10,
+            max_age_days: 0,
+        }
+    }
+}
+
+/// A data source, or one of its versions, as read by a query
+#[derive(Debug, Clone)]
+pub struct ResolvedSource {
+    pub data_source_id: String,
+    pub version: Option<i64>, // None for the live table
+    pub table_name: String,
+    pub schema: Vec<ColumnSchema>,
+    pub row_count: i64,
+}
+
+/// Lists and restores the versions data sources keep of their loads, and
+/// applies the retention policy to them.
+///
+/// Versions are recorded by [`record`] as part of each load, so they are
+/// numbered in load order and taken in the load's transaction.
+#[derive(Clone)]
+pub struct VersionService {
+    db_pool: DatabasePool,
+    query_cache: QueryCacheService,
+    retention: VersionRetention,
+}
+
+impl VersionService {
+    pub fn new(db_pool: DatabasePool, query_cache: QueryCacheService, retention: VersionRetention) -> Self {
+        Self {
+            db_pool,
+            query_cache,
+            retention,
+        }
+    }
+
+    /// A data source's versions, newest first. Deleted sources list the
+    /// versions they left behind.
+    pub async fn list(&self, data_source_id: &str) -> AppResult<Vec<SourceVersion>> {
+        let conn_guard = self.db_pool.get_connection().await?;
+        let versions = VersionQueries::list(&conn_guard, data_source_id)?;
+        if versions.is_empty() && DataSourceQueries::get_by_id(&conn_guard, data_source_id)?.is_none() {
+            return Err(AppError::not_found(format!("Data source not found: {}", data_source_id)));
+        }
+        Ok(versions)
+    }
+
+    /// Replace a data source's rows, schema and stats with those of one of
+    /// its versions, recording the result as a new version. A deleted data
+    /// source is registered again as it was at that version.
+    pub async fn restore(&self, data_source_id: &str, request: RestoreVersionRequest) -> AppResult<RestoreVersionResponse> {
+        let conn_guard = self.db_pool.get_writer().await?;
+        let version = match (request.version, request.at.as_deref()) {
+            (Some(number), None) => VersionQueries::get(&conn_guard, data_source_id, number)?,
+            (None, Some(at)) => VersionQueries::at(&conn_guard, data_source_id, parse_point_in_time(at)?)?,
+            _ => return Err(AppError::validation("Restore takes either a version or a point in time (at)")),
+        }
+        .ok_or_else(|| AppError::not_found(format!("No such version of data source {}", data_source_id)))?;
+
+        conn_guard.execute_batch("BEGIN TRANSACTION")?;
+        match restore_version(&conn_guard, &version, &self.retention) {
+            Ok(restored) => {
+                conn_guard.execute_batch("COMMIT")?;
+                self.query_cache.invalidate_source(&conn_guard, data_source_id)?;
+                info!(
+                    "Restored data source {} to version {} as version {}",
+                    data_source_id, version.version, restored.version.version
+                );
+                Ok(restored)
+            }
+            Err(e) => {
+                let _ = conn_guard.execute_batch("ROLLBACK");
+                Err(e)
+            }
+        }
+    }
+
+    /// Drop the versions of every data source past the retention policy,
+    /// returning how many were dropped
+    pub async fn sweep(&self) -> AppResult<usize> {
+        let conn_guard = self.db_pool.get_writer().await?;
+        let mut removed = 0;
+        for data_source_id in VersionQueries::source_ids(&conn_guard)? {
+            removed += prune(&conn_guard, &data_source_id, &self.retention)?;
+        }
+
+        if removed > 0 {
+            info!("Removed {} data source versions past retention", removed);
+        }
+        Ok(removed)
+    }
+
+    /// Run [`Self::sweep`] periodically for the lifetime of the process
+    pub fn spawn_sweeper(&self) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = service.sweep().await {
+                    warn!("Version sweep failed: {}", e);
+                }
+            }
+        })
+    }
+}
+
+/// Snapshot a data source's table as its next version, then drop the
+/// versions past `retention`. Runs in the caller's transaction.
+pub fn record(
+    conn: &Connection,
+    data_source: &DataSource,
+    retention: &VersionRetention,
+    restored_from: Option<i64>,
+) -> AppResult<SourceVersion> {
+    let number = VersionQueries::next_version(conn, &data_source.id)?;
+    let version = SourceVersion::new(data_source, number, restored_from);
+    debug!("Snapshotting data source {} as {}", data_source.id, version.table_name);
+
+    conn.execute_batch(&format!(
+        "CREATE OR REPLACE TABLE {} AS SELECT * FROM {}",
+        version.table_name,
+        source_table(&data_source.id)
+    ))?;
+    VersionQueries::create(conn, &version)?;
+    prune(conn, &data_source.id, retention)?;
+
+    Ok(version)
+}
+
+/// Drop every version of a data source, returning how many there were
+pub fn purge(conn: &Connection, data_source_id: &str) -> AppResult<usize> {
+    let versions = VersionQueries::list(conn, data_source_id)?;
+    for version in &versions {
+        drop_version(conn, version)?;
+    }
+    Ok(versions.len())
+}
+
+/// Drop the versions of a data source beyond `retention.max_versions` or
+/// older than `retention.max_age_days`, returning how many were dropped
+fn prune(conn: &Connection, data_source_id: &str, retention: &VersionRetention) -> AppResult<usize> {
+    let versions = VersionQueries::list(conn, data_source_id)?;
+    let keep_newest = DataSourceQueries::get_by_id(conn, data_source_id)?.is_some();
+    let cutoff = (retention.max_age_days > 0).then(|| Utc::now() - chrono::Duration::days(retention.max_age_days));
+
+    let mut removed = 0;
+    for (i, version) in versions.iter().enumerate() {
+        if keep_newest && i == 0 {
+            continue;
+        }
+        let over_count = retention.max_versions > 0 && i >= retention.max_versions;
+        let expired = cutoff.is_some_and(|cutoff| version.created_at < cutoff);
+        if over_count || expired {
+            drop_version(conn, version)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn drop_version(conn: &Connection, version: &SourceVersion) -> AppResult<()> {
+    debug!("Dropping version {} of data source {}", version.version, version.data_source_id);
+    conn.execute_batch(&format!("DROP TABLE IF EXISTS {}", version.table_name))?;
+    VersionQueries::delete(conn, &version.data_source_id, version.version)?;
+    Ok(())
+}
+
+/// Copy a version back into the data source's table, registering the source
+/// again if it was deleted
+fn restore_version(
+    conn: &Connection,
+    version: &SourceVersion,
+    retention: &VersionRetention,
+) -> AppResult<RestoreVersionResponse> {
+    let table_name = source_table(&version.data_source_id);
+    let existing = DataSourceQueries::get_by_id(conn, &version.data_source_id)?;
+    if existing.as_ref().is_some_and(DataSource::is_view) {
+        return Err(AppError::validation(format!(
+            "Data source {} is queried in place and cannot be restored",
+            version.data_source_id
+        )));
+    }
+
+    conn.execute_batch(&format!(
+        "CREATE OR REPLACE TABLE {} AS SELECT * FROM {}",
+        table_name, version.table_name
+    ))?;
+
+    let data_source = match existing {
+        Some(data_source) => {
+            DataSourceQueries::update_schema(conn, &data_source.id, &version.schema)?;
+            DataSourceQueries::update_stats(conn, &data_source.id, version.row_count, version.size_bytes)?;
+            // The restored rows no longer match the last uploaded file
+            DataSourceQueries::update_content_hash(conn, &data_source.id, None)?;
+            let mut data_source = data_source
+                .with_schema(version.schema.clone())
+                .with_stats(version.row_count, version.size_bytes);
+            data_source.content_hash = None;
+            // Loads continue from the restored rows' watermark
+            if let Some(incremental) = data_source.incremental.as_mut() {
+                incremental.last_value = if version.schema.iter().any(|c| c.name == incremental.watermark_column) {
+                    watermark(conn, &table_name, &incremental.watermark_column)?
+                } else {
+                    None
+                };
+                incremental.updated_at = Some(Utc::now());
+                DataSourceQueries::set_incremental(conn, &data_source.id, Some(incremental))?;
+            }
+            data_source
+        }
+        None => {
+            let mut data_source = version.data_source();
+            // The SQL of a deleted derived source went with it, so it comes
+            // back as a plain table
+            if data_source.is_derived() {
+                data_source.r#type = "file".to_string();
+            }
+            DataSourceQueries::create(conn, &data_source)?;
+            data_source
+        }
+    };
+
+    let restored = record(conn, &data_source, retention, Some(version.version))?;
+    Ok(RestoreVersionResponse {
+        data_source,
+        version: restored,
+    })
+}
+
+/// Resolve a data source reference: a data source id for its live table,
+/// `id@N` for version N, or `id@<timestamp>` for the version current at
+/// that time. Versions resolve after the data source is deleted.
+pub fn resolve(conn: &Connection, reference: &str) -> AppResult<ResolvedSource> {
+    let Some((data_source_id, at)) = reference.split_once('@') else {
+        let data_source = DataSourceQueries::get_by_id(conn, reference)?
+            .ok_or_else(|| AppError::not_found(format!("Data source not found: {}", reference)))?;
+        return Ok(ResolvedSource {
+            table_name: source_table(&data_source.id),
+            data_source_id: data_source.id,
+            version: None,
+            schema: data_source.schema,
+            row_count: data_source.row_count,
+        });
+    };
+
+    let version = match at.parse::<i64>() {
+        Ok(number) => VersionQueries::get(conn, data_source_id, number)?,
+        Err(_) => VersionQueries::at(conn, data_source_id, parse_point_in_time(at)?)?,
+    }
+    .ok_or_else(|| AppError::not_found(format!("Data source version not found: {}", reference)))?;
+
+    Ok(ResolvedSource {
+        data_source_id: version.data_source_id,
+        version: Some(version.version),
+        table_name: version.table_name,
+        schema: version.schema,
+        row_count: version.row_count,
+    })
+}
+
+/// Make `sql` read a version instead of the data source's live table, by
+/// shadowing the live table's name with a CTE over the version. A query
+/// naming the table with its schema would read past the CTE, so it is refused.
+pub fn pin_version(sql: &str, resolved: &ResolvedSource) -> AppResult<String> {
+    if resolved.version.is_none() {
+        return Ok(sql.to_string());
+    }
+    let live_table = source_table(&resolved.data_source_id);
+    if reads_qualified(sql, &live_table) {
+        return Err(AppError::validation(format!(
+            "Queries of a version must name the table as {}, without a schema",
+            live_table
+        )));
+    }
+    let cte = format!("{} AS (SELECT * FROM {})", live_table, resolved.table_name);
+
+    let sql = sql.trim_start();
+    let lower = sql.to_lowercase();
+    for keyword in ["with recursive", "with"] {
+        if lower.starts_with(keyword) && lower[keyword.len()..].starts_with(char::is_whitespace) {
+            return Ok(format!("{} {}, {}", &sql[..keyword.len()], cte, sql[keyword.len()..].trim_start()));
+        }
+    }
+    Ok(format!("WITH {} {}", cte, sql))
+}
+
+/// Reference to what a query read, as the data source id or `id@N`, so
+/// results for a point in time are cached under the version it resolved to
+pub fn canonical_reference(resolved: &ResolvedSource) -> String {
+    match resolved.version {
+        Some(version) => format!("{}@{}", resolved.data_source_id, version),
+        None => resolved.data_source_id.clone(),
+    }
+}
+
+/// Cache key of a query over a resolved source, or `None` if its result
+/// cannot be cached. Results of a version are filed under its data source,
+/// so they are not cached once the source is deleted.
+pub fn cache_key(
+    conn: &Connection,
+    sql: &str,
+    params: Option<&serde_json::Value>,
+    resolved: &ResolvedSource,
+) -> AppResult<Option<CacheKey>> {
+    if resolved.version.is_none() {
+        return Ok(Some(CacheKey::new(sql, params, Some(&resolved.data_source_id))));
+    }
+    if DataSourceQueries::get_by_id(conn, &resolved.data_source_id)?.is_none() {
+        return Ok(None);
+    }
+    let reference = canonical_reference(resolved);
+    Ok(Some(CacheKey::for_version(sql, params, &resolved.data_source_id, &reference)))
+}
+
+/// Parse an RFC 3339 timestamp, or a date or timestamp in UTC
+fn parse_point_in_time(raw: &str) -> AppResult<DateTime<Utc>> {
+    parse_timestamp(raw)
+        .map(|at| at.and_utc())
+        .map_err(|_| AppError::validation(format!("Invalid version or point in time: {}", raw)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        models::{version_table, LoadMode, QueryResult},
+        services::{file_processor::FileProcessor, upload_spool::SpooledUpload},
+    };
+
+    async fn load(processor: &FileProcessor, dir: &std::path::Path, data_source: &DataSource, csv: &[u8]) -> DataSource {
+        let upload = SpooledUpload::from_bytes(dir, "load.csv".to_string(), csv).await.unwrap();
+        let result = processor
+            .load_into(data_source, &upload, &HashMap::new(), LoadMode::Replace, &[])
+            .await
+            .unwrap();
+        result.data_source
+    }
+
+    fn total(conn: &Connection, table_name: &str) -> i64 {
+        conn.query_row(&format!("SELECT SUM(amount)::BIGINT FROM {}", table_name), [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_versions_resolve_and_restore_deleted_source() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = crate::database::test_pool(dir.path()).await;
+        let processor = FileProcessor::new(db_pool.clone()).with_version_retention(VersionRetention::default());
+        let service = VersionService::new(db_pool.clone(), QueryCacheService::new(300), VersionRetention::default());
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "q1.csv".to_string(), b"id,amount\n1,10\n2,20\n")
+            .await
+            .unwrap();
+        let data_source = processor.process_file(&upload).await.unwrap();
+        {
+            let conn = db_pool.get_writer().await.unwrap();
+            DataSourceQueries::create(&conn, &data_source).unwrap();
+            assert_eq!(processor.record_version(&conn, &data_source).unwrap(), Some(1));
+        }
+        let loaded_at = Utc::now();
+        let data_source = load(&processor, dir.path(), &data_source, b"id,amount,region\n1,15,north\n").await;
+
+        let versions = service.list(&data_source.id).await.unwrap();
+        assert_eq!(versions.iter().map(|v| v.version).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!((versions[1].row_count, versions[1].schema.len()), (2, 2));
+        assert_eq!((versions[0].row_count, versions[0].schema.len()), (1, 3));
+
+        {
+            let conn = db_pool.get_connection().await.unwrap();
+            let live = resolve(&conn, &data_source.id).unwrap();
+            assert_eq!((live.version, total(&conn, &live.table_name)), (None, 15));
+            let first = resolve(&conn, &format!("{}@1", data_source.id)).unwrap();
+            assert_eq!((first.version, total(&conn, &first.table_name)), (Some(1), 30));
+            let then = resolve(&conn, &format!("{}@{}", data_source.id, loaded_at.to_rfc3339())).unwrap();
+            assert_eq!(then.version, Some(1));
+            assert!(resolve(&conn, &format!("{}@2000-01-01", data_source.id)).is_err());
+            assert!(resolve(&conn, &format!("{}@last-quarter", data_source.id)).is_err());
+
+            // Queries naming the live table read the version instead
+            let sql = format!("SELECT SUM(amount)::BIGINT FROM {}", source_table(&data_source.id));
+            let pinned: i64 = conn.query_row(&pin_version(&sql, &first).unwrap(), [], |row| row.get(0)).unwrap();
+            assert_eq!(pinned, 30);
+        }
+
+        // Versions outlive a source deleted with keep_versions
+        {
+            let conn = db_pool.get_writer().await.unwrap();
+            DataSourceQueries::delete(&conn, &data_source.id).unwrap();
+            conn.execute_batch(&format!("DROP TABLE {}", source_table(&data_source.id))).unwrap();
+        }
+        assert_eq!(service.list(&data_source.id).await.unwrap().len(), 2);
+
+        let request = RestoreVersionRequest { version: Some(1), at: None };
+        let restored = service.restore(&data_source.id, request).await.unwrap();
+        assert_eq!(restored.data_source.row_count, 2);
+        assert_eq!((restored.version.version, restored.version.restored_from), (3, Some(1)));
+
+        let conn = db_pool.get_connection().await.unwrap();
+        let stored = DataSourceQueries::get_by_id(&conn, &data_source.id).unwrap().unwrap();
+        assert_eq!((stored.name.as_str(), stored.schema.len()), ("q1.csv", 2));
+        assert_eq!(total(&conn, &source_table(&data_source.id)), 30);
+    }
+
+    #[tokio::test]
+    async fn test_retention_keeps_newest_versions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = crate::database::test_pool(dir.path()).await;
+        let retention = VersionRetention { max_versions: 2, max_age_days: 0 };
+        let processor = FileProcessor::new(db_pool.clone()).with_version_retention(retention);
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "sales.csv".to_string(), b"id,amount\n1,10\n")
+            .await
+            .unwrap();
+        let mut data_source = processor.process_file(&upload).await.unwrap();
+        DataSourceQueries::create(&db_pool.get_writer().await.unwrap(), &data_source).unwrap();
+        for amount in [20, 30, 40] {
+            let csv = format!("id,amount\n1,{}\n", amount);
+            data_source = load(&processor, dir.path(), &data_source, csv.as_bytes()).await;
+        }
+
+        let conn = db_pool.get_writer().await.unwrap();
+        let versions = VersionQueries::list(&conn, &data_source.id).unwrap();
+        assert_eq!(versions.iter().map(|v| v.version).collect::<Vec<_>>(), vec![3, 2]);
+        assert!(conn.execute_batch(&format!("SELECT * FROM {}", version_table(&data_source.id, 1))).is_err());
+
+        assert_eq!(purge(&conn, &data_source.id).unwrap(), 2);
+        assert!(VersionQueries::list(&conn, &data_source.id).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_version_results_are_cached_under_their_source() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_pool = crate::database::test_pool(dir.path()).await;
+        let processor = FileProcessor::new(db_pool.clone()).with_version_retention(VersionRetention::default());
+        let cache = QueryCacheService::new(300);
+
+        let upload = SpooledUpload::from_bytes(dir.path(), "q1.csv".to_string(), b"id,amount\n1,10\n")
+            .await
+            .unwrap();
+        let data_source = processor.process_file(&upload).await.unwrap();
+        let conn = db_pool.get_writer().await.unwrap();
+        DataSourceQueries::create(&conn, &data_source).unwrap();
+        processor.record_version(&conn, &data_source).unwrap();
+
+        let sql = format!("SELECT SUM(amount)::BIGINT FROM {}", source_table(&data_source.id));
+        let version = resolve(&conn, &format!("{}@1", data_source.id)).unwrap();
+        let key = cache_key(&conn, &sql, None, &version).unwrap().unwrap();
+        let live_key = cache_key(&conn, &sql, None, &resolve(&conn, &data_source.id).unwrap()).unwrap().unwrap();
+        assert_ne!(key.hash, live_key.hash);
+        assert_eq!(key.data_source_id.as_deref(), Some(data_source.id.as_str()));
+
+        let result = QueryResult::new(vec!["sum".to_string()], vec![vec![serde_json::json!(10)]]);
+        cache.put(&conn, &key, &result, None);
+        assert_eq!(cache.get(&conn, &key).unwrap().row_count, 1);
+        assert_eq!(cache.stats(&conn).hits, 1);
+
+        // Versions of a deleted source are not cached
+        cache.invalidate_source(&conn, &data_source.id).unwrap();
+        DataSourceQueries::delete(&conn, &data_source.id).unwrap();
+        assert!(cache_key(&conn, &sql, None, &version).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pin_version_shadows_source_table() {
+        let source = ResolvedSource {
+            data_source_id: "abc".to_string(),
+            version: Some(2),
+            table_name: "data_source_abc_v2".to_string(),
+            schema: Vec::new(),
+            row_count: 0,
+        };
+        assert_eq!(
+            pin_version("SELECT * FROM data_source_abc", &source).unwrap(),
+            "WITH data_source_abc AS (SELECT * FROM data_source_abc_v2) SELECT * FROM data_source_abc"
+        );
+        assert_eq!(
+            pin_version("  with t AS (SELECT 1) SELECT * FROM t", &source).unwrap(),
+            "with data_source_abc AS (SELECT * FROM data_source_abc_v2), t AS (SELECT 1) SELECT * FROM t"
+        );
+        // The schema-qualified name is not shadowed by the CTE
+        assert!(pin_version("SELECT * FROM main.data_source_abc", &source).is_err());
+        assert_eq!(canonical_reference(&source), "abc@2");
+    }
+}
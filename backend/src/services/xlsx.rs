@@ -0,0 +1,461 @@
+use std::path::Path;
+
+use calamine::{open_workbook, Data, Range, Reader, Xlsx};
+use chrono::{NaiveDateTime, Timelike};
+use tracing::debug;
+
+use crate::{
+    models::{SheetInfo, XlsxOptions},
+    services::{
+        ingest::PreparedUpload,
+        upload_spool::{spool_reader, SpooledUpload},
+    },
+    utils::error::{AppError, AppResult},
+};
+
+/// Names and dimensions of the sheets in a workbook
+pub fn list_sheets(path: &Path) -> AppResult<Vec<SheetInfo>> {
+    let mut workbook: Xlsx<_> = open_workbook(path).map_err(xlsx_error)?;
+
+    workbook
+        .sheet_names()
+        .into_iter()
+        .map(|name| {
+            let range = workbook.worksheet_range(&name).map_err(xlsx_error)?;
+            Ok(SheetInfo {
+                name,
+                rows: range.height(),
+                columns: range.width(),
+            })
+        })
+        .collect()
+}
+
+/// Write each selected sheet of the workbook to a CSV file next to it, with
+/// the column types inferred from its cells, failing once the CSV files add
+/// up to more than `max_size` bytes
+pub fn convert_workbook(upload: &SpooledUpload, options: &XlsxOptions, max_size: u64) -> AppResult<Vec<PreparedUpload>> {
+    let mut workbook: Xlsx<_> = open_workbook(&upload.path).map_err(xlsx_error)?;
+    let names = workbook.sheet_names();
+
+    let selected = match (&options.sheet, options.all_sheets) {
+        (Some(_), true) => return Err(AppError::bad_request("Choose either a sheet or all_sheets, not both")),
+        (Some(sheet), false) => {
+            if !names.contains(sheet) {
+                return Err(AppError::bad_request(format!(
+                    "Unknown sheet {}. Sheets: {}",
+                    sheet,
+                    names.join(", ")
+                )));
+            }
+            vec![sheet.clone()]
+        }
+        (None, true) => names,
+        (None, false) => names.into_iter().take(1).collect(),
+    };
+
+    let dir = upload.spool_dir();
+    let mut budget = max_size;
+    let mut prepared = Vec::with_capacity(selected.len());
+    for sheet in selected {
+        let range = workbook.worksheet_range(&sheet).map_err(xlsx_error)?;
+        let range = select_range(&range, options.range.as_deref())?;
+        let table = SheetTable::from_range(&range, options.header);
+        if table.columns.is_empty() {
+            if options.all_sheets {
+                debug!("Skipping empty sheet {}", sheet);
+                continue;
+            }
+            return Err(AppError::file_upload(format!("Sheet {} has no data", sheet)));
+        }
+
+        let csv = table.to_csv()?;
+        let spooled = spool_reader(&dir, format!("{}.csv", sheet), &csv[..], budget)?;
+        budget -= spooled.size;
+        debug!("Converted sheet {} to {} ({} rows)", sheet, spooled.path.display(), table.rows.len());
+
+        prepared.push(PreparedUpload {
+            name: format!("{} - {}", upload.file_name, sheet),
+            upload: spooled,
+            column_types: table.columns.into_iter().collect(),
+            rejected: None,
+        });
+    }
+
+    if prepared.is_empty() {
+        return Err(AppError::file_upload("Workbook has no sheets with data"));
+    }
+    Ok(prepared)
+}
+
+/// Narrow a worksheet to an A1-style range such as `B2:F200` or `B2`
+fn select_range(range: &Range<Data>, selection: Option<&str>) -> AppResult<Range<Data>> {
+    let Some(selection) = selection.map(str::trim).filter(|s| !s.is_empty()) else {
+        return Ok(range.clone());
+    };
+    let Some(sheet_end) = range.end() else {
+        return Ok(range.clone());
+    };
+
+    let invalid = || AppError::bad_request(format!("Invalid cell range: {}", selection));
+    let (start, end) = match selection.split_once(':') {
+        Some((start, end)) => (parse_cell(start).ok_or_else(invalid)?, parse_cell(end).ok_or_else(invalid)?),
+        None => (parse_cell(selection).ok_or_else(invalid)?, sheet_end),
+    };
+    if start.0 > end.0 || start.1 > end.1 {
+        return Err(invalid());
+    }
+    Ok(range.range(start, end))
+}
+
+/// Zero-based (row, column) of an A1-style cell reference
+fn parse_cell(cell: &str) -> Option<(u32, u32)> {
+    let cell = cell.trim().to_uppercase();
+    let split = cell.find(|c: char| c.is_ascii_digit())?;
+    let (letters, digits) = cell.split_at(split);
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+
+    let column = letters
+        .chars()
+        .try_fold(0u32, |acc, c| acc.checked_mul(26)?.checked_add(c as u32 - 'A' as u32 + 1))?;
+    let row: u32 = digits.parse().ok()?;
+    Some((row.checked_sub(1)?, column - 1))
+}
+
+/// Kind of value held by a cell, ordered loosely from narrow to wide
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CellKind {
+    Boolean,
+    Integer,
+    Double,
+    Date,
+    Timestamp,
+    Text,
+}
+
+impl CellKind {
+    fn of(cell: &Data) -> Option<Self> {
+        match cell {
+            Data::Empty | Data::Error(_) => None,
+            Data::String(s) if s.trim().is_empty() => None,
+            Data::Bool(_) => Some(Self::Boolean),
+            Data::Int(_) => Some(Self::Integer),
+            Data::Float(f) if f.fract() == 0.0 && f.abs() < 9_007_199_254_740_992.0 => Some(Self::Integer),
+            Data::Float(_) => Some(Self::Double),
+            Data::DateTime(dt) if !dt.is_duration() => match dt.as_datetime() {
+                Some(value) if value.time().num_seconds_from_midnight() == 0 && value.time().nanosecond() == 0 => {
+                    Some(Self::Date)
+                }
+                Some(_) => Some(Self::Timestamp),
+                None => Some(Self::Text),
+            },
+            _ => Some(Self::Text),
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        use CellKind::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Integer, Double) | (Double, Integer) => Double,
+            (Date, Timestamp) | (Timestamp, Date) => Timestamp,
+            _ => Text,
+        }
+    }
+
+    fn type_name(self) -> &'static str {
+        match self {
+            Self::Boolean => "BOOLEAN",
+            Self::Integer => "BIGINT",
+            Self::Double => "DOUBLE",
+            Self::Date => "DATE",
+            Self::Timestamp => "TIMESTAMP",
+            Self::Text => "VARCHAR",
+        }
+    }
+}
+
+/// Sheet cells split into named, typed columns
+struct SheetTable {
+    /// Column names with their DuckDB types
+    columns: Vec<(String, String)>,
+    kinds: Vec<CellKind>,
+    rows: Vec<Vec<Data>>,
+}
+
+impl SheetTable {
+    fn from_range(range: &Range<Data>, header: Option<bool>) -> Self {
+        let mut rows: Vec<Vec<Data>> = range.rows().map(|row| row.to_vec()).collect();
+        // Trailing blank rows are common below spreadsheet tables
+        while rows.last().is_some_and(|row| row.iter().all(|c| CellKind::of(c).is_none())) {
+            rows.pop();
+        }
+
+        let has_header = header.unwrap_or_else(|| detect_header(&rows));
+        let labels = if has_header && !rows.is_empty() { Some(rows.remove(0)) } else { None };
+        let width = range.width();
+
+        let kinds: Vec<CellKind> = (0..width)
+            .map(|i| {
+                rows.iter()
+                    .filter_map(|row| row.get(i).and_then(CellKind::of))
+                    .reduce(CellKind::merge)
+                    .unwrap_or(CellKind::Text)
+            })
+            .collect();
+
+        let mut names: Vec<String> = Vec::with_capacity(width);
+        for i in 0..width {
+            let label = labels
+                .as_ref()
+                .and_then(|labels| labels.get(i))
+                .map(|cell| cell_text(cell, CellKind::Text).trim().to_string())
+                .filter(|label| !label.is_empty())
+                .unwrap_or_else(|| format!("column{}", i + 1));
+
+            let mut name = label.clone();
+            let mut suffix = 2;
+            while names.contains(&name) {
+                name = format!("{}_{}", label, suffix);
+                suffix += 1;
+            }
+            names.push(name);
+        }
+
+        Self {
+            columns: names.into_iter().zip(kinds.iter().map(|k| k.type_name().to_string())).collect(),
+            kinds,
+            rows,
+        }
+    }
+
+    fn to_csv(&self) -> AppResult<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(self.columns.iter().map(|(name, _)| name)).map_err(csv_error)?;
+        for row in &self.rows {
+            writer
+                .write_record(self.kinds.iter().enumerate().map(|(i, kind)| {
+                    row.get(i).map(|cell| cell_text(cell, *kind)).unwrap_or_default()
+                }))
+                .map_err(csv_error)?;
+        }
+        writer.into_inner().map_err(|e| AppError::internal(format!("Failed to write sheet: {}", e)))
+    }
+}
+
+/// A header row holds only text labels, with at least one label
+fn detect_header(rows: &[Vec<Data>]) -> bool {
+    let Some(first) = rows.first() else {
+        return false;
+    };
+    let labels: Vec<&Data> = first.iter().filter(|c| CellKind::of(c).is_some()).collect();
+    if labels.is_empty() || labels.iter().any(|c| CellKind::of(c) != Some(CellKind::Text)) {
+        return false;
+    }
+
+    // Text over typed values is a header; over text, only distinct labels are
+    let typed_below = rows[1..]
+        .iter()
+        .any(|row| row.iter().any(|c| matches!(CellKind::of(c), Some(kind) if kind != CellKind::Text)));
+    let mut distinct: Vec<String> = labels.iter().map(|c| c.to_string()).collect();
+    distinct.sort();
+    distinct.dedup();
+    typed_below || rows.len() == 1 || distinct.len() == labels.len()
+}
+
+/// CSV text of a cell loaded as `kind`
+fn cell_text(cell: &Data, kind: CellKind) -> String {
+    match cell {
+        Data::Empty | Data::Error(_) => String::new(),
+        Data::Float(f) if kind == CellKind::Integer => format!("{}", *f as i64),
+        Data::DateTime(dt) if !dt.is_duration() => match dt.as_datetime() {
+            Some(value) if kind == CellKind::Date => value.format("%Y-%m-%d").to_string(),
+            Some(value) => format_timestamp(value),
+            None => cell.to_string(),
+        },
+        _ => cell.to_string(),
+    }
+}
+
+fn format_timestamp(value: NaiveDateTime) -> String {
+    value.format("%Y-%m-%d %H:%M:%S%.f").to_string()
+}
+
+fn xlsx_error(e: impl std::fmt::Display) -> AppError {
+    AppError::file_upload(format!("Failed to read workbook: {}", e))
+}
+
+fn csv_error(e: csv::Error) -> AppError {
+    AppError::internal(format!("Failed to write sheet: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calamine::ExcelDateTime;
+
+    fn range(rows: Vec<Vec<Data>>) -> Range<Data> {
+        let height = rows.len() as u32;
+        let width = rows.iter().map(|r| r.len()).max().unwrap_or(0) as u32;
+        let mut range = Range::new((0, 0), (height - 1, width - 1));
+        for (r, row) in rows.into_iter().enumerate() {
+            for (c, cell) in row.into_iter().enumerate() {
+                range.set_value((r as u32, c as u32), cell);
+            }
+        }
+        range
+    }
+
+    fn date(serial: f64) -> Data {
+        Data::DateTime(ExcelDateTime::new(serial, calamine::ExcelDateTimeType::DateTime, false))
+    }
+
+    #[test]
+    fn test_parse_cell() {
+        assert_eq!(parse_cell("A1"), Some((0, 0)));
+        assert_eq!(parse_cell("b12"), Some((11, 1)));
+        assert_eq!(parse_cell("AA3"), Some((2, 26)));
+        assert_eq!(parse_cell("12"), None);
+        assert_eq!(parse_cell("A0"), None);
+    }
+
+    #[test]
+    fn test_typed_columns_with_header() {
+        let sheet = range(vec![
+            vec![Data::String("Region".into()), Data::String("Amount".into()), Data::String("Closed".into()), Data::String("Booked".into())],
+            vec![Data::String("north".into()), Data::Float(10.0), Data::Bool(true), date(45322.0)],
+            vec![Data::String("south".into()), Data::Float(12.5), Data::Bool(false), date(45323.5)],
+            vec![Data::Empty, Data::Empty, Data::Empty, Data::Empty],
+        ]);
+
+        let table = SheetTable::from_range(&sheet, None);
+        let types: Vec<_> = table.columns.iter().map(|(n, t)| (n.as_str(), t.as_str())).collect();
+        assert_eq!(types, vec![("Region", "VARCHAR"), ("Amount", "DOUBLE"), ("Closed", "BOOLEAN"), ("Booked", "TIMESTAMP")]);
+
+        let csv = String::from_utf8(table.to_csv().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "Region,Amount,Closed,Booked\nnorth,10,true,2024-01-31 00:00:00\nsouth,12.5,false,2024-02-01 12:00:00\n"
+        );
+    }
+
+    #[test]
+    fn test_headerless_range() {
+        let sheet = range(vec![
+            vec![Data::String("title".into()), Data::Empty],
+            vec![Data::Float(1.0), Data::String("a".into())],
+            vec![Data::Float(2.0), Data::String("a".into())],
+        ]);
+
+        let body = select_range(&sheet, Some("A2:B3")).unwrap();
+        let table = SheetTable::from_range(&body, Some(false));
+        assert_eq!(table.columns, vec![("column1".to_string(), "BIGINT".to_string()), ("column2".to_string(), "VARCHAR".to_string())]);
+        assert_eq!(String::from_utf8(table.to_csv().unwrap()).unwrap(), "column1,column2\n1,a\n2,a\n");
+
+        assert!(select_range(&sheet, Some("B3:A1")).is_err());
+    }
+
+    /// A minimal `.xlsx` file with a sheet per `(name, rows)`, its cells
+    /// written as inline strings or numbers
+    fn workbook(sheets: &[(&str, Vec<Vec<&str>>)]) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        let mut add = |name: &str, xml: String| {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(xml.as_bytes()).unwrap();
+        };
+
+        let mut entries = String::new();
+        let mut relationships = String::new();
+        for (i, (name, rows)) in sheets.iter().enumerate() {
+            let n = i + 1;
+            entries.push_str(&format!(r#"<sheet name="{}" sheetId="{}" r:id="rId{}"/>"#, name, n, n));
+            relationships.push_str(&format!(
+                r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{}.xml"/>"#,
+                n, n
+            ));
+            let mut data = String::new();
+            for (r, row) in rows.iter().enumerate() {
+                data.push_str(&format!(r#"<row r="{}">"#, r + 1));
+                for (c, value) in row.iter().enumerate() {
+                    let cell = format!("{}{}", (b'A' + c as u8) as char, r + 1);
+                    if value.parse::<f64>().is_ok() {
+                        data.push_str(&format!(r#"<c r="{}"><v>{}</v></c>"#, cell, value));
+                    } else {
+                        data.push_str(&format!(r#"<c r="{}" t="inlineStr"><is><t>{}</t></is></c>"#, cell, value));
+                    }
+                }
+                data.push_str("</row>");
+            }
+            add(
+                &format!("xl/worksheets/sheet{}.xml", n),
+                format!(
+                    r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{}</sheetData></worksheet>"#,
+                    data
+                ),
+            );
+        }
+        add(
+            "[Content_Types].xml",
+            r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/></Types>"#.to_string(),
+        );
+        add(
+            "_rels/.rels",
+            r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#.to_string(),
+        );
+        add(
+            "xl/workbook.xml",
+            format!(
+                r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>{}</sheets></workbook>"#,
+                entries
+            ),
+        );
+        add(
+            "xl/_rels/workbook.xml.rels",
+            format!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{}</Relationships>"#,
+                relationships
+            ),
+        );
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_list_and_convert_workbook() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let data = workbook(&[
+            ("Sales", vec![vec!["Region", "Amount"], vec!["north", "10"], vec!["south", "12.5"]]),
+            ("Empty", Vec::new()),
+            ("Notes", vec![vec!["Note"], vec!["checked"]]),
+        ]);
+        let upload = SpooledUpload::from_bytes(dir.path(), "book.xlsx".to_string(), &data).await.unwrap();
+
+        let sheets: Vec<_> = list_sheets(&upload.path)
+            .unwrap()
+            .into_iter()
+            .map(|sheet| (sheet.name, sheet.rows, sheet.columns))
+            .collect();
+        assert_eq!(
+            sheets,
+            vec![("Sales".to_string(), 3, 2), ("Empty".to_string(), 0, 0), ("Notes".to_string(), 2, 1)]
+        );
+
+        let options = XlsxOptions { all_sheets: true, ..Default::default() };
+        let prepared = convert_workbook(&upload, &options, 1024).unwrap();
+        let names: Vec<_> = prepared.iter().map(|sheet| sheet.name.as_str()).collect();
+        assert_eq!(names, vec!["book.xlsx - Sales", "book.xlsx - Notes"]);
+        assert_eq!(
+            std::fs::read_to_string(&prepared[0].upload.path).unwrap(),
+            "Region,Amount\nnorth,10\nsouth,12.5\n"
+        );
+        assert_eq!(prepared[0].column_types.get("Amount").map(String::as_str), Some("DOUBLE"));
+
+        // The sheets together exceed the limit, though each fits on its own
+        let limit = prepared[0].upload.size + 1;
+        assert!(convert_workbook(&upload, &options, limit).is_err());
+    }
+}
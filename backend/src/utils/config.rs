@@ -3,29 +3,71 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub database_path: String,
+    pub secret_key_path: String, // key stored credentials are sealed with
     pub host: String,
     pub port: u16,
     pub max_upload_size: usize,
+    pub max_unpacked_size: u64,
     pub query_timeout: u64,
     pub cache_ttl: i64,
+    pub db_pool_size: usize,
+    pub db_checkout_timeout: u64,
+    pub db_writer_checkout_timeout: u64, // writes wait behind bulk loads
+    pub exports_dir: String,
+    pub export_ttl: i64,
+    pub max_reject_ratio: f64,
+    pub local_roots: Vec<String>, // directories local files may be ingested from
+    pub watch_interval: u64,
+    pub versioning: bool, // snapshot every load of a data source as a version
+    pub max_versions: usize, // versions kept per data source; 0 keeps all
+    pub version_max_age_days: i64, // 0 keeps versions forever
     pub cors_origins: Vec<String>,
 }
 
 impl Config {
     pub fn new(database_path: String, host: String, port: u16) -> Self {
         Self {
+            secret_key_path: format!("{}.key", database_path),
             database_path,
             host,
             port,
             max_upload_size: 1024 * 1024 * 1024, // 1GB
+            max_unpacked_size: 10 * 1024 * 1024 * 1024, // 10GB decompressed per upload
             query_timeout: 30, // 30 seconds
             cache_ttl: 300, // 5 minutes
+            db_pool_size: 8,
+            db_checkout_timeout: 5, // 5 seconds
+            db_writer_checkout_timeout: 300, // 5 minutes
+            exports_dir: "exports".to_string(),
+            export_ttl: 24 * 60 * 60, // 24 hours
+            max_reject_ratio: 0.0, // any unparseable CSV row fails the upload
+            local_roots: Vec::new(), // local ingestion disabled
+            watch_interval: 10, // 10 seconds
+            versioning: false, // loads replace data without keeping the old rows
+            max_versions: 10,
+            version_max_age_days: 0, // versions kept until replaced by newer ones
             cors_origins: vec!["*".to_string()],
         }
     }
 
+    pub fn pool_options(&self) -> crate::database::PoolOptions {
+        crate::database::PoolOptions {
+            size: self.db_pool_size,
+            checkout_timeout: std::time::Duration::from_secs(self.db_checkout_timeout),
+            writer_checkout_timeout: std::time::Duration::from_secs(self.db_writer_checkout_timeout),
+        }
+    }
+
+    pub fn version_retention(&self) -> crate::services::versions::VersionRetention {
+        crate::services::versions::VersionRetention {
+            max_versions: self.max_versions,
+            max_age_days: self.version_max_age_days,
+        }
+    }
+
     pub fn from_env() -> Self {
         let database_path = std::env::var("DATABASE_PATH").unwrap_or_else(|_| "dashboard.db".to_string());
+        let secret_key_path = std::env::var("SECRET_KEY_PATH").unwrap_or_else(|_| format!("{}.key", database_path));
         let host = std::env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
         let port = std::env::var("PORT")
             .unwrap_or_else(|_| "3000".to_string())
@@ -37,6 +79,11 @@ impl Config {
             .parse()
             .unwrap_or(1024 * 1024 * 1024);
 
+        let max_unpacked_size = std::env::var("MAX_UNPACKED_SIZE")
+            .unwrap_or_else(|_| "10737418240".to_string()) // 10GB in bytes
+            .parse()
+            .unwrap_or(10 * 1024 * 1024 * 1024);
+
         let query_timeout = std::env::var("QUERY_TIMEOUT")
             .unwrap_or_else(|_| "30".to_string())
             .parse()
@@ -47,6 +94,59 @@ impl Config {
             .parse()
             .unwrap_or(300);
 
+        let db_pool_size = std::env::var("DB_POOL_SIZE")
+            .unwrap_or_else(|_| "8".to_string())
+            .parse()
+            .unwrap_or(8);
+
+        let db_checkout_timeout = std::env::var("DB_CHECKOUT_TIMEOUT")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()
+            .unwrap_or(5);
+
+        let db_writer_checkout_timeout = std::env::var("DB_WRITER_CHECKOUT_TIMEOUT")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .unwrap_or(300);
+
+        let exports_dir = std::env::var("EXPORTS_DIR").unwrap_or_else(|_| "exports".to_string());
+
+        let export_ttl = std::env::var("EXPORT_TTL")
+            .unwrap_or_else(|_| "86400".to_string())
+            .parse()
+            .unwrap_or(24 * 60 * 60);
+
+        let max_reject_ratio = std::env::var("MAX_REJECT_RATIO")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0.0);
+
+        let local_roots = std::env::var("LOCAL_ROOTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let watch_interval = std::env::var("WATCH_INTERVAL")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .unwrap_or(10);
+
+        let versioning = std::env::var("VERSIONING")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+
+        let max_versions = std::env::var("MAX_VERSIONS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .unwrap_or(10);
+
+        let version_max_age_days = std::env::var("VERSION_MAX_AGE_DAYS")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+
         let cors_origins = std::env::var("CORS_ORIGINS")
             .unwrap_or_else(|_| "*".to_string())
             .split(',')
@@ -55,11 +155,24 @@ impl Config {
 
         Self {
             database_path,
+            secret_key_path,
             host,
             port,
             max_upload_size,
+            max_unpacked_size,
             query_timeout,
             cache_ttl,
+            db_pool_size,
+            db_checkout_timeout,
+            db_writer_checkout_timeout,
+            exports_dir,
+            export_ttl,
+            max_reject_ratio,
+            local_roots,
+            watch_interval,
+            versioning,
+            max_versions,
+            version_max_age_days,
             cors_origins,
         }
     }
@@ -78,6 +191,7 @@ mod tests {
         );
 
         assert_eq!(config.database_path, "test.db");
+        assert_eq!(config.secret_key_path, "test.db.key");
         assert_eq!(config.host, "localhost");
         assert_eq!(config.port, 8080);
         assert_eq!(config.max_upload_size, 1024 * 1024 * 1024);
@@ -6,6 +6,8 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::database::sql_guard::SqlRejection;
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Database error: {0}")]
@@ -38,9 +40,15 @@ pub enum AppError {
     #[error("Bad request: {0}")]
     BadRequest(String),
     
+    #[error("Query rejected: {0}")]
+    QueryRejected(#[from] SqlRejection),
+    
     #[error("Query timeout")]
     QueryTimeout,
     
+    #[error("No database connection available")]
+    PoolExhausted,
+    
     #[error("File upload error: {0}")]
     FileUpload(String),
     
@@ -58,6 +66,11 @@ pub struct ErrorResponse {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let details = match &self {
+            AppError::QueryRejected(rejection) => Some(serde_json::json!({ "construct": rejection.construct() })),
+            _ => None,
+        };
+
         let (status, error_type, message) = match self {
             AppError::Database(ref e) => {
                 tracing::error!("Database error: {:?}", e);
@@ -99,10 +112,18 @@ impl IntoResponse for AppError {
                 tracing::warn!("Bad request: {}", msg);
                 (StatusCode::BAD_REQUEST, "BAD_REQUEST", msg.clone())
             }
+            AppError::QueryRejected(ref rejection) => {
+                tracing::warn!("Query rejected: {}", rejection);
+                (StatusCode::BAD_REQUEST, "QUERY_REJECTED", rejection.to_string())
+            }
             AppError::QueryTimeout => {
                 tracing::warn!("Query timeout");
                 (StatusCode::REQUEST_TIMEOUT, "QUERY_TIMEOUT", "Query execution timed out".to_string())
             }
+            AppError::PoolExhausted => {
+                tracing::warn!("Database pool exhausted");
+                (StatusCode::SERVICE_UNAVAILABLE, "POOL_EXHAUSTED", "No database connection available, try again".to_string())
+            }
             AppError::FileUpload(ref msg) => {
                 tracing::error!("File upload error: {}", msg);
                 (StatusCode::BAD_REQUEST, "FILE_UPLOAD_ERROR", msg.clone())
@@ -117,7 +138,7 @@ impl IntoResponse for AppError {
             error: error_type.to_string(),
             message,
             code: Some(error_type.to_string()),
-            details: None,
+            details,
         };
 
         (status, Json(error_response)).into_response()
@@ -1,2 +1,3 @@
 pub mod config;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod secrets;
\ No newline at end of file
@@ -0,0 +1,118 @@
+use std::path::Path;
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use tracing::info;
+
+use crate::utils::error::{AppError, AppResult};
+
+/// Marks a stored value as sealed, and the scheme it was sealed with
+const SEALED_PREFIX: &str = "sealed:v1:";
+
+/// Bytes of the AES-GCM nonce stored in front of each sealed value
+const NONCE_LEN: usize = 12;
+
+/// Seals credentials before they are stored in the catalog, so neither the
+/// catalog tables nor a copy of the database file holds them in plaintext.
+///
+/// Values are encrypted with AES-256-GCM under a key kept in a file next to
+/// the database, created with owner-only permissions on first start.
+#[derive(Clone)]
+pub struct Secrets {
+    cipher: Aes256Gcm,
+}
+
+impl Secrets {
+    /// Load the key from `path`, generating it if the file does not exist
+    pub fn from_key_file(path: &Path) -> AppResult<Self> {
+        if !path.exists() {
+            info!("Generating secret key at {}", path.display());
+            let key = Aes256Gcm::generate_key(OsRng);
+            write_private(path, hex::encode(key).as_bytes())?;
+        }
+
+        let encoded = std::fs::read_to_string(path)?;
+        let key = hex::decode(encoded.trim())
+            .ok()
+            .filter(|key| key.len() == 32)
+            .ok_or_else(|| AppError::internal(format!("Secret key file {} is not a 256-bit hex key", path.display())))?;
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        })
+    }
+
+    /// Secrets under a random key that is never stored, e.g. for tests
+    pub fn ephemeral() -> Self {
+        Self {
+            cipher: Aes256Gcm::new(&Aes256Gcm::generate_key(OsRng)),
+        }
+    }
+
+    /// Encrypt `value` for storage
+    pub fn seal(&self, value: &str) -> AppResult<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, value.as_bytes())
+            .map_err(|_| AppError::internal("Failed to seal secret"))?;
+        Ok(format!("{}{}{}", SEALED_PREFIX, hex::encode(nonce), hex::encode(ciphertext)))
+    }
+
+    /// Decrypt a value sealed by [`Self::seal`]. Values stored before
+    /// secrets were sealed are returned as they are.
+    pub fn open(&self, stored: &str) -> AppResult<String> {
+        let Some(sealed) = stored.strip_prefix(SEALED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+
+        let bytes = hex::decode(sealed)
+            .ok()
+            .filter(|bytes| bytes.len() > NONCE_LEN)
+            .ok_or_else(|| AppError::internal("Stored secret is malformed"))?;
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| AppError::internal("Stored secret cannot be opened with the server's secret key"))?;
+        String::from_utf8(plaintext).map_err(|_| AppError::internal("Stored secret is not UTF-8"))
+    }
+}
+
+/// Create `path` readable and writable by its owner only
+fn write_private(path: &Path, contents: &[u8]) -> AppResult<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("test.db.key");
+        let secrets = Secrets::from_key_file(&path).unwrap();
+
+        let sealed = secrets.seal("hunter2").unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX) && !sealed.contains("hunter2"));
+        assert_ne!(sealed, secrets.seal("hunter2").unwrap());
+
+        // The key survives a restart, and only opens its own values
+        let reloaded = Secrets::from_key_file(&path).unwrap();
+        assert_eq!(reloaded.open(&sealed).unwrap(), "hunter2");
+        assert!(Secrets::ephemeral().open(&sealed).is_err());
+        assert_eq!(reloaded.open("stored before sealing").unwrap(), "stored before sealing");
+    }
+}
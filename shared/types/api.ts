@@ -1,5 +1,7 @@
 // API request and response types
 
+import { IngestJob } from './data';
+
 export interface ApiResponse<T = any> {
   data?: T;
   error?: string;
@@ -27,11 +29,12 @@ export interface CreateDataSourceRequest {
 export interface DataPreviewRequest {
   limit?: number;
   offset?: number;
-  filters?: Record<string, any>;
+  filters?: Filter;
 }
 
 export interface DataPreviewResponse {
   columns: string[];
+  columnTypes: string[];
   data: any[][];
   totalRows: number;
   previewRows: number;
@@ -41,7 +44,7 @@ export interface DataPreviewResponse {
 export interface CreateDashboardRequest {
   name: string;
   layout: WidgetLayout[];
-  filters?: any;
+  filters?: Filter;
   dataSourceId?: string;
   refreshInterval?: number;
 }
@@ -49,37 +52,90 @@ export interface CreateDashboardRequest {
 export interface UpdateDashboardRequest {
   name?: string;
   layout?: WidgetLayout[];
-  filters?: any;
+  filters?: Filter;
   dataSourceId?: string;
   refreshInterval?: number;
 }
 
 // Analytics API
+export type QueryParamValue =
+  | null
+  | boolean
+  | number
+  | string
+  | QueryParamValue[]
+  | {
+      type: 'date' | 'timestamp' | 'time' | 'decimal' | 'integer' | 'double' | 'varchar' | 'boolean' | 'list';
+      value: any;
+    };
+
+export type FilterOperator =
+  | 'eq' | 'neq' | 'gt' | 'gte' | 'lt' | 'lte'
+  | 'in' | 'between' | 'like' | 'is_null' | 'is_not_null' | 'relative';
+
+export interface RelativeDateRange {
+  last?: number; // omitted for the current unit, e.g. this month
+  unit: 'hour' | 'day' | 'week' | 'month' | 'quarter' | 'year';
+}
+
+export interface FilterCondition {
+  field: string;
+  op: FilterOperator;
+  value?: QueryParamValue | RelativeDateRange;
+}
+
+export type Filter =
+  | FilterCondition
+  | { and: Filter[] }
+  | { or: Filter[] }
+  | { not: Filter };
+
 export interface QueryRequest {
   sql: string;
-  dataSourceId?: string;
-  params?: Record<string, any>;
+  dataSourceId?: string; // 'id@version' or 'id@<timestamp>' reads that version in place of the source's table
+  params?: Record<string, QueryParamValue> | QueryParamValue[];
   cache?: boolean;
+  timeoutMs?: number;
 }
 
 export interface QueryResult {
   columns: string[];
+  columnTypes: string[];
   data: any[][];
   rowCount: number;
+  truncated?: boolean;
 }
 
 export interface AggregationRequest {
   dataSourceId: string;
   operations: AggregationOperation[];
   groupBy?: string[];
-  filters?: any;
+  timeBucket?: TimeBucket;
+  filters?: Filter;
+  having?: Filter; // on group keys and operation aliases
+  orderBy?: OrderBy[];
+  totals?: 'rollup' | 'cube'; // subtotal rows have a non-zero grouping_id column
   limit?: number;
 }
 
 export interface AggregationOperation {
-  field: string;
-  operation: 'sum' | 'avg' | 'count' | 'min' | 'max' | 'distinct_count';
+  field: string; // '*' is accepted for 'count'
+  operation:
+    | 'sum' | 'avg' | 'count' | 'min' | 'max' | 'distinct_count'
+    | 'median' | 'percentile' | 'stddev' | 'approx_count_distinct';
   alias?: string;
+  percentile?: number; // 0..1, required for 'percentile'
+}
+
+export interface TimeBucket {
+  field: string;
+  interval: RelativeDateRange['unit'];
+  alias?: string; // defaults to `${field}_${interval}`
+}
+
+export interface OrderBy {
+  field: string;
+  direction?: 'asc' | 'desc';
 }
 
 export interface AggregationResult {
@@ -92,14 +148,14 @@ export interface AggregationResult {
 export interface AggregationSummary {
   field: string;
   operation: string;
-  result: any;
+  result: any; // grand total over all filtered rows
 }
 
 export interface ExportRequest {
   dataSourceId?: string;
   query?: string;
   format: 'csv' | 'json' | 'parquet';
-  filters?: any;
+  filters?: Filter;
   columns?: string[];
 }
 
@@ -135,6 +191,65 @@ export interface MetricValue {
   unit?: string;
 }
 
+export interface StatisticsRequest {
+  dataSourceId: string;
+  column: string;
+}
+
+export interface StatisticsResult {
+  dataSourceId: string;
+  column: string;
+  statistics: Record<string, number>; // count, mean, min, max, std_dev, median, q1, q3
+}
+
+export interface TimeSeriesRequest {
+  dataSourceId: string;
+  timeColumn: string;
+  valueColumn: string;
+  interval: 'hour' | 'day' | 'week' | 'month';
+  aggregation: 'sum' | 'avg' | 'count' | 'min' | 'max';
+}
+
+export interface OutlierRequest {
+  dataSourceId: string;
+  column: string;
+  method: 'iqr' | 'zscore';
+  threshold?: number;
+}
+
+export interface CorrelationRequest {
+  dataSourceId: string;
+  columns: string[];
+}
+
+export interface CorrelationResult {
+  dataSourceId: string;
+  matrix: Record<string, Record<string, number>>;
+}
+
+export interface MovingAverageRequest {
+  dataSourceId: string;
+  valueColumn: string;
+  orderColumn: string;
+  windowSize: number;
+}
+
+export interface DataQualityReport {
+  dataSourceId: string;
+  columns: DataQualityMetric[];
+}
+
+export interface DataQualityMetric {
+  columnName: string;
+  dataType: string;
+  totalRows: number;
+  nullCount: number;
+  nullPercentage: number;
+  uniqueCount?: number;
+  minLength?: number;
+  maxLength?: number;
+}
+
 // System API
 export interface HealthResponse {
   status: string;
@@ -147,6 +262,16 @@ export interface SystemStats {
   memoryUsage: number;
   activeConnections: number;
   uptimeSeconds: number;
+  queryCache: CacheStats;
+}
+
+export interface CacheStats {
+  hits: number;
+  misses: number;
+  stores: number;
+  invalidations: number;
+  hitRatio: number;
+  entries: number;
 }
 
 export interface DatabaseInfo {
@@ -160,7 +285,7 @@ export type ClientMessage =
   | {
       type: 'data:subscribe';
       sourceId: string;
-      filters?: any;
+      filters?: Filter; // not supported yet; a non-empty filter is refused
     }
   | {
       type: 'data:unsubscribe';
@@ -169,7 +294,11 @@ export type ClientMessage =
   | {
       type: 'query:execute';
       sql: string;
-      params?: any;
+      params?: Record<string, QueryParamValue> | QueryParamValue[];
+    }
+  | {
+      type: 'job:cancel';
+      jobId: string;
     };
 
 export type ServerMessage = 
@@ -184,6 +313,14 @@ export type ServerMessage =
       data: any[];
       error?: string;
     }
+  | {
+      type: 'job:progress';
+      job: IngestJob;
+    }
+  | {
+      type: 'job:finished'; // done, failed or cancelled
+      job: IngestJob;
+    }
   | {
       type: 'system:status';
       memory: number;
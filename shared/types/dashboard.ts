@@ -1,10 +1,12 @@
 // Dashboard and widget configuration types
 
+import { Filter } from './api';
+
 export interface DashboardConfig {
   id: string;
   name: string;
   layout: WidgetLayout[];
-  filters?: any;
+  filters?: Filter;
   dataSourceId?: string;
   refreshInterval?: number; // in seconds
   createdAt: string;
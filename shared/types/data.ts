@@ -3,24 +3,140 @@
 export interface DataSource {
   id: string;
   name: string;
-  type: 'file' | 'database' | 'api';
+  type: 'file' | 'local' | 'database' | 'api' | 'derived'; // 'local' and 'database' are queried in place
   filePath?: string;
   schema: ColumnSchema[];
   rowCount: number;
   sizeBytes: number;
+  contentHash?: string; // SHA-256 of the file the rows were last loaded from
+  rejectedRecords?: RejectedRecords; // CSV rows and JSON records skipped by the last load
+  incremental?: IncrementalConfig;
+  derived?: DerivedConfig; // set for 'derived' sources
+  dependencies?: string[]; // ids of the sources a derived source reads
   createdAt: string;
   updatedAt: string;
 }
 
+// Data sources defined by SQL over other sources, which it reads as
+// data_source_<id> like custom queries. Views always read current rows;
+// materialized sources are tables refreshed on demand
+// (POST /api/data/derived/:id/refresh), on a cron schedule or after a
+// dependency is loaded into.
+export interface DerivedConfig {
+  sql: string;
+  materialized: boolean;
+  schedule?: string; // cron expression, e.g. '0 * * * *'
+  refreshOnChange: boolean;
+  refreshedAt?: string;
+  nextRefreshAt?: string;
+}
+
+// POST /api/data/derived
+export interface CreateDerivedSourceRequest {
+  name: string;
+  sql: string;
+  materialized?: boolean;
+  schedule?: string; // materialized sources only
+  refreshOnChange?: boolean; // materialized sources only
+}
+
+// PUT /api/data/sources/:id/incremental; appends and upserts then keep only
+// rows above the watermark
+export interface IncrementalConfig {
+  watermarkColumn: string; // e.g. 'updated_at' or a monotonically increasing id
+  keyColumns: string[]; // refreshes upsert on these when set, and append otherwise
+  lastValue?: string; // highest watermark loaded
+  updatedAt?: string;
+}
+
+export interface IncrementalConfigRequest {
+  watermarkColumn: string;
+  keyColumns?: string[];
+}
+
 export interface ColumnSchema {
   name: string;
   type: 'INTEGER' | 'DOUBLE' | 'VARCHAR' | 'DATE' | 'TIMESTAMP' | 'BOOLEAN';
   nullable: boolean;
   unique: boolean;
   primaryKey: boolean;
+  confidence?: number; // share of sampled values matching the inferred type
+  overridden?: boolean; // type chosen by the user; confidence stays that of the inferred type
+}
+
+export interface CsvDialect {
+  delimiter: string;
+  quote: string;
+  escape: string;
+  hasHeader: boolean;
+  dateFormat?: string;
+  timestampFormat?: string;
+}
+
+export interface CsvInspection {
+  dialect: CsvDialect;
+  columns: ColumnSchema[];
+  sampleSize: number;
+}
+
+// Upload into an existing data source
+export type LoadMode = 'append' | 'replace' | 'upsert';
+
+export interface LoadResult {
+  dataSource: DataSource;
+  mode: LoadMode;
+  rowsLoaded: number;
+  rowsRemoved: number; // existing rows dropped by replace or upsert
+  rowsSkipped: number; // rows at or below the incremental watermark
+  rowsWithoutWatermark: number; // rows dropped for lacking a watermark value
+  addedColumns: string[];
+  widenedColumns: string[];
+  version?: number; // version of the data source the load created
 }
 
 // File upload types
+// Archives (.zip, .tar, .tgz) load into one source per file unless combined
+export type ArchiveMode = 'combine' | 'separate';
+
+// JSON uploads keep nested objects as STRUCT columns or flatten them to parent_child columns
+export type JsonNesting = 'nested' | 'flatten';
+
+// Uploads fail when more than the max_reject_ratio form field (0 to 1) of records are rejected
+export interface RejectedRecords {
+  count: number;
+  total: number; // records read, rejected or not
+  samples: RejectedRecord[]; // first 100
+  files?: string[]; // download URLs listing every rejected CSV row, kept until the source is deleted
+}
+
+export interface RejectedRecord {
+  file: string;
+  record: number; // line of a CSV or newline-delimited file, or position in an array, from 1
+  error: string;
+  column?: string;
+  value?: string; // the offending value as written in the file
+  raw?: string; // the whole line as written in the file
+}
+
+// Excel workbook options, sent as upload form fields
+export interface XlsxOptions {
+  sheet?: string; // defaults to the first sheet
+  range?: string; // e.g. 'B2:F200', or 'B2' to read to the end of the sheet
+  header?: boolean; // detected when unset
+  allSheets?: boolean; // one data source per sheet
+}
+
+// Response of POST /api/data/upload/sheets
+export interface SheetInfo {
+  name: string;
+  rows: number;
+  columns: number;
+}
+
+// Response of POST /api/data/upload: the data sources created, one unless an
+// archive is split into sources or a workbook loads several sheets
+export type UploadResponse = DataSource[];
+
 export interface FileUploadProgress {
   loaded: number;
   total: number;
@@ -34,6 +150,188 @@ export interface FileUploadResult {
   warnings?: string[];
 }
 
+// Ingestion job types
+// Uploads run as jobs; POST /api/data/upload?background=true responds with the queued job
+export type JobStatus = 'queued' | 'running' | 'done' | 'failed' | 'cancelled';
+
+export interface IngestJob {
+  id: string;
+  fileName: string;
+  sizeBytes: number;
+  dataSourceId?: string; // target of an upload into an existing source
+  status: JobStatus;
+  filesTotal: number; // data files after unpacking, known once running
+  filesDone: number;
+  bytesTotal: number; // size of the data files after unpacking
+  bytesDone: number;
+  progress: number; // share of the bytes loaded, 0 to 1
+  rowsLoaded: number;
+  error?: string;
+  result?: UploadResponse | LoadResult;
+  createdAt: string;
+  startedAt?: string;
+  finishedAt?: string;
+}
+
+// Local ingestion types
+// POST /api/data/local loads files on the server under its --local-roots and,
+// in either mode, responds with an UploadResponse
+export type LocalMode = 'copy' | 'in_place';
+
+export interface LocalSourceRequest {
+  path: string; // a file or glob, e.g. '/data/drops/*.parquet'
+  name?: string; // defaults to the path
+  mode?: LocalMode; // defaults to 'copy'
+  dataSourceId?: string; // copy into an existing source instead
+  loadMode?: LoadMode;
+  keyColumns?: string[];
+  columnTypes?: Record<string, string>;
+  maxRejectRatio?: number;
+}
+
+// Files matching a watched glob are loaded as they arrive
+export interface FolderWatch {
+  id: string;
+  path: string;
+  dataSourceId?: string; // created from the first new file when unset
+  name?: string;
+  filesIngested: number;
+  lastIngestedAt?: string;
+  lastError?: string;
+  createdAt: string;
+}
+
+export interface CreateWatchRequest {
+  path: string;
+  dataSourceId?: string;
+  name?: string;
+  includeExisting?: boolean; // also load files already present
+}
+
+// Remote source types
+// POST /api/data/remote loads an HTTP(S) URL as a data source of type 'api'
+export type RemoteFormat = 'csv' | 'json' | 'ndjson' | 'parquet';
+
+export interface BasicAuth {
+  username: string;
+  password?: string;
+}
+
+export interface CreateRemoteSourceRequest {
+  url: string;
+  name?: string; // defaults to the URL
+  format?: RemoteFormat; // detected from the URL or Content-Type when unset
+  headers?: Record<string, string>;
+  basicAuth?: BasicAuth;
+  schedule?: string; // cron expression, e.g. '0 */6 * * *'
+  watermarkParam?: string; // query parameter sent with the incremental watermark, e.g. 'since'
+}
+
+// Header values and the password are redacted
+export interface RemoteSource {
+  dataSourceId: string;
+  url: string;
+  format?: RemoteFormat;
+  headers: Record<string, string>;
+  basicAuth?: BasicAuth;
+  schedule?: string;
+  watermarkParam?: string;
+  etag?: string;
+  lastModified?: string;
+  contentHash?: string;
+  nextRefreshAt?: string;
+}
+
+export type RefreshStatus = 'updated' | 'unchanged' | 'failed';
+
+export interface SourceRefresh {
+  id: string;
+  dataSourceId: string;
+  status: RefreshStatus; // 'unchanged' after a 304 or an identical payload
+  httpStatus?: number;
+  rowsLoaded: number;
+  rowsRemoved: number; // existing rows replaced
+  rowsSkipped: number; // rows at or below the incremental watermark
+  rowsWithoutWatermark: number; // rows dropped for lacking a watermark value
+  watermark?: string; // incremental watermark after the refresh
+  sizeBytes: number;
+  error?: string;
+  startedAt: string;
+  finishedAt?: string;
+}
+
+// External database types
+// POST /api/data/databases attaches a database read-only, one data source per table
+export type DatabaseKind = 'sqlite' | 'duckdb' | 'postgres';
+
+export interface PostgresConnection {
+  host: string;
+  port?: number;
+  dbname: string;
+  user?: string;
+  password?: string; // redacted in responses
+  sslmode?: string;
+}
+
+export interface ExternalTable {
+  dataSourceId: string;
+  schema: string;
+  name: string;
+}
+
+export interface ExternalDatabase {
+  id: string;
+  name: string;
+  kind: DatabaseKind;
+  path?: string; // SQLite or DuckDB file under the server's --local-roots
+  postgres?: PostgresConnection;
+  tables: ExternalTable[];
+  createdAt: string;
+}
+
+export interface AttachDatabaseRequest {
+  name?: string;
+  kind: DatabaseKind;
+  path?: string;
+  postgres?: PostgresConnection;
+  tables?: string[]; // 'table' or 'schema.table'; every table when empty
+}
+
+export interface AttachDatabaseResponse {
+  database: ExternalDatabase;
+  dataSources: DataSource[];
+}
+
+// Data source versions
+// Every load snapshots the source as a new version, kept as the server's
+// --max-versions and --version-max-age-days allow, and after the source is
+// deleted. Analytics requests read a version with a dataSourceId of
+// 'id@3' or 'id@2026-06-30T00:00:00Z' (the version current then).
+export interface SourceVersion {
+  dataSourceId: string;
+  version: number;
+  name: string;
+  type: DataSource['type'];
+  filePath?: string;
+  schema: ColumnSchema[];
+  rowCount: number;
+  sizeBytes: number;
+  tableName: string; // what custom SQL reads the version as
+  restoredFrom?: number;
+  createdAt: string;
+}
+
+// POST /api/data/sources/:id/restore with either field
+export interface RestoreVersionRequest {
+  version?: number;
+  at?: string; // RFC 3339 timestamp or 'YYYY-MM-DD', in UTC
+}
+
+export interface RestoreVersionResponse {
+  dataSource: DataSource;
+  version: SourceVersion; // the new version holding the restored rows
+}
+
 // Data validation types
 export interface ValidationResult {
   isValid: boolean;
@@ -30,8 +30,8 @@ export function validateDataSource(dataSource: Partial<DataSource>): ValidationR
     errors.push(new ValidationError('Name must be less than 255 characters', 'name', 'MAX_LENGTH'));
   }
 
-  if (!dataSource.type || !['file', 'database', 'api'].includes(dataSource.type)) {
-    errors.push(new ValidationError('Type must be one of: file, database, api', 'type', 'INVALID_VALUE'));
+  if (!dataSource.type || !['file', 'local', 'database', 'api', 'derived'].includes(dataSource.type)) {
+    errors.push(new ValidationError('Type must be one of: file, local, database, api, derived', 'type', 'INVALID_VALUE'));
   }
 
   if (dataSource.rowCount !== undefined && dataSource.rowCount < 0) {